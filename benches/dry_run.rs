@@ -0,0 +1,27 @@
+//! Times displaying planned changes with huge lines, like the generated
+//! one-liners some profiles contain. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use set_env_perm::PlannedChange;
+
+const ITERATIONS: u32 = 1_000;
+
+fn main() {
+    for (name, line) in [
+        ("8 MB ascii line", "x".repeat(8 << 20)),
+        ("8 MB multi-byte line", "€".repeat((8 << 20) / 3)),
+    ] {
+        let change = PlannedChange::AddLine {
+            path: PathBuf::from("profile"),
+            line,
+        };
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            black_box(black_box(&change).to_string());
+        }
+        println!("{}: {:?} per display", name, start.elapsed() / ITERATIONS);
+    }
+}
@@ -0,0 +1,39 @@
+/* Uses the C interface, see include/set_env.h. Build and run with
+ *
+ *   cargo rustc --features ffi --crate-type cdylib
+ *   cc -Iinclude examples/ffi.c -Ltarget/debug -lset_env_perm -o target/ffi
+ *   LD_LIBRARY_PATH=target/debug target/ffi
+ */
+#include <stdio.h>
+
+#include "set_env.h"
+
+int main(void) {
+    /* A failure leaves a message the caller owns. */
+    if (set_env_set(NULL, "1") != SET_ENV_INVALID_ARGUMENT) {
+        return 1;
+    }
+    char *invalid = set_env_last_error_message();
+    if (invalid == NULL) {
+        return 1;
+    }
+    set_env_string_free(invalid);
+
+    if (set_env_check_or_set("DUMMY", "1") != SET_ENV_OK) {
+        char *message = set_env_last_error_message();
+        fprintf(stderr, "Failed to set DUMMY: %s\n", message);
+        set_env_string_free(message);
+        return 1;
+    }
+
+    size_t removed = 0;
+    int code = set_env_unset("DUMMY", &removed);
+    if (code != SET_ENV_OK) {
+        char *message = set_env_last_error_message();
+        fprintf(stderr, "Failed to unset DUMMY (%d): %s\n", code, message);
+        set_env_string_free(message);
+        return 1;
+    }
+    printf("Removed %zu lines\n", removed);
+    return 0;
+}
@@ -1,5 +1,3 @@
-use set_env_perm;
-
 fn main() {
     // set_env_perm::check_or_set("d", "world6777").expect("not working");
     set_env_perm::set("XXX", "man").unwrap();
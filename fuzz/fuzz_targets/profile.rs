@@ -0,0 +1,23 @@
+//! Feeds arbitrary bytes to the profile parser as every supported
+//! shell, which must never panic. Run with `cargo fuzz run profile`.
+
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use set_env_perm::{line_cost, simulate, SyntaxInfo};
+
+const SHELLS: [&str; 4] = ["bash", "fish", "csh", "powershell"];
+
+fuzz_target!(|data: &[u8]| {
+    let profile = String::from_utf8_lossy(data);
+    for shell in SHELLS {
+        let _ = simulate(&profile, shell, &HashMap::new());
+        if let Some(syntax) = SyntaxInfo::for_shell(shell) {
+            for line in profile.lines() {
+                line_cost(&syntax, line);
+            }
+        }
+    }
+});
@@ -0,0 +1,53 @@
+/* C interface of set_env_perm, built with the `ffi` feature.
+ * Keep in sync with src/ffi.rs.
+ *
+ * Strings passed in are NUL-terminated UTF-8 and stay owned by the
+ * caller. Strings returned are owned by the caller and must be freed
+ * with set_env_string_free, not free.
+ * Every function returns one of the SET_ENV_* codes; the message of the
+ * last failure on the calling thread is returned by
+ * set_env_last_error_message. */
+
+#ifndef SET_ENV_H
+#define SET_ENV_H
+
+#include <stddef.h>
+
+#define SET_ENV_OK 0
+/* A pointer was null or a string wasn't valid UTF-8. */
+#define SET_ENV_INVALID_ARGUMENT 1
+#define SET_ENV_NOT_FOUND 2
+#define SET_ENV_PERMISSION_DENIED 3
+/* A profile has content set_env refuses to change. */
+#define SET_ENV_INVALID_DATA 4
+/* The platform or shell isn't supported. */
+#define SET_ENV_UNSUPPORTED 5
+#define SET_ENV_OTHER 6
+/* The call panicked, which is a bug in set_env. */
+#define SET_ENV_PANIC 7
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+int set_env_set(const char *var, const char *value);
+
+int set_env_append(const char *var, const char *value);
+
+int set_env_check_or_set(const char *var, const char *value);
+
+/* Stores how many lines were removed in `removed` unless it's NULL.
+ * Returns SET_ENV_UNSUPPORTED on Windows. */
+int set_env_unset(const char *var, size_t *removed);
+
+/* Returns NULL if the last call on this thread succeeded. */
+char *set_env_last_error_message(void);
+
+/* Ignores NULL. */
+void set_env_string_free(char *ptr);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SET_ENV_H */
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An append-only log of every line this crate adds to or removes from
+/// a file, for audits, see `set_audit_log`.
+///
+/// Every record is one line of tab separated fields:
+///
+/// ```text
+/// <unix seconds>\t<user>\t<tool>\t<added|removed>\t<path>\t<line>
+/// ```
+///
+/// Tabs and line breaks in fields are written as `\t`, `\n` and `\r`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    tool: String,
+    max_size: u64,
+    keep: usize,
+    redacted: Vec<String>,
+}
+
+impl AuditLog {
+    /// Logs to the file at `path`, which is rotated once it reaches
+    /// 1 MiB, keeping 5 old files.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        AuditLog {
+            path: path.into(),
+            tool: String::new(),
+            max_size: 1024 * 1024,
+            keep: 5,
+            redacted: Vec::new(),
+        }
+    }
+
+    /// Sets the name of the tool making the changes, logged with each
+    /// record.
+    pub fn tool<T: Into<String>>(mut self, tool: T) -> Self {
+        self.tool = tool.into();
+        self
+    }
+
+    /// Rotates the log once it reaches `max_size` bytes: it's renamed
+    /// to `<path>.1`, older ones are shifted to `<path>.2` and so on,
+    /// and ones beyond `keep` are deleted.
+    pub fn rotate(mut self, max_size: u64, keep: usize) -> Self {
+        self.max_size = max_size;
+        self.keep = keep;
+        self
+    }
+
+    /// Logs `<redacted>` instead of the value of lines assigning `var`.
+    pub fn redact<T: Into<String>>(mut self, var: T) -> Self {
+        self.redacted.push(var.into());
+        self
+    }
+}
+
+static LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+/// The content of files about to be written, to find what changed.
+static BEFORE: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+/// Enables logging every line this crate adds or removes to `log`.
+/// Failing to log only emits an `Event::Warning`, the change itself stays
+/// successful. Pass `None` to stop logging. Disabled by default.
+pub fn set_audit_log(log: Option<AuditLog>) {
+    *LOG.lock().unwrap_or_else(|err| err.into_inner()) = log;
+}
+
+fn enabled() -> bool {
+    LOG.lock().unwrap_or_else(|err| err.into_inner()).is_some()
+}
+
+/// Remembers the content of the file at `path` before it's written.
+pub(crate) fn before_write(path: &Path) {
+    if !enabled() {
+        return;
+    }
+    let content = crate::filesystem::current()
+        .read_to_string(path)
+        .unwrap_or_default();
+    let mut before = BEFORE.lock().unwrap_or_else(|err| err.into_inner());
+    before.retain(|(pending, _)| pending != path);
+    before.push((path.to_path_buf(), content));
+}
+
+/// Logs the lines that changed in the file at `path` since
+/// `before_write`.
+pub(crate) fn wrote(path: &Path) {
+    let log = LOG.lock().unwrap_or_else(|err| err.into_inner()).clone();
+    let before = {
+        let mut pending = BEFORE.lock().unwrap_or_else(|err| err.into_inner());
+        let idx = pending.iter().position(|(pending, _)| pending == path);
+        idx.map(|idx| pending.remove(idx).1)
+    };
+    let (Some(log), Some(before)) = (log, before) else {
+        return;
+    };
+    let after = crate::filesystem::current()
+        .read_to_string(path)
+        .unwrap_or_default();
+
+    let (added, removed) = changed_lines(&before, &after);
+    let records: Vec<String> = removed
+        .iter()
+        .map(|line| ("removed", line))
+        .chain(added.iter().map(|line| ("added", line)))
+        .map(|(action, line)| record(&log, action, path, line))
+        .collect();
+    if records.is_empty() {
+        return;
+    }
+    if let Err(err) = append(&log, &records) {
+        crate::events::warn(format!(
+            "audit log {} could not be written: {}",
+            log.path.display(),
+            err
+        ));
+    }
+}
+
+/// Returns the lines only in `after` and the lines only in `before`,
+/// counting repeated lines.
+pub(crate) fn changed_lines<'a>(before: &'a str, after: &'a str) -> (Vec<&'a str>, Vec<&'a str>) {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in before.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    for line in after.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+
+    let mut added = Vec::new();
+    for line in after.lines() {
+        let count = counts.entry(line).or_default();
+        if *count > 0 {
+            *count -= 1;
+            added.push(line);
+        }
+    }
+    let mut removed = Vec::new();
+    for line in before.lines() {
+        let count = counts.entry(line).or_default();
+        if *count < 0 {
+            *count += 1;
+            removed.push(line);
+        }
+    }
+    (added, removed)
+}
+
+fn record(log: &AuditLog, action: &str, path: &Path, line: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let user = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default();
+    let line = match crate::simulate::parse_var_value(line.trim()) {
+        Some((var, value)) if log.redacted.iter().any(|it| it == var) => {
+            line.replacen(value, "<redacted>", 1)
+        }
+        _ => line.to_string(),
+    };
+    [
+        secs.to_string(),
+        user,
+        log.tool.clone(),
+        action.to_string(),
+        path.display().to_string(),
+        line,
+    ]
+    .iter()
+    .map(|field| escape(field))
+    .collect::<Vec<_>>()
+    .join("\t")
+}
+
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn append(log: &AuditLog, records: &[String]) -> io::Result<()> {
+    let fs = crate::filesystem::current();
+    if fs
+        .metadata(&log.path)
+        .is_ok_and(|metadata| metadata.len >= log.max_size)
+    {
+        rotate(log)?;
+    }
+
+    let mut content = records.join("\n");
+    content.push('\n');
+    fs.append_private(&log.path, &content)
+}
+
+fn rotate(log: &AuditLog) -> io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = log.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    let fs = crate::filesystem::current();
+    if log.keep == 0 {
+        return fs.remove_file(&log.path);
+    }
+    match fs.remove_file(&numbered(log.keep)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    for n in (1..log.keep).rev() {
+        match fs.rename(&numbered(n), &numbered(n + 1)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    fs.rename(&log.path, &numbered(1))
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{Event, VecSink};
+    use std::fs;
+    use std::sync::Arc;
+
+    /// Returns the records of the log at `path` about lines mentioning
+    /// `needle`, split into their fields.
+    fn records(path: &Path, needle: &str) -> Vec<Vec<String>> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|it| it.contains(needle))
+            .map(|it| it.split('\t').map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn records_are_tab_separated_and_redacted() {
+        let sandbox = sandbox("/bin/bash");
+        let log_path = sandbox.path("audit.log");
+        set_audit_log(Some(
+            AuditLog::new(&log_path)
+                .tool("installer")
+                .redact("SET_ENV_AUDIT_TOKEN"),
+        ));
+        crate::set("SET_ENV_AUDIT_NAME", "tab\there").unwrap();
+        crate::set("SET_ENV_AUDIT_TOKEN", "secret").unwrap();
+        crate::unset("SET_ENV_AUDIT_NAME").unwrap();
+
+        let profile = sandbox.path(".bash_profile").display().to_string();
+        let records = records(&log_path, "SET_ENV_AUDIT");
+        let fields: Vec<Vec<&str>> = records
+            .iter()
+            .map(|it| it.iter().skip(2).map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                [
+                    "installer",
+                    "added",
+                    &profile,
+                    "export SET_ENV_AUDIT_NAME=tab\\there"
+                ],
+                [
+                    "installer",
+                    "added",
+                    &profile,
+                    "export SET_ENV_AUDIT_TOKEN=<redacted>"
+                ],
+                [
+                    "installer",
+                    "removed",
+                    &profile,
+                    "export SET_ENV_AUDIT_NAME=tab\\there"
+                ],
+            ]
+        );
+        for record in &records {
+            assert!(record[0].parse::<u64>().unwrap() > 0);
+            assert_eq!(record[1], env::var("USER").unwrap_or_default());
+        }
+        assert!(!fs::read_to_string(&log_path).unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn escapes_separators_in_fields() {
+        assert_eq!(escape("a\tb\nc\rd\\e"), "a\\tb\\nc\\rd\\\\e");
+    }
+
+    #[test]
+    fn full_logs_are_rotated_keeping_the_newest() {
+        let sandbox = sandbox("/bin/bash");
+        let log_path = sandbox.path("audit.log");
+        let numbered = |n: usize| sandbox.path(&format!("audit.log.{}", n));
+        set_audit_log(Some(AuditLog::new(&log_path).rotate(1, 2)));
+        for n in 1..=4 {
+            crate::set(format!("SET_ENV_AUDIT_{}", n), n).unwrap();
+        }
+
+        assert_eq!(records(&log_path, "SET_ENV_AUDIT_").len(), 1);
+        assert_eq!(records(&log_path, "SET_ENV_AUDIT_4").len(), 1);
+        assert_eq!(records(&numbered(1), "SET_ENV_AUDIT_3").len(), 1);
+        assert_eq!(records(&numbered(2), "SET_ENV_AUDIT_2").len(), 1);
+        assert!(!numbered(3).exists());
+
+        set_audit_log(Some(AuditLog::new(&log_path).rotate(1, 0)));
+        crate::set("SET_ENV_AUDIT_5", "5").unwrap();
+        assert_eq!(records(&log_path, "SET_ENV_AUDIT_").len(), 1);
+        assert_eq!(records(&log_path, "SET_ENV_AUDIT_5").len(), 1);
+    }
+
+    #[test]
+    fn a_failing_log_only_warns() {
+        let sandbox = sandbox("/bin/bash");
+        // A regular file where the log's directory should be.
+        sandbox.write("not_a_dir", "");
+        let log_path = sandbox.path("not_a_dir/audit.log");
+        set_audit_log(Some(AuditLog::new(&log_path)));
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        crate::set("SET_ENV_AUDIT_FAILING", "1").unwrap();
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("export SET_ENV_AUDIT_FAILING=1\n"));
+        let warnings: Vec<String> = sink
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Warning { message } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with(&format!(
+            "audit log {} could not be written",
+            log_path.display()
+        )));
+    }
+}
@@ -0,0 +1,282 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::filesystem::{FileMetadata, Filesystem};
+
+/// Where `set`, `append` and `prepend` make their changes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Backend {
+    /// The profile, for new shells.
+    #[default]
+    Profile,
+    /// Only the environment of the current process.
+    /// Nothing is written; the changes that would have been made to the
+    /// profile are recorded as in dry run, see `drain_planned_changes`,
+    /// so a host application can apply them later.
+    /// The profiles are changed in memory instead, so `list_managed`
+    /// and the like see the changes, and `get` sees the variables.
+    ProcessOnly,
+}
+
+static BACKEND: Mutex<Backend> = Mutex::new(Backend::Profile);
+/// What the files changed with `Backend::ProcessOnly` would contain.
+static STAGED: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+/// Sets where changes are made for the whole process.
+/// Switching it forgets the profiles changed in memory with
+/// `Backend::ProcessOnly`.
+/// Defaults to `Backend::Profile`.
+pub fn set_backend(backend: Backend) {
+    let mut current = BACKEND.lock().unwrap_or_else(|err| err.into_inner());
+    if *current != backend {
+        lock_staged().clear();
+    }
+    *current = backend;
+}
+
+pub(crate) fn backend() -> Backend {
+    *BACKEND.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+pub(crate) fn is_process_only() -> bool {
+    backend() == Backend::ProcessOnly
+}
+
+/// A change to a variable of the current process.
+#[derive(Clone, Copy)]
+pub(crate) enum Update<'a> {
+    Set(&'a str),
+    Append(&'a str),
+    Prepend(&'a str),
+}
+
+/// Applies `update` to `var` in the environment of the current process
+/// if the backend is `Backend::ProcessOnly`. References to variables
+/// and a leading `~` in the value are expanded as the shell would,
+/// ones to variables that aren't set are kept as they are.
+pub(crate) fn apply(var: &str, update: Update) {
+    if !is_process_only() {
+        return;
+    }
+
+    let expand = |value: &str| crate::path_entries::expand_str(value).unwrap_or(value.to_string());
+    let update = match update {
+        Update::Set(value) => Update::Set(&expand(value)),
+        Update::Append(value) => Update::Append(&expand(value)),
+        Update::Prepend(value) => Update::Prepend(&expand(value)),
+    };
+    let current = env::var(var).unwrap_or_default();
+    let sep = crate::LIST_SEPARATOR;
+    let value = match update {
+        Update::Set(value) => value.to_string(),
+        Update::Append(value) if current.is_empty() => value.to_string(),
+        Update::Append(value) => format!("{}{}{}", current, sep, value),
+        Update::Prepend(value) if current.is_empty() => value.to_string(),
+        Update::Prepend(value) => format!("{}{}{}", value, sep, current),
+    };
+    env::set_var(var, value);
+}
+
+fn lock_staged() -> std::sync::MutexGuard<'static, Vec<(PathBuf, String)>> {
+    STAGED.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Keeps `content` as what the file at `path` contains from now on, if
+/// the backend is `Backend::ProcessOnly`.
+pub(crate) fn stage(path: &Path, content: &str) {
+    if !is_process_only() {
+        return;
+    }
+    let mut staged = lock_staged();
+    match staged.iter_mut().find(|(staged, _)| staged == path) {
+        Some((_, staged)) => *staged = content.to_string(),
+        None => staged.push((path.to_path_buf(), content.to_string())),
+    }
+}
+
+fn staged(path: &Path) -> Option<String> {
+    lock_staged()
+        .iter()
+        .find(|(staged, _)| staged == path)
+        .map(|(_, content)| content.clone())
+}
+
+/// The filesystem used with `Backend::ProcessOnly`: files changed in
+/// memory are read from there, the rest from `base`, and nothing is
+/// ever written to `base`.
+pub(crate) struct InMemory(pub Arc<dyn Filesystem>);
+
+impl Filesystem for InMemory {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match staged(path) {
+            Some(content) => Ok(content),
+            None => self.0.read_to_string(path),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+        stage(path, content);
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, content: &str) -> io::Result<()> {
+        let before = match self.read_to_string(path) {
+            Ok(before) => before,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        stage(path, &(before + content));
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        staged(path).is_some() || self.0.exists(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        match staged(path) {
+            Some(content) => Ok(FileMetadata {
+                is_dir: false,
+                is_symlink: false,
+                len: content.len() as u64,
+            }),
+            None => self.0.metadata(path),
+        }
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let content = staged(from).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} can't be moved in memory", from.display()),
+            )
+        })?;
+        lock_staged().retain(|(staged, _)| staged != from);
+        stage(to, &content);
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.0.read_dir(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut staged = lock_staged();
+        let before = staged.len();
+        staged.retain(|(staged, _)| staged != path);
+        if staged.len() == before {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} can't be removed in memory", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn target_metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        match staged(path) {
+            Some(_) => self.metadata(path),
+            None => self.0.target_metadata(path),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.0.read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match staged(path) {
+            Some(_) => Ok(path.to_path_buf()),
+            None => self.0.canonicalize(path),
+        }
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        staged(path).is_some() || self.0.is_writable(path)
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::{sandbox, ReadOnlyFilesystem};
+    use crate::PlannedChange;
+
+    #[test]
+    fn works_with_a_home_that_does_not_exist() {
+        let sandbox = sandbox("/bin/bash");
+        let home = sandbox.path("missing/home");
+        env::set_var("HOME", &home);
+        set_backend(Backend::ProcessOnly);
+
+        crate::set("SET_ENV_PROCESS_ONLY_SET", "$HOME/tools").unwrap();
+        crate::append("SET_ENV_PROCESS_ONLY_LIST", "~/bin").unwrap();
+        crate::prepend("SET_ENV_PROCESS_ONLY_LIST", "/opt/bin").unwrap();
+        assert_eq!(
+            crate::get("SET_ENV_PROCESS_ONLY_SET").unwrap(),
+            format!("{}/tools", home.display())
+        );
+        assert_eq!(
+            crate::get("SET_ENV_PROCESS_ONLY_LIST").unwrap(),
+            format!("/opt/bin:{}/bin", home.display())
+        );
+
+        let vars: Vec<String> = crate::list_managed()
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.var)
+            .collect();
+        assert!(vars.contains(&"SET_ENV_PROCESS_ONLY_SET".to_string()));
+        assert!(vars.contains(&"SET_ENV_PROCESS_ONLY_LIST".to_string()));
+
+        assert_eq!(crate::unset("SET_ENV_PROCESS_ONLY_SET").unwrap(), 1);
+        assert!(crate::get("SET_ENV_PROCESS_ONLY_SET").is_err());
+        let planned = crate::drain_planned_changes();
+        assert!(planned
+            .iter()
+            .any(|change| matches!(change, PlannedChange::AddLine { .. })));
+        assert!(!home.exists());
+        env::remove_var("SET_ENV_PROCESS_ONLY_LIST");
+    }
+
+    #[test]
+    fn reads_the_profiles_changed_in_memory() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            &format!(
+                "{}\nexport SET_ENV_ON_DISK=1\n{}\n",
+                crate::DEFS_BEG,
+                crate::DEFS_END
+            ),
+        );
+        let before = sandbox.read(".bash_profile");
+        crate::set_filesystem(ReadOnlyFilesystem);
+        set_backend(Backend::ProcessOnly);
+
+        crate::set("SET_ENV_IN_MEMORY", "2").unwrap();
+        let vars: Vec<String> = crate::list_managed()
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.var)
+            .collect();
+        assert_eq!(vars, ["SET_ENV_ON_DISK", "SET_ENV_IN_MEMORY"]);
+        assert_eq!(sandbox.read(".bash_profile"), before);
+
+        set_backend(Backend::Profile);
+        set_backend(Backend::ProcessOnly);
+        let vars: Vec<String> = crate::list_managed()
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.var)
+            .collect();
+        assert_eq!(vars, ["SET_ENV_ON_DISK"]);
+        env::remove_var("SET_ENV_IN_MEMORY");
+    }
+}
@@ -0,0 +1,406 @@
+//! The block of definitions managed by this crate in a profile.
+//!
+//! ```text
+//! # ----------------------------------SET_ENV_DEFS_BEG
+//! export FOO=1
+//! # ----------------------------------SET_ENV_DEFS_END
+//! ```
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dry_run::{self, PlannedChange};
+use crate::{line_endings, simulate, usage, DEFS_BEG, DEFS_END};
+
+/// Returns the indices of the start and end marker lines of the block.
+pub(crate) fn find(lines: &[&str]) -> Option<(usize, usize)> {
+    let beg = lines.iter().position(|line| line.trim() == DEFS_BEG)?;
+    let end = lines[beg..]
+        .iter()
+        .position(|line| line.trim() == DEFS_END)?
+        + beg;
+    Some((beg, end))
+}
+
+/// Returns the trimmed lines in the block managed by this crate with
+/// their index.
+pub(crate) fn managed_lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut managed = false;
+    content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(move |(_, line)| {
+            if *line == DEFS_BEG || *line == DEFS_END {
+                managed = *line == DEFS_BEG;
+                return false;
+            }
+            managed
+        })
+}
+
+/// Adds `line` at the end of the block in `content`.
+/// If there is no block yet, one is created at the line with index `at`,
+/// or at the end of `content` if `at` is `None`, taking in the lines
+/// older versions appended there, see `legacy_start`.
+pub(crate) fn insert(content: &str, line: &str, at: Option<usize>) -> String {
+    let mut lines: Vec<&str> = content.lines().collect();
+
+    match find(&lines) {
+        Some((_, end)) => lines.insert(end, line),
+        None => {
+            let (at, end) = match at {
+                Some(at) => (at.min(lines.len()), at.min(lines.len())),
+                None => (legacy_start(&lines), lines.len()),
+            };
+            lines.splice(end..end, [line, DEFS_END]);
+            lines.insert(at, DEFS_BEG);
+        }
+    }
+
+    line_endings::rejoin(content, &lines)
+}
+
+/// Returns where the lines older versions appended to the profile start.
+/// Their `set`, `append` and `prepend` wrote each as an `export` after
+/// an empty line at the end of the file. Starting the block there makes
+/// `list_managed` and `unset` see them without reordering anything.
+fn legacy_start(lines: &[&str]) -> usize {
+    let mut start = lines.len();
+    while start >= 2
+        && lines[start - 2].is_empty()
+        && lines[start - 1].starts_with("export ")
+        && simulate::parse_var_value(lines[start - 1]).is_some()
+    {
+        start -= 2;
+    }
+    start
+}
+
+/// Returns the index of the first end marker without a start marker
+/// before it.
+fn stray_end(lines: &[&str]) -> Option<usize> {
+    let end = lines.iter().position(|line| line.trim() == DEFS_END)?;
+    let beg = lines.iter().position(|line| line.trim() == DEFS_BEG);
+    beg.is_none_or(|beg| beg > end).then_some(end)
+}
+
+/// Returns the index of the start marker if there is no end marker
+/// after it. Readers then take the block to end at the end of the file.
+fn missing_end(lines: &[&str]) -> Option<usize> {
+    let beg = lines.iter().position(|line| line.trim() == DEFS_BEG)?;
+    (!lines[beg..].iter().any(|line| line.trim() == DEFS_END)).then_some(beg)
+}
+
+/// Fails if the block in `content` is damaged, so it isn't changed
+/// before `repair` fixed it.
+pub(crate) fn check(content: &str, path: &Path) -> io::Result<()> {
+    let lines: Vec<&str> = content.lines().collect();
+    let damage = if let Some(end) = stray_end(&lines) {
+        format!("an end marker at line {} has no start marker", end + 1)
+    } else if let Some(beg) = missing_end(&lines) {
+        format!("the start marker at line {} has no end marker", beg + 1)
+    } else {
+        return Ok(());
+    };
+    Err(crate::events::error(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "The block managed by set_env in {} is damaged, {}; call repair() first",
+            path.display(),
+            damage
+        ),
+    )))
+}
+
+/// What `repair` did to a damaged block.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RepairAction {
+    /// Removed an end marker without a start marker, at the given line
+    /// counted from 1.
+    RemovedStrayEnd { path: PathBuf, line: usize },
+    /// Added the missing end marker after the lines written by this
+    /// crate that follow the start marker, at the given line of the
+    /// repaired file counted from 1.
+    AddedEnd { path: PathBuf, line: usize },
+}
+
+/// Repairs the blocks managed by this crate in the profiles, which
+/// may be damaged by deleting one of their marker lines by hand.
+/// Other changes refuse to touch a damaged block until it's repaired.
+/// Returns what was done, which is nothing if no block is damaged.
+pub fn repair() -> io::Result<Vec<RepairAction>> {
+    let _operation = crate::events::operation();
+    let mut actions = Vec::new();
+    for path in crate::managed_profiles()? {
+        let content = match crate::filesystem::current().read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let (_, found, _) = repair_content(&content, &path);
+        if found.is_empty() {
+            continue;
+        }
+
+        if crate::is_dry_run() {
+            for action in found {
+                dry_run::plan(|| match &action {
+                    RepairAction::RemovedStrayEnd { path, .. } => PlannedChange::RemoveLine {
+                        path: path.clone(),
+                        line: DEFS_END.to_string(),
+                    },
+                    RepairAction::AddedEnd { path, .. } => PlannedChange::AddLine {
+                        path: path.clone(),
+                        line: DEFS_END.to_string(),
+                    },
+                });
+                actions.push(action);
+            }
+            continue;
+        }
+
+        let vars = crate::edit_file(&path, |content| {
+            let (repaired, done, vars) = repair_content(content, &path);
+            actions.extend(done);
+            Ok((repaired, vars))
+        })?;
+        for var in vars {
+            usage::record(usage::Event::Repaired, &var);
+        }
+    }
+    Ok(actions)
+}
+
+/// Returns `content` with a repaired block, what was done and the
+/// variables assigned by the lines now inside the block again, once each.
+fn repair_content(content: &str, path: &Path) -> (String, Vec<RepairAction>, Vec<String>) {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let mut actions = Vec::new();
+    let mut vars = Vec::new();
+
+    while let Some(end) = stray_end(&lines) {
+        lines.remove(end);
+        actions.push(RepairAction::RemovedStrayEnd {
+            path: path.to_path_buf(),
+            line: end + 1,
+        });
+    }
+
+    if let Some(beg) = missing_end(&lines) {
+        let mut end = beg + 1;
+        for (idx, line) in lines.iter().enumerate().skip(beg + 1) {
+            match simulate::parse_var_value(line.trim()) {
+                Some((var, _)) => {
+                    if !vars.iter().any(|it| it == var) {
+                        vars.push(var.to_string());
+                    }
+                    end = idx + 1;
+                }
+                None if line.trim().is_empty() => {}
+                None => break,
+            }
+        }
+        lines.insert(end, DEFS_END);
+        actions.push(RepairAction::AddedEnd {
+            path: path.to_path_buf(),
+            line: end + 1,
+        });
+    }
+
+    (line_endings::rejoin(content, &lines), actions, vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{DEFS_BEG, DEFS_END};
+
+    /// A profile with a damaged block, what `check` reports about it,
+    /// the variables readers see in it, and what `repair` makes of it.
+    struct Damaged {
+        content: String,
+        damage: &'static str,
+        managed: &'static [&'static str],
+        repaired: String,
+        actions: &'static [(bool, usize)],
+    }
+
+    /// One fixture per shape of damage. An action is whether it added an
+    /// end marker, and the line it did so at.
+    fn damaged() -> Vec<Damaged> {
+        vec![
+            // The end marker was deleted: readers take the block to end at
+            // the end of the file, and the repaired block ends after the
+            // last assignment following the start marker.
+            Damaged {
+                content: format!(
+                    "alias a=b\n{}\nexport A=1\n\nexport B=2\nalias c=d\n",
+                    DEFS_BEG
+                ),
+                damage: "the start marker at line 2 has no end marker",
+                managed: &["A", "B"],
+                repaired: format!(
+                    "alias a=b\n{}\nexport A=1\n\nexport B=2\n{}\nalias c=d\n",
+                    DEFS_BEG, DEFS_END
+                ),
+                actions: &[(true, 6)],
+            },
+            // The start marker was deleted: nothing is managed, and the
+            // end marker left behind is removed.
+            Damaged {
+                content: format!("alias a=b\nexport A=1\n{}\n", DEFS_END),
+                damage: "an end marker at line 3 has no start marker",
+                managed: &[],
+                repaired: "alias a=b\nexport A=1\n".to_string(),
+                actions: &[(false, 3)],
+            },
+            // An end marker pasted before an intact block.
+            Damaged {
+                content: format!(
+                    "{}\nalias a=b\n{}\nexport A=1\n{}\n",
+                    DEFS_END, DEFS_BEG, DEFS_END
+                ),
+                damage: "an end marker at line 1 has no start marker",
+                managed: &["A"],
+                repaired: format!("alias a=b\n{}\nexport A=1\n{}\n", DEFS_BEG, DEFS_END),
+                actions: &[(false, 1)],
+            },
+            // The markers were swapped by moving the start marker down.
+            Damaged {
+                content: format!("{}\n{}\nexport A=1\n", DEFS_END, DEFS_BEG),
+                damage: "an end marker at line 1 has no start marker",
+                managed: &["A"],
+                repaired: format!("{}\nexport A=1\n{}\n", DEFS_BEG, DEFS_END),
+                actions: &[(false, 1), (true, 3)],
+            },
+        ]
+    }
+
+    #[test]
+    fn damaged_blocks_are_parsed_refused_and_repaired() {
+        for fixture in damaged() {
+            let sandbox = sandbox("/bin/bash");
+            let path = sandbox.path(".bash_profile");
+            sandbox.write(".bash_profile", &fixture.content);
+
+            let managed: Vec<&str> = managed_lines(&fixture.content)
+                .filter_map(|(_, line)| simulate::parse_var_value(line))
+                .map(|(var, _)| var)
+                .collect();
+            assert_eq!(managed, fixture.managed, "{}", fixture.content);
+
+            let err = check(&fixture.content, &path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(fixture.damage), "{}", err);
+            let err = crate::set("SET_ENV_REPAIRED", "1").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            // With nothing managed, unset has nothing to refuse to change.
+            if !fixture.managed.is_empty() {
+                assert!(crate::unset("A").is_err(), "{}", fixture.content);
+            }
+            assert_eq!(sandbox.read(".bash_profile"), fixture.content);
+
+            let actions: Vec<RepairAction> = fixture
+                .actions
+                .iter()
+                .map(|&(added, line)| {
+                    let path = path.clone();
+                    if added {
+                        RepairAction::AddedEnd { path, line }
+                    } else {
+                        RepairAction::RemovedStrayEnd { path, line }
+                    }
+                })
+                .collect();
+            assert_eq!(crate::repair().unwrap(), actions, "{}", fixture.content);
+            assert_eq!(sandbox.read(".bash_profile"), fixture.repaired);
+            assert_eq!(crate::repair().unwrap(), []);
+
+            crate::set("SET_ENV_REPAIRED", "1").unwrap();
+            let vars: Vec<String> = crate::list_managed()
+                .unwrap()
+                .into_iter()
+                .map(|it| it.var)
+                .collect();
+            let mut expected = fixture.managed.to_vec();
+            expected.push("SET_ENV_REPAIRED");
+            assert_eq!(vars, expected, "{}", fixture.content);
+            check(&sandbox.read(".bash_profile"), &path).unwrap();
+        }
+    }
+
+    #[test]
+    fn lines_outside_the_block_keep_their_endings() {
+        let sandbox = sandbox("/bin/bash");
+        let before = "alias a=b\r\nexport X=1\r\n\n# mixed\n";
+        sandbox.write(".bash_profile", before);
+
+        crate::set("FOO", "1").unwrap();
+        crate::set("BAR", "2").unwrap();
+        assert!(sandbox.read(".bash_profile").starts_with(before));
+
+        crate::unset("FOO").unwrap();
+        crate::unset("BAR").unwrap();
+        assert_eq!(sandbox.read(".bash_profile"), before);
+    }
+
+    #[test]
+    fn lines_appended_by_older_versions_are_taken_into_the_block() {
+        let sandbox = sandbox("/bin/bash");
+        let legacy = "\nexport A=1\n\nexport PATH=\"/opt/bin:$PATH\"\n";
+        sandbox.write(".bash_profile", &format!("alias a=b\n{}", legacy));
+
+        crate::set("FOO", "1").unwrap();
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "alias a=b\n{}\n{}export FOO=1\n{}\n",
+                DEFS_BEG, legacy, DEFS_END
+            )
+        );
+        let vars: Vec<String> = crate::list_managed()
+            .unwrap()
+            .into_iter()
+            .map(|it| it.var)
+            .collect();
+        assert_eq!(vars, ["A", "PATH", "FOO"]);
+
+        crate::unset("A").unwrap();
+        assert!(!sandbox.read(".bash_profile").contains("export A=1"));
+    }
+
+    #[test]
+    fn user_lines_after_old_ones_keep_them_outside_the_block() {
+        let sandbox = sandbox("/bin/bash");
+        let before = "\nexport A=1\nalias a=b\n";
+        sandbox.write(".bash_profile", before);
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").starts_with(before));
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_added_before_the_block() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias a=b");
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").starts_with("alias a=b\n# "));
+    }
+
+    #[test]
+    fn crlf_profiles_get_crlf_lines() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias a=b\r\n");
+
+        crate::set("FOO", "1").unwrap();
+        let content = sandbox.read(".bash_profile");
+        assert!(content.starts_with("alias a=b\r\n"));
+        assert!(content.contains("export FOO=1\r\n"));
+        assert!(!content.replace("\r\n", "").contains('\n'));
+    }
+}
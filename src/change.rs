@@ -0,0 +1,59 @@
+use std::fmt;
+
+use crate::hygiene::ValueWarning;
+
+/// What a call changing a profile noticed about the change, like
+/// `append` to a variable that isn't a list or a value with a
+/// no-break space in it.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Change {
+    /// What looks like a mistake about the change, which was made
+    /// anyway.
+    pub warnings: Vec<ChangeWarning>,
+}
+
+/// Something that looks like a mistake about a change, see `Change`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChangeWarning {
+    /// An entry was added to `var`, which usually holds a single value,
+    /// see `var_kind`.
+    NotAList { var: String },
+    /// The value written to `var` has a character that is likely pasted
+    /// by accident, see `check_value`.
+    Value { var: String, warning: ValueWarning },
+    /// The value written to `var` runs a command whenever a shell
+    /// starts, see `allow_command_substitution`.
+    CommandSubstitution { var: String },
+}
+
+impl fmt::Display for ChangeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeWarning::NotAList { var } => write!(
+                f,
+                "{} usually holds a single value, adding an entry to it is likely a mistake, \
+                 see register_list_var",
+                var
+            ),
+            ChangeWarning::Value { var, warning } => {
+                write!(f, "the value of {} has {}", var, warning)
+            }
+            ChangeWarning::CommandSubstitution { var } => write!(
+                f,
+                "the value of {} runs a command whenever a shell starts, slowing down every \
+                 new terminal, see allow_command_substitution",
+                var
+            ),
+        }
+    }
+}
+
+impl Change {
+    /// Emits the warnings as `Event::Warning`s, for functions returning
+    /// something else.
+    pub(crate) fn emit_warnings(self) {
+        for warning in self.warnings {
+            crate::events::warn(warning.to_string());
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// The differences between two environments, see `compare`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvDiff {
+    /// Variables only set in the first environment, with their values.
+    pub only_in_a: Vec<(String, String)>,
+    /// Variables only set in the second environment, with their values.
+    pub only_in_b: Vec<(String, String)>,
+    /// Variables set to different values, with the value in the first
+    /// and the second environment.
+    pub changed: Vec<(String, String, String)>,
+    /// List variables like PATH set to different values,
+    /// compared entry by entry.
+    pub lists: Vec<ListDiff>,
+}
+
+/// The differences between the entries of a list variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListDiff {
+    pub var: String,
+    /// Entries only in the first environment.
+    pub missing: Vec<String>,
+    /// Entries only in the second environment.
+    pub extra: Vec<String>,
+    /// Whether the entries in both environments are in a different order.
+    pub reordered: bool,
+}
+
+impl EnvDiff {
+    /// Returns whether both environments are the same.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty()
+            && self.only_in_b.is_empty()
+            && self.changed.is_empty()
+            && self.lists.is_empty()
+    }
+}
+
+/// Compares two environments, for example of a working and a broken
+/// machine, as computed by `simulate` or read from `std::env::vars`.
+/// Variables whose name ends with `PATH` are compared entry by entry.
+pub fn compare(a: &HashMap<String, String>, b: &HashMap<String, String>) -> EnvDiff {
+    let _operation = crate::events::operation();
+    // Sorted, so the result doesn't depend on the order of the maps.
+    let a: BTreeMap<_, _> = a.iter().collect();
+    let b: BTreeMap<_, _> = b.iter().collect();
+    let mut diff = EnvDiff::default();
+
+    for (var, a_value) in &a {
+        match b.get(var) {
+            None => diff.only_in_a.push((var.to_string(), a_value.to_string())),
+            Some(b_value) if a_value == b_value => {}
+            Some(b_value) if var.ends_with("PATH") => {
+                let list = compare_list(var, a_value, b_value);
+                // Entries may differ only in ways that don't matter,
+                // like a trailing slash.
+                if !list.missing.is_empty() || !list.extra.is_empty() || list.reordered {
+                    diff.lists.push(list);
+                }
+            }
+            Some(b_value) => {
+                diff.changed
+                    .push((var.to_string(), a_value.to_string(), b_value.to_string()))
+            }
+        }
+    }
+    for (var, b_value) in &b {
+        if !a.contains_key(var) {
+            diff.only_in_b.push((var.to_string(), b_value.to_string()));
+        }
+    }
+
+    diff
+}
+
+fn compare_list(var: &str, a: &str, b: &str) -> ListDiff {
+    let a: Vec<&str> = a.split(crate::LIST_SEPARATOR).collect();
+    let b: Vec<&str> = b.split(crate::LIST_SEPARATOR).collect();
+    let contains = |list: &[&str], entry: &str| list.iter().any(|it| crate::same_entry(it, entry));
+
+    let missing = a
+        .iter()
+        .filter(|entry| !contains(&b, entry))
+        .map(|entry| entry.to_string())
+        .collect();
+    let extra = b
+        .iter()
+        .filter(|entry| !contains(&a, entry))
+        .map(|entry| entry.to_string())
+        .collect();
+
+    let a_common = a.iter().filter(|entry| contains(&b, entry));
+    let mut b_common = b.iter().filter(|entry| contains(&a, entry));
+    let reordered = a_common
+        .zip(&mut b_common)
+        .any(|(a_entry, b_entry)| !crate::same_entry(a_entry, b_entry));
+
+    ListDiff {
+        var: var.to_string(),
+        missing,
+        extra,
+        reordered,
+    }
+}
+
+impl fmt::Display for EnvDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+
+        if !self.only_in_a.is_empty() {
+            writeln!(f, "Only in A:")?;
+            for (var, value) in &self.only_in_a {
+                writeln!(f, "  {}={}", var, value)?;
+            }
+        }
+        if !self.only_in_b.is_empty() {
+            writeln!(f, "Only in B:")?;
+            for (var, value) in &self.only_in_b {
+                writeln!(f, "  {}={}", var, value)?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Different:")?;
+            for (var, a, b) in &self.changed {
+                writeln!(f, "  {}: A={} B={}", var, a, b)?;
+            }
+        }
+        for list in &self.lists {
+            writeln!(f, "{}:", list.var)?;
+            for entry in &list.missing {
+                writeln!(f, "  only in A: {}", entry)?;
+            }
+            for entry in &list.extra {
+                writeln!(f, "  only in B: {}", entry)?;
+            }
+            if list.reordered {
+                writeln!(f, "  entries are in a different order")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &[&str])]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(var, entries)| {
+                let value = entries.join(&crate::LIST_SEPARATOR.to_string());
+                (var.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn fixtures() -> (HashMap<String, String>, HashMap<String, String>) {
+        let working = env(&[
+            ("EDITOR", &["vim"]),
+            ("JAVA_HOME", &["/opt/jdk"]),
+            ("ONLY_WORKING", &["1"]),
+            ("PATH", &["/usr/bin", "/bin", "/opt/tool/bin"]),
+            ("MANPATH", &["/usr/share/man", "/opt/tool/man"]),
+            ("PYTHONPATH", &["/opt/tool/lib/"]),
+        ]);
+        let broken = env(&[
+            ("EDITOR", &["nano"]),
+            ("JAVA_HOME", &["/opt/jdk"]),
+            ("ONLY_BROKEN", &["2"]),
+            ("PATH", &["/usr/bin", "/opt/other/bin", "/bin"]),
+            ("MANPATH", &["/opt/tool/man", "/usr/share/man"]),
+            ("PYTHONPATH", &["/opt/tool/lib"]),
+        ]);
+        (working, broken)
+    }
+
+    #[test]
+    fn every_kind_of_difference_is_reported() {
+        let (working, broken) = fixtures();
+        let diff = compare(&working, &broken);
+        assert_eq!(
+            diff,
+            EnvDiff {
+                only_in_a: vec![("ONLY_WORKING".to_string(), "1".to_string())],
+                only_in_b: vec![("ONLY_BROKEN".to_string(), "2".to_string())],
+                changed: vec![("EDITOR".to_string(), "vim".to_string(), "nano".to_string())],
+                lists: vec![
+                    ListDiff {
+                        var: "MANPATH".to_string(),
+                        missing: vec![],
+                        extra: vec![],
+                        reordered: true,
+                    },
+                    ListDiff {
+                        var: "PATH".to_string(),
+                        missing: vec!["/opt/tool/bin".to_string()],
+                        extra: vec!["/opt/other/bin".to_string()],
+                        reordered: false,
+                    },
+                ],
+            }
+        );
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn the_same_environments_have_no_differences() {
+        let (working, _) = fixtures();
+        let diff = compare(&working, &working);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "No differences\n");
+    }
+
+    #[test]
+    fn the_diff_is_rendered_for_a_ticket() {
+        let (working, broken) = fixtures();
+        assert_eq!(
+            compare(&working, &broken).to_string(),
+            "Only in A:\n  ONLY_WORKING=1\n\
+             Only in B:\n  ONLY_BROKEN=2\n\
+             Different:\n  EDITOR: A=vim B=nano\n\
+             MANPATH:\n  entries are in a different order\n\
+             PATH:\n  only in A: /opt/tool/bin\n  only in B: /opt/other/bin\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn diffs_can_be_serialized() {
+        fn serializable<T: serde::Serialize + serde::de::DeserializeOwned>(_: &T) {}
+        let (working, broken) = fixtures();
+        let diff = compare(&working, &broken);
+        serializable(&diff);
+        serializable(&diff.lists[0]);
+    }
+}
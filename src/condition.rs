@@ -0,0 +1,315 @@
+use std::fmt;
+use std::io;
+
+use crate::syntax::{AssignmentStyle, SyntaxInfo};
+use crate::{DEFS_BEG, DEFS_END};
+
+/// When a line written by `set_when` takes effect.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Condition {
+    /// In interactive shells only, not in scripts.
+    Interactive,
+    /// When the command is on the PATH.
+    CommandExists(String),
+    /// When the variable is set and, in POSIX shells, not empty.
+    EnvSet(String),
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Interactive,
+    CommandExists,
+    EnvSet,
+}
+
+const KINDS: [Kind; 3] = [Kind::Interactive, Kind::CommandExists, Kind::EnvSet];
+
+impl Condition {
+    fn kind(&self) -> (Kind, &str) {
+        match self {
+            Condition::Interactive => (Kind::Interactive, ""),
+            Condition::CommandExists(command) => (Kind::CommandExists, command),
+            Condition::EnvSet(var) => (Kind::EnvSet, var),
+        }
+    }
+
+    /// Formats `line` so it only runs when the condition holds.
+    pub fn guard(&self, syntax: &SyntaxInfo, line: &str) -> String {
+        let (kind, name) = self.kind();
+        let (before, after, end) = template(kind, syntax.assignment);
+        format!("{}{}{}{}{}", before, name, after, line, end)
+    }
+}
+
+/// Returns the text before the name of a condition, after it and after
+/// the guarded line. Conditions without a name have an empty one.
+fn template(kind: Kind, style: AssignmentStyle) -> (&'static str, &'static str, &'static str) {
+    match (style, kind) {
+        // `case` rather than `[[`, as sh and dash read `.profile` as well.
+        (AssignmentStyle::Export, Kind::Interactive) => ("case $- in *i*) ", "", ";; esac"),
+        (AssignmentStyle::Export, Kind::CommandExists) => {
+            ("command -v ", " >/dev/null 2>&1 && ", "")
+        }
+        (AssignmentStyle::Export, Kind::EnvSet) => ("[ -n \"${", ":-}\" ] && ", ""),
+        (AssignmentStyle::SetGx, Kind::Interactive) => ("status is-interactive; and ", "", ""),
+        (AssignmentStyle::SetGx, Kind::CommandExists) => ("type -q ", "; and ", ""),
+        (AssignmentStyle::SetGx, Kind::EnvSet) => ("set -q ", "; and ", ""),
+        (AssignmentStyle::Setenv, Kind::Interactive) => ("if ($?prompt) ", "", ""),
+        (AssignmentStyle::Setenv, Kind::CommandExists) => {
+            ("if ( { which ", " >& /dev/null } ) ", "")
+        }
+        (AssignmentStyle::Setenv, Kind::EnvSet) => ("if ($?", ") ", ""),
+        (AssignmentStyle::PowerShellHelper, Kind::Interactive) => {
+            ("if ([Environment]::UserInteractive) { ", "", " }")
+        }
+        (AssignmentStyle::PowerShellHelper, Kind::CommandExists) => (
+            "if (Get-Command ",
+            " -ErrorAction SilentlyContinue) { ",
+            " }",
+        ),
+        (AssignmentStyle::PowerShellHelper, Kind::EnvSet) => ("if (Test-Path env:", ") { ", " }"),
+    }
+}
+
+/// Returns the condition and the guarded line if `line` was formatted
+/// by `Condition::guard`, for any shell.
+pub(crate) fn unguard(line: &str) -> Option<(Condition, &str)> {
+    let styles = [
+        AssignmentStyle::Export,
+        AssignmentStyle::SetGx,
+        AssignmentStyle::Setenv,
+        AssignmentStyle::PowerShellHelper,
+    ];
+    styles.into_iter().find_map(|style| {
+        KINDS.into_iter().find_map(|kind| {
+            let (before, after, end) = template(kind, style);
+            let rest = line.strip_prefix(before)?;
+            let (name, rest) = match kind {
+                Kind::Interactive => ("", rest.strip_prefix(after)?),
+                Kind::CommandExists | Kind::EnvSet => rest.split_once(after)?,
+            };
+            let guarded = rest.strip_suffix(end)?;
+            let condition = match kind {
+                Kind::Interactive => Condition::Interactive,
+                _ if name.is_empty() || name.contains(char::is_whitespace) => return None,
+                Kind::CommandExists => Condition::CommandExists(name.to_string()),
+                Kind::EnvSet => Condition::EnvSet(name.to_string()),
+            };
+            Some((condition, guarded))
+        })
+    })
+}
+
+/// Sets an environment variable in the profile, taking effect only
+/// when `condition` holds in the shell reading it, like only in
+/// interactive shells.
+/// The variable isn't changed in the current process, even with
+/// `Backend::ProcessOnly`.
+pub fn set_when<T: fmt::Display, U: fmt::Display>(
+    condition: Condition,
+    var: T,
+    value: U,
+) -> io::Result<()> {
+    let _operation = crate::events::operation();
+    crate::startup_cost::warn_value(&var.to_string(), &value.to_string())?;
+    let syntax = crate::profile_syntax()?;
+    let line = condition.guard(&syntax, &syntax.set_line(var, value));
+    write(&line)
+}
+
+#[cfg(target_family = "unix")]
+fn write(line: &str) -> io::Result<()> {
+    crate::write_to_profile(line)
+}
+
+#[cfg(target_os = "windows")]
+fn write(line: &str) -> io::Result<()> {
+    crate::inject(line)
+}
+
+/// Returns the condition of the last line assigning `var` in the block
+/// managed by this crate, or `None` if it's unconditional or missing.
+pub fn condition_of<T: fmt::Display>(var: T) -> io::Result<Option<Condition>> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let content = match crate::filesystem::current().read_to_string(&crate::profile_path_for(&var)?)
+    {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut managed = false;
+    let mut found = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line == DEFS_BEG {
+            managed = true;
+        } else if line == DEFS_END {
+            managed = false;
+        } else if managed
+            && crate::simulate::parse_var_value(line).is_some_and(|(assigned, _)| assigned == var)
+        {
+            found = Some(unguard(line).map(|(condition, _)| condition));
+        }
+    }
+    Ok(found.flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: (&str, &str) = ("MYTOOL_PROFILE", "interactive");
+
+    fn conditions() -> [Condition; 3] {
+        [
+            Condition::Interactive,
+            Condition::CommandExists("docker".to_string()),
+            Condition::EnvSet("SSH_CONNECTION".to_string()),
+        ]
+    }
+
+    #[test]
+    fn guards_are_formatted_per_shell() {
+        let expected = [
+            (
+                SyntaxInfo::POSIX,
+                [
+                    "case $- in *i*) export MYTOOL_PROFILE=interactive;; esac",
+                    "command -v docker >/dev/null 2>&1 && export MYTOOL_PROFILE=interactive",
+                    "[ -n \"${SSH_CONNECTION:-}\" ] && export MYTOOL_PROFILE=interactive",
+                ],
+            ),
+            (
+                SyntaxInfo::FISH,
+                [
+                    "status is-interactive; and set -gx MYTOOL_PROFILE interactive",
+                    "type -q docker; and set -gx MYTOOL_PROFILE interactive",
+                    "set -q SSH_CONNECTION; and set -gx MYTOOL_PROFILE interactive",
+                ],
+            ),
+            (
+                SyntaxInfo::CSH,
+                [
+                    "if ($?prompt) setenv MYTOOL_PROFILE interactive",
+                    "if ( { which docker >& /dev/null } ) setenv MYTOOL_PROFILE interactive",
+                    "if ($?SSH_CONNECTION) setenv MYTOOL_PROFILE interactive",
+                ],
+            ),
+            (
+                SyntaxInfo::POWERSHELL,
+                [
+                    "if ([Environment]::UserInteractive) { setenv_set MYTOOL_PROFILE interactive }",
+                    "if (Get-Command docker -ErrorAction SilentlyContinue) \
+                     { setenv_set MYTOOL_PROFILE interactive }",
+                    "if (Test-Path env:SSH_CONNECTION) { setenv_set MYTOOL_PROFILE interactive }",
+                ],
+            ),
+        ];
+        for (syntax, lines) in expected {
+            let line = syntax.set_line(LINE.0, LINE.1);
+            for (condition, expected) in conditions().into_iter().zip(lines) {
+                assert_eq!(condition.guard(&syntax, &line), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn unguard_returns_the_condition_and_the_line() {
+        for syntax in [
+            SyntaxInfo::POSIX,
+            SyntaxInfo::FISH,
+            SyntaxInfo::CSH,
+            SyntaxInfo::POWERSHELL,
+        ] {
+            let line = syntax.set_line(LINE.0, LINE.1);
+            assert_eq!(unguard(&line), None);
+            for condition in conditions() {
+                let guarded = condition.guard(&syntax, &line);
+                assert_eq!(
+                    unguard(&guarded),
+                    Some((condition, line.as_str())),
+                    "{}",
+                    guarded
+                );
+            }
+        }
+        assert_eq!(
+            unguard("command -v my tool >/dev/null 2>&1 && export A=1"),
+            None
+        );
+        assert_eq!(unguard("[ -n \"${:-}\" ] && export A=1"), None);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn set_when_writes_a_line_condition_of_reads_back() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        set_when(Condition::Interactive, "SET_ENV_WHEN", "interactive").unwrap();
+        set_when(
+            Condition::EnvSet("SSH_CONNECTION".to_string()),
+            "SET_ENV_WHEN_REMOTE",
+            "1",
+        )
+        .unwrap();
+        crate::set("SET_ENV_WHEN_ALWAYS", "1").unwrap();
+
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\ncase $- in *i*) export SET_ENV_WHEN=interactive;; esac\n\
+                 [ -n \"${{SSH_CONNECTION:-}}\" ] && export SET_ENV_WHEN_REMOTE=1\n\
+                 export SET_ENV_WHEN_ALWAYS=1\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+        assert_eq!(
+            condition_of("SET_ENV_WHEN").unwrap(),
+            Some(Condition::Interactive)
+        );
+        assert_eq!(
+            condition_of("SET_ENV_WHEN_REMOTE").unwrap(),
+            Some(Condition::EnvSet("SSH_CONNECTION".to_string()))
+        );
+        assert_eq!(condition_of("SET_ENV_WHEN_ALWAYS").unwrap(), None);
+        assert_eq!(condition_of("SET_ENV_WHEN_MISSING").unwrap(), None);
+
+        assert_eq!(crate::unset("SET_ENV_WHEN").unwrap(), 1);
+        assert_eq!(condition_of("SET_ENV_WHEN").unwrap(), None);
+    }
+
+    /// Runs `line` in an interactive bash or not, and returns what it
+    /// set `MYTOOL_PROFILE` to, or `None` if bash isn't installed.
+    #[cfg(target_family = "unix")]
+    fn run_in_bash(line: &str, interactive: bool) -> Option<String> {
+        let script = format!("{}\nprintf %s \"${{MYTOOL_PROFILE-}}\"", line);
+        let mut command = std::process::Command::new("bash");
+        command
+            .arg("--norc")
+            .arg(if interactive { "-ic" } else { "-c" })
+            .arg(script)
+            .env_remove("MYTOOL_PROFILE")
+            .stdin(std::process::Stdio::null());
+        match command.output() {
+            Ok(output) => {
+                assert!(output.status.success(), "{:?}", output);
+                Some(String::from_utf8(output.stdout).unwrap())
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => panic!("bash: {}", err),
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn the_interactive_guard_tells_bash_ic_from_bash_c() {
+        let syntax = SyntaxInfo::POSIX;
+        let line = Condition::Interactive.guard(&syntax, &syntax.set_line(LINE.0, LINE.1));
+        let Some(interactive) = run_in_bash(&line, true) else {
+            return;
+        };
+        assert_eq!(interactive, "interactive");
+        assert_eq!(run_in_bash(&line, false).unwrap(), "");
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static IGNORE: AtomicBool = AtomicBool::new(false);
+
+/// Merge conflict markers, as left in a file by git and by dotfile
+/// managers built on it.
+const MARKERS: [&str; 4] = ["<<<<<<<", "|||||||", "=======", ">>>>>>>"];
+
+/// What sync tools put in the name of the conflicting copy they leave
+/// next to a file: Syncthing, Dropbox and Nextcloud.
+const CONFLICT_COPIES: [&str; 2] = [".sync-conflict-", "conflicted copy"];
+
+/// Why a profile wasn't changed: a dotfile sync tool left a conflict
+/// in it or next to it, which writing to it would make harder to
+/// merge by hand. The payload of the error, see `ignore_conflicts`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProfileInConflict {
+    pub path: PathBuf,
+    /// The lines with conflict markers and the conflicting copies found.
+    pub evidence: Vec<String>,
+}
+
+impl fmt::Display for ProfileInConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} has an unresolved sync conflict ({}); resolve it or call ignore_conflicts(true)",
+            self.path.display(),
+            self.evidence.join(", ")
+        )
+    }
+}
+
+impl Error for ProfileInConflict {}
+
+/// Lets changes go ahead on profiles with a dotfile sync conflict.
+///
+/// By default a profile with a line starting with a merge conflict
+/// marker like `<<<<<<<`, or with a conflicting copy next to it like
+/// `.zshrc.sync-conflict-20240101-120000-ABCDEFG`, isn't changed.
+/// The error has `ErrorKind::InvalidData` and a `ProfileInConflict`,
+/// which `io::Error::get_ref` and `downcast_ref` get at.
+pub fn ignore_conflicts(ignore: bool) {
+    let _operation = crate::events::operation();
+    IGNORE.store(ignore, Ordering::SeqCst);
+}
+
+/// Fails if the profile at `path` is in conflict, unless conflicts are
+/// ignored.
+pub(crate) fn check(path: &Path) -> io::Result<()> {
+    if IGNORE.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let evidence = evidence(path);
+    if evidence.is_empty() {
+        return Ok(());
+    }
+    Err(crate::events::error(io::Error::new(
+        io::ErrorKind::InvalidData,
+        ProfileInConflict {
+            path: path.to_path_buf(),
+            evidence,
+        },
+    )))
+}
+
+fn evidence(path: &Path) -> Vec<String> {
+    let fs = crate::filesystem::current();
+    let mut evidence = Vec::new();
+    if let Ok(content) = fs.read_to_string(path) {
+        for (idx, line) in content.lines().enumerate() {
+            if let Some(marker) = MARKERS.iter().find(|marker| is_marker(line, marker)) {
+                evidence.push(format!("line {} starts with {}", idx + 1, marker));
+            }
+        }
+    }
+
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return evidence;
+    };
+    let name = name.to_string_lossy();
+    let mut copies: Vec<PathBuf> = fs
+        .read_dir(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|sibling| {
+            sibling.file_name().is_some_and(|sibling| {
+                let sibling = sibling.to_string_lossy();
+                sibling.len() > name.len()
+                    && sibling.starts_with(name.as_ref())
+                    && CONFLICT_COPIES
+                        .iter()
+                        .any(|it| sibling[name.len()..].contains(it))
+            })
+        })
+        .collect();
+    copies.sort();
+    evidence.extend(
+        copies
+            .iter()
+            .map(|copy| format!("conflicting copy {}", copy.display())),
+    );
+    evidence
+}
+
+/// Returns whether `line` starts with `marker`, on its own or followed
+/// by a space and a label.
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\r']))
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    fn conflict(err: &io::Error) -> &ProfileInConflict {
+        err.get_ref().unwrap().downcast_ref().unwrap()
+    }
+
+    #[test]
+    fn conflict_markers_are_refused() {
+        let sandbox = sandbox("/bin/bash");
+        let before = "<<<<<<< HEAD\nexport A=1\n=======\nexport A=2\n>>>>>>> theirs\r\n";
+        sandbox.write(".bash_profile", before);
+
+        let err = crate::set("SET_ENV_CONFLICT", "1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            *conflict(&err),
+            ProfileInConflict {
+                path: sandbox.path(".bash_profile"),
+                evidence: vec![
+                    "line 1 starts with <<<<<<<".to_string(),
+                    "line 3 starts with =======".to_string(),
+                    "line 5 starts with >>>>>>>".to_string(),
+                ],
+            }
+        );
+        assert_eq!(sandbox.read(".bash_profile"), before);
+    }
+
+    #[test]
+    fn conflicting_copies_are_refused() {
+        for copy in [
+            ".bash_profile.sync-conflict-20240101-120000-ABCDEFG",
+            ".bash_profile (conflicted copy 2024-01-01)",
+        ] {
+            let sandbox = sandbox("/bin/bash");
+            sandbox.write(".bash_profile", "export A=1\n");
+            sandbox.write(copy, "export A=2\n");
+
+            let err = crate::set("SET_ENV_CONFLICT", "1").unwrap_err();
+            assert_eq!(
+                conflict(&err).evidence,
+                [format!("conflicting copy {}", sandbox.path(copy).display())]
+            );
+            assert_eq!(sandbox.read(".bash_profile"), "export A=1\n");
+        }
+    }
+
+    #[test]
+    fn lookalikes_are_not_conflicts() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            "cat <<EOF\n========\n<<<<<<<<\n# =======\nEOF\n",
+        );
+        sandbox.write(".bash_profile.bak", "");
+        sandbox.write(".bashrc.sync-conflict-20240101-120000-ABCDEFG", "");
+
+        assert_eq!(
+            evidence(&sandbox.path(".bash_profile")),
+            Vec::<String>::new()
+        );
+        crate::set("SET_ENV_CONFLICT", "1").unwrap();
+    }
+
+    #[test]
+    fn conflicts_can_be_ignored() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "=======\n");
+        sandbox.write(".bash_profile.sync-conflict-1", "");
+        ignore_conflicts(true);
+
+        crate::set("SET_ENV_CONFLICT", "1").unwrap();
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("export SET_ENV_CONFLICT=1"));
+    }
+}
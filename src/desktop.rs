@@ -0,0 +1,284 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::block;
+
+/// A desktop environment with its own way of setting variables for the
+/// whole graphical session, see `set_desktop`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Desktop {
+    Kde,
+    Gnome,
+}
+
+/// The file `set_desktop` writes to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DesktopMechanism {
+    /// `~/.config/plasma-workspace/env/set_env.sh`, which KDE Plasma
+    /// sources when the session starts.
+    PlasmaEnv(PathBuf),
+    /// `~/.config/environment.d/set_env.conf`, which the systemd user
+    /// manager reads for GNOME sessions.
+    EnvironmentD(PathBuf),
+}
+
+impl DesktopMechanism {
+    pub fn path(&self) -> &Path {
+        match self {
+            DesktopMechanism::PlasmaEnv(path) | DesktopMechanism::EnvironmentD(path) => path,
+        }
+    }
+
+    fn line<T: fmt::Display, U: fmt::Display>(&self, var: T, value: U) -> String {
+        match self {
+            DesktopMechanism::PlasmaEnv(_) => format!("export {}={}", var, value),
+            DesktopMechanism::EnvironmentD(_) => format!("{}={}", var, value),
+        }
+    }
+
+    /// Returns the variable and unevaluated value `line` assigns.
+    fn parse<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        let line = line.trim();
+        let line = match self {
+            DesktopMechanism::PlasmaEnv(_) => line.strip_prefix("export ")?,
+            DesktopMechanism::EnvironmentD(_) => line,
+        };
+        let (var, value) = line.split_once('=')?;
+        let valid = var.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        valid.then_some((var, value))
+    }
+}
+
+/// Returns the desktop environment of the current session, detected
+/// through `XDG_CURRENT_DESKTOP`, or `None` if it's neither KDE nor GNOME.
+pub fn detect_desktop() -> Option<Desktop> {
+    let current = env::var("XDG_CURRENT_DESKTOP").ok()?;
+    current
+        .split(':')
+        .find_map(|name| match name.to_ascii_uppercase().as_str() {
+            "KDE" => Some(Desktop::Kde),
+            "GNOME" | "GNOME-CLASSIC" | "GNOME-FLASHBACK" => Some(Desktop::Gnome),
+            _ => None,
+        })
+}
+
+/// Returns the file `set_desktop` writes to for the current desktop.
+/// Fails with `ErrorKind::Unsupported` for other desktops; the profile
+/// is the place for their variables.
+pub fn desktop_mechanism() -> io::Result<DesktopMechanism> {
+    let _operation = crate::events::operation();
+    let (home_dir, _) = crate::home_and_shell()?;
+    match detect_desktop() {
+        Some(Desktop::Kde) => Ok(DesktopMechanism::PlasmaEnv(
+            home_dir.join(".config/plasma-workspace/env/set_env.sh"),
+        )),
+        Some(Desktop::Gnome) => Ok(DesktopMechanism::EnvironmentD(
+            home_dir.join(".config/environment.d/set_env.conf"),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "No supported desktop environment in XDG_CURRENT_DESKTOP",
+        )),
+    }
+}
+
+/// Sets `var` to `value` for the graphical session of the current
+/// desktop, replacing a value set there before, so programs started
+/// from the desktop and not from a shell get it as well.
+/// Returns which mechanism was used.
+pub fn set_desktop<T: fmt::Display, U: fmt::Display>(
+    var: T,
+    value: U,
+) -> io::Result<DesktopMechanism> {
+    let _operation = crate::events::operation();
+    let mechanism = desktop_mechanism()?;
+    let var = var.to_string();
+    let line = mechanism.line(&var, value);
+    let path = mechanism.path();
+
+    if let Some(dir) = path.parent() {
+        let fs = crate::filesystem::current();
+        if !fs.exists(dir) && !crate::is_dry_run() {
+            fs.create_dir_all(dir)?;
+        }
+    }
+    crate::edit_file(path, |content| {
+        block::check(content, path)?;
+        let kept: Vec<&str> = content
+            .lines()
+            .filter(|it| {
+                mechanism
+                    .parse(it)
+                    .is_none_or(|(assigned, _)| assigned != var)
+            })
+            .collect();
+        let kept = crate::line_endings::rejoin(content, &kept);
+        Ok((block::insert(&kept, &line, None), ()))
+    })?;
+    Ok(mechanism)
+}
+
+/// Removes what `set_desktop` wrote for `var`. The file is deleted
+/// once it has no assignments left.
+/// Returns whether anything was removed.
+pub fn unset_desktop<T: fmt::Display>(var: T) -> io::Result<bool> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let mechanism = desktop_mechanism()?;
+    let path = mechanism.path();
+    let assigned = list_in(&mechanism)?;
+    if !assigned.iter().any(|(assigned, _)| *assigned == var) {
+        return Ok(false);
+    }
+
+    crate::remove_lines(path, |line| {
+        mechanism
+            .parse(line)
+            .is_some_and(|(assigned, _)| assigned == var)
+    })?;
+    if assigned.iter().all(|(assigned, _)| *assigned == var)
+        && !crate::dry_run::plan(|| crate::PlannedChange::RemoveFile {
+            path: path.to_path_buf(),
+        })
+    {
+        crate::filesystem::current().remove_file(path)?;
+    }
+    Ok(true)
+}
+
+/// Returns the variables and unevaluated values `set_desktop` wrote
+/// for the current desktop, in file order.
+pub fn list_desktop() -> io::Result<Vec<(String, String)>> {
+    let _operation = crate::events::operation();
+    list_in(&desktop_mechanism()?)
+}
+
+fn list_in(mechanism: &DesktopMechanism) -> io::Result<Vec<(String, String)>> {
+    let content = match crate::filesystem::current().read_to_string(mechanism.path()) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| mechanism.parse(line))
+        .map(|(var, value)| (var.to_string(), value.to_string()))
+        .collect())
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{DEFS_BEG, DEFS_END};
+
+    #[test]
+    fn the_desktop_is_detected_from_xdg_current_desktop() {
+        let _sandbox = sandbox("/bin/bash");
+        assert_eq!(detect_desktop(), None);
+        let cases = [
+            ("KDE", Some(Desktop::Kde)),
+            ("kde", Some(Desktop::Kde)),
+            ("GNOME", Some(Desktop::Gnome)),
+            ("ubuntu:GNOME", Some(Desktop::Gnome)),
+            ("GNOME-Classic:GNOME", Some(Desktop::Gnome)),
+            ("GNOME-Flashback:GNOME", Some(Desktop::Gnome)),
+            ("X-Cinnamon", None),
+            ("XFCE", None),
+            ("", None),
+        ];
+        for (current, expected) in cases {
+            env::set_var("XDG_CURRENT_DESKTOP", current);
+            assert_eq!(detect_desktop(), expected, "{}", current);
+        }
+    }
+
+    #[test]
+    fn kde_gets_a_plasma_env_script() {
+        let sandbox = sandbox("/bin/bash");
+        env::set_var("XDG_CURRENT_DESKTOP", "KDE");
+        let path = sandbox.path(".config/plasma-workspace/env/set_env.sh");
+        assert_eq!(
+            desktop_mechanism().unwrap(),
+            DesktopMechanism::PlasmaEnv(path.clone())
+        );
+
+        assert_eq!(
+            set_desktop("SET_ENV_DESKTOP", "1").unwrap().path(),
+            path.as_path()
+        );
+        set_desktop("SET_ENV_DESKTOP_OTHER", "2").unwrap();
+        set_desktop("SET_ENV_DESKTOP", "3").unwrap();
+        assert_eq!(
+            sandbox.read(".config/plasma-workspace/env/set_env.sh"),
+            format!(
+                "{}\nexport SET_ENV_DESKTOP_OTHER=2\nexport SET_ENV_DESKTOP=3\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+        assert_eq!(
+            list_desktop().unwrap(),
+            [
+                ("SET_ENV_DESKTOP_OTHER".to_string(), "2".to_string()),
+                ("SET_ENV_DESKTOP".to_string(), "3".to_string()),
+            ]
+        );
+        assert!(!sandbox.path(".bash_profile").exists());
+
+        assert!(unset_desktop("SET_ENV_DESKTOP").unwrap());
+        assert!(!unset_desktop("SET_ENV_DESKTOP").unwrap());
+        assert!(unset_desktop("SET_ENV_DESKTOP_OTHER").unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn gnome_gets_an_environment_d_file() {
+        let sandbox = sandbox("/bin/bash");
+        env::set_var("XDG_CURRENT_DESKTOP", "ubuntu:GNOME");
+        let name = ".config/environment.d/set_env.conf";
+        // A value the user set in the file by hand is replaced.
+        sandbox.write(name, "SET_ENV_DESKTOP=old\nSET_ENV_DESKTOP_USER=kept\n");
+
+        let mechanism = set_desktop("SET_ENV_DESKTOP", "/opt/tool").unwrap();
+        assert_eq!(
+            mechanism,
+            DesktopMechanism::EnvironmentD(sandbox.path(name))
+        );
+        assert_eq!(
+            sandbox.read(name),
+            format!(
+                "SET_ENV_DESKTOP_USER=kept\n{}\nSET_ENV_DESKTOP=/opt/tool\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+
+        // The file is kept as long as it assigns something.
+        assert!(unset_desktop("SET_ENV_DESKTOP").unwrap());
+        assert_eq!(
+            list_desktop().unwrap(),
+            [("SET_ENV_DESKTOP_USER".to_string(), "kept".to_string())]
+        );
+    }
+
+    #[test]
+    fn other_desktops_are_unsupported() {
+        let sandbox = sandbox("/bin/bash");
+        for current in [None, Some("XFCE")] {
+            match current {
+                Some(current) => env::set_var("XDG_CURRENT_DESKTOP", current),
+                None => env::remove_var("XDG_CURRENT_DESKTOP"),
+            }
+            let kinds = [
+                desktop_mechanism().unwrap_err().kind(),
+                set_desktop("SET_ENV_DESKTOP", "1").unwrap_err().kind(),
+                unset_desktop("SET_ENV_DESKTOP").unwrap_err().kind(),
+                list_desktop().unwrap_err().kind(),
+            ];
+            assert_eq!(kinds, [io::ErrorKind::Unsupported; 4], "{:?}", current);
+        }
+        assert!(!sandbox.path(".config").exists());
+    }
+}
@@ -0,0 +1,282 @@
+use std::env;
+use std::fmt;
+#[cfg(target_family = "unix")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A change that would have been made while dry run was enabled.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PlannedChange {
+    /// `line` would have been added to the file at `path`.
+    AddLine { path: PathBuf, line: String },
+    /// `line` would have been removed from the file at `path`.
+    RemoveLine { path: PathBuf, line: String },
+    /// The file at `path` would have been removed.
+    RemoveFile { path: PathBuf },
+    /// The persistent user variable `var` would have been set to `value`.
+    SetRegistry { var: String, value: String },
+}
+
+/// Lines longer than this are shortened when displayed.
+const MAX_DISPLAYED_LINE: usize = 200;
+
+impl fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedChange::AddLine { path, line } => {
+                write!(f, "{}: + {}", path.display(), shorten(line))
+            }
+            PlannedChange::RemoveLine { path, line } => {
+                write!(f, "{}: - {}", path.display(), shorten(line))
+            }
+            PlannedChange::RemoveFile { path } => write!(f, "{}: removed", path.display()),
+            PlannedChange::SetRegistry { var, value } => {
+                write!(f, "registry: {}={}", var, shorten(value))
+            }
+        }
+    }
+}
+
+/// Shortens `line` for display, noting how much was left out.
+/// Profiles sometimes contain huge generated one-liners which would
+/// otherwise drown everything else.
+fn shorten(line: &str) -> String {
+    match line.char_indices().nth(MAX_DISPLAYED_LINE) {
+        Some((end, _)) => format!("{}… ({} more bytes)", &line[..end], line.len() - end),
+        None => line.to_string(),
+    }
+}
+
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+static PLANNED: Mutex<Vec<PlannedChange>> = Mutex::new(Vec::new());
+
+/// Enables or disables dry run for the whole process.
+/// While enabled, all functions that would change a file only record
+/// the change, see `drain_planned_changes`.
+/// Calls nest: dry run stays enabled until it was disabled as often
+/// as it was enabled.
+/// Setting the `SET_ENV_DRY_RUN` environment variable to anything
+/// but `0` enables dry run as well.
+pub fn set_dry_run(enabled: bool) {
+    if enabled {
+        DEPTH.fetch_add(1, Ordering::SeqCst);
+    } else {
+        let _ = DEPTH.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| {
+            Some(depth.saturating_sub(1))
+        });
+    }
+}
+
+/// Returns whether dry run is enabled.
+/// It always is with `Backend::ProcessOnly`.
+pub fn is_dry_run() -> bool {
+    crate::backend::is_process_only()
+        || DEPTH.load(Ordering::SeqCst) > 0
+        || env::var("SET_ENV_DRY_RUN").is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Returns the changes recorded during dry run, in the order they
+/// would have been made, and forgets them.
+pub fn drain_planned_changes() -> Vec<PlannedChange> {
+    std::mem::take(&mut *PLANNED.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+/// Records `change` if dry run is enabled.
+/// Returns whether it was recorded, in which case the caller must not
+/// make the change.
+pub(crate) fn plan(change: impl FnOnce() -> PlannedChange) -> bool {
+    if !is_dry_run() {
+        return false;
+    }
+    PLANNED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(change());
+    true
+}
+
+/// Records the lines `after` adds to and removes from `before`, the
+/// content of the file at `path`, if dry run is enabled, so a line
+/// moved or replaced shows up as removed and added again.
+/// Returns whether they were recorded, like `plan`.
+#[cfg(target_family = "unix")]
+pub(crate) fn plan_edit(path: &Path, before: &str, after: &str) -> bool {
+    if !is_dry_run() {
+        return false;
+    }
+    PLANNED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .extend(diff(path, before, after));
+    true
+}
+
+/// Returns the changes turning `before` into `after` line by line, in
+/// the order of the lines, along their longest common subsequence.
+#[cfg(target_family = "unix")]
+fn diff(path: &Path, before: &str, after: &str) -> Vec<PlannedChange> {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    // Edits touch a few lines of a profile, so only the lines between
+    // the unchanged start and end need comparing.
+    let start = before
+        .iter()
+        .zip(&after)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let end = before[start..]
+        .iter()
+        .rev()
+        .zip(after[start..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old = &before[start..before.len() - end];
+    let new = &after[start..after.len() - end];
+
+    // `common[i][j]` is how many lines `old[i..]` and `new[j..]` share.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(PlannedChange::RemoveLine {
+                path: path.to_path_buf(),
+                line: old[i].to_string(),
+            });
+            i += 1;
+        } else {
+            changes.push(PlannedChange::AddLine {
+                path: path.to_path_buf(),
+                line: new[j].to_string(),
+            });
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::{sandbox, snapshot, ReadOnlyFilesystem};
+    use crate::{DEFS_BEG, DEFS_END};
+
+    #[test]
+    fn plans_every_kind_of_change_without_touching_the_filesystem() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            &format!(
+                "alias ll='ls -l'\n{}\nexport SET_ENV_DRY_OLD=1\nexport SET_ENV_DRY_KEPT=1\n\
+                 export SET_ENV_DRY_GONE=1\n{}\n",
+                DEFS_BEG, DEFS_END
+            ),
+        );
+        let before = snapshot(&sandbox.home);
+        // Any write fails, so an operation touching the filesystem fails.
+        crate::set_filesystem(ReadOnlyFilesystem);
+        set_dry_run(true);
+
+        crate::set("SET_ENV_DRY_NEW", "1").unwrap();
+        assert!(crate::prelude::persist("SET_ENV_DRY_OLD", "2").unwrap());
+        assert_eq!(crate::disable("SET_ENV_DRY_KEPT").unwrap(), 1);
+        assert_eq!(crate::unset("SET_ENV_DRY_GONE").unwrap(), 1);
+
+        let path = sandbox.path(".bash_profile");
+        let add = |line: &str| PlannedChange::AddLine {
+            path: path.clone(),
+            line: line.to_string(),
+        };
+        let remove = |line: &str| PlannedChange::RemoveLine {
+            path: path.clone(),
+            line: line.to_string(),
+        };
+        assert_eq!(
+            drain_planned_changes(),
+            [
+                add("export SET_ENV_DRY_NEW=1"),
+                remove("export SET_ENV_DRY_OLD=1"),
+                add("export SET_ENV_DRY_OLD=2"),
+                remove("export SET_ENV_DRY_KEPT=1"),
+                add("# set_env disabled: export SET_ENV_DRY_KEPT=1"),
+                remove("export SET_ENV_DRY_GONE=1"),
+            ]
+        );
+        assert_eq!(snapshot(&sandbox.home), before);
+    }
+
+    #[test]
+    fn plans_a_moved_line_as_removed_and_added() {
+        let path = Path::new("profile");
+        assert_eq!(
+            diff(path, "a\nb\nc\n", "b\nc\na\n"),
+            [
+                PlannedChange::RemoveLine {
+                    path: path.to_path_buf(),
+                    line: "a".to_string(),
+                },
+                PlannedChange::AddLine {
+                    path: path.to_path_buf(),
+                    line: "a".to_string(),
+                },
+            ]
+        );
+        assert_eq!(diff(path, "a\r\nb\r\n", "a\nb\n"), []);
+    }
+
+    #[test]
+    fn long_lines_are_shortened_to_max_displayed_line_chars() {
+        let fits = "x".repeat(MAX_DISPLAYED_LINE);
+        assert_eq!(shorten(""), "");
+        assert_eq!(shorten("export A=1"), "export A=1");
+        assert_eq!(shorten(&fits), fits);
+        assert_eq!(
+            shorten(&format!("{}yz", fits)),
+            format!("{}… (2 more bytes)", fits)
+        );
+    }
+
+    #[test]
+    fn lines_are_shortened_at_a_char_boundary() {
+        let accents = "é".repeat(MAX_DISPLAYED_LINE + 1);
+        assert_eq!(
+            shorten(&accents),
+            format!("{}… (2 more bytes)", "é".repeat(MAX_DISPLAYED_LINE))
+        );
+
+        // The limit falls in the middle of the bytes of a character.
+        let mixed = format!("a{}", "€".repeat(MAX_DISPLAYED_LINE));
+        let shortened = shorten(&mixed);
+        assert_eq!(
+            shortened,
+            format!("a{}… (3 more bytes)", "€".repeat(MAX_DISPLAYED_LINE - 1))
+        );
+
+        let change = PlannedChange::AddLine {
+            path: PathBuf::from("profile"),
+            line: accents,
+        };
+        assert_eq!(
+            change.to_string(),
+            format!(
+                "profile: + {}… (2 more bytes)",
+                "é".repeat(MAX_DISPLAYED_LINE)
+            )
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Something this crate did or found, passed to the sink set with
+/// `set_event_sink` right when it happens, in order.
+/// Displayed as a short status line, like `Updating /home/u/.zshrc...`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The shell whose profile is changed was detected. The profile is
+    /// looked up several times per operation, like one call of `set`,
+    /// but the detection and the probing are only reported once per
+    /// operation unless they found something new.
+    ShellDetected { shell: String },
+    /// A possible profile of the shell was looked at, after the shell
+    /// was detected.
+    ProbingCandidate { path: PathBuf, exists: bool },
+    /// Nothing was written for `var` because it already is configured.
+    /// `var` is empty for a line of `ensure_line`.
+    Skipped { var: String, reason: String },
+    /// The file sourced by line `line_no` of `path` wasn't looked at
+    /// for definitions, because of `reason`.
+    SourceSkipped {
+        path: PathBuf,
+        line_no: usize,
+        reason: String,
+    },
+    /// The new content of `path` was written to `temp`, which is about
+    /// to replace it.
+    Staged { path: PathBuf, temp: PathBuf },
+    /// The file at `path` is about to be changed.
+    Writing { path: PathBuf },
+    /// The file at `path` was changed.
+    Wrote { path: PathBuf },
+    /// The persistent user variable `var` was set in the registry, on
+    /// Windows.
+    RegistryWritten { var: String },
+    /// What was just written to `path` was read back and matches, see
+    /// `set_strict_verify`.
+    Verified { path: PathBuf },
+    /// Something went wrong without failing the operation.
+    Warning { message: String },
+    /// The operation is about to fail with `message`.
+    Error { message: String },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::ShellDetected { shell } => write!(f, "Detected shell {}", shell),
+            Event::ProbingCandidate { path, exists: true } => {
+                write!(f, "Found {}", path.display())
+            }
+            Event::ProbingCandidate {
+                path,
+                exists: false,
+            } => write!(f, "No {}", path.display()),
+            Event::Skipped { var, reason } => write!(f, "Skipped {}: {}", var, reason),
+            Event::SourceSkipped {
+                path,
+                line_no,
+                reason,
+            } => write!(
+                f,
+                "Not following line {} of {}: {}",
+                line_no,
+                path.display(),
+                reason
+            ),
+            Event::Staged { path, .. } => write!(f, "Staged {}", path.display()),
+            Event::Writing { path } => write!(f, "Updating {}...", path.display()),
+            Event::Wrote { path } => write!(f, "Updated {}", path.display()),
+            Event::RegistryWritten { var } => write!(f, "Updated the user variable {}", var),
+            Event::Verified { path } => write!(f, "Verified {}", path.display()),
+            Event::Warning { message } => write!(f, "warning: {}", message),
+            Event::Error { message } => write!(f, "error: {}", message),
+        }
+    }
+}
+
+/// Receives the events of this crate, see `set_event_sink`.
+/// Called on the thread doing the operation, which waits for it.
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> EventSink for F {
+    fn on_event(&self, event: &Event) {
+        self(event)
+    }
+}
+
+/// A sink keeping every event, for tests and for showing a log of an
+/// operation afterwards.
+#[derive(Default, Debug)]
+pub struct VecSink {
+    events: Mutex<Vec<Event>>,
+}
+
+impl VecSink {
+    pub fn new() -> VecSink {
+        VecSink::default()
+    }
+
+    /// Returns the events received so far.
+    pub fn events(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Returns the events received so far and forgets them.
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+impl EventSink for VecSink {
+    fn on_event(&self, event: &Event) {
+        self.events
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(event.clone());
+    }
+}
+
+static SINK: Mutex<Option<Arc<dyn EventSink>>> = Mutex::new(None);
+
+type Lookup = (String, Vec<(PathBuf, bool)>);
+
+thread_local! {
+    /// How many operations the thread is in, nested ones included, and
+    /// what the profile lookup reported last during the outermost one.
+    static OPERATION: RefCell<(usize, Option<Lookup>)> = const { RefCell::new((0, None)) };
+}
+
+/// Sets the sink receiving the events of this crate for the whole
+/// process, for showing live status in a GUI or logging warnings,
+/// which this crate never prints itself. Pass `None` to stop sending
+/// events.
+pub fn set_event_sink(sink: Option<Arc<dyn EventSink>>) {
+    *SINK.lock().unwrap_or_else(|err| err.into_inner()) = sink;
+}
+
+/// Passes `event` to the sink, if one is set.
+pub(crate) fn emit(event: Event) {
+    // The sink may call into this crate, so it's called unlocked.
+    let sink = SINK.lock().unwrap_or_else(|err| err.into_inner()).clone();
+    if let Some(sink) = sink {
+        sink.on_event(&event);
+    }
+}
+
+/// One operation of this crate, like a call of `set`, lasting until
+/// it's dropped, see `operation`.
+pub(crate) struct Operation;
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        OPERATION.with_borrow_mut(|(depth, last)| {
+            *depth -= 1;
+            if *depth == 0 {
+                *last = None;
+            }
+        });
+    }
+}
+
+/// Starts an operation on this thread, within which the profile lookup
+/// is reported once, see `looked_up`. Operations calling each other
+/// make up one.
+pub(crate) fn operation() -> Operation {
+    OPERATION.with_borrow_mut(|(depth, _)| *depth += 1);
+    Operation
+}
+
+/// Emits the detected `shell` and the `probed` candidate profiles with
+/// whether they exist, unless the operation already looked up the same.
+/// Outside of an operation every lookup is reported.
+pub(crate) fn looked_up(shell: &str, probed: Vec<(PathBuf, bool)>) {
+    let lookup = (shell.to_string(), probed);
+    let seen = OPERATION.with_borrow_mut(|(depth, last)| {
+        if *depth == 0 {
+            return false;
+        }
+        let seen = last.as_ref() == Some(&lookup);
+        *last = Some(lookup.clone());
+        seen
+    });
+    if seen {
+        return;
+    }
+    let (shell, probed) = lookup;
+    emit(Event::ShellDetected { shell });
+    for (path, exists) in probed {
+        emit(Event::ProbingCandidate { path, exists });
+    }
+}
+
+/// Emits the warning `message`.
+pub(crate) fn warn(message: String) {
+    emit(Event::Warning { message });
+}
+
+/// Emits `err` as an error and returns it, for failing with it.
+pub(crate) fn error(err: io::Error) -> io::Error {
+    emit(Event::Error {
+        message: err.to_string(),
+    });
+    err
+}
+
+/// Emits that nothing was written for `var`.
+pub(crate) fn skipped(var: &str, reason: impl fmt::Display) {
+    emit(Event::Skipped {
+        var: var.to_string(),
+        reason: reason.to_string(),
+    });
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn reports_the_lookup_once_per_operation() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bashrc", "");
+        let profile = sandbox.path(".bashrc");
+        let expected = vec![
+            Event::ShellDetected {
+                shell: "bash".to_string(),
+            },
+            Event::ProbingCandidate {
+                path: sandbox.path(".bash_profile"),
+                exists: false,
+            },
+            Event::ProbingCandidate {
+                path: profile.clone(),
+                exists: true,
+            },
+            Event::Writing {
+                path: profile.clone(),
+            },
+            Event::Staged {
+                path: profile.clone(),
+                temp: sandbox.path("..bashrc.set_env.tmp"),
+            },
+            Event::Wrote {
+                path: profile.clone(),
+            },
+        ];
+
+        let sink = Arc::new(VecSink::new());
+        set_event_sink(Some(sink.clone()));
+        crate::set("SET_ENV_EVENTS_FIRST", "1").unwrap();
+        assert_eq!(sink.take(), expected);
+        crate::set("SET_ENV_EVENTS_SECOND", "2").unwrap();
+        assert_eq!(sink.take(), expected);
+
+        let later = Arc::new(VecSink::new());
+        set_event_sink(Some(later.clone()));
+        crate::set("SET_ENV_EVENTS_THIRD", "3").unwrap();
+        assert_eq!(later.events(), expected);
+        assert_eq!(sink.events(), []);
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::io;
+
+#[cfg(target_family = "unix")]
+use crate::{block, write_mode, Access};
+
+/// The decisions this crate makes before writing anything,
+/// see `explain`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Explanation {
+    pub decisions: Vec<Decision>,
+}
+
+/// A single decision, like which shell was detected.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Decision {
+    /// What was decided, like `shell`.
+    pub subject: &'static str,
+    /// What was chosen or found, like `zsh`.
+    pub choice: String,
+    /// Why, like `SHELL is /bin/zsh`.
+    pub reason: String,
+}
+
+impl Explanation {
+    fn push(
+        &mut self,
+        subject: &'static str,
+        choice: impl fmt::Display,
+        reason: impl Into<String>,
+    ) {
+        self.decisions.push(Decision {
+            subject,
+            choice: choice.to_string(),
+            reason: reason.into(),
+        });
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for decision in &self.decisions {
+            write!(f, "{}: {}", decision.subject, decision.choice)?;
+            if !decision.reason.is_empty() {
+                write!(f, " ({})", decision.reason)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explains which shell and profile would be used and how lines would
+/// be written there, without changing anything.
+/// Meant for verbose output and bug reports.
+pub fn explain() -> io::Result<Explanation> {
+    let _operation = crate::events::operation();
+    let mut explanation = Explanation::default();
+    explain_profile(&mut explanation)?;
+
+    explanation.push(
+        "backend",
+        format!("{:?}", crate::backend::backend()),
+        "see set_backend",
+    );
+    explanation.push(
+        "dry run",
+        crate::is_dry_run(),
+        "see set_dry_run and SET_ENV_DRY_RUN",
+    );
+    Ok(explanation)
+}
+
+#[cfg(target_family = "unix")]
+fn explain_profile(explanation: &mut Explanation) -> io::Result<()> {
+    let (home_dir, shell) = crate::home_and_shell_with(Access::ReadOnly)?;
+    let sudo_user = crate::sudo::invoking_user(Access::ReadOnly)?;
+    let from_passwd = sudo_user.is_some();
+    let origin = match &sudo_user {
+        Some(user) => format!("passwd entry of {}, who invoked sudo", user.name),
+        None => "HOME".to_string(),
+    };
+
+    explanation.push("home", home_dir.display(), origin.clone());
+    let shell_origin = if from_passwd {
+        origin
+    } else {
+        "SHELL".to_string()
+    };
+    match crate::select_shell(&shell) {
+        Some(selected) => explanation.push(
+            "shell",
+            selected.name(),
+            format!("{} is {:?}", shell_origin, shell),
+        ),
+        None => explanation.push(
+            "shell",
+            "unsupported",
+            format!(
+                "{} is {:?}, using {}",
+                shell_origin,
+                shell,
+                crate::platform().fallback_profile
+            ),
+        ),
+    }
+
+    let profile = crate::profile_path_with(crate::Access::ReadOnly)?;
+    let split = crate::zsh::split_profile(&home_dir, &shell, Some("PATH"));
+    let candidates = match split {
+        Some(_) => Vec::new(),
+        None => crate::profile_candidates(&home_dir, &shell).unwrap_or_default(),
+    };
+    let first_existing = candidates
+        .iter()
+        .position(|path| crate::filesystem::current().exists(path));
+    for (idx, candidate) in candidates.iter().enumerate() {
+        let reason = match first_existing {
+            Some(first) if idx == first => "the first one that exists",
+            Some(first) if idx < first => "doesn't exist",
+            Some(_) => "an earlier one exists",
+            None if idx == 0 => "none exists, so the first one is created",
+            None => "doesn't exist",
+        };
+        let choice = if *candidate == profile {
+            "chosen"
+        } else {
+            "skipped"
+        };
+        explanation.push(
+            "candidate",
+            format!("{} {}", candidate.display(), choice),
+            reason,
+        );
+    }
+
+    let content = match crate::filesystem::current().read_to_string(&profile) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let reason = match block::find(&lines) {
+        Some((beg, end)) => format!("into the managed block at lines {}-{}", beg + 1, end + 1),
+        None => "into a new managed block at the end".to_string(),
+    };
+    explanation.push("profile", profile.display(), reason);
+    if let Some(lists) = split {
+        explanation.push(
+            "zsh policy",
+            "SplitByKind",
+            format!("list variables like PATH go to {}", lists.display()),
+        );
+    }
+    explanation.push(
+        "syntax",
+        format!("{:?}", crate::profile_syntax()?.assignment),
+        "",
+    );
+
+    let mode = write_mode::write_mode();
+    let reason = match (mode, crate::network_filesystem(&profile)) {
+        (crate::WriteMode::Auto, Some(kind)) => format!("Auto, on {:?}", kind),
+        (crate::WriteMode::Auto, None) => "Auto, on a local filesystem".to_string(),
+        (mode, _) => format!("{:?}, see set_write_mode", mode),
+    };
+    let choice = if write_mode::is_locked(&profile) {
+        "locked, through a temporary file"
+    } else {
+        "in place"
+    };
+    explanation.push("write mode", choice, reason);
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn explain_profile(explanation: &mut Explanation) -> io::Result<()> {
+    let (profile, source) = crate::windows::profile_location()?;
+    let reason = match source {
+        crate::windows::ProfileSource::PowerShell => "reported by PowerShell",
+        crate::windows::ProfileSource::KnownFolder => {
+            "derived from the documents folder, PowerShell couldn't be asked"
+        }
+    };
+    explanation.push("profile", profile.display(), reason);
+    explanation.push(
+        "syntax",
+        format!("{:?}", crate::profile_syntax()?.assignment),
+        "",
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decisions_are_rendered_one_per_line() {
+        let mut explanation = Explanation::default();
+        explanation.push("shell", "zsh", "SHELL is \"/bin/zsh\"");
+        explanation.push("syntax", "Export", "");
+        assert_eq!(
+            explanation.to_string(),
+            "shell: zsh (SHELL is \"/bin/zsh\")\nsyntax: Export\n"
+        );
+        assert_eq!(Explanation::default().to_string(), "");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn explains_the_profile_without_changing_it() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        sandbox.write(".bashrc", "alias ll='ls -l'\n");
+        let before = crate::test_support::snapshot(&sandbox.home);
+        let path = |name: &str| sandbox.path(name).display().to_string();
+        let decisions = |explanation: Explanation| -> Vec<(&str, String, String)> {
+            explanation
+                .decisions
+                .into_iter()
+                // Depends on the filesystem the tests run on.
+                .filter(|it| it.subject != "write mode")
+                .map(|it| (it.subject, it.choice, it.reason))
+                .collect()
+        };
+        let decision =
+            |subject, choice: String, reason: &str| (subject, choice, reason.to_string());
+
+        let explanation = explain().unwrap();
+        assert_eq!(crate::test_support::snapshot(&sandbox.home), before);
+        assert_eq!(
+            decisions(explanation),
+            [
+                decision("home", sandbox.home.display().to_string(), "HOME"),
+                decision("shell", "bash".to_string(), "SHELL is \"bash\""),
+                decision(
+                    "candidate",
+                    format!("{} skipped", path(".bash_profile")),
+                    "doesn't exist"
+                ),
+                decision(
+                    "candidate",
+                    format!("{} chosen", path(".bashrc")),
+                    "the first one that exists"
+                ),
+                decision(
+                    "candidate",
+                    format!("{} skipped", path(".bash_login")),
+                    "an earlier one exists"
+                ),
+                decision(
+                    "profile",
+                    path(".bashrc"),
+                    "into a new managed block at the end"
+                ),
+                decision("syntax", "Export".to_string(), ""),
+                decision("backend", "Profile".to_string(), "see set_backend"),
+                decision(
+                    "dry run",
+                    "false".to_string(),
+                    "see set_dry_run and SET_ENV_DRY_RUN"
+                ),
+            ]
+        );
+
+        crate::set("SET_ENV_EXPLAINED", "1").unwrap();
+        crate::set_dry_run(true);
+        let explanation = decisions(explain().unwrap());
+        assert!(explanation.contains(&decision(
+            "profile",
+            path(".bashrc"),
+            "into the managed block at lines 2-4"
+        )));
+        assert_eq!(
+            explanation.last(),
+            Some(&decision(
+                "dry run",
+                "true".to_string(),
+                "see set_dry_run and SET_ENV_DRY_RUN"
+            ))
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! A C interface for installers not written in Rust, enabled with the
+//! `ffi` feature. `include/set_env.h` declares it.
+//!
+//! Strings passed in are NUL-terminated UTF-8 and stay owned by the
+//! caller. Strings returned are owned by the caller from then on and
+//! must be freed with `set_env_string_free`, not `free`.
+//! Every function returns one of the `SET_ENV_*` codes; the message of
+//! the last failure on the calling thread is available through
+//! `set_env_last_error_message`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+pub const SET_ENV_OK: c_int = 0;
+/// A pointer was null or a string wasn't valid UTF-8.
+pub const SET_ENV_INVALID_ARGUMENT: c_int = 1;
+pub const SET_ENV_NOT_FOUND: c_int = 2;
+pub const SET_ENV_PERMISSION_DENIED: c_int = 3;
+/// A profile has content this crate refuses to change.
+pub const SET_ENV_INVALID_DATA: c_int = 4;
+/// The platform or shell isn't supported.
+pub const SET_ENV_UNSUPPORTED: c_int = 5;
+pub const SET_ENV_OTHER: c_int = 6;
+/// The call panicked, which is a bug in this crate.
+pub const SET_ENV_PANIC: c_int = 7;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, turning errors and panics into a code and remembering
+/// their message for `set_env_last_error_message`.
+fn call(f: impl FnOnce() -> Result<(), (c_int, String)>) -> c_int {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|it| it.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err((SET_ENV_PANIC, message))
+    });
+    match result {
+        Ok(()) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = None);
+            SET_ENV_OK
+        }
+        Err((code, message)) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            code
+        }
+    }
+}
+
+fn io_error(err: io::Error) -> (c_int, String) {
+    let code = match err.kind() {
+        io::ErrorKind::NotFound => SET_ENV_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => SET_ENV_PERMISSION_DENIED,
+        io::ErrorKind::InvalidData => SET_ENV_INVALID_DATA,
+        io::ErrorKind::Unsupported => SET_ENV_UNSUPPORTED,
+        _ => SET_ENV_OTHER,
+    };
+    (code, err.to_string())
+}
+
+/// # Safety
+/// `ptr` must be null or point to a NUL-terminated string.
+unsafe fn string<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, (c_int, String)> {
+    if ptr.is_null() {
+        return Err((SET_ENV_INVALID_ARGUMENT, format!("{} is null", name)));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| (SET_ENV_INVALID_ARGUMENT, format!("{} is not UTF-8", name)))
+}
+
+/// Does the same as `set`.
+///
+/// # Safety
+/// `var` and `value` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn set_env_set(var: *const c_char, value: *const c_char) -> c_int {
+    call(|| {
+        let (var, value) = (string(var, "var")?, string(value, "value")?);
+        crate::set(var, value)
+            .map(crate::Change::emit_warnings)
+            .map_err(io_error)
+    })
+}
+
+/// Does the same as `append`.
+///
+/// # Safety
+/// `var` and `value` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn set_env_append(var: *const c_char, value: *const c_char) -> c_int {
+    call(|| {
+        let (var, value) = (string(var, "var")?, string(value, "value")?);
+        crate::append(var, value)
+            .map(crate::Change::emit_warnings)
+            .map_err(io_error)
+    })
+}
+
+/// Does the same as `check_or_set`.
+///
+/// # Safety
+/// `var` and `value` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn set_env_check_or_set(var: *const c_char, value: *const c_char) -> c_int {
+    call(|| {
+        let (var, value) = (string(var, "var")?, string(value, "value")?);
+        crate::check_or_set(var, value).map_err(io_error)
+    })
+}
+
+/// Does the same as `unset`, storing how many lines were removed in
+/// `removed` unless it's null. Not supported on Windows.
+///
+/// # Safety
+/// `var` must be null or point to a NUL-terminated string, `removed`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn set_env_unset(var: *const c_char, removed: *mut usize) -> c_int {
+    call(|| {
+        let var = string(var, "var")?;
+        let count = unset(var)?;
+        if !removed.is_null() {
+            *removed = count;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(target_family = "unix")]
+fn unset(var: &str) -> Result<usize, (c_int, String)> {
+    crate::unset(var).map_err(io_error)
+}
+
+#[cfg(target_os = "windows")]
+fn unset(_var: &str) -> Result<usize, (c_int, String)> {
+    Err((
+        SET_ENV_UNSUPPORTED,
+        "unset is not supported on Windows".to_string(),
+    ))
+}
+
+/// Returns the message of the last failure on the calling thread, or
+/// null if the last call succeeded. Free it with `set_env_string_free`.
+#[no_mangle]
+pub extern "C" fn set_env_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_deref() {
+        // A NUL inside the message would end it early, so it's dropped.
+        Some(message) => {
+            CString::new(message.replace('\0', "")).map_or(ptr::null_mut(), CString::into_raw)
+        }
+        None => ptr::null_mut(),
+    })
+}
+
+/// Frees a string returned by this library. Null is ignored.
+///
+/// # Safety
+/// `ptr` must be null or a string returned by this library that wasn't
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn set_env_string_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        drop(CString::from_raw(ptr));
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use crate::test_support::sandbox;
+
+    /// Builds the library and `examples/ffi.c` against it, and returns
+    /// the program, or `None` if there is no C compiler.
+    fn build_example() -> Option<PathBuf> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        // A target directory of its own, as the one of the test is locked.
+        let target = root.join("target").join("ffi");
+        let status = Command::new(env!("CARGO"))
+            .current_dir(root)
+            .args([
+                "rustc",
+                "--lib",
+                "--features",
+                "ffi",
+                "--crate-type",
+                "cdylib",
+            ])
+            .arg("--target-dir")
+            .arg(&target)
+            .status()
+            .expect("run cargo");
+        assert!(status.success(), "building the library failed");
+
+        let program = target.join("ffi");
+        let output = Command::new("cc")
+            .current_dir(root)
+            .args(["-Iinclude", "examples/ffi.c"])
+            .arg(format!("-L{}", target.join("debug").display()))
+            .args(["-lset_env_perm", "-o"])
+            .arg(&program)
+            .output();
+        match output {
+            Ok(output) => assert!(output.status.success(), "{:?}", output),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(err) => panic!("cc: {}", err),
+        }
+        Some(program)
+    }
+
+    #[test]
+    fn the_c_example_sets_and_unsets_through_the_library() {
+        let Some(program) = build_example() else {
+            return;
+        };
+        let sandbox = sandbox("/bin/bash");
+        let run = |dummy: Option<&str>| {
+            let mut command = Command::new(&program);
+            command
+                .env("HOME", &sandbox.home)
+                .env("SHELL", "/bin/bash")
+                .env("LD_LIBRARY_PATH", program.with_file_name("debug"))
+                .env("DYLD_LIBRARY_PATH", program.with_file_name("debug"))
+                .env_remove("DUMMY");
+            if let Some(dummy) = dummy {
+                command.env("DUMMY", dummy);
+            }
+            let output = command.output().expect("run the example");
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        // Set in its environment, check_or_set leaves the profile alone.
+        assert_eq!(run(Some("1")), "Removed 0 lines\n");
+        assert!(!sandbox.path(".bash_profile").exists());
+
+        assert_eq!(run(None), "Removed 1 lines\n");
+        assert!(!sandbox.read(".bash_profile").contains("DUMMY"));
+    }
+}
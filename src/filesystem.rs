@@ -0,0 +1,460 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The file operations this crate performs on profiles and the files
+/// next to them, see `set_filesystem`.
+pub trait Filesystem: Send + Sync {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    /// Replaces the content of the file at `path`, creating it if needed.
+    fn write(&self, path: &Path, content: &str) -> io::Result<()>;
+    /// Adds `content` to the end of the file at `path`, creating it if
+    /// needed.
+    fn append(&self, path: &Path, content: &str) -> io::Result<()>;
+    fn exists(&self, path: &Path) -> bool;
+    /// Returns what is at `path`, without following a symlink there.
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Returns the paths of the entries of the directory at `path`.
+    /// Filesystems that can't list directories report them as empty.
+    fn read_dir(&self, _path: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} can't be removed from this filesystem", path.display()),
+        ))
+    }
+    /// Creates the file at `path` with `content`, failing with
+    /// `ErrorKind::AlreadyExists` if there is one, which lock files rely on.
+    fn create_new(&self, path: &Path, content: &str) -> io::Result<()> {
+        if self.exists(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        self.write(path, content)
+    }
+    /// Adds `content` to the end of the file at `path` like `append`,
+    /// creating it readable and writable only by its owner if needed.
+    fn append_private(&self, path: &Path, content: &str) -> io::Result<()> {
+        self.append(path, content)
+    }
+    /// Returns what is at `path`, following a symlink there.
+    /// Filesystems without symlinks return the same as `metadata`.
+    fn target_metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        self.metadata(path)
+    }
+    /// Returns where the symlink at `path` points.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a symlink", path.display()),
+        ))
+    }
+    /// Returns `path` with symlinks resolved.
+    /// Filesystems without symlinks return it as it is.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if !self.exists(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            ));
+        }
+        Ok(path.to_path_buf())
+    }
+    /// Gives the file at `to` the permissions of the one at `from`.
+    /// Filesystems without permissions do nothing.
+    fn copy_permissions(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Ok(())
+    }
+    /// Returns whether there is a file at `path` that can be appended to.
+    fn is_writable(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| !metadata.is_dir)
+    }
+    /// Gives the file at `path` to the user `uid` and group `gid`.
+    /// Filesystems without owners do nothing.
+    #[cfg(target_family = "unix")]
+    fn chown(&self, _path: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `Filesystem::metadata` returns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileMetadata {
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub len: u64,
+}
+
+/// The filesystem of the machine this runs on, used unless
+/// `set_filesystem` is called.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StdFilesystem;
+
+impl Filesystem for StdFilesystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+        fs::write(path, content)
+    }
+
+    fn append(&self, path: &Path, content: &str) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(content.as_bytes())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir(),
+            is_symlink: metadata.file_type().is_symlink(),
+            len: metadata.len(),
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn create_new(&self, path: &Path, content: &str) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?
+            .write_all(content.as_bytes())
+    }
+
+    fn append_private(&self, path: &Path, content: &str) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?.write_all(content.as_bytes())
+    }
+
+    fn target_metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir(),
+            is_symlink: false,
+            len: metadata.len(),
+        })
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::set_permissions(to, fs::metadata(from)?.permissions())
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        path.is_file() && OpenOptions::new().append(true).open(path).is_ok()
+    }
+
+    #[cfg(target_family = "unix")]
+    fn chown(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid))
+    }
+}
+
+static FILESYSTEM: Mutex<Option<Arc<dyn Filesystem>>> = Mutex::new(None);
+
+/// Sets the filesystem profiles are read from and written to, like an
+/// in-memory one for tests or one writing into an archive.
+/// Only the files are redirected: which profile is picked still depends
+/// on the home directory and shell of the current user.
+pub fn set_filesystem<F: Filesystem + 'static>(filesystem: F) {
+    *FILESYSTEM.lock().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(filesystem));
+}
+
+/// Returns the configured filesystem, kept from being written to with
+/// `Backend::ProcessOnly`.
+pub(crate) fn current() -> Arc<dyn Filesystem> {
+    let filesystem = FILESYSTEM
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(StdFilesystem));
+    if crate::backend::is_process_only() {
+        return Arc::new(crate::backend::InMemory(filesystem));
+    }
+    filesystem
+}
+
+/// Replaces the content of the file at `path` with `content` by writing
+/// it to a temporary file next to it and renaming that over it, so a
+/// crash while writing can't leave a truncated profile behind. If
+/// `path` is a symlink the file it points to is replaced.
+pub(crate) fn replace(path: &Path, content: &str) -> io::Result<()> {
+    let filesystem = current();
+    // Renaming over a symlink would replace the link itself.
+    let target = match filesystem.canonicalize(path) {
+        Ok(target) => target,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => match filesystem.metadata(path) {
+            // A dangling one, whose target is created by the rename.
+            Ok(metadata) if metadata.is_symlink => filesystem
+                .read_link(path)
+                .map(|link| path.parent().map_or(link.clone(), |dir| dir.join(&link)))?,
+            _ => path.to_path_buf(),
+        },
+        Err(err) => return Err(err),
+    };
+    let temp = sibling(&target, "tmp");
+    filesystem.write(&temp, content)?;
+    crate::events::emit(crate::events::Event::Staged {
+        path: target.clone(),
+        temp: temp.clone(),
+    });
+    let mut renamed = Ok(());
+    if filesystem.exists(&target) {
+        renamed = filesystem.copy_permissions(&target, &temp);
+    }
+    renamed = renamed.and_then(|_| filesystem.rename(&temp, &target));
+    if renamed.is_err() {
+        let _ = filesystem.remove_file(&temp);
+    }
+    renamed
+}
+
+/// Returns `.<file name>.set_env.<suffix>` next to `path`.
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.set_env.{}", name, suffix))
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[cfg_attr(not(feature = "testing"), allow(unused_imports))]
+pub use memory::MemoryFilesystem;
+
+#[cfg(any(test, feature = "testing"))]
+#[cfg_attr(not(feature = "testing"), allow(dead_code))]
+mod memory {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    use super::{FileMetadata, Filesystem};
+
+    #[derive(Default)]
+    struct Files {
+        files: BTreeMap<PathBuf, String>,
+        dirs: BTreeSet<PathBuf>,
+    }
+
+    /// A filesystem kept in memory, for tests.
+    /// Clones share their files, so a test can keep one to look at
+    /// what was written to the one passed to `set_filesystem`.
+    #[derive(Clone, Default)]
+    pub struct MemoryFilesystem(Arc<Mutex<Files>>);
+
+    impl MemoryFilesystem {
+        pub fn new() -> Self {
+            MemoryFilesystem::default()
+        }
+
+        /// Adds a file with `content` at `path`, and its directories.
+        pub fn insert<P: AsRef<Path>>(&self, path: P, content: &str) {
+            let path = path.as_ref();
+            let mut files = self.lock();
+            if let Some(dir) = path.parent() {
+                files.dirs.extend(dir.ancestors().map(Path::to_path_buf));
+            }
+            files.files.insert(path.to_path_buf(), content.to_string());
+        }
+
+        /// Returns the content of the file at `path`, if there is one.
+        pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+            self.lock().files.get(path.as_ref()).cloned()
+        }
+
+        /// Returns the paths of all files.
+        pub fn files(&self) -> Vec<PathBuf> {
+            self.lock().files.keys().cloned().collect()
+        }
+
+        fn lock(&self) -> MutexGuard<'_, Files> {
+            self.0.lock().unwrap_or_else(|err| err.into_inner())
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", path.display()),
+        )
+    }
+
+    /// Fails like std does when the directory of `path` is missing.
+    fn check_parent(files: &Files, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && !files.dirs.contains(dir) => {
+                Err(not_found(dir))
+            }
+            _ if files.dirs.contains(path) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a directory", path.display()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    impl Filesystem for MemoryFilesystem {
+        fn read_to_string(&self, path: &Path) -> io::Result<String> {
+            self.get(path).ok_or_else(|| not_found(path))
+        }
+
+        fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+            let mut files = self.lock();
+            check_parent(&files, path)?;
+            files.files.insert(path.to_path_buf(), content.to_string());
+            Ok(())
+        }
+
+        fn append(&self, path: &Path, content: &str) -> io::Result<()> {
+            let mut files = self.lock();
+            check_parent(&files, path)?;
+            files
+                .files
+                .entry(path.to_path_buf())
+                .or_default()
+                .push_str(content);
+            Ok(())
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            let files = self.lock();
+            files.files.contains_key(path) || files.dirs.contains(path)
+        }
+
+        fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+            let files = self.lock();
+            if files.dirs.contains(path) {
+                return Ok(FileMetadata {
+                    is_dir: true,
+                    is_symlink: false,
+                    len: 0,
+                });
+            }
+            let content = files.files.get(path).ok_or_else(|| not_found(path))?;
+            Ok(FileMetadata {
+                is_dir: false,
+                is_symlink: false,
+                len: content.len() as u64,
+            })
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut files = self.lock();
+            if let Some(file) = path.ancestors().find(|dir| files.files.contains_key(*dir)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", file.display()),
+                ));
+            }
+            files.dirs.extend(path.ancestors().map(Path::to_path_buf));
+            Ok(())
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let mut files = self.lock();
+            check_parent(&files, to)?;
+            let content = files.files.remove(from).ok_or_else(|| not_found(from))?;
+            files.files.insert(to.to_path_buf(), content);
+            Ok(())
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            let mut files = self.lock();
+            files
+                .files
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| not_found(path))
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            let files = self.lock();
+            if !files.dirs.contains(path) {
+                return Err(not_found(path));
+            }
+            Ok(files
+                .files
+                .keys()
+                .chain(files.dirs.iter())
+                .filter(|entry| entry.parent() == Some(path))
+                .cloned()
+                .collect())
+        }
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use crate::test_support::{sandbox, snapshot};
+
+    #[test]
+    fn memory_filesystem_is_hermetic() {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        crate::set_audit_log(Some(crate::AuditLog::new(sandbox.path("audit.log"))));
+        crate::set_usage_stats(true);
+
+        crate::set("FOO", "1").unwrap();
+        crate::set_noninteractive("BAR", "2", true).unwrap();
+        assert!(crate::unset_noninteractive("BAR").unwrap());
+
+        assert_eq!(snapshot(&sandbox.home), Vec::new());
+        let profile = memory.get(sandbox.path(".bash_profile")).unwrap();
+        assert!(profile.contains("export FOO=1\n"));
+        assert!(memory.get(sandbox.path("audit.log")).is_some());
+        assert!(memory
+            .get(sandbox.path(".config/set_env/stats.tsv"))
+            .is_some());
+        assert_eq!(
+            memory.get(sandbox.path(".config/set_env/noninteractive.sh")),
+            None
+        );
+    }
+}
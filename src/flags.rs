@@ -0,0 +1,237 @@
+use std::env;
+use std::fmt;
+use std::io;
+
+/// How the flags of an option variable are keyed, so a new flag
+/// replaces the one with the same key, see `merge_flags`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlagStyle {
+    /// JVM flags as in JAVA_OPTS, MAVEN_OPTS and GRADLE_OPTS:
+    /// `-Xmx2g` is keyed by `-Xmx`, `-Dname=value` by `-Dname`,
+    /// `-XX:+UseG1GC` and `-XX:-UseG1GC` by `-XX:UseG1GC`,
+    /// `-XX:Name=value` and `--name=value` by what comes before `=`.
+    JavaX,
+    /// `key=value` flags keyed by what comes before `=`.
+    KeyEquals,
+}
+
+/// Merges `flags` into the option variable `var`, replacing flags with
+/// the same key and appending new ones, and sets the result.
+/// Starts from the value the block managed by this crate sets,
+/// or the value in the current process if there is none.
+/// Flags are separated by whitespace and may be quoted.
+/// Returns the merged value.
+pub fn merge_flags<T: fmt::Display>(
+    var: T,
+    flags: &[&str],
+    style: FlagStyle,
+) -> io::Result<String> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let current = match crate::find_definition(&var)? {
+        Some(definition) if definition.managed && definition.chain.is_empty() => {
+            unquote(&definition.value).to_string()
+        }
+        _ => env::var(&var).unwrap_or_default(),
+    };
+
+    let mut merged = split_flags(&current);
+    for flag in flags {
+        let key = flag_key(flag, style);
+        match merged.iter().position(|it| flag_key(it, style) == key) {
+            Some(idx) => merged[idx] = flag.to_string(),
+            None => merged.push(flag.to_string()),
+        }
+    }
+
+    let merged = merged.join(" ");
+    replace_managed(&var, &format!("\"{}\"", merged))?;
+    Ok(merged)
+}
+
+/// Sets `var` to `value`, replacing the assignments to it in the block
+/// managed by this crate instead of adding another one.
+#[cfg(target_family = "unix")]
+fn replace_managed(var: &str, value: &str) -> io::Result<()> {
+    let line = crate::profile_syntax()?.set_line(var, value);
+    crate::edit_profile(&line, |content| {
+        let lines: Vec<&str> = content.lines().collect();
+        let block = crate::block::find(&lines);
+        let kept: Vec<&str> = lines
+            .iter()
+            .enumerate()
+            .filter(|(idx, it)| {
+                let managed = block.is_some_and(|(beg, end)| *idx > beg && *idx < end);
+                !managed
+                    || crate::simulate::parse_var_value(it.trim())
+                        .is_none_or(|(assigned, _)| assigned != var)
+            })
+            .map(|(_, it)| *it)
+            .collect();
+        let kept = crate::line_endings::rejoin(content, &kept);
+        (crate::block::insert(&kept, &line, None), ())
+    })?;
+    crate::changed(var, crate::backend::Update::Set(unquote(value)));
+    Ok(())
+}
+
+/// Sets `var` to `value`; the PowerShell profile runs top to bottom,
+/// so the new assignment overrides earlier ones.
+#[cfg(target_os = "windows")]
+fn replace_managed(var: &str, value: &str) -> io::Result<()> {
+    crate::set(var, value).map(crate::Change::emit_warnings)
+}
+
+pub(crate) fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value)
+}
+
+/// Splits `value` at whitespace outside of quotes,
+/// keeping the quotes in the flags.
+fn split_flags(value: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut flag = String::new();
+    let mut quote = None;
+    for c in value.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c.is_whitespace() => {
+                if !flag.is_empty() {
+                    flags.push(std::mem::take(&mut flag));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        flag.push(c);
+    }
+    if !flag.is_empty() {
+        flags.push(flag);
+    }
+    flags
+}
+
+/// Returns the key of `flag`, quoted as a whole or not.
+fn flag_key(flag: &str, style: FlagStyle) -> String {
+    let flag = unquote(flag);
+    let before_equals = flag.split('=').next().unwrap_or(flag);
+    if style == FlagStyle::KeyEquals {
+        return before_equals.to_string();
+    }
+
+    if let Some(option) = flag.strip_prefix("-XX:") {
+        let option = option.trim_start_matches(['+', '-']);
+        return format!("-XX:{}", option.split('=').next().unwrap_or(option));
+    }
+    // -Xmx2g, -Xss512k: the key ends where the size starts.
+    if let Some(option) = flag.strip_prefix("-X") {
+        let name_len = option
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(option.len());
+        return format!("-X{}", &option[..name_len]);
+    }
+    before_equals.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_split_outside_of_quotes() {
+        assert_eq!(
+            split_flags("  -Xmx1g\t-Dmsg='hello world' \"-Dpath=C:\\Program Files\"  -ea "),
+            [
+                "-Xmx1g",
+                "-Dmsg='hello world'",
+                "\"-Dpath=C:\\Program Files\"",
+                "-ea"
+            ]
+        );
+        assert!(split_flags("   ").is_empty());
+    }
+
+    #[test]
+    fn java_flags_are_keyed_by_what_they_set() {
+        let cases = [
+            ("-Xmx2g", "-Xmx"),
+            ("-Xmx512m", "-Xmx"),
+            ("-Xss1m", "-Xss"),
+            ("-Xshare:off", "-Xshare:off"),
+            ("-Dapp.env=prod", "-Dapp.env"),
+            ("-Dmsg='a=b'", "-Dmsg"),
+            ("'-Dmsg=a b'", "-Dmsg"),
+            ("\"-Xmx2g\"", "-Xmx"),
+            ("-XX:+UseG1GC", "-XX:UseG1GC"),
+            ("-XX:-UseG1GC", "-XX:UseG1GC"),
+            ("-XX:MaxMetaspaceSize=256m", "-XX:MaxMetaspaceSize"),
+            ("--add-opens=java.base/java.lang=ALL-UNNAMED", "--add-opens"),
+            ("-ea", "-ea"),
+        ];
+        for (flag, key) in cases {
+            assert_eq!(flag_key(flag, FlagStyle::JavaX), key, "{}", flag);
+        }
+        assert_eq!(flag_key("-Xmx2g", FlagStyle::KeyEquals), "-Xmx2g");
+        assert_eq!(flag_key("level=debug", FlagStyle::KeyEquals), "level");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn merging_replaces_flags_with_the_same_key() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        crate::set(
+            "JAVA_OPTS",
+            "\"-Xmx1g -Dapp.env=dev -XX:+UseG1GC -Dmsg='hello world'\"",
+        )
+        .unwrap();
+
+        let merged = merge_flags(
+            "JAVA_OPTS",
+            &["-Xmx2g", "-Dapp.env=prod", "-XX:-UseG1GC", "-Dnew=1"],
+            FlagStyle::JavaX,
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            "-Xmx2g -Dapp.env=prod -XX:-UseG1GC -Dmsg='hello world' -Dnew=1"
+        );
+        assert_eq!(
+            merge_flags("JAVA_OPTS", &["-Dmsg='bye now'"], FlagStyle::JavaX).unwrap(),
+            "-Xmx2g -Dapp.env=prod -XX:-UseG1GC -Dmsg='bye now' -Dnew=1"
+        );
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\nexport JAVA_OPTS=\"-Xmx2g -Dapp.env=prod -XX:-UseG1GC -Dmsg='bye now' -Dnew=1\"\n{}\n",
+                crate::DEFS_BEG,
+                crate::DEFS_END
+            )
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn merging_starts_from_the_process_without_a_managed_value() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        env::set_var(
+            "SET_ENV_FLAGS_MAVEN_OPTS",
+            "-Xmx1g '-Dmaven.repo.local=/tmp/m 2'",
+        );
+
+        let merged = merge_flags(
+            "SET_ENV_FLAGS_MAVEN_OPTS",
+            &["-Xmx3g", "-Dmaven.repo.local=/opt/m2"],
+            FlagStyle::JavaX,
+        );
+        env::remove_var("SET_ENV_FLAGS_MAVEN_OPTS");
+        assert_eq!(merged.unwrap(), "-Xmx3g -Dmaven.repo.local=/opt/m2");
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("export SET_ENV_FLAGS_MAVEN_OPTS=\"-Xmx3g -Dmaven.repo.local=/opt/m2\"\n"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::runner::{Runner, SystemRunner};
+
+type Hook = Box<dyn Fn(&Path) + Send>;
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+static RUNNER: Mutex<Option<Arc<dyn Runner + Send + Sync>>> = Mutex::new(None);
+
+/// How long the `after_write_command` of the preferences may take.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Registers a callback that is called with the path of every file
+/// this crate modified, once the modification is complete.
+/// Useful for dotfiles kept under version control.
+///
+/// Users can also set a command in the preferences file, see
+/// `set_preferences_file`, which is run with the path of the file as
+/// its last argument. If it can't be run or fails an
+/// `Event::Warning` is emitted, the modification itself stays
+/// successful.
+pub fn after_write<F>(hook: F)
+where
+    F: Fn(&Path) + Send + 'static,
+{
+    HOOKS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Box::new(hook));
+}
+
+/// Sets how the `after_write_command` of the preferences is run.
+/// Defaults to `SystemRunner`.
+pub fn set_hook_runner<R: Runner + Send + Sync + 'static>(runner: R) {
+    *RUNNER.lock().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(runner));
+}
+
+fn runner() -> Arc<dyn Runner + Send + Sync> {
+    RUNNER
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(SystemRunner))
+}
+
+/// Forgets the registered callbacks.
+#[cfg(test)]
+pub(crate) fn clear() {
+    HOOKS.lock().unwrap_or_else(|err| err.into_inner()).clear();
+}
+
+/// Runs the registered hooks for the modified file at `path`.
+pub(crate) fn wrote(path: &Path) {
+    crate::audit::wrote(path);
+    crate::events::emit(crate::events::Event::Wrote {
+        path: path.to_path_buf(),
+    });
+    for hook in HOOKS.lock().unwrap_or_else(|err| err.into_inner()).iter() {
+        hook(path);
+    }
+
+    let Some(command) = crate::preferences::load().after_write_command else {
+        return;
+    };
+    let path_arg = path.to_string_lossy();
+    let args: Vec<&str> = command[1..]
+        .iter()
+        .map(String::as_str)
+        .chain([path_arg.as_ref()])
+        .collect();
+    if let Err(err) = runner().output(&command[0], &args, COMMAND_TIMEOUT) {
+        crate::events::warn(format!(
+            "after write command {} failed for {}: {}",
+            command.join(" "),
+            path.display(),
+            err
+        ));
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::events::{Event, VecSink};
+    use crate::test_support::sandbox;
+    use std::io;
+
+    /// Records the commands it's asked to run, failing them all if
+    /// `fail` is set.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        runs: Arc<Mutex<Vec<Vec<String>>>>,
+        fail: bool,
+    }
+
+    impl Runner for Recorder {
+        fn output(&self, program: &str, args: &[&str], _timeout: Duration) -> io::Result<String> {
+            let run = [program]
+                .into_iter()
+                .chain(args.iter().copied())
+                .map(|it| it.to_string());
+            self.runs.lock().unwrap().push(run.collect());
+            if self.fail {
+                return Err(io::Error::other(format!(
+                    "{} failed: exit status: 1",
+                    program
+                )));
+            }
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn callbacks_run_once_per_modified_file() {
+        let sandbox = sandbox("/bin/bash");
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let recorded = paths.clone();
+        after_write(move |path| recorded.lock().unwrap().push(path.to_path_buf()));
+
+        crate::set("FOO", "1").unwrap();
+        crate::set("BAR", "2").unwrap();
+        assert_eq!(
+            *paths.lock().unwrap(),
+            vec![sandbox.path(".bash_profile"); 2]
+        );
+    }
+
+    #[test]
+    fn the_command_of_the_preferences_gets_the_path() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".config/set_env/preferences",
+            "after_write_command = commit-dotfiles --quiet\n",
+        );
+        let recorder = Recorder::default();
+        set_hook_runner(recorder.clone());
+
+        crate::set("FOO", "1").unwrap();
+        let profile = sandbox.path(".bash_profile");
+        assert_eq!(
+            *recorder.runs.lock().unwrap(),
+            vec![vec![
+                "commit-dotfiles".to_string(),
+                "--quiet".to_string(),
+                profile.to_string_lossy().into_owned()
+            ]]
+        );
+    }
+
+    #[test]
+    fn without_preferences_no_command_runs() {
+        let _sandbox = sandbox("/bin/bash");
+        let recorder = Recorder::default();
+        set_hook_runner(recorder.clone());
+
+        crate::set("FOO", "1").unwrap();
+        assert!(recorder.runs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_failing_command_is_a_warning() {
+        let sandbox = sandbox("/bin/bash");
+        let preferences = sandbox.path("prefs");
+        sandbox.write("prefs", "after_write_command = commit-dotfiles\n");
+        crate::set_preferences_file(Some(preferences));
+        set_hook_runner(Recorder {
+            fail: true,
+            ..Recorder::default()
+        });
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").contains("export FOO=1"));
+        let warnings: Vec<Event> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::Warning { .. }))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().contains("commit-dotfiles failed"));
+    }
+}
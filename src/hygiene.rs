@@ -0,0 +1,410 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use unicode_normalization::char::{canonical_combining_class, compose};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::change::ChangeWarning;
+
+/// The kind of suspicious character found by `check_value`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueIssue {
+    /// Whitespace other than the ASCII space, like a no-break space.
+    Whitespace,
+    /// A character that takes up no space, like a zero-width joiner.
+    Invisible,
+    /// A control character changing the direction of text.
+    Bidi,
+    /// A character that looks like, but isn't, an ASCII character.
+    Confusable,
+    /// A character that is written differently in NFC, the form text
+    /// typed on most systems is in, like an `e` followed by a combining
+    /// accent instead of an `é`. File names with either don't match.
+    Unnormalized,
+}
+
+/// A suspicious character in a value, see `check_value`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ValueWarning {
+    pub issue: ValueIssue,
+    /// Position of the character in the value, counted in chars.
+    pub position: usize,
+    pub character: char,
+    /// The unicode name of the character, of its block for letters
+    /// of other scripts, or what it is for unnormalized ones.
+    pub name: &'static str,
+}
+
+impl fmt::Display for ValueWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "U+{:04X} {} at position {}",
+            self.character as u32, self.name, self.position
+        )
+    }
+}
+
+static WHITESPACE: &[(char, &str)] = &[
+    ('\u{00A0}', "NO-BREAK SPACE"),
+    ('\u{1680}', "OGHAM SPACE MARK"),
+    ('\u{2000}', "EN QUAD"),
+    ('\u{2001}', "EM QUAD"),
+    ('\u{2002}', "EN SPACE"),
+    ('\u{2003}', "EM SPACE"),
+    ('\u{2004}', "THREE-PER-EM SPACE"),
+    ('\u{2005}', "FOUR-PER-EM SPACE"),
+    ('\u{2006}', "SIX-PER-EM SPACE"),
+    ('\u{2007}', "FIGURE SPACE"),
+    ('\u{2008}', "PUNCTUATION SPACE"),
+    ('\u{2009}', "THIN SPACE"),
+    ('\u{200A}', "HAIR SPACE"),
+    ('\u{2028}', "LINE SEPARATOR"),
+    ('\u{2029}', "PARAGRAPH SEPARATOR"),
+    ('\u{202F}', "NARROW NO-BREAK SPACE"),
+    ('\u{205F}', "MEDIUM MATHEMATICAL SPACE"),
+    ('\u{3000}', "IDEOGRAPHIC SPACE"),
+];
+
+static INVISIBLE: &[(char, &str)] = &[
+    ('\u{00AD}', "SOFT HYPHEN"),
+    ('\u{200B}', "ZERO WIDTH SPACE"),
+    ('\u{200C}', "ZERO WIDTH NON-JOINER"),
+    ('\u{200D}', "ZERO WIDTH JOINER"),
+    ('\u{2060}', "WORD JOINER"),
+    ('\u{FEFF}', "ZERO WIDTH NO-BREAK SPACE"),
+];
+
+static BIDI: &[(char, &str)] = &[
+    ('\u{061C}', "ARABIC LETTER MARK"),
+    ('\u{200E}', "LEFT-TO-RIGHT MARK"),
+    ('\u{200F}', "RIGHT-TO-LEFT MARK"),
+    ('\u{202A}', "LEFT-TO-RIGHT EMBEDDING"),
+    ('\u{202B}', "RIGHT-TO-LEFT EMBEDDING"),
+    ('\u{202C}', "POP DIRECTIONAL FORMATTING"),
+    ('\u{202D}', "LEFT-TO-RIGHT OVERRIDE"),
+    ('\u{202E}', "RIGHT-TO-LEFT OVERRIDE"),
+    ('\u{2066}', "LEFT-TO-RIGHT ISOLATE"),
+    ('\u{2067}', "RIGHT-TO-LEFT ISOLATE"),
+    ('\u{2068}', "FIRST STRONG ISOLATE"),
+    ('\u{2069}', "POP DIRECTIONAL ISOLATE"),
+];
+
+static CONFUSABLE: &[(char, &str)] = &[
+    ('\u{2018}', "LEFT SINGLE QUOTATION MARK"),
+    ('\u{2019}', "RIGHT SINGLE QUOTATION MARK"),
+    ('\u{201C}', "LEFT DOUBLE QUOTATION MARK"),
+    ('\u{201D}', "RIGHT DOUBLE QUOTATION MARK"),
+    ('\u{2010}', "HYPHEN"),
+    ('\u{2011}', "NON-BREAKING HYPHEN"),
+    ('\u{2013}', "EN DASH"),
+    ('\u{2014}', "EM DASH"),
+    ('\u{2215}', "DIVISION SLASH"),
+    ('\u{FF0F}', "FULLWIDTH SOLIDUS"),
+];
+
+fn lookup(table: &[(char, &'static str)], c: char) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(candidate, _)| *candidate == c)
+        .map(|(_, name)| *name)
+}
+
+/// Names the script of letters that are commonly mistaken for latin ones.
+fn confusable_script(c: char) -> Option<&'static str> {
+    match c {
+        '\u{0370}'..='\u{03FF}' => Some("GREEK LETTER"),
+        '\u{0400}'..='\u{04FF}' => Some("CYRILLIC LETTER"),
+        '\u{FF01}'..='\u{FF5E}' => Some("FULLWIDTH FORM"),
+        _ => None,
+    }
+}
+
+/// Names `c` if it's written differently in NFC, given the last
+/// character before it that combining marks can be composed with.
+fn unnormalized(starter: Option<char>, c: char) -> Option<&'static str> {
+    if canonical_combining_class(c) != 0 {
+        starter
+            .and_then(|starter| compose(starter, c))
+            .map(|_| "COMBINING MARK")
+    } else if c.to_string().nfc().ne(std::iter::once(c)) {
+        Some("CHARACTER WITH A CANONICAL EQUIVALENT")
+    } else {
+        None
+    }
+}
+
+/// Looks for characters in `value` that are likely pasted by accident
+/// and break the variable while being invisible in the profile:
+/// non-ASCII whitespace, zero-width and bidi control characters,
+/// typographic quotes and dashes, letters of other scripts
+/// mixed with latin ones, and characters that aren't in NFC.
+pub fn check_value(value: &str) -> Vec<ValueWarning> {
+    let has_latin = value.chars().any(|c| c.is_ascii_alphabetic());
+    let mut warnings = Vec::new();
+    let mut starter = None;
+
+    for (position, character) in value.chars().enumerate() {
+        let composed = starter.and_then(|starter| compose(starter, character));
+        let found = if let Some(name) = lookup(WHITESPACE, character) {
+            Some((ValueIssue::Whitespace, name))
+        } else if let Some(name) = lookup(INVISIBLE, character) {
+            Some((ValueIssue::Invisible, name))
+        } else if let Some(name) = lookup(BIDI, character) {
+            Some((ValueIssue::Bidi, name))
+        } else if let Some(name) = lookup(CONFUSABLE, character) {
+            Some((ValueIssue::Confusable, name))
+        } else if let Some(name) = unnormalized(starter, character) {
+            Some((ValueIssue::Unnormalized, name))
+        } else if has_latin {
+            confusable_script(character).map(|name| (ValueIssue::Confusable, name))
+        } else {
+            None
+        };
+        if composed.is_some() {
+            starter = composed;
+        } else if canonical_combining_class(character) == 0 {
+            starter = Some(character);
+        }
+
+        if let Some((issue, name)) = found {
+            warnings.push(ValueWarning {
+                issue,
+                position,
+                character,
+                name,
+            });
+        }
+    }
+
+    warnings
+}
+
+/// Cleans up `value`: non-ASCII whitespace is replaced with a regular
+/// space, zero-width and bidi control characters are removed, and the
+/// rest is normalized to NFC.
+/// Confusable characters are left alone since replacing them could
+/// change the meaning of the value, they are only reported by
+/// `check_value`.
+pub fn sanitize_value(value: &str) -> String {
+    value
+        .chars()
+        .filter(|&c| lookup(INVISIBLE, c).is_none() && lookup(BIDI, c).is_none())
+        .map(|c| {
+            if lookup(WHITESPACE, c).is_some() {
+                ' '
+            } else {
+                c
+            }
+        })
+        .nfc()
+        .collect()
+}
+
+static SANITIZE: AtomicBool = AtomicBool::new(false);
+
+/// Makes `set`, `append` and `prepend` write values cleaned up with
+/// `sanitize_value` instead of as they are given.
+/// Either way, what `check_value` finds in the value that is written is
+/// returned as warnings in the `Change`. Off by default.
+pub fn set_sanitize_values(sanitize: bool) {
+    SANITIZE.store(sanitize, Ordering::SeqCst);
+}
+
+/// Returns the value to write for `value` of `var`, sanitized if
+/// `set_sanitize_values` says so, with the warnings about it.
+pub(crate) fn review(var: &str, value: &str) -> (String, Vec<ChangeWarning>) {
+    let value = if SANITIZE.load(Ordering::SeqCst) {
+        sanitize_value(value)
+    } else {
+        value.to_string()
+    };
+    let warnings = check_value(&value)
+        .into_iter()
+        .map(|warning| ChangeWarning::Value {
+            var: var.to_string(),
+            warning,
+        })
+        .collect();
+    (value, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The issue, position and name of a character `check_value` finds.
+    type Found = (ValueIssue, usize, &'static str);
+
+    /// Values pasted from web pages and documents, with what is found
+    /// in them.
+    static NASTY: &[(&str, &[Found])] = &[
+        ("/opt/tool/bin", &[]),
+        ("/home/jürgen/bin", &[]),
+        ("/usr/local/share/日本語", &[]),
+        ("αβγ", &[]),
+        (
+            "/opt/my\u{00A0}tool",
+            &[(ValueIssue::Whitespace, 7, "NO-BREAK SPACE")],
+        ),
+        (
+            "\u{202F}/opt\u{3000}",
+            &[
+                (ValueIssue::Whitespace, 0, "NARROW NO-BREAK SPACE"),
+                (ValueIssue::Whitespace, 5, "IDEOGRAPHIC SPACE"),
+            ],
+        ),
+        (
+            "/opt/bin\u{200B}",
+            &[(ValueIssue::Invisible, 8, "ZERO WIDTH SPACE")],
+        ),
+        (
+            "\u{FEFF}nvim",
+            &[(ValueIssue::Invisible, 0, "ZERO WIDTH NO-BREAK SPACE")],
+        ),
+        (
+            "a\u{200D}b\u{00AD}c",
+            &[
+                (ValueIssue::Invisible, 1, "ZERO WIDTH JOINER"),
+                (ValueIssue::Invisible, 3, "SOFT HYPHEN"),
+            ],
+        ),
+        (
+            "/opt/\u{202E}nib",
+            &[(ValueIssue::Bidi, 5, "RIGHT-TO-LEFT OVERRIDE")],
+        ),
+        (
+            "\u{2066}x\u{2069}",
+            &[
+                (ValueIssue::Bidi, 0, "LEFT-TO-RIGHT ISOLATE"),
+                (ValueIssue::Bidi, 2, "POP DIRECTIONAL ISOLATE"),
+            ],
+        ),
+        (
+            "\u{201C}/opt/bin\u{201D}",
+            &[
+                (ValueIssue::Confusable, 0, "LEFT DOUBLE QUOTATION MARK"),
+                (ValueIssue::Confusable, 9, "RIGHT DOUBLE QUOTATION MARK"),
+            ],
+        ),
+        (
+            "--color\u{2013}always",
+            &[(ValueIssue::Confusable, 7, "EN DASH")],
+        ),
+        (
+            "/opt\u{2215}bin",
+            &[(ValueIssue::Confusable, 4, "DIVISION SLASH")],
+        ),
+        (
+            "/h\u{043E}me/bin",
+            &[(ValueIssue::Confusable, 2, "CYRILLIC LETTER")],
+        ),
+        (
+            "/opt/b\u{03B9}n",
+            &[(ValueIssue::Confusable, 6, "GREEK LETTER")],
+        ),
+        (
+            "\u{FF4F}pt",
+            &[(ValueIssue::Confusable, 0, "FULLWIDTH FORM")],
+        ),
+        (
+            "/home/je\u{0301}ro\u{0302}me",
+            &[
+                (ValueIssue::Unnormalized, 8, "COMBINING MARK"),
+                (ValueIssue::Unnormalized, 11, "COMBINING MARK"),
+            ],
+        ),
+        (
+            "/opt/\u{212B}ngstrom",
+            &[(
+                ValueIssue::Unnormalized,
+                5,
+                "CHARACTER WITH A CANONICAL EQUIVALENT",
+            )],
+        ),
+        // Combining marks without a precomposed form are in NFC.
+        ("/opt/q\u{0301}", &[]),
+        ("\u{0301}", &[]),
+    ];
+
+    #[test]
+    fn the_catalogue_is_found() {
+        for (value, expected) in NASTY {
+            let found: Vec<Found> = check_value(value)
+                .into_iter()
+                .map(|warning| (warning.issue, warning.position, warning.name))
+                .collect();
+            assert_eq!(found, *expected, "in {:?}", value);
+        }
+    }
+
+    #[test]
+    fn warnings_show_the_code_point() {
+        let warnings = check_value("a\u{00A0}b");
+        assert_eq!(
+            warnings[0].to_string(),
+            "U+00A0 NO-BREAK SPACE at position 1"
+        );
+    }
+
+    #[test]
+    fn sanitizing_cleans_up_and_normalizes() {
+        let cases = [
+            ("/opt/tool/bin", "/opt/tool/bin"),
+            ("/opt/my\u{00A0}tool", "/opt/my tool"),
+            ("\u{2003}a\u{2009}b\u{3000}", " a b "),
+            ("\u{FEFF}/opt/bin\u{200B}", "/opt/bin"),
+            ("/opt/\u{202E}nib\u{202C}", "/opt/nib"),
+            ("/home/je\u{0301}rome", "/home/j\u{00E9}rome"),
+            ("/opt/\u{212B}", "/opt/\u{00C5}"),
+            ("e\u{200B}\u{0301}", "\u{00E9}"),
+            ("\u{201C}quoted\u{201D}", "\u{201C}quoted\u{201D}"),
+            ("/h\u{043E}me", "/h\u{043E}me"),
+        ];
+        for (value, expected) in cases {
+            let sanitized = sanitize_value(value);
+            assert_eq!(sanitized, expected, "for {:?}", value);
+            assert_eq!(sanitize_value(&sanitized), sanitized);
+            assert!(check_value(&sanitized)
+                .iter()
+                .all(|warning| warning.issue == ValueIssue::Confusable));
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn writes_warn_about_the_value_without_changing_it() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        let change = crate::set("TOOL_HOME", "/opt/my\u{00A0}tool").unwrap();
+        assert_eq!(change.warnings.len(), 1);
+        assert_eq!(
+            change.warnings[0].to_string(),
+            "the value of TOOL_HOME has U+00A0 NO-BREAK SPACE at position 7"
+        );
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("/opt/my\u{00A0}tool"));
+
+        let change = crate::append("PATH", "/opt/bin\u{200B}").unwrap();
+        assert!(matches!(
+            &change.warnings[..],
+            [ChangeWarning::Value { var, warning }]
+                if var == "PATH" && warning.issue == ValueIssue::Invisible
+        ));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn sanitize_mode_writes_the_cleaned_up_value() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        set_sanitize_values(true);
+        let change = crate::set("TOOL_HOME", "/opt/my\u{00A0}tool").unwrap();
+        assert_eq!(change.warnings, Vec::new());
+        crate::prepend("PATH", "/home/je\u{0301}rome/bin").unwrap();
+
+        let profile = sandbox.read(".bash_profile");
+        assert!(profile.contains("/opt/my tool"));
+        assert!(profile.contains("/home/j\u{00E9}rome/bin"));
+        assert!(!profile.contains('\u{00A0}') && !profile.contains('\u{0301}'));
+    }
+}
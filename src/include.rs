@@ -0,0 +1,316 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::syntax::SyntaxInfo;
+
+/// Which include file a definition goes to.
+/// Definitions in include files are sourced by the profile instead of
+/// being written to it, so they can be shared between users.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    /// `~/.config/set_env/env.sh`, sourced by the user's profile.
+    User,
+    /// `/etc/set_env/env.sh`, in POSIX syntax, sourced for every user
+    /// through `/etc/profile.d/set_env.sh`, before the user's profile
+    /// and so before `Scope::User`.
+    /// `/etc` is the system configuration directory of the platform,
+    /// see `Platform`; where there is none this scope is unsupported.
+    System,
+}
+
+/// Returns the include file of `scope`.
+pub fn include_path(scope: Scope) -> io::Result<PathBuf> {
+    let _operation = crate::events::operation();
+    match scope {
+        Scope::User => {
+            let (home_dir, _) = crate::home_and_shell()?;
+            Ok(home_dir.join(".config/set_env/env.sh"))
+        }
+        Scope::System => crate::platform().system_file("set_env/env.sh"),
+    }
+}
+
+/// Does the same as `set`, writing to the include file of `scope`.
+pub fn set_in<T: fmt::Display, U: fmt::Display>(scope: Scope, var: T, value: U) -> io::Result<()> {
+    let _operation = crate::events::operation();
+    crate::startup_cost::warn_value(&var.to_string(), &value.to_string())?;
+    let line = syntax(scope)?.set_line(var, value);
+    write_line(scope, &line)
+}
+
+/// Does the same as `append`, writing to the include file of `scope`.
+pub fn append_in<T: fmt::Display>(scope: Scope, var: T, value: T) -> io::Result<crate::Change> {
+    let _operation = crate::events::operation();
+    let mut change = crate::Change::default();
+    change
+        .warnings
+        .extend(crate::var_kind::check_list(&var.to_string())?);
+    change.warnings.extend(crate::startup_cost::check_value(
+        &var.to_string(),
+        &value.to_string(),
+    )?);
+    let line = syntax(scope)?.append_line(var, value);
+    write_line(scope, &line)?;
+    Ok(change)
+}
+
+/// Does the same as `prepend`, writing to the include file of `scope`.
+pub fn prepend_in<T: fmt::Display>(scope: Scope, var: T, value: T) -> io::Result<crate::Change> {
+    let _operation = crate::events::operation();
+    let mut change = crate::Change::default();
+    change
+        .warnings
+        .extend(crate::var_kind::check_list(&var.to_string())?);
+    change.warnings.extend(crate::startup_cost::check_value(
+        &var.to_string(),
+        &value.to_string(),
+    )?);
+    let line = syntax(scope)?.prepend_line(var, value);
+    write_line(scope, &line)?;
+    Ok(change)
+}
+
+/// Removes the lines assigning `var` from the include file of `scope`.
+/// Returns how many lines were removed.
+pub fn remove_in<T: fmt::Display>(scope: Scope, var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    crate::remove_lines(&include_path(scope)?, |line| {
+        crate::simulate::parse_var_value(line.trim()).is_some_and(|(assigned, _)| assigned == var)
+    })
+}
+
+/// Returns the variables and unevaluated values the include file of
+/// `scope` assigns, in file order.
+pub fn list_in(scope: Scope) -> io::Result<Vec<(String, String)>> {
+    let _operation = crate::events::operation();
+    let content = match crate::filesystem::current().read_to_string(&include_path(scope)?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| crate::simulate::parse_var_value(line.trim()))
+        .map(|(var, value)| (var.to_string(), value.to_string()))
+        .collect())
+}
+
+/// Returns what both include files assign, in the order the shell
+/// runs them: `Scope::System` first.
+/// An unreadable system include file is left out, so users can still
+/// list their own definitions.
+pub fn list_included() -> io::Result<Vec<(Scope, String, String)>> {
+    let _operation = crate::events::operation();
+    let system = match list_in(Scope::System) {
+        Ok(system) => system,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let user = list_in(Scope::User)?;
+
+    Ok(system
+        .into_iter()
+        .map(|(var, value)| (Scope::System, var, value))
+        .chain(
+            user.into_iter()
+                .map(|(var, value)| (Scope::User, var, value)),
+        )
+        .collect())
+}
+
+/// The system include file is sourced by every POSIX login shell,
+/// whatever the shell of the user writing it.
+fn syntax(scope: Scope) -> io::Result<SyntaxInfo> {
+    match scope {
+        Scope::User => crate::profile_syntax(),
+        Scope::System => Ok(SyntaxInfo::POSIX),
+    }
+}
+
+fn write_line(scope: Scope, line: &str) -> io::Result<()> {
+    let include = include_path(scope)?;
+    add_line(&include, line)?;
+
+    let source_line = syntax(scope)?.source_line(include.display());
+    match scope {
+        Scope::User => {
+            if !crate::profile_contains(&source_line)? {
+                crate::write_to_profile(&source_line)?;
+            }
+        }
+        Scope::System => {
+            let platform = crate::platform();
+            let loader = platform.system_file("profile.d/set_env.sh")?;
+            if platform.profile_d && loader.parent().is_some_and(Path::is_dir) {
+                add_line(&loader, &source_line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds `line` to the end of the file at `path` unless it's there already.
+fn add_line(path: &Path, line: &str) -> io::Result<()> {
+    let contains = |content: &str| content.lines().any(|it| it.trim() == line);
+    let fs = crate::filesystem::current();
+    match fs.read_to_string(path) {
+        Ok(content) if contains(&content) => return Ok(()),
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    if let Some(dir) = path.parent() {
+        if !fs.exists(dir) && !crate::is_dry_run() {
+            fs.create_dir_all(dir)?;
+        }
+    }
+    crate::edit_file(path, |content| {
+        if contains(content) {
+            return Ok((content.to_string(), ()));
+        }
+        let mut content = content.to_string();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(line);
+        content.push('\n');
+        Ok((content, ()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::{FileMetadata, Filesystem, MemoryFilesystem};
+    use crate::test_support::sandbox;
+
+    const EDITOR: &str = "SET_ENV_INCLUDED_EDITOR";
+
+    /// Refuses to read the system include file, like one readable only
+    /// by root.
+    struct UnreadableSystem(MemoryFilesystem);
+
+    impl Filesystem for UnreadableSystem {
+        fn read_to_string(&self, path: &Path) -> io::Result<String> {
+            if path == include_path(Scope::System)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is readable only by root", path.display()),
+                ));
+            }
+            self.0.read_to_string(path)
+        }
+        fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+            self.0.write(path, content)
+        }
+        fn append(&self, path: &Path, content: &str) -> io::Result<()> {
+            self.0.append(path, content)
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.0.exists(path)
+        }
+        fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+            self.0.metadata(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.0.create_dir_all(path)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.0.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.0.remove_file(path)
+        }
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.0.read_dir(path)
+        }
+    }
+
+    fn listed(entries: &[(Scope, &str)]) -> Vec<(Scope, String, String)> {
+        entries
+            .iter()
+            .map(|(scope, value)| (*scope, EDITOR.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn both_include_files_are_listed_in_the_order_they_run() {
+        let sandbox = sandbox("/bin/bash");
+        // The system include file goes to /etc, which stays untouched.
+        let memory = sandbox.memory();
+        set_in(Scope::User, EDITOR, "vim").unwrap();
+        set_in(Scope::System, EDITOR, "vi").unwrap();
+        set_in(Scope::User, "SET_ENV_INCLUDED_PAGER", "less").unwrap();
+
+        assert_eq!(
+            list_in(Scope::System).unwrap(),
+            [(EDITOR.to_string(), "vi".to_string())]
+        );
+        assert_eq!(
+            list_in(Scope::User).unwrap(),
+            [
+                (EDITOR.to_string(), "vim".to_string()),
+                ("SET_ENV_INCLUDED_PAGER".to_string(), "less".to_string()),
+            ]
+        );
+        let mut expected = listed(&[(Scope::System, "vi"), (Scope::User, "vim")]);
+        expected.push((
+            Scope::User,
+            "SET_ENV_INCLUDED_PAGER".to_string(),
+            "less".to_string(),
+        ));
+        assert_eq!(list_included().unwrap(), expected);
+
+        let user = include_path(Scope::User).unwrap();
+        let source_line = format!(". \"{}\"", user.display());
+        let profile = memory.get(sandbox.path(".bash_profile")).unwrap();
+        assert_eq!(profile.matches(&source_line).count(), 1, "{}", profile);
+        assert_eq!(
+            memory.get("/etc/set_env/env.sh").unwrap(),
+            format!("export {}=vi\n", EDITOR)
+        );
+        if Path::new("/etc/profile.d").is_dir() {
+            assert_eq!(
+                memory.get("/etc/profile.d/set_env.sh").unwrap(),
+                ". \"/etc/set_env/env.sh\"\n"
+            );
+        }
+
+        assert_eq!(remove_in(Scope::User, EDITOR).unwrap(), 1);
+        assert_eq!(
+            list_included().unwrap(),
+            [
+                (Scope::System, EDITOR.to_string(), "vi".to_string()),
+                (
+                    Scope::User,
+                    "SET_ENV_INCLUDED_PAGER".to_string(),
+                    "less".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unreadable_system_include_file_is_left_out() {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        memory.insert("/etc/set_env/env.sh", &format!("export {}=vi\n", EDITOR));
+        crate::set_filesystem(UnreadableSystem(memory));
+        set_in(Scope::User, EDITOR, "vim").unwrap();
+
+        let err = list_in(Scope::System).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(list_included().unwrap(), listed(&[(Scope::User, "vim")]));
+    }
+
+    #[test]
+    fn missing_include_files_list_nothing() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.memory();
+        assert_eq!(list_in(Scope::User).unwrap(), []);
+        assert_eq!(list_included().unwrap(), []);
+    }
+}
@@ -6,102 +6,521 @@
 //! // export DUMMY=1
 //! set_env_perm::check_or_set("DUMMY", 1).expect("Failed to find or set DUMMY");
 //! // Append $HOME/some/cool/bin to $PATH
-//! // export PATH= "$HOME/some/cool/bin:$PATH"
+//! // export PATH="$PATH:$HOME/some/cool/bin"
 //! set_env_perm::append("PATH", "$HOME/some/cool/bin").expect("Couldn't find PATH");
 //! // Sets a variable without checking if it exists.
 //! // Note you need to use a raw string literal to include ""
 //! // export DUMMY="/something"
 //! set_env_perm::set("DUMMY", r#""/something""#).expect("Failed to set DUMMY");
 //! ```
+//!
+//! The `prelude` has the same operations named for what they guarantee.
 
-#[cfg(target_family = "unix")]
-use dirs;
-#[cfg(target_family = "unix")]
-use std::fs::{File, OpenOptions};
-#[cfg(target_family = "unix")]
-use std::io::Write;
-#[cfg(target_family = "unix")]
-use std::path::PathBuf;
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
 
 use std::env;
 use std::env::VarError;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 
-#[cfg(target_family = "windows")]
-pub fn do_prerequisites() {
-    use std::fs;
+mod audit;
+mod backend;
+#[cfg(target_family = "unix")]
+mod block;
+mod change;
+mod compare;
+mod condition;
+mod conflict;
+#[cfg(target_family = "unix")]
+mod desktop;
+mod dry_run;
+mod events;
+mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filesystem;
+mod flags;
+mod hooks;
+mod hygiene;
+#[cfg(target_family = "unix")]
+mod include;
+#[cfg(target_family = "unix")]
+mod line_endings;
+pub mod linux;
+#[cfg(target_family = "unix")]
+mod list_strategy;
+#[cfg(target_family = "unix")]
+mod managed;
+#[cfg(target_family = "unix")]
+mod metadata;
+mod nonempty;
+#[cfg(target_family = "unix")]
+mod noninteractive;
+#[cfg(target_family = "unix")]
+mod ownership;
+mod path_entries;
+mod per_os;
+#[cfg(target_family = "unix")]
+mod placement;
+#[cfg(target_family = "unix")]
+mod platform;
+mod preferences;
+pub mod prelude;
+#[cfg(target_family = "unix")]
+mod profile_file;
+#[cfg(all(test, target_family = "unix"))]
+mod proptests;
+mod quick;
+mod raw_line;
+mod readonly;
+mod reexec;
+#[cfg(target_os = "windows")]
+mod relocate;
+mod runner;
+mod simulate;
+mod source_chain;
+mod startup_cost;
+#[cfg(target_family = "unix")]
+mod sudo;
+mod syntax;
+#[cfg(test)]
+mod test_support;
+#[cfg(target_family = "unix")]
+mod toggle;
+mod usage;
+mod var_kind;
+mod verify;
+#[cfg(target_os = "windows")]
+pub mod windows;
+mod write_limit;
+#[cfg(target_family = "unix")]
+mod write_mode;
+#[cfg(target_family = "unix")]
+mod zsh;
+
+pub use audit::{set_audit_log, AuditLog};
+pub use backend::{set_backend, Backend};
+#[cfg(target_family = "unix")]
+pub use block::{repair, RepairAction};
+pub use change::{Change, ChangeWarning};
+pub use compare::{compare, EnvDiff, ListDiff};
+pub use condition::{condition_of, set_when, Condition};
+pub use conflict::{ignore_conflicts, ProfileInConflict};
+#[cfg(target_family = "unix")]
+pub use desktop::{
+    desktop_mechanism, detect_desktop, list_desktop, set_desktop, unset_desktop, Desktop,
+    DesktopMechanism,
+};
+pub use dry_run::{drain_planned_changes, is_dry_run, set_dry_run, PlannedChange};
+pub use events::{set_event_sink, Event, EventSink, VecSink};
+pub use explain::{explain, Decision, Explanation};
+#[cfg(feature = "testing")]
+pub use filesystem::MemoryFilesystem;
+pub use filesystem::{set_filesystem, FileMetadata, Filesystem, StdFilesystem};
+pub use flags::{merge_flags, FlagStyle};
+pub use hooks::{after_write, set_hook_runner};
+pub use hygiene::{check_value, sanitize_value, set_sanitize_values, ValueIssue, ValueWarning};
+#[cfg(target_family = "unix")]
+pub use include::{
+    append_in, include_path, list_in, list_included, prepend_in, remove_in, set_in, Scope,
+};
+#[cfg(target_family = "unix")]
+pub use list_strategy::{list_strategy, remove_from, set_list_strategy, ListStrategy};
+#[cfg(target_family = "unix")]
+pub use managed::{
+    list_managed, managed_duplicates, unset, DefinitionKind, Duplicate, ManagedDefinition,
+};
+#[cfg(target_family = "unix")]
+pub use metadata::{
+    metadata_of, set_metadata, set_metadata_format, DefaultMetadataFormat, Metadata, MetadataFormat,
+};
+pub use nonempty::check_or_set_nonempty;
+#[cfg(target_family = "unix")]
+pub use noninteractive::{set_noninteractive, unset_noninteractive, NonInteractive};
+#[cfg(target_family = "unix")]
+pub use ownership::{adopt, unmanage};
+pub use path_entries::{path_entries, PathEntries, PathEntry};
+pub use per_os::{append_per_os, prepend_per_os, set_per_os, PerOs, PerOsOutcome};
+#[cfg(target_family = "unix")]
+pub use placement::{append_with, prepend_with, Placement, PlacementDecision};
+#[cfg(target_family = "unix")]
+pub use platform::{platform, Platform};
+pub use preferences::set_preferences_file;
+#[cfg(target_family = "unix")]
+pub use profile_file::{set_dangling_link_policy, DanglingLinkPolicy, ProfileIsDirectory};
+pub use quick::{quick, Quick, Summary};
+pub use raw_line::{ensure_line, remove_line};
+pub use readonly::which_profile_readonly;
+#[cfg(target_family = "unix")]
+pub use readonly::{list_managed_readonly, status_readonly, Status};
+pub use reexec::{command_with_managed_env, managed_env, reexec_with_env};
+#[cfg(target_os = "windows")]
+pub use relocate::set_relocatable_paths;
+pub use runner::{Runner, SystemRunner};
+pub use simulate::simulate;
+pub use source_chain::SourceStep;
+pub use startup_cost::{
+    allow_command_substitution, line_cost, set_strict_command_substitution, startup_cost,
+    CostClass, StartupCost,
+};
+#[cfg(target_family = "unix")]
+pub use sudo::{
+    set_passwd_resolver, set_sudo_policy, PasswdEntry, PasswdFile, PasswdResolver, SudoPolicy,
+};
+pub use syntax::{AssignmentStyle, ListStyle, QuotingStyle, SyntaxInfo};
+#[cfg(target_family = "unix")]
+pub use toggle::{disable, enable, set_disabled};
+pub use usage::{set_usage_stats, stats, VarStats};
+pub use var_kind::{register_list_var, set_strict_lists, var_kind, VarKind};
+pub use verify::set_strict_verify;
+pub use write_limit::{
+    batch, set_write_limit, write_count, WriteLimitExceeded, DEFAULT_WRITE_LIMIT,
+};
+#[cfg(target_family = "unix")]
+pub use write_mode::{network_filesystem, set_write_mode, NetworkFilesystem, WriteMode};
+#[cfg(target_family = "unix")]
+pub use zsh::{set_zsh_policy, ZshPolicy};
+
+use readonly::Access;
+
+/// Marks the start of the block of definitions managed by this crate.
+const DEFS_BEG: &str = "# ----------------------------------SET_ENV_DEFS_BEG";
+/// Marks the end of the block of definitions managed by this crate.
+const DEFS_END: &str = "# ----------------------------------SET_ENV_DEFS_END";
 
-    let path = dirs::document_dir();
-    if let Some(path) = path {
-        let pkg_version = env!("CARGO_PKG_VERSION");
+/// Checks that the profile at `path` may be written to, failing once
+/// the write limit for that profile has been reached, or if the profile
+/// is in a sync conflict, see `ignore_conflicts`.
+fn record_write(path: &Path) -> io::Result<()> {
+    conflict::check(path)?;
+    write_limit::check(path).map_err(events::error)?;
+    audit::before_write(path);
+    events::emit(events::Event::Writing {
+        path: path.to_path_buf(),
+    });
+    Ok(())
+}
 
-        let template = include_str!("../scripts/profile.ps1").replace("${VER}", pkg_version);
-        let path = path.join("WindowsPowerShell");
-        if !path.exists() {
-            fs::create_dir_all(&path).unwrap();
+/// Counts the completed write to the profile at `path` against the
+/// write limit and runs the hooks for it.
+fn wrote(path: &Path) {
+    write_limit::count(path);
+    hooks::wrote(path);
+}
+
+/// The version of the helper functions in `scripts/profile.ps1`.
+/// Raise it whenever they change: profiles with an older one are
+/// upgraded, ones with a newer one are left to the newer crate, so
+/// programs embedding different versions of this crate don't fight.
+#[cfg(target_os = "windows")]
+const TEMPLATE_FORMAT: u32 = 1;
+#[cfg(any(target_os = "windows", test))]
+const FORMAT_PREFIX: &str = "# ----------------------------------FORMAT";
+#[cfg(any(target_os = "windows", test))]
+const TEMPLATE_BEG: &str = "# ----------------------------------SET_ENV_BEG";
+#[cfg(any(target_os = "windows", test))]
+const TEMPLATE_END: &str = "# ----------------------------------SET_ENV_END";
+
+/// Creates the PowerShell profile with the helpers this crate writes
+/// lines for, or upgrades them when they were written by a crate with
+/// an older `TEMPLATE_FORMAT`.
+#[cfg(target_family = "windows")]
+pub fn do_prerequisites() -> io::Result<()> {
+    let fs = filesystem::current();
+    let path = profile_path().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("PowerShell profile path can't be found: {}", err),
+        )
+    })?;
+
+    let template =
+        include_str!("../scripts/profile.ps1").replace("${FORMAT}", &TEMPLATE_FORMAT.to_string());
+    if let Some(dir) = path.parent() {
+        if !fs.exists(dir) {
+            fs.create_dir_all(dir).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("Cannot create {}: {}", dir.display(), err),
+                )
+            })?;
         }
-        let path = path.join("Profile.ps1");
-        if !path.exists() {
-            fs::write(&path, template).unwrap();
-        } else {
-            let prefix = "# ----------------------------------VER";
-            let content = fs::read_to_string(&path).unwrap();
+    }
+    if !fs.exists(&path) {
+        fs.write(&path, &template)?;
+        return verify::written(&path, &template);
+    }
 
-            let mut lines = content.lines();
+    let content = fs.read_to_string(&path)?;
+    if let Some(upgraded) = upgrade_template(&content, &template, TEMPLATE_FORMAT) {
+        filesystem::replace(&path, &upgraded)?;
+        verify::written(&path, &upgraded)?;
+    }
+    Ok(())
+}
 
-            let pos = lines.position(|it| it.starts_with(prefix));
+/// Returns `content` with the helpers replaced by `template` if they
+/// have an older format than `format`, or `template` added if there
+/// are none, or `None` if they are up to date or newer.
+/// The definitions between the block markers and everything outside
+/// the helpers are kept verbatim. Helpers written before there were
+/// formats have the crate version instead and count as format 0.
+#[cfg(any(target_os = "windows", test))]
+fn upgrade_template(content: &str, template: &str, format: u32) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let position = |marker: &str| {
+        lines
+            .iter()
+            .position(|it| it.trim_end_matches(['\r', '\n']).trim() == marker)
+    };
 
-            if let Some(pos) = pos {
-                let content = lines.nth(pos).unwrap().replace(prefix, "");
-                if content != pkg_version {
-                    fs::write(&path, template).unwrap();
-                }
-            } else {
-                fs::write(&path, template).unwrap();
-            }
+    let Some(beg) = position(TEMPLATE_BEG) else {
+        let mut upgraded = content.to_string();
+        if !upgraded.is_empty() && !upgraded.ends_with('\n') {
+            upgraded.push('\n');
         }
-        return;
+        upgraded.push_str(template);
+        return Some(upgraded);
+    };
+    let current = lines
+        .iter()
+        .find_map(|it| it.trim().strip_prefix(FORMAT_PREFIX))
+        .map_or(Some(0), |it| it.parse::<u32>().ok());
+    // A format that can't be read was written by a crate this one
+    // doesn't know, so it's treated as newer.
+    if current.is_none_or(|current| current >= format) {
+        return None;
     }
 
-    eprintln!("document path is not exists");
-    std::process::exit(1);
+    let end = position(TEMPLATE_END)
+        .filter(|end| *end > beg)
+        .map_or(lines.len(), |end| end + 1);
+    let defs: String = match (position(DEFS_BEG), position(DEFS_END)) {
+        (Some(defs_beg), Some(defs_end)) if defs_beg < defs_end => {
+            lines[defs_beg + 1..defs_end].concat()
+        }
+        (Some(defs_beg), None) => lines[defs_beg + 1..end].concat(),
+        _ => String::new(),
+    };
+
+    let template = template.replace(DEFS_END, &format!("{}{}", defs, DEFS_END));
+    let mut upgraded = lines[..beg].concat();
+    upgraded.push_str(&template);
+    let after = lines[end..].concat();
+    if !after.is_empty() {
+        if !upgraded.ends_with('\n') {
+            upgraded.push('\n');
+        }
+        upgraded.push_str(&after);
+    }
+    Some(upgraded)
 }
 
 #[cfg(target_os = "windows")]
 pub fn inject(it: &str) -> io::Result<()> {
-    use std::fs;
+    let _operation = events::operation();
+    let fs = filesystem::current();
+    let profile_path = profile_path()?;
 
-    do_prerequisites();
+    if dry_run::plan(|| PlannedChange::AddLine {
+        path: profile_path.clone(),
+        line: it.to_string(),
+    }) {
+        let staged = fs.read_to_string(&profile_path).ok();
+        if let Some(content) = staged
+            .as_deref()
+            .and_then(|content| insert_before_end(content, it))
+        {
+            backend::stage(&profile_path, &content);
+        }
+        return Ok(());
+    }
+
+    do_prerequisites()?;
 
-    let profile_path = dirs::document_dir()
-        .unwrap()
-        .join("WindowsPowerShell/Profile.ps1");
+    record_write(&profile_path)?;
 
-    let content = fs::read_to_string(&profile_path)?;
-    let mut content_parts: Vec<&str> = content.split("\r\n").collect();
+    let content = fs.read_to_string(&profile_path)?;
+    let content = insert_before_end(&content, it).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no SET_ENV_DEFS_END line", profile_path.display()),
+        )
+    })?;
 
-    let idx = content_parts
+    filesystem::replace(&profile_path, &content)?;
+    verify::written(&profile_path, &content)?;
+
+    wrote(&profile_path);
+    Ok(())
+}
+
+/// Inserts `line` before the end marker in `content`, keeping the line
+/// endings of the existing lines, which editors and git may have changed
+/// from CRLF to LF. The new line gets the ending most lines have.
+/// Returns `None` if there is no end marker.
+#[cfg(any(target_os = "windows", test))]
+fn insert_before_end(content: &str, line: &str) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let idx = lines
         .iter()
-        .position(|it| it == &"# ----------------------------------SET_ENV_DEFS_END")
-        .unwrap();
-    content_parts.insert(idx, it);
+        .position(|it| it.trim_end_matches(['\r', '\n']).trim() == DEFS_END)?;
+
+    let crlf = lines.iter().filter(|it| it.ends_with("\r\n")).count();
+    let lf = lines.iter().filter(|it| it.ends_with('\n')).count() - crlf;
+    let ending = if lf > crlf { "\n" } else { "\r\n" };
+
+    let mut result = String::with_capacity(content.len() + line.len() + ending.len());
+    for (pos, it) in lines.iter().enumerate() {
+        if pos == idx {
+            result.push_str(line);
+            result.push_str(ending);
+        }
+        result.push_str(it);
+    }
+    Some(result)
+}
 
-    fs::write(profile_path, content_parts.join("\r\n"))
+/// Returns the PowerShell profile, which never creates anything, the
+/// profile is created by `do_prerequisites`.
+#[cfg(target_os = "windows")]
+fn profile_path_with(_access: Access) -> io::Result<PathBuf> {
+    windows::profile_location().map(|(path, _)| path)
 }
 
 /// Checks if a environment variable is set.
 /// If it is then nothing will happen.
 /// If it's not then it will be added
 /// to your profile.
+/// `prelude::ensure` does the same and tells what it found.
+/// Warnings about the change are emitted as `Event::Warning`s.
 pub fn check_or_set<T, U>(var: T, value: U) -> io::Result<()>
 where
     T: fmt::Display + AsRef<std::ffi::OsStr>,
     U: fmt::Display,
 {
-    env::var(&var).map(|_| ()).or_else(|_| set(var, value))
+    let _operation = events::operation();
+    if env::var(&var).is_ok() {
+        events::skipped(&var.to_string(), "set in the current process");
+        return Ok(());
+    }
+    set(var, value).map(Change::emit_warnings)
+}
+
+/// What `check_or_set_detailed` found or did.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SetOutcome {
+    /// The variable already existed, so nothing was written.
+    Skipped {
+        source: ExistingSource,
+        value: Option<String>,
+    },
+    /// The variable was written to the profile.
+    Written,
+    /// Definitions setting the variable to an empty or whitespace-only
+    /// value were replaced in place, see `check_or_set_nonempty`.
+    Repaired { sources: Vec<ExistingSource> },
+}
+
+/// Where an existing definition of a variable was found.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ExistingSource {
+    /// The environment of the current process.
+    ProcessEnv,
+    /// The block of definitions managed by this crate in the profile.
+    ManagedEntry(PathBuf),
+    /// A line written by the user in the profile, numbered from 1.
+    UserLine(PathBuf, usize),
+    /// A line in a file sourced by the profile, numbered from 1, with
+    /// the lines sourcing it, starting in the profile.
+    Sourced {
+        chain: Vec<SourceStep>,
+        path: PathBuf,
+        line_no: usize,
+    },
+    /// The persistent user variables in the registry, on Windows.
+    Registry,
+}
+
+/// Does the same as `check_or_set` but also looks for the variable
+/// in the profile and the files it sources, like `. ~/.bash_aliases`,
+/// and tells where an existing definition was found.
+pub fn check_or_set_detailed<T, U>(var: T, value: U) -> io::Result<SetOutcome>
+where
+    T: fmt::Display + AsRef<std::ffi::OsStr>,
+    U: fmt::Display,
+{
+    let _operation = events::operation();
+    if let Ok(existing) = env::var(&var) {
+        events::skipped(&var.to_string(), "set in the current process");
+        return Ok(SetOutcome::Skipped {
+            source: ExistingSource::ProcessEnv,
+            value: Some(existing),
+        });
+    }
+
+    if let Some(definition) = find_definition(&var.to_string())? {
+        events::skipped(&var.to_string(), "assigned in the profile");
+        return Ok(SetOutcome::Skipped {
+            source: definition.source(),
+            value: Some(definition.value),
+        });
+    }
+
+    set(var, value)?;
+    Ok(SetOutcome::Written)
+}
+
+/// An assignment to a variable found in the profile or a file it
+/// sources.
+struct Definition {
+    path: PathBuf,
+    line_no: usize,
+    value: String,
+    managed: bool,
+    /// The lines sourcing `path`, empty if it's the profile.
+    chain: Vec<SourceStep>,
+}
+
+impl Definition {
+    fn source(&self) -> ExistingSource {
+        if !self.chain.is_empty() {
+            ExistingSource::Sourced {
+                chain: self.chain.clone(),
+                path: self.path.clone(),
+                line_no: self.line_no,
+            }
+        } else if self.managed {
+            ExistingSource::ManagedEntry(self.path.clone())
+        } else {
+            ExistingSource::UserLine(self.path.clone(), self.line_no)
+        }
+    }
+}
+
+/// Finds the last assignment to `var` in the profile and the files it
+/// sources, which is the one that takes effect.
+fn find_definition(var: &str) -> io::Result<Option<Definition>> {
+    let mut found = None;
+    source_chain::for_each_line(&profile_path_for(var)?, &mut |line| {
+        if let Some((assigned, value)) = simulate::parse_var_value(line.text.trim()) {
+            if assigned == var {
+                found = Some(Definition {
+                    path: line.path.to_path_buf(),
+                    line_no: line.line_no,
+                    value: value.to_string(),
+                    managed: line.managed,
+                    chain: line.chain.to_vec(),
+                });
+            }
+        }
+    })?;
+    Ok(found)
 }
 
+/// Returns the value of `var` in the environment of the current
+/// process, which `Backend::ProcessOnly` changes as well.
 pub fn get<T: fmt::Display>(var: T) -> io::Result<String> {
     env::var(var.to_string()).map_err(|err| match err {
         VarError::NotPresent => io::Error::new(io::ErrorKind::NotFound, "Variable not present."),
@@ -111,35 +530,386 @@ pub fn get<T: fmt::Display>(var: T) -> io::Result<String> {
     })
 }
 
+/// Separator between the entries of list variables like PATH.
+#[cfg(target_family = "unix")]
+const LIST_SEPARATOR: char = ':';
+/// Separator between the entries of list variables like PATH.
+#[cfg(target_os = "windows")]
+const LIST_SEPARATOR: char = ';';
+
+/// What `check_or_append` and `check_or_prepend` found or did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListOutcome {
+    /// The value already is an entry of the variable.
+    Present,
+    /// The value is not an entry of the variable yet, but the profile
+    /// already adds it, so new shells will have it.
+    Pending,
+    /// The value was written to the profile.
+    Written,
+}
+
 /// Appends a value to an environment variable
 /// Useful for appending a value to PATH
+/// The line is written as the variable's `ListStrategy` says.
+/// Appending to a variable that usually holds a single value is warned
+/// about in the returned `Change`, see `set_strict_lists`, as are
+/// characters likely pasted by accident, see `set_sanitize_values`.
 #[cfg(target_family = "unix")]
-pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
-    let mut profile = get_profile()?;
-    writeln!(profile, "\nexport {}=\"{}:${}\"", var, value, var)?;
-    profile.flush()
+pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let mut change = Change::default();
+    change.warnings.extend(var_kind::check_list(&var)?);
+    let (value, warnings) = hygiene::review(&var, &value.to_string());
+    change.warnings.extend(warnings);
+    change
+        .warnings
+        .extend(startup_cost::check_value(&var, &value)?);
+    match list_strategy::list_strategy(&var)? {
+        ListStrategy::PerEntry => write_to_profile(&append_line(&var, &value)?)?,
+        ListStrategy::Composed => list_strategy::compose(&var, &value, true)?,
+    }
+    changed(&var, backend::Update::Append(&value));
+    Ok(change)
 }
 /// Appends a value to an environment variable
 /// Useful for appending a value to PATH
+/// Appending to a variable that usually holds a single value is warned
+/// about in the returned `Change`, see `set_strict_lists`, as are
+/// characters likely pasted by accident, see `set_sanitize_values`.
 #[cfg(target_os = "windows")]
-pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
-    inject(format!("setenv_append {} {}", var, value).as_str())
+pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let mut change = Change::default();
+    change.warnings.extend(var_kind::check_list(&var)?);
+    let (value, warnings) = hygiene::review(&var, &value.to_string());
+    change.warnings.extend(warnings);
+    change
+        .warnings
+        .extend(startup_cost::check_value(&var, &value)?);
+    inject(&append_line(&var, &value)?)?;
+    changed(&var, backend::Update::Append(&value));
+    Ok(change)
 }
 
 /// Prepends a value to an environment variable
 /// Useful for prepending a value to PATH
+/// The line is written as the variable's `ListStrategy` says.
+/// Prepending to a variable that usually holds a single value is warned
+/// about in the returned `Change`, see `set_strict_lists`, as are
+/// characters likely pasted by accident, see `set_sanitize_values`.
 #[cfg(target_family = "unix")]
-pub fn prepend<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
-    let mut profile = get_profile()?;
-    writeln!(profile, "\nexport {}=\"${}:{}\"", var, value, var)?;
-    profile.flush()
+pub fn prepend<T: fmt::Display>(var: T, value: T) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let mut change = Change::default();
+    change.warnings.extend(var_kind::check_list(&var)?);
+    let (value, warnings) = hygiene::review(&var, &value.to_string());
+    change.warnings.extend(warnings);
+    change
+        .warnings
+        .extend(startup_cost::check_value(&var, &value)?);
+    match list_strategy::list_strategy(&var)? {
+        ListStrategy::PerEntry => write_to_profile(&prepend_line(&var, &value)?)?,
+        ListStrategy::Composed => list_strategy::compose(&var, &value, false)?,
+    }
+    changed(&var, backend::Update::Prepend(&value));
+    Ok(change)
 }
 
 /// Prepends a value to an environment variable
 /// Useful for prepending a value to PATH
+/// Prepending to a variable that usually holds a single value is warned
+/// about in the returned `Change`, see `set_strict_lists`, as are
+/// characters likely pasted by accident, see `set_sanitize_values`.
 #[cfg(target_os = "windows")]
-pub fn prepend<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
-    inject(format!("setenv_prepend {} {}", var, value).as_str())
+pub fn prepend<T: fmt::Display>(var: T, value: T) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let mut change = Change::default();
+    change.warnings.extend(var_kind::check_list(&var)?);
+    let (value, warnings) = hygiene::review(&var, &value.to_string());
+    change.warnings.extend(warnings);
+    change
+        .warnings
+        .extend(startup_cost::check_value(&var, &value)?);
+    inject(&prepend_line(&var, &value)?)?;
+    changed(&var, backend::Update::Prepend(&value));
+    Ok(change)
+}
+
+/// Appends a value to a list variable like PATH,
+/// mirroring `check_or_set`.
+/// If the variable already has the value as an entry,
+/// or the profile already appends it,
+/// then nothing will happen.
+/// If not then it will be appended in your profile.
+/// For PATH this is `prelude::persist_path`.
+/// Warnings about the change are emitted as `Event::Warning`s.
+pub fn check_or_append<T: fmt::Display>(var: T, value: T) -> io::Result<ListOutcome> {
+    let _operation = events::operation();
+    check_or_add(&var, &value, append_line(&var, &value)?, || {
+        append(&var, &value).map(Change::emit_warnings)
+    })
+}
+
+/// Prepends a value to a list variable like PATH,
+/// mirroring `check_or_set`.
+/// If the variable already has the value as an entry,
+/// or the profile already prepends it,
+/// then nothing will happen.
+/// If not then it will be prepended in your profile.
+/// Warnings about the change are emitted as `Event::Warning`s.
+pub fn check_or_prepend<T: fmt::Display>(var: T, value: T) -> io::Result<ListOutcome> {
+    let _operation = events::operation();
+    check_or_add(&var, &value, prepend_line(&var, &value)?, || {
+        prepend(&var, &value).map(Change::emit_warnings)
+    })
+}
+
+fn check_or_add<T: fmt::Display>(
+    var: &T,
+    value: &T,
+    line: String,
+    write: impl FnOnce() -> io::Result<()>,
+) -> io::Result<ListOutcome> {
+    let value = value.to_string();
+    if let Ok(current) = env::var(var.to_string()) {
+        if current
+            .split(LIST_SEPARATOR)
+            .any(|entry| same_entry(entry, &value))
+        {
+            events::skipped(&var.to_string(), format!("already has {}", value));
+            return Ok(ListOutcome::Present);
+        }
+    }
+
+    if profile_contains(&line)? {
+        events::skipped(
+            &var.to_string(),
+            format!("the profile already adds {}", value),
+        );
+        return Ok(ListOutcome::Pending);
+    }
+    #[cfg(target_family = "unix")]
+    if list_strategy::adds(&var.to_string(), &value)? {
+        events::skipped(
+            &var.to_string(),
+            format!("the profile already adds {}", value),
+        );
+        return Ok(ListOutcome::Pending);
+    }
+
+    write()?;
+    Ok(ListOutcome::Written)
+}
+
+/// Compares two entries of a list variable, ignoring trailing
+/// separators of directories and, on windows, case.
+fn same_entry(a: &str, b: &str) -> bool {
+    fn trimmed(entry: &str) -> &str {
+        let trimmed = entry.trim_end_matches(['/', '\\']);
+        if trimmed.is_empty() {
+            entry
+        } else {
+            trimmed
+        }
+    }
+
+    if cfg!(target_os = "windows") {
+        trimmed(a).eq_ignore_ascii_case(trimmed(b))
+    } else {
+        trimmed(a) == trimmed(b)
+    }
+}
+
+/// Checks whether the profile contains `line`,
+/// ignoring surrounding whitespace.
+fn profile_contains(line: &str) -> io::Result<bool> {
+    let content = match filesystem::current().read_to_string(&profile_path_for_line(line)?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    Ok(content.lines().any(|it| it.trim() == line))
+}
+
+/// Replaces the line numbered `line_no`, from 1, of the file at `path`
+/// with `line`, keeping its indentation and leaving everything else
+/// untouched.
+fn replace_line(path: &Path, line_no: usize, line: &str) -> io::Result<()> {
+    let content = filesystem::current().read_to_string(path)?;
+    let mut replaced = None;
+    let updated: String = content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(idx, it)| {
+            if idx + 1 != line_no {
+                return it.to_string();
+            }
+            let old = it.trim_end_matches(['\r', '\n']);
+            let indent = &old[..old.len() - old.trim_start().len()];
+            replaced = Some(old.to_string());
+            format!("{}{}{}", indent, line, &it[old.len()..])
+        })
+        .collect();
+    let Some(old) = replaced else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no line {}", path.display(), line_no),
+        ));
+    };
+
+    dry_run::plan(|| PlannedChange::RemoveLine {
+        path: path.to_path_buf(),
+        line: old,
+    });
+    let planned = dry_run::plan(|| PlannedChange::AddLine {
+        path: path.to_path_buf(),
+        line: line.to_string(),
+    });
+    if planned {
+        backend::stage(path, &updated);
+    } else {
+        record_write(path)?;
+        filesystem::replace(path, &updated)?;
+        verify::written(path, &updated)?;
+        wrote(path);
+    }
+    Ok(())
+}
+
+/// Removes the lines of the file at `path` for which `predicate`
+/// returns true, leaving everything else untouched.
+/// Returns how many lines were removed.
+fn remove_lines(path: &Path, predicate: impl Fn(&str) -> bool) -> io::Result<usize> {
+    let content = match filesystem::current().read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    let mut removed = Vec::new();
+    let kept: String = content
+        .split_inclusive('\n')
+        .filter(|line| {
+            let line = line.trim_end_matches(['\r', '\n']);
+            let matches = predicate(line);
+            if matches {
+                removed.push(line.to_string());
+            }
+            !matches
+        })
+        .collect();
+
+    if removed.is_empty() {
+        return Ok(0);
+    }
+
+    let mut planned = false;
+    for line in &removed {
+        planned = dry_run::plan(|| PlannedChange::RemoveLine {
+            path: path.to_path_buf(),
+            line: line.clone(),
+        });
+    }
+
+    if planned {
+        backend::stage(path, &kept);
+    } else {
+        record_write(path)?;
+        filesystem::replace(path, &kept)?;
+        verify::written(path, &kept)?;
+        wrote(path);
+    }
+    Ok(removed.len())
+}
+
+/// Returns the lines in the block managed by this crate that append
+/// or prepend to `var`, together with the value they add.
+fn managed_list_lines(var: &str) -> io::Result<Vec<(String, String)>> {
+    let content = match filesystem::current().read_to_string(&profile_path_for(var)?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let syntax = profile_syntax()?;
+    let mut managed = false;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line == DEFS_BEG {
+            managed = true;
+        } else if line == DEFS_END {
+            managed = false;
+        } else if managed {
+            if let Some(value) = syntax.list_value(var, line) {
+                lines.push((line.to_string(), value.to_string()));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// Returns the line appending `value` to `var`, in the guarded form
+/// if `var` may be undefined when it runs, see `is_defined`.
+fn append_line<T: fmt::Display>(var: &T, value: &T) -> io::Result<String> {
+    let syntax = profile_syntax()?;
+    #[cfg(target_os = "windows")]
+    let value = &relocate::for_profile(&value.to_string());
+    if is_defined(&var.to_string())? {
+        Ok(syntax.append_line(var, value))
+    } else {
+        syntax
+            .guarded_append_line(var, value)
+            .ok_or_else(|| unguardable(&var.to_string()))
+    }
+}
+
+/// Returns the line prepending `value` to `var`, see `append_line`.
+fn prepend_line<T: fmt::Display>(var: &T, value: &T) -> io::Result<String> {
+    let syntax = profile_syntax()?;
+    #[cfg(target_os = "windows")]
+    let value = &relocate::for_profile(&value.to_string());
+    if is_defined(&var.to_string())? {
+        Ok(syntax.prepend_line(var, value))
+    } else {
+        syntax
+            .guarded_prepend_line(var, value)
+            .ok_or_else(|| unguardable(&var.to_string()))
+    }
+}
+
+/// The error for adding to `var` while it may be undefined in a shell
+/// that has no guarded form of the line, see `guarded_append_line`.
+pub(crate) fn unguardable(var: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} isn't defined yet and this shell can't add to it only if it is",
+            var
+        ),
+    )
+}
+
+/// Returns whether `var` is set in the current process or assigned
+/// in the profile it's written to, so a line referencing it won't
+/// fail in shells treating undefined variables as an error.
+fn is_defined(var: &str) -> io::Result<bool> {
+    if env::var_os(var).is_some() {
+        return Ok(true);
+    }
+    let content = match filesystem::current().read_to_string(&profile_path_for(var)?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    Ok(content.lines().any(|line| {
+        simulate::parse_var_value(line.trim()).is_some_and(|(assigned, _)| assigned == var)
+    }))
 }
 
 /// Sets an environment variable without checking
@@ -147,42 +917,325 @@ pub fn prepend<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
 /// If it does you will end up with two
 /// assignments in your profile.
 /// It's recommended to use `check_or_set`
-/// unless you are certain it doesn't exist.
+/// unless you are certain it doesn't exist,
+/// or `prelude::persist` to update the assignment in place.
+/// Assignments managed by this crate in other configuration files of
+/// the shell are removed, see `managed_duplicates`.
+/// A variable only assigned in disabled lines stays disabled, see
+/// `disable`.
+/// Characters likely pasted by accident, like a no-break space, are
+/// warned about in the returned `Change`, see `set_sanitize_values`.
 #[cfg(target_family = "unix")]
-pub fn set<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<()> {
-    let mut profile = get_profile()?;
-    writeln!(profile, "\nexport {}={}", var, value)?;
-    profile.flush()
+pub fn set<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let (value, warnings) = hygiene::review(&var, &value.to_string());
+    let mut change = Change { warnings };
+    change
+        .warnings
+        .extend(startup_cost::check_value(&var, &value)?);
+    let line = profile_syntax()?.set_line(&var, &value);
+    if toggle::is_disabled(&var)? {
+        toggle::write_disabled(&var, &line)?;
+        managed::consolidate(&var)?;
+        return Ok(change);
+    }
+    write_to_profile(&line)?;
+    managed::consolidate(&var)?;
+    changed(&var, backend::Update::Set(&value));
+    Ok(change)
 }
 /// Sets an environment variable without checking
 /// if it exists.
 /// If it does you will override the value.
+/// `prelude::persist` updates the assignment in place instead.
+/// Characters likely pasted by accident, like a no-break space, are
+/// warned about in the returned `Change`, see `set_sanitize_values`.
 #[cfg(target_os = "windows")]
-pub fn set<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<()> {
-    inject(format!("setenv_set {} {}", var, value).as_str())?;
-    Ok(())
+pub fn set<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<Change> {
+    let _operation = events::operation();
+    let var = var.to_string();
+    let (value, mut warnings) = hygiene::review(&var, &value.to_string());
+    warnings.extend(startup_cost::check_value(&var, &value)?);
+    let relocated = relocate::for_profile(&value);
+    inject(&profile_syntax()?.set_line(&var, relocated))?;
+    changed(&var, backend::Update::Set(&value));
+    Ok(Change { warnings })
 }
 
+/// Follows up on a completed change to `var`.
+fn changed(var: &str, update: backend::Update) {
+    let event = match update {
+        backend::Update::Set(_) => usage::Event::Set,
+        backend::Update::Append(_) | backend::Update::Prepend(_) => usage::Event::Added,
+    };
+    backend::apply(var, update);
+    usage::record(event, var);
+}
+
+/// Adds `line` to the block managed by this crate in the profile.
 #[cfg(target_family = "unix")]
-fn get_profile() -> io::Result<File> {
-    let home_dir = dirs::home_dir()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No home directory"))?;
+fn write_to_profile(line: &str) -> io::Result<()> {
+    write_to_profile_at(&profile_path_for_line(line)?, line)
+}
 
-    let profile_path = find_profile(home_dir.clone()).unwrap_or_else(|_| {
-        let mut fallback = home_dir;
-        fallback.push(".profile");
-        fallback
-    });
+/// Does the same as `write_to_profile`, to the profile at `path`.
+#[cfg(target_family = "unix")]
+fn write_to_profile_at(path: &Path, line: &str) -> io::Result<()> {
+    let entry = metadata::tag(line);
+    edit_profile_at(path, |content| (block::insert(content, &entry, None), ()))
+}
+
+/// Changes the profile to what `edit` returns for its current content,
+/// where `line` is the line `edit` adds, which picks the profile.
+/// Refuses to change a profile whose managed block is damaged.
+/// During dry run the lines `edit` adds and removes are recorded.
+#[cfg(target_family = "unix")]
+fn edit_profile<R>(line: &str, edit: impl FnOnce(&str) -> (String, R)) -> io::Result<R> {
+    edit_profile_at(&profile_path_for_line(line)?, edit)
+}
+
+/// Does the same as `edit_profile`, to the profile at `profile_path`.
+#[cfg(target_family = "unix")]
+fn edit_profile_at<R>(
+    profile_path: &Path,
+    edit: impl FnOnce(&str) -> (String, R),
+) -> io::Result<R> {
+    edit_file(profile_path, |content| {
+        block::check(content, profile_path)?;
+        Ok(edit(content))
+    })
+}
+
+/// Changes the file at `path` to what `edit` returns for its current
+/// content, like `edit_profile` but without looking at the block.
+#[cfg(target_family = "unix")]
+fn edit_file<R>(
+    profile_path: &Path,
+    edit: impl FnOnce(&str) -> io::Result<(String, R)>,
+) -> io::Result<R> {
+    profile_file::check(profile_path)?;
+
+    if dry_run::is_dry_run() {
+        let content = match filesystem::current().read_to_string(profile_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let (edited, result) = edit(&content)?;
+        dry_run::plan_edit(profile_path, &content, &edited);
+        backend::stage(profile_path, &edited);
+        return Ok(result);
+    }
+
+    record_write(profile_path)?;
+    let result = write_mode::rewrite(profile_path, edit)?;
+    sudo::chown_to_invoking_user(profile_path)?;
+
+    wrote(profile_path);
+    Ok(result)
+}
+
+/// Returns every profile that may contain a block managed by this crate.
+fn managed_profiles() -> io::Result<Vec<PathBuf>> {
+    managed_profiles_with(Access::ReadWrite)
+}
+
+/// Does the same as `managed_profiles` with the given access.
+#[cfg(target_family = "unix")]
+fn managed_profiles_with(access: Access) -> io::Result<Vec<PathBuf>> {
+    let mut profiles = vec![profile_path_with(access)?];
+    let lists = profile_path_for_with("PATH", access)?;
+    if !profiles.contains(&lists) {
+        profiles.push(lists);
+    }
+    Ok(profiles)
+}
+
+/// Does the same as `managed_profiles` with the given access.
+#[cfg(target_os = "windows")]
+fn managed_profiles_with(access: Access) -> io::Result<Vec<PathBuf>> {
+    Ok(vec![profile_path_with(access)?])
+}
+
+fn profile_path() -> io::Result<PathBuf> {
+    profile_path_with(Access::ReadWrite)
+}
+
+/// Returns the profile, creating its directory if it's missing unless
+/// `access` is `Access::ReadOnly`.
+#[cfg(target_family = "unix")]
+fn profile_path_with(access: Access) -> io::Result<PathBuf> {
+    let (home_dir, shell) = home_and_shell_with(access)?;
+    if let Some(path) = zsh::split_profile(&home_dir, &shell, None) {
+        events::looked_up(&shell, Vec::new());
+        create_config_dir(&path, access)?;
+        profile_file::check_not_directory(&path)?;
+        return Ok(path);
+    }
+
+    let profile_path = match find_profile(home_dir.clone(), &shell, access) {
+        Ok(profile_path) => profile_path,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+            events::looked_up(&shell, Vec::new());
+            home_dir.join(platform().fallback_profile)
+        }
+        Err(err) => return Err(err),
+    };
+    profile_file::check_not_directory(&profile_path)?;
+
+    Ok(profile_path)
+}
+
+/// Returns the profile lines changing `var` are written to, which
+/// only differs from `profile_path` with `ZshPolicy::SplitByKind`.
+#[cfg(target_family = "unix")]
+fn profile_path_for(var: &str) -> io::Result<PathBuf> {
+    profile_path_for_with(var, Access::ReadWrite)
+}
+
+/// Does the same as `profile_path_for` with the given access.
+#[cfg(target_family = "unix")]
+fn profile_path_for_with(var: &str, access: Access) -> io::Result<PathBuf> {
+    let (home_dir, shell) = home_and_shell_with(access)?;
+    match zsh::split_profile(&home_dir, &shell, Some(var)) {
+        Some(path) => {
+            create_config_dir(&path, access)?;
+            profile_file::check_not_directory(&path)?;
+            Ok(path)
+        }
+        None => profile_path_with(access),
+    }
+}
+
+/// Returns the profile lines changing `var` are written to.
+#[cfg(target_os = "windows")]
+fn profile_path_for(_var: &str) -> io::Result<PathBuf> {
+    profile_path()
+}
+
+/// Returns the profile `line` is written to, see `profile_path_for`.
+/// `line` may span several lines, like an assignment with a comment.
+fn profile_path_for_line(line: &str) -> io::Result<PathBuf> {
+    match line
+        .lines()
+        .find_map(|line| simulate::parse_var_value(line.trim()))
+    {
+        Some((var, _)) => profile_path_for(var),
+        None => profile_path(),
+    }
+}
+
+/// Returns the home directory and the shell of the user
+/// whose profile is changed.
+#[cfg(target_family = "unix")]
+fn home_and_shell() -> io::Result<(PathBuf, String)> {
+    home_and_shell_with(Access::ReadWrite)
+}
+
+/// Does the same as `home_and_shell` with the given access: under `sudo`
+/// only `Access::ReadWrite` fails with `SudoPolicy::Error`.
+#[cfg(target_family = "unix")]
+fn home_and_shell_with(access: Access) -> io::Result<(PathBuf, String)> {
+    if let Some(user) = sudo::invoking_user(access)? {
+        return Ok((user.home, shell_name(&user.shell)));
+    }
 
-    let mut oo = OpenOptions::new();
-    oo.append(true).create(true);
-    oo.open(profile_path)
+    let home_dir = dirs::home_dir().ok_or_else(|| io::Error::other("No home directory"))?;
+    let shell = match env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => shell_name(&shell),
+        _ => platform().default_shell.to_string(),
+    };
+    Ok((home_dir, shell))
+}
+
+/// Longest `SHELL` looked at, longer ones are taken to be corrupted.
+#[cfg(target_family = "unix")]
+const MAX_SHELL_LEN: usize = 4096;
+
+/// Returns the file name of the shell at `shell`, without control
+/// characters, which is all shells are detected by. Values that look
+/// corrupted or crafted, too long or with `..` components, give an
+/// empty name no shell is detected from, so the fallback profile of
+/// the platform is used. Nothing derived from `SHELL` is ever used as
+/// a path.
+#[cfg(target_family = "unix")]
+fn shell_name(shell: &str) -> String {
+    if shell.len() > MAX_SHELL_LEN || shell.split('/').any(|part| part == "..") {
+        return String::new();
+    }
+    shell
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
 }
 
+/// A shell whose profile can be changed.
 #[cfg(target_family = "unix")]
-struct Shell {
+#[derive(Debug)]
+pub struct Shell {
     name: &'static str,
     config_files: &'static [&'static str],
+    syntax: SyntaxInfo,
+}
+
+#[cfg(target_family = "unix")]
+impl Shell {
+    /// The name of the shell, like `bash`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The syntax of the shell, which this crate uses for the lines
+    /// it writes to the profile.
+    pub fn syntax(&self) -> SyntaxInfo {
+        self.syntax
+    }
+}
+
+/// Returns the shell of the user whose profile is changed, detected
+/// through `SHELL`, or `None` if it's not supported.
+/// In that case the fallback profile of the platform, usually
+/// `.profile`, is changed using POSIX syntax, see `Platform`.
+#[cfg(target_family = "unix")]
+pub fn detect_shell() -> io::Result<Option<&'static Shell>> {
+    let (_, shell) = home_and_shell()?;
+    Ok(select_shell(&shell))
+}
+
+#[cfg(target_family = "unix")]
+fn select_shell(shell: &str) -> Option<&'static Shell> {
+    // Names like `ksh93` and `mksh` are versions or variants.
+    SHELLS.iter().find(|s| shell.contains(s.name))
+}
+
+/// Returns the profile the variables are written to.
+/// Creates its directory if it's missing, see `which_profile_readonly`.
+pub fn which_profile() -> io::Result<PathBuf> {
+    let _operation = events::operation();
+    profile_path()
+}
+
+/// Returns the syntax of the profile the variables are written to.
+#[cfg(target_family = "unix")]
+fn profile_syntax() -> io::Result<SyntaxInfo> {
+    profile_syntax_with(Access::ReadWrite)
+}
+
+/// Does the same as `profile_syntax` with the given access.
+#[cfg(target_family = "unix")]
+fn profile_syntax_with(access: Access) -> io::Result<SyntaxInfo> {
+    let (_, shell) = home_and_shell_with(access)?;
+    Ok(select_shell(&shell).map_or(SyntaxInfo::POSIX, Shell::syntax))
+}
+
+/// Returns the syntax of the profile the variables are written to.
+#[cfg(target_os = "windows")]
+fn profile_syntax() -> io::Result<SyntaxInfo> {
+    Ok(SyntaxInfo::POWERSHELL)
 }
 
 #[cfg(target_family = "unix")]
@@ -190,59 +1243,528 @@ static SHELLS: &[Shell] = &[
     Shell {
         name: "zsh",
         config_files: &[".zshenv", ".zprofile", ".zshrc", ".zlogin"],
+        syntax: SyntaxInfo::POSIX,
     },
     Shell {
         name: "fish",
         config_files: &[".config/fish/config.fish"],
+        syntax: SyntaxInfo::FISH,
     },
     Shell {
         name: "tcsh",
         config_files: &[".tcshrc", ".cshrc", ".login"],
+        syntax: SyntaxInfo::CSH,
     },
     Shell {
         name: "csh",
         config_files: &[".cshrc", ".tcshrc", ".login"],
+        syntax: SyntaxInfo::CSH,
     },
     Shell {
         name: "ksh",
         config_files: &[".kshrc"],
+        syntax: SyntaxInfo::POSIX,
     },
     Shell {
         name: "bash",
         config_files: &[".bash_profile", ".bashrc", ".bash_login"],
+        syntax: SyntaxInfo::POSIX,
     },
 ];
 
 #[cfg(target_family = "unix")]
-fn find_profile(mut home_dir: PathBuf) -> io::Result<PathBuf> {
-    let shell_env = env::var("SHELL").unwrap_or_default();
+fn find_profile(home_dir: PathBuf, shell: &str, access: Access) -> io::Result<PathBuf> {
+    let candidates = profile_candidates(&home_dir, shell)?;
 
-    let selected_shell = SHELLS
+    // Probing has no side effects, only the selected file gets
+    // its directory created.
+    let mut probed = Vec::new();
+    let profile_path = candidates
         .iter()
-        .find(|s| shell_env.contains(s.name))
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Unsupported shell"))?;
+        .find(|config_path| {
+            let exists = filesystem::current().exists(config_path);
+            probed.push((config_path.to_path_buf(), exists));
+            exists
+        })
+        .unwrap_or(&candidates[0])
+        .clone();
+    events::looked_up(shell, probed);
 
-    for config_file in selected_shell.config_files {
-        let mut config_path = home_dir.clone();
-        for part in config_file.split('/') {
-            config_path.push(part);
-        }
+    create_config_dir(&profile_path, access)?;
+    Ok(profile_path)
+}
 
-        if config_path.exists() {
-            return Ok(config_path);
+/// Creates the directory of the selected profile if it's missing,
+/// unless in dry run or `access` is `Access::ReadOnly`.
+#[cfg(target_family = "unix")]
+fn create_config_dir(profile_path: &Path, access: Access) -> io::Result<()> {
+    if access == Access::ReadOnly {
+        return Ok(());
+    }
+    if let Some(config_dir) = profile_path.parent() {
+        let fs = filesystem::current();
+        if !fs.exists(config_dir) && !dry_run::is_dry_run() {
+            fs.create_dir_all(config_dir).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!(
+                        "Cannot create config directory {}: {}{}",
+                        config_dir.display(),
+                        err,
+                        sandbox_hint()
+                    ),
+                )
+            })?;
         }
+    }
+
+    Ok(())
+}
 
-        if config_file.contains('/') {
-            if std::fs::create_dir_all(config_path.parent().unwrap()).is_ok() {
-                return Ok(config_path);
+/// Returns the config files of `shell` in `home_dir`, in the order
+/// they are preferred in.
+#[cfg(target_family = "unix")]
+fn profile_candidates(home_dir: &Path, shell: &str) -> io::Result<Vec<PathBuf>> {
+    let selected_shell = select_shell(shell)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "Unsupported shell"))?;
+
+    Ok(selected_shell
+        .config_files
+        .iter()
+        .map(|config_file| {
+            let mut config_path = if selected_shell.name == "zsh" {
+                zsh::config_dir(home_dir, config_file)
+            } else {
+                home_dir.to_path_buf()
+            };
+            for part in config_file.split('/') {
+                config_path.push(part);
+            }
+            config_path
+        })
+        .collect())
+}
+
+/// Returns a hint to append to errors when running inside a Flatpak
+/// or snap sandbox, where the home directory is usually read-only.
+#[cfg(target_family = "unix")]
+fn sandbox_hint() -> &'static str {
+    if env::var_os("FLATPAK_ID").is_some() || Path::new("/.flatpak-info").exists() {
+        " (running inside a Flatpak sandbox, the profile likely has to be changed outside of it)"
+    } else if env::var_os("SNAP").is_some() {
+        " (running inside a snap sandbox, the profile likely has to be changed outside of it)"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_family = "unix")]
+    use crate::test_support::{self, sandbox};
+    #[cfg(target_family = "unix")]
+    use std::fs;
+
+    #[cfg(target_family = "unix")]
+    const VAR: &str = "SET_ENV_TEST_DIRS";
+
+    /// The value in the environment of the process.
+    #[cfg(target_family = "unix")]
+    #[derive(Clone, Copy, Debug)]
+    enum Live {
+        Present,
+        Missing,
+        Unset,
+    }
+
+    /// What the profile already adds.
+    #[cfg(target_family = "unix")]
+    #[derive(Clone, Copy, Debug)]
+    enum Persisted {
+        Nothing,
+        Appended,
+        Prepended,
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn check_or_append_and_check_or_prepend() {
+        type Check = fn(&'static str, &'static str) -> io::Result<ListOutcome>;
+        let checks: [(&str, Check); 2] = [
+            ("append", check_or_append::<&str>),
+            ("prepend", check_or_prepend::<&str>),
+        ];
+        for (name, check) in checks {
+            for live in [Live::Present, Live::Missing, Live::Unset] {
+                for persisted in [
+                    Persisted::Nothing,
+                    Persisted::Appended,
+                    Persisted::Prepended,
+                ] {
+                    let sandbox = sandbox("/bin/bash");
+                    match persisted {
+                        Persisted::Nothing => {}
+                        Persisted::Appended => drop(append(VAR, "/opt/b").unwrap()),
+                        Persisted::Prepended => drop(prepend(VAR, "/opt/b").unwrap()),
+                    }
+                    match live {
+                        Live::Present => env::set_var(VAR, "/opt/a:/opt/b/"),
+                        Live::Missing => env::set_var(VAR, "/opt/a"),
+                        Live::Unset => env::remove_var(VAR),
+                    }
+                    let before = fs::read(sandbox.path(".bash_profile")).ok();
+
+                    let expected = match (live, persisted) {
+                        (Live::Present, _) => ListOutcome::Present,
+                        (_, Persisted::Nothing) => ListOutcome::Written,
+                        _ => ListOutcome::Pending,
+                    };
+                    let case = format!("{} with {:?} and {:?}", name, live, persisted);
+                    assert_eq!(check(VAR, "/opt/b").unwrap(), expected, "{}", case);
+
+                    let after = fs::read_to_string(sandbox.path(".bash_profile")).ok();
+                    if expected == ListOutcome::Written {
+                        let after = after.unwrap();
+                        let line = after.lines().find(|it| it.contains("/opt/b")).unwrap();
+                        let value = line.split_once('=').unwrap().1;
+                        let appends = value.find(VAR) < value.find("/opt/b");
+                        assert_eq!(appends, name == "append", "{}: {}", case, line);
+                        assert_eq!(check(VAR, "/opt/b").unwrap(), ListOutcome::Pending);
+                    } else {
+                        assert_eq!(after.map(String::into_bytes), before, "{}", case);
+                    }
+                    env::remove_var(VAR);
+                }
             }
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Cannot create config directory",
-            ));
         }
     }
 
-    home_dir.push(selected_shell.config_files[0]);
-    Ok(home_dir)
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn a_read_only_home_still_finds_an_existing_profile() {
+        for (shell, existing) in [("/bin/zsh", ".zlogin"), ("/bin/tcsh", ".login")] {
+            let sandbox = sandbox(shell);
+            sandbox.write(existing, "");
+            crate::set_filesystem(test_support::ReadOnlyFilesystem);
+
+            assert_eq!(
+                which_profile().unwrap(),
+                sandbox.path(existing),
+                "{}",
+                shell
+            );
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn a_read_only_home_names_the_directory_that_cant_be_created() {
+        let sandbox = sandbox("/usr/bin/fish");
+        crate::set_filesystem(test_support::ReadOnlyFilesystem);
+        let dir = sandbox.path(".config/fish");
+
+        let err = which_profile().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        let message = err.to_string();
+        assert!(
+            message.starts_with(&format!(
+                "Cannot create config directory {}: {} is read-only",
+                dir.display(),
+                dir.display()
+            )),
+            "{}",
+            message
+        );
+        if !Path::new("/.flatpak-info").exists() {
+            assert!(!message.contains("sandbox"), "{}", message);
+        }
+
+        env::set_var("SNAP", "/snap/mytool/1");
+        let message = which_profile().unwrap_err().to_string();
+        env::remove_var("SNAP");
+        assert!(message.contains("inside a snap sandbox"), "{}", message);
+
+        env::set_var("FLATPAK_ID", "org.example.MyTool");
+        let message = which_profile().unwrap_err().to_string();
+        env::remove_var("FLATPAK_ID");
+        assert!(message.contains("inside a Flatpak sandbox"), "{}", message);
+        assert!(!dir.exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn shell_name_keeps_only_a_plain_basename() {
+        let long = format!("/bin/{}bash", "x".repeat(MAX_SHELL_LEN));
+        let cases = [
+            ("/bin/bash", "bash"),
+            ("/usr/local/bin/fish", "fish"),
+            ("zsh", "zsh"),
+            ("", ""),
+            ("/bin/", ""),
+            ("../../etc/passwd", ""),
+            ("/bin/../bin/zsh", ""),
+            ("/opt/..fish", "..fish"),
+            ("/bin/ba\nsh", "bash"),
+            ("/bin/z\u{1b}[31msh\r", "z[31msh"),
+            (long.as_str(), ""),
+        ];
+        for (shell, expected) in cases {
+            assert_eq!(shell_name(shell), expected, "{:?}", shell);
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn a_hostile_shell_falls_back_to_the_profile_in_home() {
+        let long = format!("/bin/{}", "a".repeat(MAX_SHELL_LEN + 1));
+        for shell in [
+            "",
+            "../../etc/passwd",
+            "/bin/../../../tmp/bash",
+            "/tmp/../.bashrc",
+            "\n\t\u{7f}",
+            long.as_str(),
+        ] {
+            let sandbox = sandbox(shell);
+            assert!(detect_shell().unwrap().is_none(), "{:?}", shell);
+
+            let profile = which_profile().unwrap();
+            assert_eq!(
+                profile,
+                sandbox.path(platform().fallback_profile),
+                "{:?}",
+                shell
+            );
+            crate::set("SET_ENV_HOSTILE_SHELL", "1").unwrap();
+            assert_eq!(
+                test_support::snapshot(&sandbox.home)
+                    .into_iter()
+                    .map(|(path, _)| path)
+                    .collect::<Vec<_>>(),
+                [profile],
+                "{:?}",
+                shell
+            );
+        }
+    }
+
+    /// Runs `do_prerequisites` and then `check_or_set` of a crate
+    /// writing helpers of `format`, which define `line`, on `content`.
+    fn run_template_writer(content: &str, format: u32, line: &str) -> String {
+        let template = include_str!("../scripts/profile.ps1")
+            .replace("${FORMAT}", &format.to_string())
+            .replace(
+                "function setenv_unset",
+                &format!("# helpers of format {}\nfunction setenv_unset", format),
+            );
+        let content = upgrade_template(content, &template, format).unwrap_or(content.to_string());
+        if content.lines().any(|it| it == line) {
+            return content;
+        }
+        insert_before_end(&content, line).unwrap()
+    }
+
+    #[test]
+    fn alternating_template_writers_reach_a_fixed_point() {
+        let (old, new) = ("setenv_set OLD_APP 1", "setenv_set NEW_APP 2");
+        let mut content = "# my aliases\r\nSet-Alias ll ls\r\n".to_string();
+        let mut rounds = Vec::new();
+        for _ in 0..4 {
+            content = run_template_writer(&content, 1, old);
+            content = run_template_writer(&content, 2, new);
+            rounds.push(content.clone());
+        }
+
+        assert!(rounds.windows(2).all(|it| it[0] == it[1]), "{:#?}", rounds);
+        assert!(content.starts_with("# my aliases\r\nSet-Alias ll ls\r\n"));
+        assert_eq!(content.matches("# helpers of format 2").count(), 1);
+        assert!(!content.contains("# helpers of format 1"));
+        assert_eq!(content.matches(TEMPLATE_BEG).count(), 1);
+        let defs: Vec<&str> = content
+            .lines()
+            .skip_while(|it| *it != DEFS_BEG)
+            .skip(1)
+            .take_while(|it| *it != DEFS_END)
+            .collect();
+        assert_eq!(defs, [old, new]);
+    }
+
+    #[test]
+    fn injecting_keeps_the_line_endings_of_profile_ps1() {
+        let profile = |ending: &str, last: &str| {
+            format!(
+                "Set-Alias ll ls{e}{}{e}setenv_set A 1{l}{}{e}# after",
+                DEFS_BEG,
+                DEFS_END,
+                e = ending,
+                l = last
+            )
+        };
+        let cases = [
+            (
+                "LF",
+                profile("\n", "\n"),
+                profile("\n", "\nsetenv_set B 2\n"),
+            ),
+            (
+                "CRLF",
+                profile("\r\n", "\r\n"),
+                profile("\r\n", "\r\nsetenv_set B 2\r\n"),
+            ),
+            (
+                "mixed",
+                profile("\r\n", "\n"),
+                profile("\r\n", "\nsetenv_set B 2\r\n"),
+            ),
+        ];
+        for (name, before, expected) in cases {
+            let after = insert_before_end(&before, "setenv_set B 2");
+            assert_eq!(after.as_deref(), Some(expected.as_str()), "{}", name);
+        }
+        assert_eq!(
+            insert_before_end("setenv_set A 1\r\n", "setenv_set B 2"),
+            None
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn csh_refuses_to_add_to_an_undefined_list() {
+        let sandbox = sandbox("/bin/tcsh");
+        sandbox.write(".tcshrc", "alias ll 'ls -l'\n");
+
+        let err = append("SET_ENV_UNDEFINED_LIST", "/opt/a").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = prepend("SET_ENV_UNDEFINED_LIST", "/opt/a").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(sandbox.read(".tcshrc"), "alias ll 'ls -l'\n");
+
+        set("SET_ENV_UNDEFINED_LIST", "/opt/base").unwrap();
+        append("SET_ENV_UNDEFINED_LIST", "/opt/a").unwrap();
+        assert!(sandbox
+            .read(".tcshrc")
+            .contains("setenv SET_ENV_UNDEFINED_LIST \"${SET_ENV_UNDEFINED_LIST}:/opt/a\"\n"));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn definitions_are_found_in_the_process_and_the_profile() {
+        let sandbox = sandbox("/bin/bash");
+        let posix = SyntaxInfo::POSIX;
+        let profile = [
+            "alias ll='ls -l'".to_string(),
+            posix.set_line("SET_ENV_DEFINED_USER", "1"),
+            Condition::Interactive.guard(&posix, &posix.set_line("SET_ENV_DEFINED_WHEN", "1")),
+            posix
+                .guarded_append_line("SET_ENV_DEFINED_GUARDED", "/opt/a")
+                .unwrap(),
+            format!("  {}", posix.set_line("SET_ENV_DEFINED_INDENTED", "1")),
+            format!("# {}", posix.set_line("SET_ENV_DEFINED_COMMENTED", "1")),
+            toggle::disabled(&posix.set_line("SET_ENV_DEFINED_DISABLED", "1")),
+            "echo $SET_ENV_DEFINED_REFERENCED".to_string(),
+        ];
+        sandbox.write(".bash_profile", &(profile.join("\n") + "\n"));
+        sandbox.write(".bashrc", "export SET_ENV_DEFINED_ELSEWHERE=1\n");
+        env::set_var("SET_ENV_DEFINED_LIVE", "1");
+
+        let cases = [
+            ("SET_ENV_DEFINED_LIVE", true),
+            ("SET_ENV_DEFINED_USER", true),
+            ("SET_ENV_DEFINED_WHEN", true),
+            ("SET_ENV_DEFINED_GUARDED", true),
+            ("SET_ENV_DEFINED_INDENTED", true),
+            ("SET_ENV_DEFINED_COMMENTED", false),
+            ("SET_ENV_DEFINED_DISABLED", false),
+            ("SET_ENV_DEFINED_REFERENCED", false),
+            ("SET_ENV_DEFINED_ELSEWHERE", false),
+            ("SET_ENV_DEFINED_NOWHERE", false),
+        ];
+        for (var, defined) in cases {
+            assert_eq!(is_defined(var).unwrap(), defined, "{}", var);
+        }
+
+        append("SET_ENV_DEFINED_USER", "/opt/a").unwrap();
+        append("SET_ENV_DEFINED_NOWHERE", "/opt/a").unwrap();
+        prepend("SET_ENV_DEFINED_LIVE", "/opt/a").unwrap();
+        env::remove_var("SET_ENV_DEFINED_LIVE");
+        let content = sandbox.read(".bash_profile");
+        for line in [
+            posix.append_line("SET_ENV_DEFINED_USER", "/opt/a"),
+            posix
+                .guarded_append_line("SET_ENV_DEFINED_NOWHERE", "/opt/a")
+                .unwrap(),
+            posix.prepend_line("SET_ENV_DEFINED_LIVE", "/opt/a"),
+        ] {
+            assert!(content.contains(&format!("\n{}\n", line)), "{}", line);
+        }
+        // Once written, the guarded line defines the variable.
+        assert!(is_defined("SET_ENV_DEFINED_NOWHERE").unwrap());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn check_or_set_detailed_tells_where_the_variable_was_found() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            "alias ll='ls -l'\nexport SET_ENV_FOUND_USER=user\n. ~/.config/env.sh\n",
+        );
+        sandbox.write(".config/env.sh", "export SET_ENV_FOUND_SOURCED=sourced\n");
+        set("SET_ENV_FOUND_MANAGED", "managed").unwrap();
+        env::set_var("SET_ENV_FOUND_PROCESS", "process");
+        let profile = sandbox.path(".bash_profile");
+
+        let skipped = |source: ExistingSource, value: &str| SetOutcome::Skipped {
+            source,
+            value: Some(value.to_string()),
+        };
+        let cases = [
+            (
+                "SET_ENV_FOUND_PROCESS",
+                skipped(ExistingSource::ProcessEnv, "process"),
+            ),
+            (
+                "SET_ENV_FOUND_MANAGED",
+                skipped(ExistingSource::ManagedEntry(profile.clone()), "managed"),
+            ),
+            (
+                "SET_ENV_FOUND_USER",
+                skipped(ExistingSource::UserLine(profile.clone(), 2), "user"),
+            ),
+            (
+                "SET_ENV_FOUND_SOURCED",
+                skipped(
+                    ExistingSource::Sourced {
+                        chain: vec![SourceStep {
+                            path: profile.clone(),
+                            line_no: 3,
+                        }],
+                        path: sandbox.path(".config/env.sh"),
+                        line_no: 1,
+                    },
+                    "sourced",
+                ),
+            ),
+            ("SET_ENV_FOUND_NOWHERE", SetOutcome::Written),
+        ];
+        let before = sandbox.read(".bash_profile");
+        for (var, expected) in cases {
+            assert_eq!(
+                check_or_set_detailed(var, "new").unwrap(),
+                expected,
+                "{}",
+                var
+            );
+        }
+        env::remove_var("SET_ENV_FOUND_PROCESS");
+
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            before.replace(
+                "export SET_ENV_FOUND_MANAGED=managed\n",
+                "export SET_ENV_FOUND_MANAGED=managed\nexport SET_ENV_FOUND_NOWHERE=new\n"
+            )
+        );
+    }
 }
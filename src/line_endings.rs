@@ -0,0 +1,124 @@
+//! Rebuilding a changed file without touching the line endings of the
+//! lines that stay. Editors and git may have written CRLF into a
+//! profile, or mixed it with LF, and that is left as it was.
+
+/// Returns the ending most lines of `content` have, or `tie` if as many
+/// lines end with CRLF as with LF.
+pub(crate) fn dominant(content: &str, tie: &'static str) -> &'static str {
+    let crlf = content.matches("\r\n").count();
+    let lf = content.matches('\n').count() - crlf;
+    match lf.cmp(&crlf) {
+        std::cmp::Ordering::Greater => "\n",
+        std::cmp::Ordering::Less => "\r\n",
+        std::cmp::Ordering::Equal => tie,
+    }
+}
+
+/// Joins `lines`, the lines of a changed version of `content` without
+/// their endings, into a file.
+///
+/// Lines of `content` that are still there keep their ending, lines
+/// that are new get the one most lines of `content` have. The last
+/// line is left without an ending only if it was the last line of
+/// `content` and had none.
+pub(crate) fn rejoin<S: AsRef<str>>(content: &str, lines: &[S]) -> String {
+    let original: Vec<(&str, &str)> = content.split_inclusive('\n').map(split_ending).collect();
+    let endings = matched_endings(&original, lines);
+    let new_ending = dominant(content, "\n");
+
+    let mut joined = String::with_capacity(content.len());
+    for (idx, (line, ending)) in lines.iter().zip(endings).enumerate() {
+        joined.push_str(line.as_ref());
+        match ending {
+            Some("") if idx + 1 == lines.len() => {}
+            Some(ending) if !ending.is_empty() => joined.push_str(ending),
+            _ => joined.push_str(new_ending),
+        }
+    }
+    joined
+}
+
+/// Returns `line` without its ending, and the ending.
+fn split_ending(line: &str) -> (&str, &str) {
+    let text = match line.strip_suffix('\n') {
+        Some(text) => text.strip_suffix('\r').unwrap_or(text),
+        None => line,
+    };
+    line.split_at(text.len())
+}
+
+/// Returns the ending of the line of `original` every line of `lines`
+/// is taken to be, or `None` for the new ones. Lines at the start and
+/// end that are the same are matched first, so a single change in the
+/// middle can't pull the lines around it out of place; the rest is
+/// matched in order.
+fn matched_endings<'a, S: AsRef<str>>(
+    original: &[(&str, &'a str)],
+    lines: &[S],
+) -> Vec<Option<&'a str>> {
+    let prefix = original
+        .iter()
+        .zip(lines)
+        .take_while(|((text, _), line)| *text == line.as_ref())
+        .count();
+    let suffix = original[prefix..]
+        .iter()
+        .rev()
+        .zip(lines[prefix..].iter().rev())
+        .take_while(|((text, _), line)| *text == line.as_ref())
+        .count();
+
+    let mut endings: Vec<Option<&str>> = original[..prefix]
+        .iter()
+        .map(|(_, ending)| Some(*ending))
+        .collect();
+    let middle = &original[prefix..original.len() - suffix];
+    let mut next = 0;
+    for line in &lines[prefix..lines.len() - suffix] {
+        let found = middle[next..]
+            .iter()
+            .position(|(text, _)| *text == line.as_ref());
+        endings.push(found.map(|found| {
+            next += found + 1;
+            middle[next - 1].1
+        }));
+    }
+    endings.extend(
+        original[original.len() - suffix..]
+            .iter()
+            .map(|(_, ending)| Some(*ending)),
+    );
+    endings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kept_lines_keep_their_ending() {
+        let content = "a\r\nb\nc\r\n";
+        assert_eq!(
+            rejoin(content, &["a", "new", "b", "c"]),
+            "a\r\nnew\r\nb\nc\r\n"
+        );
+        assert_eq!(rejoin(content, &["a", "c"]), "a\r\nc\r\n");
+        assert_eq!(rejoin("a\nb\n", &["a", "b", "new"]), "a\nb\nnew\n");
+    }
+
+    #[test]
+    fn missing_final_ending_is_kept_only_for_the_last_line() {
+        assert_eq!(rejoin("a\r\nb", &["a", "b"]), "a\r\nb");
+        assert_eq!(rejoin("a\r\nb", &["a", "b", "c"]), "a\r\nb\r\nc\r\n");
+        assert_eq!(rejoin("", &["a"]), "a\n");
+    }
+
+    #[test]
+    fn repeated_lines_stay_in_place() {
+        let content = "a\r\n\r\nb\n\nc\n";
+        assert_eq!(
+            rejoin(content, &["a", "", "x", "b", "", "y", "c"]),
+            "a\r\n\r\nx\nb\n\ny\nc\n"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Linux specific helpers for diagnosing why a running program doesn't
+//! see a variable, by reading its environment from `/proc`.
+//! On other systems they fail with `ErrorKind::Unsupported`.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+
+/// Why a process doesn't have the value a variable is set to, as far
+/// as it can be told from the outside, see `process_has`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MismatchCause {
+    /// The process started before the profile was last changed, so it
+    /// has the environment from before. Restarting it should help.
+    StartedBefore,
+    /// The process belongs to another user, whose profile is not the
+    /// one written to.
+    OtherUser,
+    /// Neither of the above, like a program not started from a login
+    /// shell, or one changing its own environment.
+    Unknown,
+}
+
+/// What `process_has` found.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ProcessVar {
+    /// The process has the expected value.
+    Matches,
+    /// The variable isn't set in the process.
+    Missing(MismatchCause),
+    /// The process has another value.
+    Differs {
+        actual: OsString,
+        cause: MismatchCause,
+    },
+}
+
+/// Returns the environment the process with the id `pid` was started
+/// with, in order. Changes the process made to its own environment
+/// since are not visible.
+/// Fails with `ErrorKind::PermissionDenied` for processes of other
+/// users, unless running as root.
+pub fn process_env(pid: u32) -> io::Result<Vec<(OsString, OsString)>> {
+    imp::read_environ(pid).map(|environ| parse_environ(&environ))
+}
+
+/// Compares the value of `var` in the process with the id `pid` to
+/// `expected`, like the value persisted in the profile, and guesses
+/// why they differ.
+pub fn process_has<T: fmt::Display, U: fmt::Display>(
+    pid: u32,
+    var: T,
+    expected: U,
+) -> io::Result<ProcessVar> {
+    let var = var.to_string();
+    let expected = expected.to_string();
+    let actual = process_env(pid)?
+        .into_iter()
+        .rev()
+        .find(|(name, _)| *name == *var)
+        .map(|(_, value)| value);
+
+    match actual {
+        Some(actual) if actual == *expected => Ok(ProcessVar::Matches),
+        Some(actual) => Ok(ProcessVar::Differs {
+            actual,
+            cause: imp::cause(pid, &var),
+        }),
+        None => Ok(ProcessVar::Missing(imp::cause(pid, &var))),
+    }
+}
+
+/// Splits the content of `/proc/<pid>/environ` into variables and
+/// values. Entries are separated by NUL bytes, a final one may be
+/// missing when the content was cut off. Entries without `=` are
+/// skipped; a leading `=` is part of the name.
+fn parse_environ(environ: &[u8]) -> Vec<(OsString, OsString)> {
+    environ
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let eq = entry.iter().skip(1).position(|byte| *byte == b'=')? + 1;
+            Some((os_string(&entry[..eq]), os_string(&entry[eq + 1..])))
+        })
+        .collect()
+}
+
+#[cfg(target_family = "unix")]
+fn os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(target_family = "unix"))]
+fn os_string(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::MetadataExt;
+
+    use super::MismatchCause;
+
+    pub(super) fn read_environ(pid: u32) -> io::Result<Vec<u8>> {
+        let path = format!("/proc/{}/environ", pid);
+        fs::read(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(err.kind(), format!("There is no process {}", pid))
+            }
+            io::ErrorKind::PermissionDenied => io::Error::new(
+                err.kind(),
+                format!(
+                    "Not allowed to read the environment of process {}, \
+                     it likely belongs to another user",
+                    pid
+                ),
+            ),
+            _ => io::Error::new(err.kind(), format!("Failed to read {}: {}", path, err)),
+        })
+    }
+
+    pub(super) fn cause(pid: u32, var: &str) -> MismatchCause {
+        // The directory of a process is created when it starts.
+        let Ok(process) = fs::metadata(format!("/proc/{}", pid)) else {
+            return MismatchCause::Unknown;
+        };
+        // SAFETY: getuid has no preconditions and can't fail.
+        if process.uid() != unsafe { libc::getuid() } {
+            return MismatchCause::OtherUser;
+        }
+
+        let changed = crate::profile_path_for(var)
+            .and_then(fs::metadata)
+            .and_then(|metadata| metadata.modified());
+        match (process.modified(), changed) {
+            (Ok(started), Ok(changed)) if started < changed => MismatchCause::StartedBefore,
+            _ => MismatchCause::Unknown,
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod imp {
+    use std::io;
+
+    use super::MismatchCause;
+
+    pub(super) fn read_environ(_pid: u32) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Reading the environment of another process is only supported on Linux",
+        ))
+    }
+
+    pub(super) fn cause(_pid: u32, _var: &str) -> MismatchCause {
+        MismatchCause::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The content of an environ file and the variables in it.
+    type Fixture = (&'static [u8], &'static [(&'static str, &'static str)]);
+
+    fn pairs(environ: &[u8]) -> Vec<(String, String)> {
+        parse_environ(environ)
+            .into_iter()
+            .map(|(var, value)| {
+                (
+                    var.to_string_lossy().into_owned(),
+                    value.to_string_lossy().into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn environ_fixtures_are_parsed() {
+        let cases: [Fixture; 9] = [
+            (b"", &[]),
+            (b"\0", &[]),
+            (b"HOME=/home/me\0", &[("HOME", "/home/me")]),
+            (
+                b"HOME=/home/me\0PATH=/bin:/usr/bin\0",
+                &[("HOME", "/home/me"), ("PATH", "/bin:/usr/bin")],
+            ),
+            // Cut off in a value, or in a name before its `=`.
+            (
+                b"HOME=/home/me\0PATH=/b",
+                &[("HOME", "/home/me"), ("PATH", "/b")],
+            ),
+            (b"HOME=/home/me\0PA", &[("HOME", "/home/me")]),
+            (
+                b"EMPTY=\0OPTS=-Da=b\0NOEQUALS\0\0",
+                &[("EMPTY", ""), ("OPTS", "-Da=b")],
+            ),
+            // A leading `=` is part of the name, like `=C:` on Windows.
+            (b"=C:=C:\\dir\0=\0", &[("=C:", "C:\\dir")]),
+            (b"SAME=1\0SAME=2\0", &[("SAME", "1"), ("SAME", "2")]),
+        ];
+        for (environ, expected) in cases {
+            let expected: Vec<(String, String)> = expected
+                .iter()
+                .map(|(var, value)| (var.to_string(), value.to_string()))
+                .collect();
+            assert_eq!(pairs(environ), expected, "{:?}", environ);
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn values_are_kept_as_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let parsed = parse_environ(b"LATIN1=caf\xe9\0");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].1.as_bytes(), b"caf\xe9");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn the_environment_of_a_child_is_read_and_compared() {
+        let _sandbox = crate::test_support::sandbox("/bin/bash");
+        let Ok(mut child) = std::process::Command::new("sleep")
+            .arg("10")
+            .env_clear()
+            .env("SET_ENV_CHILD", "1")
+            .env("SET_ENV_CHILD_OTHER", "a=b")
+            .spawn()
+        else {
+            return;
+        };
+        let pid = child.id();
+        let env = process_env(pid);
+        let has = [
+            process_has(pid, "SET_ENV_CHILD", "1"),
+            process_has(pid, "SET_ENV_CHILD", "2"),
+            process_has(pid, "SET_ENV_CHILD_MISSING", "1"),
+        ];
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(
+            env.unwrap(),
+            [
+                ("SET_ENV_CHILD".into(), "1".into()),
+                ("SET_ENV_CHILD_OTHER".into(), "a=b".into()),
+            ]
+        );
+        // Without a profile there is nothing to tell when it changed.
+        let [matches, differs, missing] = has.map(Result::unwrap);
+        assert_eq!(matches, ProcessVar::Matches);
+        assert_eq!(
+            differs,
+            ProcessVar::Differs {
+                actual: "1".into(),
+                cause: MismatchCause::Unknown
+            }
+        );
+        assert_eq!(missing, ProcessVar::Missing(MismatchCause::Unknown));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_missing_process_is_reported() {
+        let err = process_env(u32::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), format!("There is no process {}", u32::MAX));
+    }
+}
@@ -0,0 +1,427 @@
+use std::cell::Cell;
+use std::env;
+use std::fmt;
+use std::io;
+
+use crate::syntax::{ListOp, SyntaxInfo};
+use crate::{block, line_endings, DEFS_BEG, DEFS_END};
+
+/// How `append` and `prepend` store the entries they add to a list
+/// variable, see `set_list_strategy`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ListStrategy {
+    /// Every entry gets its own line, like `export PATH="/a:$PATH"`
+    /// followed by `export PATH="/b:$PATH"`, so `remove_from` deletes
+    /// a line. The default.
+    #[default]
+    PerEntry,
+    /// The entries share one line appending and one prepending them,
+    /// like `export PATH="/b:/a:$PATH"`. Takes fewer lines, but
+    /// `remove_from` has to rewrite them.
+    Composed,
+}
+
+/// Marks a variable stored with `ListStrategy::Composed` in the block
+/// managed by this crate, as `# set_env list: <var> composed`.
+const MARKER: &str = "# set_env list: ";
+const COMPOSED: &str = " composed";
+
+/// Returns how the entries of `var` are stored, as recorded in the
+/// block managed by this crate.
+pub fn list_strategy<T: fmt::Display>(var: T) -> io::Result<ListStrategy> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let content = read(&var)?;
+    Ok(strategy_in(&content, &var))
+}
+
+/// Stores the entries of `var` with `strategy` from now on, rewriting
+/// the lines already adding to it so the value they compose to stays
+/// the same. The choice is recorded in the block managed by this
+/// crate, so later calls, from any program, keep to it.
+pub fn set_list_strategy<T: fmt::Display>(var: T, strategy: ListStrategy) -> io::Result<()> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let syntax = crate::profile_syntax()?;
+    let path = crate::profile_path_for(&var)?;
+    let content = read(&var)?;
+    if strategy_in(&content, &var) == strategy {
+        return Ok(());
+    }
+
+    crate::edit_file(&path, |content| {
+        block::check(content, &path)?;
+        Ok((convert(content, &syntax, &var, strategy)?, ()))
+    })
+}
+
+/// Removes the entry `value` from the lines the block managed by this
+/// crate adds to `var` with, whatever strategy they were written with.
+/// Lines left without entries are deleted.
+/// Returns how many lines were changed or deleted.
+pub fn remove_from<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let value = value.to_string();
+    let syntax = crate::profile_syntax()?;
+    let path = crate::profile_path_for(&var)?;
+    let content = read(&var)?;
+
+    let (_, changed) = without(&content, &syntax, &var, &value)?;
+    let count = if changed.is_empty() {
+        0
+    } else if changed.iter().all(|(_, line)| line.is_none()) {
+        // Only whole lines go, which is what `remove_lines` is for.
+        let managed = Cell::new(false);
+        crate::remove_lines(&path, |line| {
+            let line = line.trim();
+            if line == DEFS_BEG || line == DEFS_END {
+                managed.set(line == DEFS_BEG);
+                return false;
+            }
+            managed.get() && changed.iter().any(|(removed, _)| removed == line)
+        })?
+    } else {
+        crate::edit_file(&path, |content| {
+            block::check(content, &path)?;
+            let (content, changed) = without(content, &syntax, &var, &value)?;
+            Ok((content, changed.len()))
+        })?
+    };
+
+    if count > 0 && crate::backend::is_process_only() {
+        if let Ok(current) = env::var(&var) {
+            let kept: Vec<&str> = current
+                .split(crate::LIST_SEPARATOR)
+                .filter(|entry| !crate::same_entry(entry, &value))
+                .collect();
+            env::set_var(&var, kept.join(&crate::LIST_SEPARATOR.to_string()));
+        }
+    }
+    Ok(count)
+}
+
+/// Adds `value` to the line appending or prepending to `var` with
+/// `ListStrategy::Composed`, writing that line if there is none yet.
+pub(crate) fn compose(var: &str, value: &str, append: bool) -> io::Result<()> {
+    let syntax = crate::profile_syntax()?;
+    let path = crate::profile_path_for(var)?;
+    let op = match (append, crate::is_defined(var)?) {
+        (true, true) => ListOp::Append,
+        (true, false) => ListOp::GuardedAppend,
+        (false, true) => ListOp::Prepend,
+        (false, false) => ListOp::GuardedPrepend,
+    };
+    crate::edit_file(&path, |content| {
+        block::check(content, &path)?;
+        Ok((composed(content, &syntax, var, value, op)?, ()))
+    })
+}
+
+/// Returns whether a line in the block managed by this crate adds the
+/// entry `value` to `var`, alone or with other entries.
+pub(crate) fn adds(var: &str, value: &str) -> io::Result<bool> {
+    let syntax = crate::profile_syntax()?;
+    Ok(list_lines(&read(var)?, &syntax, var).iter().any(|it| {
+        it.entries
+            .iter()
+            .any(|entry| crate::same_entry(entry, value))
+    }))
+}
+
+fn read(var: &str) -> io::Result<String> {
+    match crate::filesystem::current().read_to_string(&crate::profile_path_for(var)?) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn strategy_in(content: &str, var: &str) -> ListStrategy {
+    let marker = format!("{}{}{}", MARKER, var, COMPOSED);
+    if block::managed_lines(content).any(|(_, line)| line == marker) {
+        ListStrategy::Composed
+    } else {
+        ListStrategy::PerEntry
+    }
+}
+
+/// A line in the block managed by this crate adding to a list variable.
+struct ListLine {
+    idx: usize,
+    op: ListOp,
+    entries: Vec<String>,
+}
+
+fn list_lines(content: &str, syntax: &SyntaxInfo, var: &str) -> Vec<ListLine> {
+    block::managed_lines(content)
+        .filter_map(|(idx, line)| {
+            let (op, value) = syntax.parse_list_line(var, line)?;
+            Some(ListLine {
+                idx,
+                op,
+                entries: syntax
+                    .split_entries(value)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Returns `content` with `value` added to the composed line of `op`'s
+/// direction.
+fn composed(
+    content: &str,
+    syntax: &SyntaxInfo,
+    var: &str,
+    value: &str,
+    op: ListOp,
+) -> io::Result<String> {
+    let existing = list_lines(content, syntax, var)
+        .into_iter()
+        .find(|it| it.op.appends() == op.appends());
+    match existing {
+        Some(mut existing) => {
+            if op.appends() {
+                existing.entries.push(value.to_string());
+            } else {
+                existing.entries.insert(0, value.to_string());
+            }
+            let line = format_line(
+                syntax,
+                existing.op,
+                var,
+                syntax.join_entries(&existing.entries),
+            )?;
+            let mut lines: Vec<&str> = content.lines().collect();
+            lines[existing.idx] = &line;
+            Ok(line_endings::rejoin(content, &lines))
+        }
+        None => {
+            let entry = crate::metadata::tag(&format_line(syntax, op, var, value)?);
+            Ok(block::insert(content, &entry, None))
+        }
+    }
+}
+
+/// A line `without` changed, and what it was changed to, or `None` if
+/// it was deleted.
+type Changed = (String, Option<String>);
+
+/// Returns `content` without the entry `value` of `var`, and the lines
+/// changed.
+fn without(
+    content: &str,
+    syntax: &SyntaxInfo,
+    var: &str,
+    value: &str,
+) -> io::Result<(String, Vec<Changed>)> {
+    let mut lines: Vec<Option<String>> = content.lines().map(|it| Some(it.to_string())).collect();
+    let mut changed = Vec::new();
+    // The line after a deleted guarded one may be the first to run
+    // with the variable undefined, so it takes over the guard.
+    let mut guard_next = false;
+    for list_line in list_lines(content, syntax, var) {
+        let kept: Vec<&String> = list_line
+            .entries
+            .iter()
+            .filter(|entry| !crate::same_entry(entry, value))
+            .collect();
+        let op = if guard_next {
+            list_line.op.guarded()
+        } else {
+            list_line.op
+        };
+        if kept.len() == list_line.entries.len() && op == list_line.op {
+            guard_next = false;
+            continue;
+        }
+        guard_next = kept.is_empty() && op != list_line.op.unguarded();
+        let line = if kept.is_empty() {
+            None
+        } else {
+            Some(format_line(syntax, op, var, syntax.join_entries(&kept))?)
+        };
+        let before = std::mem::replace(&mut lines[list_line.idx], line.clone());
+        changed.push((before.unwrap_or_default().trim().to_string(), line));
+    }
+    let lines: Vec<&str> = lines.iter().flatten().map(String::as_str).collect();
+    Ok((line_endings::rejoin(content, &lines), changed))
+}
+
+/// Returns `content` with the lines adding to `var` stored with
+/// `strategy`, and the marker recording it.
+fn convert(
+    content: &str,
+    syntax: &SyntaxInfo,
+    var: &str,
+    strategy: ListStrategy,
+) -> io::Result<String> {
+    let marker = format!("{}{}{}", MARKER, var, COMPOSED);
+    let list_lines = list_lines(content, syntax, var);
+    let mut lines: Vec<Vec<String>> = content.lines().map(|it| vec![it.to_string()]).collect();
+
+    match strategy {
+        ListStrategy::PerEntry => {
+            for (idx, line) in block::managed_lines(content) {
+                if line == marker {
+                    lines[idx].clear();
+                }
+            }
+            // Each prepended entry goes in front of the ones before it,
+            // so they are written last to first.
+            for list_line in &list_lines {
+                let mut entries = list_line.entries.clone();
+                if !list_line.op.appends() {
+                    entries.reverse();
+                }
+                lines[list_line.idx] = entries
+                    .iter()
+                    .map(|entry| format_line(syntax, list_line.op, var, entry))
+                    .collect::<io::Result<_>>()?;
+            }
+        }
+        ListStrategy::Composed => {
+            // Appending and prepending commute, so only the order among
+            // the lines of one direction matters.
+            for appends in [false, true] {
+                let same: Vec<&ListLine> = list_lines
+                    .iter()
+                    .filter(|it| it.op.appends() == appends)
+                    .collect();
+                let Some(first) = same.first() else {
+                    continue;
+                };
+                let mut entries: Vec<&str> = Vec::new();
+                for list_line in &same {
+                    let added = list_line.entries.iter().map(String::as_str);
+                    if appends {
+                        entries.extend(added);
+                    } else {
+                        entries.splice(0..0, added);
+                    }
+                }
+                for list_line in &same {
+                    lines[list_line.idx].clear();
+                }
+                lines[first.idx] = vec![format_line(
+                    syntax,
+                    first.op,
+                    var,
+                    syntax.join_entries(&entries),
+                )?];
+            }
+        }
+    }
+
+    let lines: Vec<&str> = lines.iter().flatten().map(String::as_str).collect();
+    let content = line_endings::rejoin(content, &lines);
+    Ok(match strategy {
+        ListStrategy::PerEntry => content,
+        ListStrategy::Composed => block::insert(&content, &marker, None),
+    })
+}
+
+/// Formats the line `op` stands for, failing in shells without its
+/// guarded form.
+fn format_line<T: fmt::Display>(
+    syntax: &SyntaxInfo,
+    op: ListOp,
+    var: &str,
+    value: T,
+) -> io::Result<String> {
+    syntax
+        .list_line(op, var, value)
+        .ok_or_else(|| crate::unguardable(var))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use std::collections::HashMap;
+
+    const VAR: &str = "SET_ENV_STRATEGY_DIRS";
+
+    /// Adds and removes entries, returning the value the profile gives
+    /// `VAR` after each step.
+    fn cycle(profile: impl Fn() -> String) -> Vec<String> {
+        let steps: [&dyn Fn() -> io::Result<()>; 7] = [
+            &|| crate::append(VAR, "/a").map(drop),
+            &|| crate::append(VAR, "/b").map(drop),
+            &|| crate::prepend(VAR, "/c").map(drop),
+            &|| remove_from(VAR, "/a").map(drop),
+            &|| crate::append(VAR, "/d").map(drop),
+            &|| crate::prepend(VAR, "/e").map(drop),
+            &|| remove_from(VAR, "/c").map(drop),
+        ];
+        steps
+            .iter()
+            .map(|step| {
+                step().unwrap();
+                let env = crate::simulate(&profile(), "bash", &HashMap::new()).unwrap();
+                env[VAR].clone()
+            })
+            .collect()
+    }
+
+    fn lines_for(content: &str) -> usize {
+        block::managed_lines(content)
+            .filter(|(_, line)| line.starts_with(&format!("export {}=", VAR)))
+            .count()
+    }
+
+    #[test]
+    fn both_strategies_give_the_same_value() {
+        let expected = [
+            "/a",
+            "/a:/b",
+            "/c:/a:/b",
+            "/c:/b",
+            "/c:/b:/d",
+            "/e:/c:/b:/d",
+            "/e:/b:/d",
+        ];
+        let mut lines = Vec::new();
+        for strategy in [ListStrategy::PerEntry, ListStrategy::Composed] {
+            let sandbox = sandbox("/bin/bash");
+            set_list_strategy(VAR, strategy).unwrap();
+            assert_eq!(list_strategy(VAR).unwrap(), strategy);
+
+            let values = cycle(|| sandbox.read(".bash_profile"));
+            assert_eq!(values, expected, "{:?}", strategy);
+            assert_eq!(list_strategy(VAR).unwrap(), strategy);
+            lines.push(lines_for(&sandbox.read(".bash_profile")));
+        }
+        // One line per entry, or one per direction.
+        assert_eq!(lines, [3, 2]);
+    }
+
+    #[test]
+    fn converting_between_strategies_keeps_the_value() {
+        let sandbox = sandbox("/bin/bash");
+        let value = || {
+            let content = sandbox.read(".bash_profile");
+            crate::simulate(&content, "bash", &HashMap::new()).unwrap()[VAR].clone()
+        };
+        crate::append(VAR, "/a").unwrap();
+        crate::prepend(VAR, "/b").unwrap();
+        crate::append(VAR, "/c").unwrap();
+        crate::prepend(VAR, "/d").unwrap();
+        assert_eq!(value(), "/d:/b:/a:/c");
+
+        set_list_strategy(VAR, ListStrategy::Composed).unwrap();
+        assert_eq!(value(), "/d:/b:/a:/c");
+        assert_eq!(lines_for(&sandbox.read(".bash_profile")), 2);
+        assert_eq!(remove_from(VAR, "/b").unwrap(), 1);
+        assert_eq!(value(), "/d:/a:/c");
+
+        set_list_strategy(VAR, ListStrategy::PerEntry).unwrap();
+        assert_eq!(value(), "/d:/a:/c");
+        assert_eq!(lines_for(&sandbox.read(".bash_profile")), 3);
+        assert_eq!(remove_from(VAR, "/a").unwrap(), 1);
+        assert_eq!(value(), "/d:/c");
+    }
+}
@@ -0,0 +1,356 @@
+use std::cell::Cell;
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{block, Access, DEFS_BEG, DEFS_END};
+
+/// What a line in a block managed by this crate is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DefinitionKind {
+    /// An assignment to a variable.
+    Variable,
+    /// A line written by `ensure_line`, like sourcing a file.
+    RawLine,
+}
+
+/// An assignment or other line in a block managed by this crate,
+/// see `list_managed`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ManagedDefinition {
+    pub kind: DefinitionKind,
+    /// The variable assigned, empty for a `DefinitionKind::RawLine`.
+    pub var: String,
+    /// The unevaluated value, or the whole line for a
+    /// `DefinitionKind::RawLine`.
+    pub value: String,
+    pub path: PathBuf,
+    /// The line the assignment is on, numbered from 1.
+    pub line_no: usize,
+    /// Whether the line takes effect, see `disable`.
+    pub enabled: bool,
+}
+
+/// A variable managed in more than one file of the same shell, see
+/// `managed_duplicates`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Duplicate {
+    pub var: String,
+    /// The files assigning the variable in a managed block.
+    pub paths: Vec<PathBuf>,
+    /// The file this crate writes the variable to, the one `set` keeps.
+    pub authoritative: PathBuf,
+}
+
+/// Returns the assignments and raw lines in the blocks managed by this crate in every
+/// configuration file of the user's shell, not only the one written to,
+/// in file order.
+pub fn list_managed() -> io::Result<Vec<ManagedDefinition>> {
+    let _operation = crate::events::operation();
+    list_managed_with(Access::ReadWrite)
+}
+
+/// Does the same as `list_managed` with the given access.
+pub(crate) fn list_managed_with(access: Access) -> io::Result<Vec<ManagedDefinition>> {
+    let syntax = crate::profile_syntax_with(access)?;
+    let mut definitions = Vec::new();
+    for path in candidate_files(access)? {
+        let content = match crate::filesystem::current().read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let mut managed = false;
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line == DEFS_BEG {
+                managed = true;
+            } else if line == DEFS_END {
+                managed = false;
+            } else if let Some((var, value, enabled)) = crate::toggle::parse_entry(line) {
+                if managed {
+                    definitions.push(ManagedDefinition {
+                        kind: DefinitionKind::Variable,
+                        var: var.to_string(),
+                        value: value.to_string(),
+                        path: path.clone(),
+                        line_no: idx + 1,
+                        enabled,
+                    });
+                }
+            } else if managed && crate::raw_line::is_raw_line(&syntax, line) {
+                definitions.push(ManagedDefinition {
+                    kind: DefinitionKind::RawLine,
+                    var: String::new(),
+                    value: line.to_string(),
+                    path: path.clone(),
+                    line_no: idx + 1,
+                    enabled: true,
+                });
+            }
+        }
+    }
+    Ok(definitions)
+}
+
+/// Returns the variables managed in more than one file, which happens
+/// when an older version or the user moved the block. Depending on the
+/// kind of session either file may win, so `set` removes the managed
+/// assignments from all but the authoritative file.
+pub fn managed_duplicates() -> io::Result<Vec<Duplicate>> {
+    let _operation = crate::events::operation();
+    let mut duplicates: Vec<Duplicate> = Vec::new();
+    for definition in list_managed()? {
+        if definition.kind == DefinitionKind::RawLine {
+            continue;
+        }
+        match duplicates.iter_mut().find(|it| it.var == definition.var) {
+            Some(duplicate) => {
+                if !duplicate.paths.contains(&definition.path) {
+                    duplicate.paths.push(definition.path);
+                }
+            }
+            None => duplicates.push(Duplicate {
+                authoritative: crate::profile_path_for(&definition.var)?,
+                var: definition.var,
+                paths: vec![definition.path],
+            }),
+        }
+    }
+    duplicates.retain(|duplicate| duplicate.paths.len() > 1);
+    Ok(duplicates)
+}
+
+/// Removes the assignments to `var` from the blocks managed by this
+/// crate in every configuration file of the user's shell, disabled
+/// ones included, with the metadata comments above them.
+/// Lines the user wrote are left alone.
+/// Returns how many assignments were removed.
+pub fn unset<T: fmt::Display>(var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let mut removed = 0;
+    for path in candidate_files(Access::ReadWrite)? {
+        removed += remove_managed(&path, &var)?;
+    }
+    if crate::backend::is_process_only() {
+        env::remove_var(&var);
+    }
+    Ok(removed)
+}
+
+/// Removes the managed assignments to `var` from every file but the
+/// one it's written to, after `set` wrote it there.
+/// Returns how many lines were removed.
+pub(crate) fn consolidate(var: &str) -> io::Result<usize> {
+    let authoritative = crate::profile_path_for(var)?;
+    let mut removed = 0;
+    for path in candidate_files(Access::ReadWrite)? {
+        if path != authoritative {
+            removed += remove_managed(&path, var)?;
+        }
+    }
+    Ok(removed)
+}
+
+pub(crate) fn remove_managed(path: &Path, var: &str) -> io::Result<usize> {
+    remove_managed_lines(path, |line| {
+        crate::toggle::parse_entry(line).is_some_and(|(assigned, _, _)| assigned == var)
+    })
+}
+
+/// Removes the lines of the block managed by this crate in the file at
+/// `path` for which `matches` returns true, with the metadata comments
+/// above them, and the block if it's left empty.
+/// Returns how many lines `matches` returned true for.
+pub(crate) fn remove_managed_lines(
+    path: &Path,
+    matches: impl Fn(&str) -> bool,
+) -> io::Result<usize> {
+    let content = match crate::filesystem::current().read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    if !content.lines().any(|line| line.trim() == DEFS_BEG) {
+        return Ok(0);
+    }
+    block::check(&content, path)?;
+
+    let lines: Vec<&str> = content.lines().collect();
+    let mut removed = Vec::new();
+    let mut count = 0;
+    for (idx, line) in block::managed_lines(&content) {
+        if matches(line) {
+            if idx > 0 && crate::metadata::is_metadata(lines[idx - 1]) {
+                removed.push(idx - 1);
+            }
+            removed.push(idx);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Ok(0);
+    }
+    // A block left empty goes as well, so unsetting everything that was
+    // set gives back the profile as it was.
+    if let Some((beg, end)) = block::find(&lines) {
+        if (beg + 1..end).all(|idx| removed.contains(&idx)) {
+            removed.extend([beg, end]);
+        }
+    }
+
+    // `remove_lines` passes the lines in order.
+    let idx = Cell::new(0);
+    crate::remove_lines(path, |_| {
+        idx.set(idx.get() + 1);
+        removed.contains(&(idx.get() - 1))
+    })?;
+    Ok(count)
+}
+
+/// Returns every configuration file of the user's shell and every file
+/// this crate writes to.
+fn candidate_files(access: Access) -> io::Result<Vec<PathBuf>> {
+    let (home_dir, shell) = crate::home_and_shell_with(access)?;
+    let mut files = match crate::profile_candidates(&home_dir, &shell) {
+        Ok(files) => files,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+            vec![home_dir.join(crate::platform().fallback_profile)]
+        }
+        Err(err) => return Err(err),
+    };
+    for path in crate::managed_profiles_with(access)? {
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    fn block(lines: &[&str]) -> String {
+        let mut content = format!("{}\n", DEFS_BEG);
+        for line in lines {
+            content.push_str(line);
+            content.push('\n');
+        }
+        content.push_str(DEFS_END);
+        content.push('\n');
+        content
+    }
+
+    #[test]
+    fn duplicates_in_two_files_are_consolidated() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            &block(&["export SET_ENV_DUP=profile", "export SET_ENV_DUP_KEPT=1"]),
+        );
+        sandbox.write(
+            ".bashrc",
+            &format!(
+                "alias ll='ls -l'\n{}",
+                block(&[
+                    "# set_env owner: installer",
+                    "export SET_ENV_DUP=rc",
+                    "export SET_ENV_DUP_OTHER=1"
+                ])
+            ),
+        );
+        let (profile, rc) = (sandbox.path(".bash_profile"), sandbox.path(".bashrc"));
+
+        assert_eq!(
+            managed_duplicates().unwrap(),
+            [Duplicate {
+                var: "SET_ENV_DUP".to_string(),
+                paths: vec![profile.clone(), rc.clone()],
+                authoritative: profile.clone(),
+            }]
+        );
+
+        assert_eq!(consolidate("SET_ENV_DUP").unwrap(), 1);
+        assert_eq!(
+            sandbox.read(".bashrc"),
+            format!(
+                "alias ll='ls -l'\n{}",
+                block(&["export SET_ENV_DUP_OTHER=1"])
+            )
+        );
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            block(&["export SET_ENV_DUP=profile", "export SET_ENV_DUP_KEPT=1"])
+        );
+        assert_eq!(managed_duplicates().unwrap(), []);
+        assert_eq!(consolidate("SET_ENV_DUP").unwrap(), 0);
+    }
+
+    #[test]
+    fn duplicates_in_three_files_are_consolidated_by_set() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", &block(&["export SET_ENV_DUP=profile"]));
+        sandbox.write(
+            ".bashrc",
+            &block(&["export SET_ENV_DUP=rc", "export SET_ENV_DUP_TWICE=rc"]),
+        );
+        sandbox.write(
+            ".bash_login",
+            &format!(
+                "# mine\n{}",
+                block(&[
+                    "export SET_ENV_DUP=login",
+                    "# set_env disabled: export SET_ENV_DUP_TWICE=login"
+                ])
+            ),
+        );
+        let profile = sandbox.path(".bash_profile");
+
+        assert_eq!(
+            managed_duplicates().unwrap(),
+            [
+                Duplicate {
+                    var: "SET_ENV_DUP".to_string(),
+                    paths: vec![
+                        profile.clone(),
+                        sandbox.path(".bashrc"),
+                        sandbox.path(".bash_login"),
+                    ],
+                    authoritative: profile.clone(),
+                },
+                Duplicate {
+                    var: "SET_ENV_DUP_TWICE".to_string(),
+                    paths: vec![sandbox.path(".bashrc"), sandbox.path(".bash_login")],
+                    authoritative: profile.clone(),
+                },
+            ]
+        );
+
+        crate::set("SET_ENV_DUP", "new").unwrap();
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            block(&["export SET_ENV_DUP=profile", "export SET_ENV_DUP=new"])
+        );
+        assert_eq!(
+            sandbox.read(".bashrc"),
+            block(&["export SET_ENV_DUP_TWICE=rc"])
+        );
+        assert_eq!(
+            sandbox.read(".bash_login"),
+            format!(
+                "# mine\n{}",
+                block(&["# set_env disabled: export SET_ENV_DUP_TWICE=login"])
+            )
+        );
+
+        assert_eq!(consolidate("SET_ENV_DUP_TWICE").unwrap(), 2);
+        // Blocks left empty go as well.
+        assert_eq!(sandbox.read(".bashrc"), "");
+        assert_eq!(sandbox.read(".bash_login"), "# mine\n");
+        assert_eq!(managed_duplicates().unwrap(), []);
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+
+use crate::{DEFS_BEG, DEFS_END};
+
+/// Information about why a line was written, put in a comment right
+/// above it, see `set_metadata`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Metadata {
+    /// Who or what wrote the line, like the name of an installer.
+    pub owner: String,
+    /// Anything else worth knowing, like a ticket.
+    pub note: Option<String>,
+}
+
+/// Renders metadata as a comment line and parses it back, for
+/// organizations whose dotfile policies require a specific format.
+pub trait MetadataFormat: Send {
+    /// Returns the comment line for `metadata`.
+    fn render(&self, metadata: &Metadata) -> String;
+    /// Returns the metadata in `line`, if it's a comment `render`
+    /// could have returned.
+    fn parse(&self, line: &str) -> Option<Metadata>;
+}
+
+/// The format used unless `set_metadata_format` is called:
+/// `# set_env owner: <owner> note: <note>`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DefaultMetadataFormat;
+
+const DEFAULT_PREFIX: &str = "# set_env owner: ";
+const DEFAULT_NOTE: &str = " note: ";
+
+impl MetadataFormat for DefaultMetadataFormat {
+    fn render(&self, metadata: &Metadata) -> String {
+        match &metadata.note {
+            Some(note) => format!(
+                "{}{}{}{}",
+                DEFAULT_PREFIX, metadata.owner, DEFAULT_NOTE, note
+            ),
+            None => format!("{}{}", DEFAULT_PREFIX, metadata.owner),
+        }
+    }
+
+    fn parse(&self, line: &str) -> Option<Metadata> {
+        let rest = line.trim().strip_prefix(DEFAULT_PREFIX)?;
+        let (owner, note) = match rest.split_once(DEFAULT_NOTE) {
+            Some((owner, note)) => (owner, Some(note.to_string())),
+            None => (rest, None),
+        };
+        Some(Metadata {
+            owner: owner.to_string(),
+            note,
+        })
+    }
+}
+
+static FORMAT: Mutex<Option<Box<dyn MetadataFormat>>> = Mutex::new(None);
+static CURRENT: Mutex<Option<Metadata>> = Mutex::new(None);
+
+/// Sets the format metadata comments are written in.
+/// Comments in the default format can still be read afterwards.
+pub fn set_metadata_format<F: MetadataFormat + 'static>(format: F) {
+    *FORMAT.lock().unwrap_or_else(|err| err.into_inner()) = Some(Box::new(format));
+}
+
+/// Sets the metadata written above every line added to the block
+/// managed by this crate from now on. Pass `None` to stop writing it.
+pub fn set_metadata(metadata: Option<Metadata>) {
+    *CURRENT.lock().unwrap_or_else(|err| err.into_inner()) = metadata;
+}
+
+/// Sets the metadata written from now on like `set_metadata`,
+/// returning the one set before.
+pub(crate) fn replace(metadata: Option<Metadata>) -> Option<Metadata> {
+    std::mem::replace(
+        &mut *CURRENT.lock().unwrap_or_else(|err| err.into_inner()),
+        metadata,
+    )
+}
+
+/// Returns the metadata written above the last line assigning `var` in
+/// the block managed by this crate, in the configured or the default
+/// format.
+pub fn metadata_of<T: fmt::Display>(var: T) -> io::Result<Option<Metadata>> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let content = match crate::filesystem::current().read_to_string(&crate::profile_path_for(&var)?)
+    {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut managed = false;
+    let mut previous = "";
+    let mut found = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line == DEFS_BEG {
+            managed = true;
+        } else if line == DEFS_END {
+            managed = false;
+        } else if managed
+            && crate::toggle::parse_entry(line).is_some_and(|(assigned, _, _)| assigned == var)
+        {
+            found = Some(parse(previous));
+        }
+        previous = line;
+    }
+    Ok(found.flatten())
+}
+
+/// Returns whether `line` is a metadata comment, in the configured or
+/// the default format.
+pub(crate) fn is_metadata(line: &str) -> bool {
+    parse(line).is_some()
+}
+
+fn parse(line: &str) -> Option<Metadata> {
+    let format = FORMAT.lock().unwrap_or_else(|err| err.into_inner());
+    format
+        .as_ref()
+        .and_then(|format| format.parse(line))
+        .or_else(|| DefaultMetadataFormat.parse(line))
+}
+
+/// Returns `line` preceded by the metadata comment, if metadata is set.
+pub(crate) fn tag(line: &str) -> String {
+    let current = CURRENT.lock().unwrap_or_else(|err| err.into_inner());
+    let Some(metadata) = current.as_ref() else {
+        return line.to_string();
+    };
+    let format = FORMAT.lock().unwrap_or_else(|err| err.into_inner());
+    let comment = match format.as_ref() {
+        Some(format) => format.render(metadata),
+        None => DefaultMetadataFormat.render(metadata),
+    };
+    format!("{}\n{}", comment, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    /// A format like the ones dotfile policies ask for.
+    struct Bracketed;
+
+    impl MetadataFormat for Bracketed {
+        fn render(&self, metadata: &Metadata) -> String {
+            match &metadata.note {
+                Some(note) => format!("# [{}] {}", metadata.owner, note),
+                None => format!("# [{}]", metadata.owner),
+            }
+        }
+
+        fn parse(&self, line: &str) -> Option<Metadata> {
+            let rest = line.trim().strip_prefix("# [")?;
+            let (owner, note) = rest.split_once(']')?;
+            let note = note.trim();
+            Some(Metadata {
+                owner: owner.to_string(),
+                note: (!note.is_empty()).then(|| note.to_string()),
+            })
+        }
+    }
+
+    fn metadata(note: Option<&str>) -> Metadata {
+        Metadata {
+            owner: "installer".to_string(),
+            note: note.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn metadata_round_trips_in_both_formats() {
+        for custom in [false, true] {
+            for note in [None, Some("JIRA-1")] {
+                let _sandbox = sandbox("/bin/bash");
+                if custom {
+                    set_metadata_format(Bracketed);
+                }
+                set_metadata(Some(metadata(note)));
+                crate::set("FOO", "1").unwrap();
+                crate::set("BAR", "2").unwrap();
+                assert_eq!(metadata_of("FOO").unwrap(), Some(metadata(note)));
+                assert_eq!(metadata_of("BAR").unwrap(), Some(metadata(note)));
+            }
+        }
+    }
+
+    #[test]
+    fn unset_removes_the_metadata_with_the_line() {
+        for custom in [false, true] {
+            let sandbox = sandbox("/bin/bash");
+            let before = "alias a=b\n";
+            sandbox.write(".bash_profile", before);
+            if custom {
+                set_metadata_format(Bracketed);
+            }
+
+            set_metadata(Some(metadata(Some("note"))));
+            crate::set("FOO", "1").unwrap();
+            set_metadata(None);
+            crate::set("BAR", "2").unwrap();
+            set_metadata(Some(metadata(None)));
+            crate::set("BAZ", "3").unwrap();
+
+            assert_eq!(crate::unset("FOO").unwrap(), 1);
+            let content = sandbox.read(".bash_profile");
+            assert!(!content.contains("note"), "{}", content);
+            assert_eq!(metadata_of("BAZ").unwrap(), Some(metadata(None)));
+
+            crate::unset("BAZ").unwrap();
+            crate::unset("BAR").unwrap();
+            assert_eq!(sandbox.read(".bash_profile"), before);
+        }
+    }
+}
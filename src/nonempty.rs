@@ -0,0 +1,180 @@
+use std::env;
+use std::fmt;
+use std::io;
+
+use crate::{backend, ExistingSource, SetOutcome};
+
+/// Does the same as `check_or_set_detailed`, but a variable set to an
+/// empty value or only whitespace, like a single space typed by
+/// accident, counts as unset. Such a definition in the profile is
+/// replaced in place with one setting `value` instead of being
+/// overridden by another line.
+#[cfg(target_family = "unix")]
+pub fn check_or_set_nonempty<T: fmt::Display, U: fmt::Display>(
+    var: T,
+    value: U,
+) -> io::Result<SetOutcome> {
+    let _operation = crate::events::operation();
+    let (var, value) = (var.to_string(), value.to_string());
+    crate::startup_cost::warn_value(&var, &value)?;
+    if let Some(skipped) = in_process(&var) {
+        return Ok(skipped);
+    }
+    let mut repaired = Vec::new();
+    if let Some(skipped) = in_profile(&var, &value, &mut repaired)? {
+        return Ok(skipped);
+    }
+    finish(&var, &value, repaired)
+}
+
+/// Does the same as `check_or_set_detailed`, but a variable set to an
+/// empty value or only whitespace, like a single space entered in the
+/// environment variables dialog by accident, counts as unset. Such a
+/// definition in the PowerShell profile or the user registry is
+/// replaced in place with `value` instead of being overridden by
+/// another line.
+#[cfg(target_os = "windows")]
+pub fn check_or_set_nonempty<T: fmt::Display, U: fmt::Display>(
+    var: T,
+    value: U,
+) -> io::Result<SetOutcome> {
+    let _operation = crate::events::operation();
+    crate::windows::check_or_set_nonempty_with(&crate::windows::UserEnvironment, var, value)
+}
+
+/// Returns whether `value` is empty or only whitespace.
+pub(crate) fn is_blank(value: &str) -> bool {
+    value.trim().is_empty()
+}
+
+/// Returns the outcome if the current process has a value for `var`
+/// that isn't blank.
+pub(crate) fn in_process(var: &str) -> Option<SetOutcome> {
+    let existing = env::var(var).ok().filter(|it| !is_blank(it))?;
+    crate::events::skipped(var, "set in the current process");
+    Some(SetOutcome::Skipped {
+        source: ExistingSource::ProcessEnv,
+        value: Some(existing),
+    })
+}
+
+/// Returns the outcome if the profile assigns `var` a value that isn't
+/// blank. A blank assignment is replaced with one setting `value` and
+/// added to `repaired`.
+pub(crate) fn in_profile(
+    var: &str,
+    value: &str,
+    repaired: &mut Vec<ExistingSource>,
+) -> io::Result<Option<SetOutcome>> {
+    let Some(definition) = crate::find_definition(var)? else {
+        return Ok(None);
+    };
+    if !is_blank(crate::flags::unquote(&definition.value)) {
+        crate::events::skipped(var, "assigned in the profile");
+        return Ok(Some(SetOutcome::Skipped {
+            source: definition.source(),
+            value: Some(definition.value),
+        }));
+    }
+    let line = crate::profile_syntax()?.set_line(var, value);
+    crate::replace_line(&definition.path, definition.line_no, &line)?;
+    repaired.push(definition.source());
+    Ok(None)
+}
+
+/// Sets `var` to `value` unless blank definitions were `repaired`.
+pub(crate) fn finish(
+    var: &str,
+    value: &str,
+    repaired: Vec<ExistingSource>,
+) -> io::Result<SetOutcome> {
+    if repaired.is_empty() {
+        crate::set(var, value)?;
+        return Ok(SetOutcome::Written);
+    }
+    crate::changed(var, backend::Update::Set(value));
+    Ok(SetOutcome::Repaired { sources: repaired })
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{DEFS_BEG, DEFS_END};
+
+    #[test]
+    fn blank_profile_lines_are_replaced_in_place() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            &format!(
+                "export SET_ENV_BLANK_USER=\" \"\nalias ll='ls -l'\n{}\n\
+                 export SET_ENV_BLANK_MANAGED='\t'\nexport SET_ENV_BLANK_EMPTY=\n{}\n",
+                DEFS_BEG, DEFS_END
+            ),
+        );
+        let profile = sandbox.path(".bash_profile");
+
+        assert_eq!(
+            check_or_set_nonempty("SET_ENV_BLANK_USER", "user").unwrap(),
+            SetOutcome::Repaired {
+                sources: vec![ExistingSource::UserLine(profile.clone(), 1)]
+            }
+        );
+        assert_eq!(
+            check_or_set_nonempty("SET_ENV_BLANK_MANAGED", "managed").unwrap(),
+            SetOutcome::Repaired {
+                sources: vec![ExistingSource::ManagedEntry(profile.clone())]
+            }
+        );
+        assert_eq!(
+            check_or_set_nonempty("SET_ENV_BLANK_EMPTY", "empty").unwrap(),
+            SetOutcome::Repaired {
+                sources: vec![ExistingSource::ManagedEntry(profile.clone())]
+            }
+        );
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "export SET_ENV_BLANK_USER=user\nalias ll='ls -l'\n{}\n\
+                 export SET_ENV_BLANK_MANAGED=managed\nexport SET_ENV_BLANK_EMPTY=empty\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+
+        assert_eq!(
+            check_or_set_nonempty("SET_ENV_BLANK_USER", "other").unwrap(),
+            SetOutcome::Skipped {
+                source: ExistingSource::UserLine(profile, 1),
+                value: Some("user".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn a_blank_value_in_the_process_counts_as_unset() {
+        let sandbox = sandbox("/bin/bash");
+        env::set_var("SET_ENV_BLANK_PROCESS", " \t ");
+        let outcome = check_or_set_nonempty("SET_ENV_BLANK_PROCESS", "set");
+        env::set_var("SET_ENV_BLANK_PROCESS_KEPT", " kept ");
+        let kept = check_or_set_nonempty("SET_ENV_BLANK_PROCESS_KEPT", "set");
+        env::remove_var("SET_ENV_BLANK_PROCESS");
+        env::remove_var("SET_ENV_BLANK_PROCESS_KEPT");
+
+        assert_eq!(outcome.unwrap(), SetOutcome::Written);
+        assert_eq!(
+            kept.unwrap(),
+            SetOutcome::Skipped {
+                source: ExistingSource::ProcessEnv,
+                value: Some(" kept ".to_string()),
+            }
+        );
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\nexport SET_ENV_BLANK_PROCESS=set\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::managed::{remove_managed, remove_managed_lines};
+
+/// The variables naming the file non-interactive bash and POSIX sh source.
+const STARTUP_VARS: &[&str] = &["BASH_ENV", "ENV"];
+
+/// Where `set_noninteractive` wrote the variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NonInteractive {
+    /// The file `BASH_ENV` or `ENV` already pointed to.
+    Existing(PathBuf),
+    /// The crate's own file, which `BASH_ENV` and `ENV` now point to
+    /// in the profile.
+    Bootstrapped { file: PathBuf, profile: PathBuf },
+}
+
+/// Sets an environment variable for non-interactive shells,
+/// like `bash -c` run from cron or a Makefile,
+/// which don't read the profile.
+/// The assignment goes to the block managed by this crate in the file
+/// named by `BASH_ENV` or `ENV`.
+/// If neither is set to a writable file and `bootstrap` is true,
+/// `~/.config/set_env/noninteractive.sh` is created and both variables
+/// are set to it in the profile.
+pub fn set_noninteractive<T: fmt::Display, U: fmt::Display>(
+    var: T,
+    value: U,
+    bootstrap: bool,
+) -> io::Result<NonInteractive> {
+    let _operation = crate::events::operation();
+    let line = format!("export {}={}", var, value);
+
+    if let Some(file) = existing_startup_file() {
+        crate::write_to_profile_at(&file, &line)?;
+        return Ok(NonInteractive::Existing(file));
+    }
+
+    if !bootstrap {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither BASH_ENV nor ENV point to a writable file",
+        ));
+    }
+
+    let file = bootstrap_file()?;
+    if let Some(dir) = file.parent() {
+        if !crate::is_dry_run() {
+            crate::filesystem::current().create_dir_all(dir)?;
+        }
+    }
+    crate::write_to_profile_at(&file, &line)?;
+
+    for startup_var in STARTUP_VARS {
+        if !crate::profile_contains(&startup_line(startup_var, &file)?)? {
+            crate::set(startup_var, quoted(&file))?;
+        }
+    }
+
+    Ok(NonInteractive::Bootstrapped {
+        file,
+        profile: crate::profile_path()?,
+    })
+}
+
+/// Removes what `set_noninteractive` wrote for `var`, from the blocks
+/// managed by this crate only, so assignments the user wrote to the
+/// `BASH_ENV` or `ENV` file stay.
+/// When the crate's own file is left without assignments it is
+/// deleted and `BASH_ENV` and `ENV` are removed from the profile again.
+/// Returns whether anything was removed.
+pub fn unset_noninteractive<T: fmt::Display>(var: T) -> io::Result<bool> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let mut removed = false;
+
+    let mut files: Vec<PathBuf> = existing_startup_file().into_iter().collect();
+    let own_file = bootstrap_file()?;
+    if !files.contains(&own_file) {
+        files.push(own_file.clone());
+    }
+
+    for file in &files {
+        removed |= remove_managed(file, &var)? > 0;
+    }
+
+    let fs = crate::filesystem::current();
+    let own_content = match fs.read_to_string(&own_file) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(removed),
+        Err(err) => return Err(err),
+    };
+    if own_content.trim().is_empty() {
+        if !crate::dry_run::plan(|| crate::PlannedChange::RemoveFile {
+            path: own_file.clone(),
+        }) {
+            fs.remove_file(&own_file)?;
+        }
+        let startup_lines = STARTUP_VARS
+            .iter()
+            .map(|startup_var| startup_line(startup_var, &own_file))
+            .collect::<io::Result<Vec<String>>>()?;
+        remove_managed_lines(&crate::profile_path()?, |line| {
+            startup_lines.iter().any(|it| it == line)
+        })?;
+        removed = true;
+    }
+
+    Ok(removed)
+}
+
+fn existing_startup_file() -> Option<PathBuf> {
+    let fs = crate::filesystem::current();
+    STARTUP_VARS
+        .iter()
+        .filter_map(env::var_os)
+        .map(PathBuf::from)
+        .find(|file| fs.is_writable(file))
+}
+
+fn bootstrap_file() -> io::Result<PathBuf> {
+    let (home_dir, _) = crate::home_and_shell()?;
+    Ok(home_dir.join(".config/set_env/noninteractive.sh"))
+}
+
+/// Formats the line `set_noninteractive` adds to the profile
+/// to point `startup_var` to `file`.
+fn startup_line(startup_var: &str, file: &Path) -> io::Result<String> {
+    Ok(crate::profile_syntax()?.set_line(startup_var, quoted(file)))
+}
+
+fn quoted(file: &Path) -> String {
+    format!("\"{}\"", file.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn writes_into_the_existing_startup_file_and_leaves_the_users_lines() {
+        let sandbox = sandbox("/bin/bash");
+        let user = "export SET_ENV_NI_MINE=1\nexport SET_ENV_NI_VAR=user\n";
+        sandbox.write(".bash_env", user);
+        env::set_var("BASH_ENV", sandbox.path(".bash_env"));
+
+        assert_eq!(
+            set_noninteractive("SET_ENV_NI_VAR", "crate", false).unwrap(),
+            NonInteractive::Existing(sandbox.path(".bash_env"))
+        );
+        let content = sandbox.read(".bash_env");
+        assert!(content.starts_with(user), "{}", content);
+        assert!(
+            content.contains(&format!(
+                "{}\nexport SET_ENV_NI_VAR=crate\n{}",
+                crate::DEFS_BEG,
+                crate::DEFS_END
+            )),
+            "{}",
+            content
+        );
+        assert!(!sandbox.path(".config/set_env/noninteractive.sh").exists());
+
+        assert!(unset_noninteractive("SET_ENV_NI_VAR").unwrap());
+        assert_eq!(sandbox.read(".bash_env"), user);
+        assert!(!unset_noninteractive("SET_ENV_NI_VAR").unwrap());
+        assert_eq!(sandbox.read(".bash_env"), user);
+    }
+
+    #[test]
+    fn bootstraps_its_own_file_and_removes_it_again() {
+        let sandbox = sandbox("/bin/bash");
+        let profile = "alias ll='ls -l'\n";
+        sandbox.write(".bash_profile", profile);
+        let file = sandbox.path(".config/set_env/noninteractive.sh");
+
+        let err = set_noninteractive("SET_ENV_NI_ONE", 1, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!file.exists());
+
+        let bootstrapped = NonInteractive::Bootstrapped {
+            file: file.clone(),
+            profile: sandbox.path(".bash_profile"),
+        };
+        assert_eq!(
+            set_noninteractive("SET_ENV_NI_ONE", 1, true).unwrap(),
+            bootstrapped
+        );
+        assert_eq!(
+            set_noninteractive("SET_ENV_NI_TWO", 2, true).unwrap(),
+            bootstrapped
+        );
+        let content = sandbox.read(".config/set_env/noninteractive.sh");
+        assert!(content.contains("export SET_ENV_NI_ONE=1\nexport SET_ENV_NI_TWO=2\n"));
+        let content = sandbox.read(".bash_profile");
+        for startup_var in STARTUP_VARS {
+            let line = format!("export {}=\"{}\"", startup_var, file.display());
+            assert_eq!(content.matches(&line).count(), 1, "{}", content);
+        }
+
+        assert!(unset_noninteractive("SET_ENV_NI_ONE").unwrap());
+        assert!(file.exists());
+        assert!(unset_noninteractive("SET_ENV_NI_TWO").unwrap());
+        assert!(!file.exists());
+        assert_eq!(sandbox.read(".bash_profile"), profile);
+    }
+}
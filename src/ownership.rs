@@ -0,0 +1,182 @@
+use std::fmt;
+use std::io;
+
+use crate::{block, line_endings, metadata, placement, toggle, DEFS_BEG, DEFS_END};
+
+/// Moves the lines assigning `var` out of the block managed by this
+/// crate, right below it, so the value stays in effect but the user
+/// owns the lines from then on. Disabled lines stay disabled, and the
+/// metadata comments above the lines are dropped.
+/// Returns how many lines were moved.
+pub fn unmanage<T: fmt::Display>(var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    move_lines(&var, |content| take_managed(content, &var))
+}
+
+/// Moves the lines assigning `var` written by the user into the block
+/// managed by this crate, the inverse of `unmanage`.
+/// The lines end up at the end of the block, which is created where
+/// the first of them was if there is none, preceded by the metadata
+/// comment if metadata is set, like lines written by `set`. Lines
+/// disabled by `unmanage` come back disabled.
+/// Returns how many lines were moved.
+pub fn adopt<T: fmt::Display>(var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    move_lines(&var, |content| take_unmanaged(content, &var))
+}
+
+/// The lines of a profile with some of them taken out.
+struct Taken {
+    rest: Vec<String>,
+    taken: Vec<String>,
+    /// The index in `rest` the first taken line was at.
+    first: usize,
+}
+
+/// Changes the profile to what `take` returns, which also returns
+/// the lines it moved, or `None` if there are none.
+fn move_lines(
+    var: &str,
+    take: impl Fn(&str) -> Option<(String, Vec<String>)>,
+) -> io::Result<usize> {
+    let path = crate::profile_path_for(var)?;
+    let content = match crate::filesystem::current().read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    block::check(&content, &path)?;
+    let Some((_, moved)) = take(&content) else {
+        return Ok(0);
+    };
+
+    crate::edit_profile(&moved.join("\n"), |content| match take(content) {
+        Some((content, moved)) => (content, moved.len()),
+        None => (content.to_string(), 0),
+    })
+}
+
+fn take_managed(content: &str, var: &str) -> Option<(String, Vec<String>)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let (beg, end) = block::find(&lines)?;
+    let moved = |idx: usize| idx > beg && idx < end && assigns(lines[idx], var);
+    let Taken {
+        mut rest, taken, ..
+    } = take(&lines, |idx, line| {
+        moved(idx) || (metadata::is_metadata(line) && moved(idx + 1))
+    })?;
+    // The user owns the lines now, so who wrote them no longer applies.
+    let taken: Vec<String> = taken
+        .into_iter()
+        .filter(|line| !metadata::is_metadata(line))
+        .collect();
+
+    let end = rest.iter().position(|line| line.trim() == DEFS_END)?;
+    rest.splice(end + 1..end + 1, taken.iter().cloned());
+    // An empty block is left out entirely.
+    if end > 0 && rest[end - 1].trim() == DEFS_BEG {
+        rest.drain(end - 1..=end);
+    }
+    Some((line_endings::rejoin(content, &rest), taken))
+}
+
+fn take_unmanaged(content: &str, var: &str) -> Option<(String, Vec<String>)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let block = block::find(&lines);
+    let inside = |idx: usize| block.is_some_and(|(beg, end)| idx >= beg && idx <= end);
+    let Taken { rest, taken, first } =
+        take(&lines, |idx, line| !inside(idx) && assigns(line, var))?;
+
+    let mut content = line_endings::rejoin(content, &rest);
+    for line in &taken {
+        content = block::insert(&content, &metadata::tag(line), Some(first));
+    }
+    Some((content, taken))
+}
+
+/// Returns whether `line` changes `var`, disabled or not.
+fn assigns(line: &str, var: &str) -> bool {
+    placement::modifies(toggle::undisabled(line.trim()), var)
+}
+
+/// Splits `lines` into the ones `predicate` matches and the rest,
+/// or returns `None` if it matches none.
+fn take(lines: &[&str], predicate: impl Fn(usize, &str) -> bool) -> Option<Taken> {
+    let mut taken = Vec::new();
+    let mut rest = Vec::new();
+    let mut first = None;
+    for (idx, line) in lines.iter().enumerate() {
+        if predicate(idx, line) {
+            first.get_or_insert(rest.len());
+            taken.push(line.to_string());
+        } else {
+            rest.push(line.to_string());
+        }
+    }
+    first.map(|first| Taken { rest, taken, first })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::Metadata;
+
+    #[test]
+    fn unmanage_and_adopt_round_trip_with_metadata() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias ll='ls -l'\n");
+        crate::set_metadata(Some(Metadata {
+            owner: "installer".to_string(),
+            note: None,
+        }));
+        crate::set("SET_ENV_OWNED", "1").unwrap();
+        let managed = sandbox.read(".bash_profile");
+        assert!(managed.contains("# set_env owner: installer\n"));
+
+        assert_eq!(unmanage("SET_ENV_OWNED").unwrap(), 1);
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            "alias ll='ls -l'\nexport SET_ENV_OWNED=1\n"
+        );
+        assert_eq!(unmanage("SET_ENV_OWNED").unwrap(), 0);
+
+        assert_eq!(adopt("SET_ENV_OWNED").unwrap(), 1);
+        assert_eq!(sandbox.read(".bash_profile"), managed);
+        assert_eq!(adopt("SET_ENV_OWNED").unwrap(), 0);
+    }
+
+    #[test]
+    fn unmanage_and_adopt_keep_disabled_lines_disabled() {
+        let sandbox = sandbox("/bin/bash");
+        crate::set("SET_ENV_OWNED_KEPT", "1").unwrap();
+        crate::set_disabled("SET_ENV_OWNED_OFF", "2").unwrap();
+
+        assert_eq!(unmanage("SET_ENV_OWNED_OFF").unwrap(), 1);
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\nexport SET_ENV_OWNED_KEPT=1\n{}\n\
+                 # set_env disabled: export SET_ENV_OWNED_OFF=2\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+        assert_eq!(crate::enable("SET_ENV_OWNED_OFF").unwrap(), 0);
+
+        assert_eq!(adopt("SET_ENV_OWNED_OFF").unwrap(), 1);
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\nexport SET_ENV_OWNED_KEPT=1\n\
+                 # set_env disabled: export SET_ENV_OWNED_OFF=2\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+        assert_eq!(crate::enable("SET_ENV_OWNED_OFF").unwrap(), 1);
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("\nexport SET_ENV_OWNED_OFF=2\n"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An entry of a list variable like PATH, see `path_entries`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PathEntry {
+    /// The position in the variable, counted from 0.
+    pub index: usize,
+    /// The entry as it is in the variable.
+    pub raw: String,
+    /// The entry with a leading `~` and references to other variables
+    /// expanded, or `None` if it refers to a variable that isn't set.
+    pub expanded: Option<PathBuf>,
+    /// Whether the expanded entry is an existing directory.
+    pub exists: bool,
+    /// Whether the block managed by this crate adds the entry.
+    pub managed: bool,
+    /// The index of the first entry this one duplicates, if any.
+    pub duplicate_of: Option<usize>,
+}
+
+/// The entries of a list variable, displayed as a table.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PathEntries(pub Vec<PathEntry>);
+
+/// Returns the entries of the list variable `var` in the current
+/// process, annotated with whether they exist, are added by the
+/// block managed by this crate and duplicate earlier entries.
+pub fn path_entries<T: fmt::Display>(var: T) -> io::Result<PathEntries> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let value = env::var(&var).unwrap_or_default();
+    let syntax = crate::profile_syntax()?;
+    let managed: Vec<String> = crate::managed_list_lines(&var)?
+        .iter()
+        .flat_map(|(_, value)| syntax.split_entries(value))
+        .map(str::to_string)
+        .collect();
+
+    let raw: Vec<&str> = value
+        .split(crate::LIST_SEPARATOR)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    // Entries are compared by what they expand to, so `~/bin` and
+    // `$HOME/bin` are the same.
+    let keys: Vec<String> = raw.iter().map(|entry| comparable(entry)).collect();
+    let entries = raw
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let expanded = expand(entry);
+            let same = |other: &String| crate::same_entry(other, &keys[index]);
+            PathEntry {
+                index,
+                raw: entry.to_string(),
+                exists: expanded.as_deref().is_some_and(Path::is_dir),
+                expanded,
+                managed: managed.iter().map(|it| comparable(it)).any(|it| same(&it)),
+                duplicate_of: keys[..index].iter().position(same),
+            }
+        })
+        .collect();
+    Ok(PathEntries(entries))
+}
+
+fn comparable(entry: &str) -> String {
+    expand_str(entry).unwrap_or(entry.to_string())
+}
+
+/// Expands a leading `~`, `$VAR`, `${VAR}` and, on Windows, `%VAR%`.
+fn expand(entry: &str) -> Option<PathBuf> {
+    expand_str(entry).map(PathBuf::from)
+}
+
+/// Does the same as `expand`, returning a string. `None` if a variable
+/// isn't set.
+pub(crate) fn expand_str(entry: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = entry;
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            out.push_str(&dirs::home_dir()?.to_string_lossy());
+            rest = after;
+        }
+    }
+
+    while let Some(start) = rest.find(['$', '%']) {
+        out.push_str(&rest[..start]);
+        let (name, after) = if rest[start..].starts_with('%') {
+            if !cfg!(target_os = "windows") {
+                out.push('%');
+                rest = &rest[start + 1..];
+                continue;
+            }
+            match rest[start + 1..].split_once('%') {
+                Some((name, after)) => (name, after),
+                None => ("", &rest[start + 1..]),
+            }
+        } else if let Some(braced) = rest[start + 1..].strip_prefix('{') {
+            braced.split_once('}').unwrap_or(("", braced))
+        } else {
+            let after = &rest[start + 1..];
+            let len = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            after.split_at(len)
+        };
+        if name.is_empty() {
+            out.push_str(&rest[start..start + 1]);
+            rest = &rest[start + 1..];
+            continue;
+        }
+        out.push_str(&env::var(name).ok()?);
+        rest = after;
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+impl fmt::Display for PathEntries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|entry| entry.raw.len())
+            .max()
+            .unwrap_or(0);
+        for entry in &self.0 {
+            let mut notes = Vec::new();
+            if entry.managed {
+                notes.push("managed".to_string());
+            }
+            if !entry.exists {
+                notes.push("missing".to_string());
+            }
+            if let Some(first) = entry.duplicate_of {
+                notes.push(format!("duplicate of {}", first));
+            }
+            let line = format!(
+                "{:>3}  {:<width$}  {}",
+                entry.index,
+                entry.raw,
+                notes.join(", "),
+                width = width
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn entries_expand_home_and_variables() {
+        let sandbox = sandbox("/bin/bash");
+        env::set_var("SET_ENV_ENTRIES_ROOT", "/opt/root");
+        env::remove_var("SET_ENV_ENTRIES_UNSET");
+        let home = sandbox.home.to_string_lossy().into_owned();
+        let cases = [
+            ("~", Some(home.clone())),
+            ("~/bin", Some(format!("{}/bin", home))),
+            ("~other/bin", Some("~other/bin".to_string())),
+            ("/srv/~/bin", Some("/srv/~/bin".to_string())),
+            ("$HOME/bin", Some(format!("{}/bin", home))),
+            (
+                "$SET_ENV_ENTRIES_ROOT/bin",
+                Some("/opt/root/bin".to_string()),
+            ),
+            (
+                "${SET_ENV_ENTRIES_ROOT}bin",
+                Some("/opt/rootbin".to_string()),
+            ),
+            (
+                "$SET_ENV_ENTRIES_ROOT-1/bin",
+                Some("/opt/root-1/bin".to_string()),
+            ),
+            ("$SET_ENV_ENTRIES_UNSET/bin", None),
+            ("${SET_ENV_ENTRIES_UNSET}/bin", None),
+            ("/usr/$/bin", Some("/usr/$/bin".to_string())),
+            (
+                "/usr/%SET_ENV_ENTRIES_ROOT%",
+                Some("/usr/%SET_ENV_ENTRIES_ROOT%".to_string()),
+            ),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(expand_str(entry), expected, "{}", entry);
+        }
+        env::remove_var("SET_ENV_ENTRIES_ROOT");
+    }
+
+    #[test]
+    fn entries_are_annotated() {
+        let sandbox = sandbox("/bin/bash");
+        std::fs::create_dir_all(sandbox.path("bin")).unwrap();
+        std::fs::create_dir_all(sandbox.path("managed")).unwrap();
+        env::remove_var("SET_ENV_ENTRIES_UNSET");
+        crate::append("SET_ENV_ENTRIES", "~/managed").unwrap();
+        let value = [
+            "~/bin",
+            "$HOME/managed",
+            "${SET_ENV_ENTRIES_UNSET}/bin",
+            "",
+            "/set_env/missing",
+            "$HOME/bin",
+        ]
+        .join(":");
+        env::set_var("SET_ENV_ENTRIES", &value);
+
+        let entries = path_entries("SET_ENV_ENTRIES").unwrap();
+        env::remove_var("SET_ENV_ENTRIES");
+        let annotated: Vec<_> = entries
+            .0
+            .iter()
+            .map(|it| {
+                (
+                    it.index,
+                    it.raw.as_str(),
+                    it.exists,
+                    it.managed,
+                    it.duplicate_of,
+                )
+            })
+            .collect();
+        assert_eq!(
+            annotated,
+            [
+                (0, "~/bin", true, false, None),
+                (1, "$HOME/managed", true, true, None),
+                (2, "${SET_ENV_ENTRIES_UNSET}/bin", false, false, None),
+                (3, "/set_env/missing", false, false, None),
+                (4, "$HOME/bin", true, false, Some(0)),
+            ]
+        );
+        assert_eq!(entries.0[0].expanded, Some(sandbox.path("bin")));
+        assert_eq!(entries.0[2].expanded, None);
+
+        assert_eq!(
+            entries.to_string(),
+            "  0  ~/bin\n\
+             \x20 1  $HOME/managed                 managed\n\
+             \x20 2  ${SET_ENV_ENTRIES_UNSET}/bin  missing\n\
+             \x20 3  /set_env/missing              missing\n\
+             \x20 4  $HOME/bin                     duplicate of 0\n"
+        );
+    }
+}
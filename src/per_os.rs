@@ -0,0 +1,233 @@
+use std::fmt;
+use std::io;
+
+/// A value per operating system, see `set_per_os`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PerOs<V> {
+    pub linux: Option<V>,
+    pub macos: Option<V>,
+    pub windows: Option<V>,
+    /// Used on any platform without its own value.
+    pub other: Option<V>,
+}
+
+impl<V> Default for PerOs<V> {
+    fn default() -> Self {
+        PerOs {
+            linux: None,
+            macos: None,
+            windows: None,
+            other: None,
+        }
+    }
+}
+
+impl<V> PerOs<V> {
+    /// Creates a `PerOs` without any values, to be filled in with
+    /// `linux`, `macos`, `windows` and `other`.
+    pub fn new() -> Self {
+        PerOs::default()
+    }
+
+    pub fn linux(mut self, value: V) -> Self {
+        self.linux = Some(value);
+        self
+    }
+
+    pub fn macos(mut self, value: V) -> Self {
+        self.macos = Some(value);
+        self
+    }
+
+    pub fn windows(mut self, value: V) -> Self {
+        self.windows = Some(value);
+        self
+    }
+
+    pub fn other(mut self, value: V) -> Self {
+        self.other = Some(value);
+        self
+    }
+
+    /// Returns the value for the platform this runs on.
+    pub fn current(&self) -> Option<&V> {
+        let own = if cfg!(target_os = "linux") {
+            self.linux.as_ref()
+        } else if cfg!(target_os = "macos") {
+            self.macos.as_ref()
+        } else if cfg!(target_os = "windows") {
+            self.windows.as_ref()
+        } else {
+            None
+        };
+        own.or(self.other.as_ref())
+    }
+}
+
+/// What `set_per_os`, `append_per_os` and `prepend_per_os` did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PerOsOutcome {
+    /// The value for this platform was written.
+    Written,
+    /// There is no value for this platform, so nothing was written.
+    NoValueForPlatform,
+}
+
+/// Does the same as `set` with the value for the platform this runs on,
+/// if there is one. Warnings about the change are emitted as
+/// `Event::Warning`s.
+pub fn set_per_os<T: fmt::Display, V: fmt::Display>(
+    var: T,
+    values: PerOs<V>,
+) -> io::Result<PerOsOutcome> {
+    let _operation = crate::events::operation();
+    with_current(&values, |value| {
+        crate::set(&var, value).map(crate::Change::emit_warnings)
+    })
+}
+
+/// Does the same as `append` with the value for the platform this
+/// runs on, if there is one. Warnings about the change are emitted as
+/// `Event::Warning`s.
+pub fn append_per_os<T: fmt::Display, V: fmt::Display>(
+    var: T,
+    values: PerOs<V>,
+) -> io::Result<PerOsOutcome> {
+    let _operation = crate::events::operation();
+    with_current(&values, |value| {
+        crate::append(var.to_string(), value.to_string()).map(crate::Change::emit_warnings)
+    })
+}
+
+/// Does the same as `prepend` with the value for the platform this
+/// runs on, if there is one. Warnings about the change are emitted as
+/// `Event::Warning`s.
+pub fn prepend_per_os<T: fmt::Display, V: fmt::Display>(
+    var: T,
+    values: PerOs<V>,
+) -> io::Result<PerOsOutcome> {
+    let _operation = crate::events::operation();
+    with_current(&values, |value| {
+        crate::prepend(var.to_string(), value.to_string()).map(crate::Change::emit_warnings)
+    })
+}
+
+fn with_current<V>(
+    values: &PerOs<V>,
+    write: impl FnOnce(&V) -> io::Result<()>,
+) -> io::Result<PerOsOutcome> {
+    match values.current() {
+        Some(value) => {
+            write(value)?;
+            Ok(PerOsOutcome::Written)
+        }
+        None => Ok(PerOsOutcome::NoValueForPlatform),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The index of the platform the tests run on in the expected
+    /// values: Linux, macOS, Windows or another one.
+    fn this_platform() -> usize {
+        if cfg!(target_os = "linux") {
+            0
+        } else if cfg!(target_os = "macos") {
+            1
+        } else if cfg!(target_os = "windows") {
+            2
+        } else {
+            3
+        }
+    }
+
+    /// Only a value for a platform the tests don't run on.
+    fn other_platform_only() -> PerOs<&'static str> {
+        if cfg!(target_os = "linux") {
+            PerOs::new().macos("mac")
+        } else {
+            PerOs::new().linux("lin")
+        }
+    }
+
+    #[test]
+    fn current_picks_the_value_of_this_platform() {
+        let cases: [(PerOs<&str>, [Option<&str>; 4]); 5] = [
+            (PerOs::new(), [None; 4]),
+            (
+                PerOs::new().linux("lin").macos("mac").windows("win"),
+                [Some("lin"), Some("mac"), Some("win"), None],
+            ),
+            (PerOs::new().other("any"), [Some("any"); 4]),
+            (
+                PerOs::new().macos("mac").other("any"),
+                [Some("any"), Some("mac"), Some("any"), Some("any")],
+            ),
+            (
+                PerOs::new().linux("lin").windows("win").other("any"),
+                [Some("lin"), Some("any"), Some("win"), Some("any")],
+            ),
+        ];
+        for (values, expected) in cases {
+            assert_eq!(
+                values.current().copied(),
+                expected[this_platform()],
+                "{:?}",
+                values
+            );
+        }
+    }
+
+    #[test]
+    fn nothing_is_written_without_a_value_for_this_platform() {
+        let written = std::cell::Cell::new(false);
+        let outcome = with_current(&other_platform_only(), |_| {
+            written.set(true);
+            Ok(())
+        });
+        assert_eq!(outcome.unwrap(), PerOsOutcome::NoValueForPlatform);
+        assert!(!written.get());
+    }
+
+    #[test]
+    fn errors_writing_the_value_are_returned() {
+        let outcome = with_current(&PerOs::new().other("any"), |value| {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, *value))
+        });
+        assert_eq!(outcome.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn writes_the_value_for_this_platform_only() {
+        let sandbox = crate::test_support::sandbox("/bin/bash");
+        assert_eq!(
+            set_per_os("SET_ENV_PER_OS_SKIPPED", other_platform_only()).unwrap(),
+            PerOsOutcome::NoValueForPlatform
+        );
+        assert_eq!(
+            append_per_os("SET_ENV_PER_OS_SKIPPED", other_platform_only()).unwrap(),
+            PerOsOutcome::NoValueForPlatform
+        );
+        assert!(!sandbox.path(".bash_profile").exists());
+
+        let values = PerOs::new()
+            .linux("/opt/linux")
+            .macos("/opt/macos")
+            .other("/opt/other");
+        let expected = values.current().unwrap().to_string();
+        assert_eq!(
+            set_per_os("SET_ENV_PER_OS", values.clone()).unwrap(),
+            PerOsOutcome::Written
+        );
+        assert_eq!(
+            prepend_per_os("SET_ENV_PER_OS_DIRS", values).unwrap(),
+            PerOsOutcome::Written
+        );
+        let profile = sandbox.read(".bash_profile");
+        assert!(profile.contains(&format!("export SET_ENV_PER_OS={}\n", expected)));
+        assert!(profile.contains(&format!("{}${{SET_ENV_PER_OS_DIRS:+", expected)));
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+use std::io;
+
+use crate::list_strategy::{self, ListStrategy};
+use crate::{backend, block, Change};
+
+/// Where `append_with` and `prepend_with` put the block managed by
+/// this crate when creating it, relative to the lines of the profile
+/// that already change the variable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Placement {
+    /// At the end of the profile, like `append` and `prepend`.
+    End,
+    /// Right after the last line changing the variable, so the
+    /// entry comes after the ones added by the user.
+    AfterLastManipulation,
+    /// Right before the first line changing the variable, so the
+    /// entry comes before the ones added by the user.
+    BeforeFirstManipulation,
+    /// At the end of the profile, with a line putting the entry right
+    /// before the first system directory of the variable when the shell
+    /// starts, see `SyntaxInfo::before_system_line`. The entry shadows
+    /// programs like `/usr/bin/git` but not the ones in the user's
+    /// `~/bin` or `~/.local/bin`. Not supported for csh.
+    BeforeSystemPaths,
+}
+
+/// Where `append_with` and `prepend_with` put the line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlacementDecision {
+    /// At the end of the profile, because that was asked for or no line
+    /// changing the variable was found.
+    End,
+    /// After the line with the given number, counted from 1.
+    AfterLine(usize),
+    /// Before the line with the given number, counted from 1.
+    BeforeLine(usize),
+    /// Into the existing managed block, wherever that is.
+    ExistingBlock,
+}
+
+/// Does the same as `append`, placing the managed block according to
+/// `placement` if it doesn't exist yet, or splicing the entry in with
+/// `Placement::BeforeSystemPaths`. Warnings about the change are
+/// emitted as `Event::Warning`s.
+pub fn append_with<T: fmt::Display>(
+    var: T,
+    value: T,
+    placement: Placement,
+) -> io::Result<PlacementDecision> {
+    let _operation = crate::events::operation();
+    add_with(&var.to_string(), &value.to_string(), placement, true)
+}
+
+/// Does the same as `prepend`, placing the managed block according to
+/// `placement` if it doesn't exist yet, or splicing the entry in with
+/// `Placement::BeforeSystemPaths`. Warnings about the change are
+/// emitted as `Event::Warning`s.
+pub fn prepend_with<T: fmt::Display>(
+    var: T,
+    value: T,
+    placement: Placement,
+) -> io::Result<PlacementDecision> {
+    let _operation = crate::events::operation();
+    add_with(&var.to_string(), &value.to_string(), placement, false)
+}
+
+/// Mirrors `append` and `prepend`, with the line placed by `place`.
+/// A variable stored with `ListStrategy::Composed` already has its
+/// entries in the managed block, so they go there.
+fn add_with(
+    var: &str,
+    value: &str,
+    placement: Placement,
+    append: bool,
+) -> io::Result<PlacementDecision> {
+    let mut change = Change::default();
+    change.warnings.extend(crate::var_kind::check_list(var)?);
+    let (value, warnings) = crate::hygiene::review(var, value);
+    change.warnings.extend(warnings);
+    change
+        .warnings
+        .extend(crate::startup_cost::check_value(var, &value)?);
+
+    let decision = match (placement, list_strategy::list_strategy(var)?) {
+        (Placement::BeforeSystemPaths, _) => {
+            place(var, &before_system_line(var, &value)?, placement)?
+        }
+        (_, ListStrategy::Composed) => {
+            list_strategy::compose(var, &value, append)?;
+            PlacementDecision::ExistingBlock
+        }
+        (_, ListStrategy::PerEntry) if append => {
+            place(var, &crate::append_line(&var, &value.as_str())?, placement)?
+        }
+        (_, ListStrategy::PerEntry) => {
+            place(var, &crate::prepend_line(&var, &value.as_str())?, placement)?
+        }
+    };
+
+    crate::changed(
+        var,
+        if append {
+            backend::Update::Append(&value)
+        } else {
+            backend::Update::Prepend(&value)
+        },
+    );
+    change.emit_warnings();
+    Ok(decision)
+}
+
+fn before_system_line(var: &str, value: &str) -> io::Result<String> {
+    let syntax = crate::profile_syntax()?;
+    syntax.before_system_line(var, value).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} can't be put before the system directories in this shell",
+                var
+            ),
+        )
+    })
+}
+
+fn place(var: &str, line: &str, placement: Placement) -> io::Result<PlacementDecision> {
+    let entry = crate::metadata::tag(line);
+    crate::edit_profile(&entry, |content| {
+        let lines: Vec<&str> = content.lines().collect();
+        let manipulations = || {
+            lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| modifies(line, var))
+                .map(|(idx, _)| idx)
+        };
+
+        let (at, decision) = if block::find(&lines).is_some() {
+            (None, PlacementDecision::ExistingBlock)
+        } else {
+            match placement {
+                Placement::End | Placement::BeforeSystemPaths => (None, PlacementDecision::End),
+                Placement::AfterLastManipulation => match manipulations().next_back() {
+                    Some(idx) => (Some(idx + 1), PlacementDecision::AfterLine(idx + 1)),
+                    None => (None, PlacementDecision::End),
+                },
+                Placement::BeforeFirstManipulation => match manipulations().next() {
+                    Some(idx) => (Some(idx), PlacementDecision::BeforeLine(idx + 1)),
+                    None => (None, PlacementDecision::End),
+                },
+            }
+        };
+
+        (block::insert(content, &entry, at), decision)
+    })
+}
+
+/// Recognizes the usual ways of changing `var` in a profile,
+/// besides the lines written by this crate.
+pub(crate) fn modifies(line: &str, var: &str) -> bool {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return false;
+    }
+    if crate::simulate::parse_var_value(line).is_some_and(|(assigned, _)| assigned == var) {
+        return true;
+    }
+
+    let assigned = line
+        .strip_prefix("export ")
+        .or_else(|| line.strip_prefix("declare -x "))
+        .or_else(|| line.strip_prefix("typeset -x "))
+        .unwrap_or(line)
+        .trim_start();
+    if assigned
+        .strip_prefix(var)
+        .is_some_and(|rest| rest.starts_with('=') || rest.starts_with("+="))
+    {
+        return true;
+    }
+
+    let fish_set = [
+        "set -x ",
+        "set -gx ",
+        "set -Ux ",
+        "set --export ",
+        "set -xg ",
+    ]
+    .iter()
+    .filter_map(|prefix| line.strip_prefix(prefix))
+    .any(|rest| rest.split_whitespace().next() == Some(var));
+    if fish_set {
+        return true;
+    }
+
+    // zsh ties `path` to PATH, fish has a helper for it.
+    var == "PATH"
+        && (line.starts_with("path+=")
+            || line.starts_with("path=(")
+            || line.starts_with("fish_add_path "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{Backend, SyntaxInfo, DEFS_BEG};
+    use std::env;
+
+    /// Returns the number of the line starting the managed block,
+    /// counted from 1.
+    fn block_start(content: &str) -> usize {
+        content.lines().position(|line| line == DEFS_BEG).unwrap() + 1
+    }
+
+    const NONE: &str = "alias ll='ls -l'\numask 022\n";
+    const ONE: &str = "alias ll='ls -l'\nexport PATH=\"$HOME/bin:$PATH\"\numask 022\n";
+    const SEVERAL: &str = "export PATH=\"$HOME/bin:$PATH\"\nalias ll='ls -l'\n\
+        PATH=$PATH:/opt/tools\numask 022\nfish_add_path ~/go/bin\n";
+
+    #[test]
+    fn places_the_block_around_the_path_lines_of_the_profile() {
+        let cases = [
+            (
+                NONE,
+                Placement::AfterLastManipulation,
+                PlacementDecision::End,
+                3,
+            ),
+            (
+                NONE,
+                Placement::BeforeFirstManipulation,
+                PlacementDecision::End,
+                3,
+            ),
+            (
+                ONE,
+                Placement::AfterLastManipulation,
+                PlacementDecision::AfterLine(2),
+                3,
+            ),
+            (
+                ONE,
+                Placement::BeforeFirstManipulation,
+                PlacementDecision::BeforeLine(2),
+                2,
+            ),
+            (ONE, Placement::End, PlacementDecision::End, 4),
+            (
+                SEVERAL,
+                Placement::AfterLastManipulation,
+                PlacementDecision::AfterLine(5),
+                6,
+            ),
+            (
+                SEVERAL,
+                Placement::BeforeFirstManipulation,
+                PlacementDecision::BeforeLine(1),
+                1,
+            ),
+            (SEVERAL, Placement::End, PlacementDecision::End, 6),
+        ];
+        for (profile, placement, decision, start) in cases {
+            let sandbox = sandbox("/bin/bash");
+            sandbox.write(".bash_profile", profile);
+
+            assert_eq!(
+                append_with("PATH", "/opt/placed", placement).unwrap(),
+                decision,
+                "{:?} in {:?}",
+                placement,
+                profile
+            );
+            let content = sandbox.read(".bash_profile");
+            assert_eq!(block_start(&content), start, "{}", content);
+            for line in profile.lines() {
+                assert!(content.lines().any(|it| it == line), "{}", content);
+            }
+            assert!(content.contains("export PATH=\"$PATH:/opt/placed\""));
+        }
+    }
+
+    #[test]
+    fn goes_into_the_existing_block() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", ONE);
+        crate::append("PATH", "/opt/first").unwrap();
+
+        assert_eq!(
+            prepend_with("PATH", "/opt/second", Placement::BeforeFirstManipulation).unwrap(),
+            PlacementDecision::ExistingBlock
+        );
+        let content = sandbox.read(".bash_profile");
+        assert_eq!(block_start(&content), 4, "{}", content);
+        assert_eq!(content.matches(DEFS_BEG).count(), 1);
+        assert!(content.contains("export PATH=\"/opt/second:$PATH\""));
+    }
+
+    #[test]
+    fn follows_the_list_strategy() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", SEVERAL);
+        crate::append("PATH", "/opt/first").unwrap();
+        crate::set_list_strategy("PATH", ListStrategy::Composed).unwrap();
+
+        assert_eq!(
+            append_with("PATH", "/opt/second", Placement::AfterLastManipulation).unwrap(),
+            PlacementDecision::ExistingBlock
+        );
+        let content = sandbox.read(".bash_profile");
+        assert!(
+            content.contains("export PATH=\"$PATH:/opt/first:/opt/second\""),
+            "{}",
+            content
+        );
+    }
+
+    #[test]
+    fn records_usage_and_updates_the_process_only_env() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", ONE);
+        crate::set_usage_stats(true);
+        append_with("PATH", "/opt/placed", Placement::AfterLastManipulation).unwrap();
+        assert_eq!(crate::stats("PATH").unwrap().times_added, 1);
+
+        crate::set_backend(Backend::ProcessOnly);
+        env::set_var("SET_ENV_PLACEMENT_LIST", "/usr/bin");
+        append_with(
+            "SET_ENV_PLACEMENT_LIST",
+            "/opt/after",
+            Placement::AfterLastManipulation,
+        )
+        .unwrap();
+        prepend_with(
+            "SET_ENV_PLACEMENT_LIST",
+            "/opt/before",
+            Placement::BeforeFirstManipulation,
+        )
+        .unwrap();
+        assert_eq!(
+            env::var("SET_ENV_PLACEMENT_LIST").unwrap(),
+            "/opt/before:/usr/bin:/opt/after"
+        );
+        env::remove_var("SET_ENV_PLACEMENT_LIST");
+    }
+
+    #[test]
+    fn puts_the_entry_before_the_system_paths_in_each_shell() {
+        for (shell, profile, syntax) in [
+            ("/bin/bash", ".bash_profile", SyntaxInfo::POSIX),
+            ("/bin/zsh", ".zshrc", SyntaxInfo::POSIX),
+            (
+                "/usr/bin/fish",
+                ".config/fish/config.fish",
+                SyntaxInfo::FISH,
+            ),
+        ] {
+            let sandbox = sandbox(shell);
+            sandbox.write(profile, ONE);
+
+            assert_eq!(
+                prepend_with("PATH", "/opt/placed", Placement::BeforeSystemPaths).unwrap(),
+                PlacementDecision::End,
+                "{}",
+                shell
+            );
+            let line = syntax.before_system_line("PATH", "/opt/placed").unwrap();
+            let content = sandbox.read(profile);
+            assert!(content.starts_with(ONE), "{}", content);
+            assert!(
+                content.ends_with(&format!("{}\n{}\n{}\n", DEFS_BEG, line, crate::DEFS_END)),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_to_put_the_entry_before_the_system_paths_in_csh() {
+        let sandbox = sandbox("/bin/tcsh");
+        sandbox.write(".tcshrc", "setenv PATH ~/bin:$PATH\n");
+
+        let err = append_with("PATH", "/opt/placed", Placement::BeforeSystemPaths).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(sandbox.read(".tcshrc"), "setenv PATH ~/bin:$PATH\n");
+    }
+}
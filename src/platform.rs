@@ -0,0 +1,176 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+/// What this crate assumes about the unix-like system it runs on,
+/// see `platform`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Platform {
+    /// The value of `std::env::consts::OS` this entry is for, or `unix`
+    /// for the entry used for all others.
+    pub os: &'static str,
+    /// The shell assumed when `SHELL` isn't set.
+    pub default_shell: &'static str,
+    /// The profile written in POSIX syntax when the shell isn't
+    /// supported, relative to the home directory.
+    pub fallback_profile: &'static str,
+    /// The directory of system-wide configuration shells read, or
+    /// `None` if there is none.
+    pub system_config_dir: Option<&'static str>,
+    /// Whether login shells source the `profile.d/*.sh` scripts in
+    /// `system_config_dir`.
+    pub profile_d: bool,
+    /// The passwd database in the usual colon separated format, used to
+    /// find the user invoking `sudo`, or `None` if there is none.
+    pub passwd: Option<&'static str>,
+}
+
+const UNIX: Platform = Platform {
+    os: "unix",
+    default_shell: "sh",
+    fallback_profile: ".profile",
+    system_config_dir: Some("/etc"),
+    profile_d: true,
+    passwd: Some("/etc/passwd"),
+};
+
+static PLATFORMS: &[Platform] = &[
+    Platform {
+        os: "macos",
+        default_shell: "zsh",
+        ..UNIX
+    },
+    // A single user system whose bash reads the profile in the
+    // settings directory, and whose /etc is a link into the system
+    // package.
+    Platform {
+        os: "haiku",
+        default_shell: "bash",
+        fallback_profile: "config/settings/profile",
+        system_config_dir: Some("/boot/system/settings/etc"),
+        profile_d: true,
+        passwd: None,
+    },
+    // Its default shell is ion, which this crate doesn't support, and
+    // its passwd database is separated by semicolons.
+    Platform {
+        os: "redox",
+        default_shell: "ion",
+        profile_d: false,
+        passwd: None,
+        ..UNIX
+    },
+];
+
+/// Returns the description of the system this runs on, picked at
+/// runtime from the operating system's name.
+pub fn platform() -> Platform {
+    for_os(env::consts::OS)
+}
+
+/// Returns the description of the system named `os`, see `platform`.
+fn for_os(os: &str) -> Platform {
+    PLATFORMS
+        .iter()
+        .find(|platform| platform.os == os)
+        .copied()
+        .unwrap_or(UNIX)
+}
+
+impl Platform {
+    /// Returns `file` in the system-wide configuration directory.
+    pub(crate) fn system_file(&self, file: &str) -> io::Result<PathBuf> {
+        let dir = self.system_config_dir.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} has no system-wide shell configuration", self.os),
+            )
+        })?;
+        Ok(PathBuf::from(dir).join(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platforms_by_os() {
+        // The OS, the entry used for it, its default shell, fallback
+        // profile, system profile, whether it has profile.d and its
+        // passwd database.
+        let cases = [
+            (
+                "linux",
+                "unix",
+                "sh",
+                ".profile",
+                Some("/etc/profile"),
+                true,
+                Some("/etc/passwd"),
+            ),
+            (
+                "freebsd",
+                "unix",
+                "sh",
+                ".profile",
+                Some("/etc/profile"),
+                true,
+                Some("/etc/passwd"),
+            ),
+            (
+                "macos",
+                "macos",
+                "zsh",
+                ".profile",
+                Some("/etc/profile"),
+                true,
+                Some("/etc/passwd"),
+            ),
+            (
+                "haiku",
+                "haiku",
+                "bash",
+                "config/settings/profile",
+                Some("/boot/system/settings/etc/profile"),
+                true,
+                None,
+            ),
+            (
+                "redox",
+                "redox",
+                "ion",
+                ".profile",
+                Some("/etc/profile"),
+                false,
+                None,
+            ),
+        ];
+        for (os, entry, shell, fallback, system_profile, profile_d, passwd) in cases {
+            let platform = for_os(os);
+            assert_eq!(platform.os, entry, "{}", os);
+            assert_eq!(platform.default_shell, shell, "{}", os);
+            assert_eq!(platform.fallback_profile, fallback, "{}", os);
+            assert_eq!(
+                platform.system_file("profile").ok(),
+                system_profile.map(PathBuf::from),
+                "{}",
+                os
+            );
+            assert_eq!(platform.profile_d, profile_d, "{}", os);
+            assert_eq!(platform.passwd, passwd, "{}", os);
+        }
+        assert_eq!(platform(), for_os(env::consts::OS));
+    }
+
+    #[test]
+    fn a_platform_without_system_configuration_has_no_system_file() {
+        let platform = Platform {
+            system_config_dir: None,
+            ..UNIX
+        };
+        let err = platform.system_file("profile").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().starts_with("unix has"), "{}", err);
+    }
+}
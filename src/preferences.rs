@@ -0,0 +1,124 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// What the user configured for every program using this crate, read
+/// from the preferences file, see `set_preferences_file`.
+///
+/// The file has one `key = value` per line, lines starting with `#`
+/// are comments:
+///
+/// ```text
+/// # Commit the dotfiles whenever a profile changes.
+/// after_write_command = /home/u/bin/commit-dotfiles --quiet
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub(crate) struct Preferences {
+    /// The program and its arguments run after a file was modified,
+    /// with the path of the file as the last argument, see `after_write`.
+    pub after_write_command: Option<Vec<String>>,
+}
+
+static FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets the preferences file to read instead of `set_env/preferences`
+/// in the config directory of the user, `~/.config` on Unix.
+/// Pass `None` to read the default one again.
+pub fn set_preferences_file(path: Option<PathBuf>) {
+    *FILE.lock().unwrap_or_else(|err| err.into_inner()) = path;
+}
+
+/// Reads the preferences file. A missing file means no preferences,
+/// one that can't be read or has unknown lines is warned about.
+pub(crate) fn load() -> Preferences {
+    let path = match FILE.lock().unwrap_or_else(|err| err.into_inner()).clone() {
+        Some(path) => path,
+        None => match default_file() {
+            Ok(path) => path,
+            Err(_) => return Preferences::default(),
+        },
+    };
+    match crate::filesystem::current().read_to_string(&path) {
+        Ok(content) => parse(&content, &mut |line_no, message| {
+            crate::events::warn(format!(
+                "line {} of the preferences {}: {}",
+                line_no,
+                path.display(),
+                message
+            ))
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Preferences::default(),
+        Err(err) => {
+            crate::events::warn(format!(
+                "preferences {} could not be read: {}",
+                path.display(),
+                err
+            ));
+            Preferences::default()
+        }
+    }
+}
+
+/// Parses the content of a preferences file, calling `invalid` with the
+/// number and a description of every line that is ignored.
+fn parse(content: &str, invalid: &mut dyn FnMut(usize, &str)) -> Preferences {
+    let mut preferences = Preferences::default();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            invalid(idx + 1, "expected `key = value`");
+            continue;
+        };
+        match key.trim() {
+            "after_write_command" => {
+                let command: Vec<String> = value.split_whitespace().map(String::from).collect();
+                preferences.after_write_command = (!command.is_empty()).then_some(command);
+            }
+            key => invalid(idx + 1, &format!("unknown key {}", key)),
+        }
+    }
+    preferences
+}
+
+#[cfg(target_family = "unix")]
+fn default_file() -> io::Result<PathBuf> {
+    let (home_dir, _) = crate::home_and_shell_with(crate::Access::ReadOnly)?;
+    Ok(home_dir.join(".config/set_env/preferences"))
+}
+
+#[cfg(target_os = "windows")]
+fn default_file() -> io::Result<PathBuf> {
+    let dir = dirs::config_dir().ok_or_else(|| io::Error::other("No config directory"))?;
+    Ok(dir.join("set_env").join("preferences"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_and_unknown_lines_reported() {
+        let mut invalid = Vec::new();
+        let preferences = parse(
+            "# hooks\n\nafter_write_command =  git-commit-dotfiles  --quiet \ncolor = red\noops\n",
+            &mut |line_no, message| invalid.push((line_no, message.to_string())),
+        );
+        assert_eq!(
+            preferences.after_write_command,
+            Some(vec![
+                "git-commit-dotfiles".to_string(),
+                "--quiet".to_string()
+            ])
+        );
+        assert_eq!(
+            invalid,
+            vec![
+                (4, "unknown key color".to_string()),
+                (5, "expected `key = value`".to_string())
+            ]
+        );
+    }
+}
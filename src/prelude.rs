@@ -0,0 +1,236 @@
+//! The functions most programs need, named for what they guarantee.
+//! All of them can be called on every run of a program, they only
+//! write what's missing.
+//!
+//! ```no_run
+//! use set_env_perm::prelude::*;
+//!
+//! persist("EDITOR", "vim")?;
+//! persist_path("/opt/mytool/bin")?;
+//! ensure("PAGER", "less")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fmt;
+use std::io;
+
+use crate::{backend, DEFS_BEG, DEFS_END};
+
+pub use crate::{get, ExistingSource, ListOutcome, SetOutcome};
+
+/// Makes `var` have `value` in new shells, replacing the value the
+/// block managed by this crate assigns it, or adding it if there is
+/// none. Unlike `set` this never leaves more than one managed line for
+/// `var`, and writes nothing if it already has `value`.
+/// A disabled variable stays disabled.
+/// Returns whether the profile was changed.
+pub fn persist<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<bool> {
+    let _operation = crate::events::operation();
+    let var = var.to_string();
+    let value = value.to_string();
+    crate::startup_cost::warn_value(&var, &value)?;
+    let line = crate::profile_syntax()?.set_line(&var, &value);
+
+    let enabled = match update(&var, &line)? {
+        Some(enabled) => enabled,
+        None => {
+            crate::events::skipped(&var, format!("already set to {}", value));
+            return Ok(false);
+        }
+    };
+    if enabled {
+        crate::changed(&var, backend::Update::Set(&value));
+    }
+    Ok(true)
+}
+
+/// Makes `dir` an entry of PATH in new shells, appending it unless
+/// PATH already has it or the profile already adds it, so it's never
+/// added twice. Does the same as `check_or_append`.
+pub fn persist_path<T: fmt::Display>(dir: T) -> io::Result<ListOutcome> {
+    crate::check_or_append("PATH".to_string(), dir.to_string())
+}
+
+/// Makes sure `var` is set, setting it to `default` only if it isn't
+/// already set in the current process or the profile.
+/// Does the same as `check_or_set_detailed`.
+pub fn ensure<T: fmt::Display, U: fmt::Display>(var: T, default: U) -> io::Result<SetOutcome> {
+    let _operation = crate::events::operation();
+    crate::check_or_set_detailed(var.to_string(), default)
+}
+
+/// Returns the indices of the lines in the block managed by this crate
+/// assigning `var`, enabled or not.
+fn assignments(content: &str, var: &str) -> Vec<usize> {
+    let mut managed = false;
+    let mut found = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line == DEFS_BEG || line == DEFS_END {
+            managed = line == DEFS_BEG;
+        } else if managed && assigned(line) == Some(var) {
+            found.push(idx);
+        }
+    }
+    found
+}
+
+#[cfg(target_family = "unix")]
+fn assigned(line: &str) -> Option<&str> {
+    crate::toggle::parse_entry(line).map(|(var, _, _)| var)
+}
+
+#[cfg(target_os = "windows")]
+fn assigned(line: &str) -> Option<&str> {
+    crate::simulate::parse_var_value(line).map(|(var, _)| var)
+}
+
+/// Replaces the last managed assignment to `var` with `line` and
+/// removes the others, or adds `line` if there is none.
+/// Returns whether the assignment is enabled, or `None` if nothing had
+/// to change.
+#[cfg(target_family = "unix")]
+fn update(var: &str, line: &str) -> io::Result<Option<bool>> {
+    let path = crate::profile_path_for(var)?;
+    let content = match crate::filesystem::current().read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let (new_content, enabled) = updated(&content, var, line);
+    if new_content == content {
+        crate::managed::consolidate(var)?;
+        return Ok(None);
+    }
+
+    crate::edit_file(&path, |content| {
+        crate::block::check(content, &path)?;
+        Ok((updated(content, var, line).0, ()))
+    })?;
+    crate::managed::consolidate(var)?;
+    Ok(Some(enabled))
+}
+
+/// Returns `content` with the assignments to `var` replaced by `line`,
+/// and whether the assignment is enabled.
+#[cfg(target_family = "unix")]
+fn updated(content: &str, var: &str, line: &str) -> (String, bool) {
+    let found = assignments(content, var);
+    let Some(&last) = found.last() else {
+        let entry = crate::metadata::tag(line);
+        return (crate::block::insert(content, &entry, None), true);
+    };
+
+    let lines: Vec<&str> = content.lines().collect();
+    let enabled = crate::toggle::parse_entry(lines[last].trim()).is_none_or(|(_, _, it)| it);
+    let replacement = if enabled {
+        line.to_string()
+    } else {
+        crate::toggle::disabled(line)
+    };
+    let mut kept: Vec<&str> = Vec::with_capacity(lines.len());
+    for (idx, it) in lines.iter().enumerate() {
+        if idx == last {
+            kept.push(&replacement);
+        } else if !found.contains(&idx) {
+            kept.push(it);
+        }
+    }
+    (crate::line_endings::rejoin(content, &kept), enabled)
+}
+
+/// Replaces the managed assignments to `var` with `line`, unless
+/// `line` already is the only one.
+#[cfg(target_os = "windows")]
+fn update(var: &str, line: &str) -> io::Result<Option<bool>> {
+    let path = crate::profile_path()?;
+    let content = match crate::filesystem::current().read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let found = assignments(&content, var);
+    let lines: Vec<&str> = content.lines().collect();
+    if let [only] = found.as_slice() {
+        if lines[*only].trim() == line {
+            return Ok(None);
+        }
+    }
+
+    if !found.is_empty() {
+        // `remove_lines` passes the lines in order.
+        let idx = std::cell::Cell::new(0);
+        crate::remove_lines(&path, |_| {
+            idx.set(idx.get() + 1);
+            found.contains(&(idx.get() - 1))
+        })?;
+    }
+    crate::inject(line)?;
+    Ok(Some(true))
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn persist_keeps_one_managed_line() {
+        let sandbox = sandbox("/bin/bash");
+        let user = "export SET_ENV_PERSISTED=user\n";
+        sandbox.write(".bash_profile", user);
+        let block = |lines: &str| format!("{}{}\n{}{}\n", user, DEFS_BEG, lines, DEFS_END);
+
+        assert!(persist("SET_ENV_PERSISTED", "1").unwrap());
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            block("export SET_ENV_PERSISTED=1\n")
+        );
+        assert!(!persist("SET_ENV_PERSISTED", "1").unwrap());
+
+        // Lines left by `set` collapse into the last one.
+        crate::set("SET_ENV_PERSISTED", "2").unwrap();
+        crate::set("SET_ENV_PERSISTED_OTHER", "3").unwrap();
+        crate::set("SET_ENV_PERSISTED", "4").unwrap();
+        assert!(persist("SET_ENV_PERSISTED", "5").unwrap());
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            block("export SET_ENV_PERSISTED_OTHER=3\nexport SET_ENV_PERSISTED=5\n")
+        );
+        assert!(!persist("SET_ENV_PERSISTED", "5").unwrap());
+    }
+
+    #[test]
+    fn persist_keeps_a_disabled_variable_disabled() {
+        let sandbox = sandbox("/bin/bash");
+        crate::set_disabled("SET_ENV_PERSISTED", "off").unwrap();
+
+        assert!(persist("SET_ENV_PERSISTED", "on").unwrap());
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\n# set_env disabled: export SET_ENV_PERSISTED=on\n{}\n",
+                DEFS_BEG, DEFS_END
+            )
+        );
+        assert!(!persist("SET_ENV_PERSISTED", "on").unwrap());
+    }
+
+    #[test]
+    fn persist_path_adds_a_directory_once() {
+        let sandbox = sandbox("/bin/bash");
+        let dir = "/opt/set_env_persisted/bin";
+
+        assert_eq!(persist_path(dir).unwrap(), ListOutcome::Written);
+        let written = sandbox.read(".bash_profile");
+        assert!(written.contains(&format!("{}\"\n", dir)), "{}", written);
+        assert_eq!(persist_path(dir).unwrap(), ListOutcome::Pending);
+        assert_eq!(sandbox.read(".bash_profile"), written);
+
+        // A directory the process already has isn't written at all.
+        let present = std::env::var("PATH").unwrap();
+        let present = present.split(':').find(|it| !it.is_empty()).unwrap();
+        assert_eq!(persist_path(present).unwrap(), ListOutcome::Present);
+        assert_eq!(sandbox.read(".bash_profile"), written);
+    }
+}
@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Why a profile wasn't changed: there is a directory where it should
+/// be, or a symlink at it points to one. The payload of the error,
+/// which has `ErrorKind::IsADirectory`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProfileIsDirectory {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for ProfileIsDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Profile {} is a directory", self.path.display())
+    }
+}
+
+impl Error for ProfileIsDirectory {}
+
+/// What is done when the profile is a symlink to a file that doesn't
+/// exist, like one into a deleted dotfiles checkout, see
+/// `set_dangling_link_policy`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DanglingLinkPolicy {
+    /// Create the file the link points to, and its directories.
+    #[default]
+    CreateTarget,
+    /// Replace the link with a regular file.
+    ReplaceLink,
+}
+
+static POLICY: Mutex<DanglingLinkPolicy> = Mutex::new(DanglingLinkPolicy::CreateTarget);
+
+/// Sets what is done when the profile is a dangling symlink.
+/// Defaults to `DanglingLinkPolicy::CreateTarget`.
+pub fn set_dangling_link_policy(policy: DanglingLinkPolicy) {
+    *POLICY.lock().unwrap_or_else(|err| err.into_inner()) = policy;
+}
+
+/// Makes sure the profile at `path` can be written.
+/// A dangling symlink is handled as `set_dangling_link_policy` says,
+/// a directory at `path` fails with a `ProfileIsDirectory`.
+pub(crate) fn check(path: &Path) -> io::Result<()> {
+    check_not_directory(path)?;
+    if crate::is_dry_run() || !is_dangling(path)? {
+        return Ok(());
+    }
+    let policy = *POLICY.lock().unwrap_or_else(|err| err.into_inner());
+    match policy {
+        DanglingLinkPolicy::CreateTarget => create_target(path),
+        DanglingLinkPolicy::ReplaceLink => {
+            crate::filesystem::current()
+                .remove_file(path)
+                .map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!(
+                            "Profile {} is a dangling symlink and cannot be removed: {}",
+                            path.display(),
+                            err
+                        ),
+                    )
+                })
+        }
+    }
+}
+
+/// Fails with a `ProfileIsDirectory` if there is a directory at `path`,
+/// or a symlink to one, without changing anything.
+pub(crate) fn check_not_directory(path: &Path) -> io::Result<()> {
+    let fs = crate::filesystem::current();
+    let is_dir = match fs.metadata(path) {
+        // Only a symlink needs following.
+        Ok(metadata) if metadata.is_symlink => fs.target_metadata(path).is_ok_and(|it| it.is_dir),
+        Ok(metadata) => metadata.is_dir,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+    if is_dir {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            ProfileIsDirectory {
+                path: path.to_path_buf(),
+            },
+        ));
+    }
+    Ok(())
+}
+
+fn is_dangling(path: &Path) -> io::Result<bool> {
+    let fs = crate::filesystem::current();
+    match fs.metadata(path) {
+        Ok(metadata) if metadata.is_symlink => match fs.target_metadata(path) {
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(err) => Err(err),
+        },
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Creates the directories of the file the dangling symlink at `path`
+/// points to, so writing the profile creates the file.
+fn create_target(path: &Path) -> io::Result<()> {
+    let fs = crate::filesystem::current();
+    let target = link_target(path)?;
+    let Some(parent) = target.parent() else {
+        return Ok(());
+    };
+    fs.create_dir_all(parent).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!(
+                "Profile {} is a dangling symlink and {} cannot be created: {}",
+                path.display(),
+                target.display(),
+                err
+            ),
+        )
+    })
+}
+
+/// Returns the path the symlink at `path` points to, relative ones
+/// resolved against its directory.
+fn link_target(path: &Path) -> io::Result<PathBuf> {
+    let link = crate::filesystem::current().read_link(path)?;
+    Ok(path.parent().map_or(link.clone(), |dir| dir.join(&link)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn a_directory_is_reported_with_its_path() {
+        let sandbox = sandbox("/bin/bash");
+        fs::create_dir(sandbox.path(".bash_profile")).unwrap();
+
+        let err = crate::set("FOO", "1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+        let payload = err.get_ref().unwrap().downcast_ref::<ProfileIsDirectory>();
+        assert_eq!(
+            payload,
+            Some(&ProfileIsDirectory {
+                path: sandbox.path(".bash_profile")
+            })
+        );
+    }
+
+    #[test]
+    fn a_link_to_a_directory_is_reported_with_its_path() {
+        let sandbox = sandbox("/bin/bash");
+        fs::create_dir(sandbox.path("dotfiles")).unwrap();
+        symlink(sandbox.path("dotfiles"), sandbox.path(".bash_profile")).unwrap();
+
+        let err = crate::set("FOO", "1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+        assert!(err.to_string().contains(".bash_profile"));
+    }
+
+    #[test]
+    fn the_target_of_a_dangling_link_is_created() {
+        let sandbox = sandbox("/bin/bash");
+        symlink(
+            "dotfiles/deleted/bash_profile",
+            sandbox.path(".bash_profile"),
+        )
+        .unwrap();
+
+        crate::set("FOO", "1").unwrap();
+        let link = fs::symlink_metadata(sandbox.path(".bash_profile")).unwrap();
+        assert!(link.file_type().is_symlink());
+        assert!(sandbox
+            .read("dotfiles/deleted/bash_profile")
+            .contains("export FOO=1"));
+    }
+
+    #[test]
+    fn a_dangling_link_can_be_replaced() {
+        let sandbox = sandbox("/bin/bash");
+        symlink(
+            "dotfiles/deleted/bash_profile",
+            sandbox.path(".bash_profile"),
+        )
+        .unwrap();
+        set_dangling_link_policy(DanglingLinkPolicy::ReplaceLink);
+
+        crate::set("FOO", "1").unwrap();
+        let file = fs::symlink_metadata(sandbox.path(".bash_profile")).unwrap();
+        assert!(file.file_type().is_file());
+        assert!(sandbox.read(".bash_profile").contains("export FOO=1"));
+        assert!(!sandbox.path("dotfiles").exists());
+    }
+
+    #[test]
+    fn dry_run_leaves_a_dangling_link_alone() {
+        let sandbox = sandbox("/bin/bash");
+        symlink(
+            "dotfiles/deleted/bash_profile",
+            sandbox.path(".bash_profile"),
+        )
+        .unwrap();
+        crate::set_dry_run(true);
+
+        crate::set("FOO", "1").unwrap();
+        assert!(!sandbox.path("dotfiles").exists());
+        assert!(fs::symlink_metadata(sandbox.path(".bash_profile")).is_ok());
+    }
+}
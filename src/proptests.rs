@@ -0,0 +1,256 @@
+//! Properties of writing to profiles, checked on random profiles made
+//! of lines of the user, comments and junk, with random line endings,
+//! and on malformed ones nothing may panic on.
+
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+
+use crate::test_support::sandbox;
+use crate::{Metadata, DEFS_BEG, DEFS_END};
+
+const VARS: [&str; 3] = ["SET_ENV_PROP_A", "SET_ENV_PROP_B", "SET_ENV_PROP_C"];
+
+/// A line the user wrote. Block and conflict markers are left out, as
+/// the crate refuses to write to a profile with a damaged block or an
+/// unresolved merge.
+fn user_line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-z_]{1,8}=[a-z0-9]{0,8}".prop_map(|it| format!("export {}", it)),
+        "[A-Z_]{1,8}=\"[ -~]{0,10}\"",
+        "# [ -~]{0,20}",
+        "alias [a-z]{1,5}='[a-z ]{0,10}'",
+        "[ -~]{0,30}",
+        "\t[ -~]{0,10}",
+        Just(String::new()),
+    ]
+    .prop_filter("no markers", |line| {
+        !line.contains("SET_ENV_DEFS")
+            && !["<<<<<<<", "|||||||", "=======", ">>>>>>>"]
+                .iter()
+                .any(|marker| line.contains(marker))
+    })
+}
+
+/// A profile of user lines, each ending with LF or CRLF.
+fn profile() -> impl Strategy<Value = String> {
+    prop::collection::vec((user_line(), any::<bool>()), 0..12)
+        .prop_map(|lines| {
+            lines
+                .into_iter()
+                .map(|(line, crlf)| format!("{}{}", line, if crlf { "\r\n" } else { "\n" }))
+                .collect()
+        })
+        .prop_filter("no lines of older versions at the end", |it: &String| {
+            !ends_like_older_versions(it)
+        })
+}
+
+/// Returns whether `content` ends with an `export` after an empty line,
+/// which is taken into the block when it's created.
+fn ends_like_older_versions(content: &str) -> bool {
+    let lines: Vec<&str> = content.lines().collect();
+    matches!(lines.as_slice(), [.., "", last] if last.starts_with("export "))
+}
+
+fn value() -> impl Strategy<Value = String> {
+    "[a-z0-9/._]{1,8}"
+}
+
+fn metadata() -> impl Strategy<Value = Option<Metadata>> {
+    prop::option::of(("[a-z]{1,8}", prop::option::of("[a-z0-9][a-z0-9 -]{0,11}"))).prop_map(|it| {
+        it.map(|(owner, note)| Metadata {
+            owner,
+            note: note.map(|note: String| note.trim().to_string()),
+        })
+    })
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Set(usize, String),
+    Append(usize, String),
+    Prepend(usize, String),
+    Unset(usize),
+    Disable(usize),
+    Enable(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let var = 0..VARS.len();
+    prop_oneof![
+        (var.clone(), value()).prop_map(|(var, value)| Op::Set(var, value)),
+        (var.clone(), value()).prop_map(|(var, value)| Op::Append(var, value)),
+        (var.clone(), value()).prop_map(|(var, value)| Op::Prepend(var, value)),
+        var.clone().prop_map(Op::Unset),
+        var.clone().prop_map(Op::Disable),
+        var.prop_map(Op::Enable),
+    ]
+}
+
+fn apply(op: &Op) {
+    match op {
+        Op::Set(var, value) => drop(crate::set(VARS[*var], value).unwrap()),
+        Op::Append(var, value) => drop(crate::append(VARS[*var], value).unwrap()),
+        Op::Prepend(var, value) => drop(crate::prepend(VARS[*var], value).unwrap()),
+        Op::Unset(var) => drop(crate::unset(VARS[*var]).unwrap()),
+        Op::Disable(var) => drop(crate::disable(VARS[*var]).unwrap()),
+        Op::Enable(var) => drop(crate::enable(VARS[*var]).unwrap()),
+    }
+}
+
+/// A piece of a malformed profile: markers cut short, markers out of
+/// place, binary garbage or an enormous line.
+fn malformed_piece() -> impl Strategy<Value = Vec<u8>> {
+    let marker = prop_oneof![
+        Just(DEFS_BEG),
+        Just(DEFS_END),
+        Just("<<<<<<< HEAD"),
+        Just("======="),
+        Just(">>>>>>> theirs"),
+    ];
+    prop_oneof![
+        (marker.clone(), any::<prop::sample::Index>())
+            .prop_map(|(marker, cut)| marker.as_bytes()[..cut.index(marker.len())].to_vec()),
+        marker.prop_map(|marker| format!("{}\n", marker).into_bytes()),
+        prop::collection::vec(any::<u8>(), 0..64),
+        (1usize..4)
+            .prop_map(|it| format!("export HUGE=\"{}\"\n", "x".repeat(it << 18)).into_bytes()),
+        "[ -~]{0,30}\n".prop_map(String::into_bytes),
+    ]
+}
+
+fn malformed_profile() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(malformed_piece(), 0..8).prop_map(|pieces| pieces.concat())
+}
+
+/// Calls every public function reading or changing the profiles,
+/// ignoring whether they fail.
+fn call_everything() {
+    let var = VARS[0];
+    drop(crate::list_managed());
+    drop(crate::list_managed_readonly());
+    drop(crate::status_readonly());
+    drop(crate::which_profile_readonly());
+    drop(crate::managed_duplicates());
+    drop(crate::managed_env());
+    drop(crate::metadata_of(var));
+    drop(crate::condition_of(var));
+    drop(crate::path_entries(var));
+    drop(crate::explain());
+    drop(crate::startup_cost());
+    drop(crate::set(var, "1"));
+    drop(crate::append(var, "/a"));
+    drop(crate::prepend(var, "/b"));
+    drop(crate::check_or_set(VARS[1], "2"));
+    drop(crate::check_or_append(VARS[2], "/c"));
+    drop(crate::check_or_prepend(VARS[2], "/d"));
+    drop(crate::set_disabled(VARS[1], "3"));
+    drop(crate::disable(var));
+    drop(crate::enable(var));
+    drop(crate::remove_from(VARS[2], "/c"));
+    drop(crate::ensure_line("source ~/.extra"));
+    drop(crate::remove_line("source ~/.extra"));
+    drop(crate::adopt("HUGE"));
+    drop(crate::unmanage(var));
+    drop(crate::repair());
+    drop(crate::unset(var));
+    drop(crate::quick().var(var, "4").run());
+}
+
+/// Returns `content` without the block managed by this crate.
+fn outside_block(content: &str) -> String {
+    let mut managed = false;
+    content
+        .split_inclusive('\n')
+        .filter(|line| {
+            let trimmed = line.trim();
+            if trimmed == DEFS_BEG || trimmed == DEFS_END {
+                managed = trimmed == DEFS_BEG;
+                return false;
+            }
+            !managed
+        })
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn set_round_trips(
+        before in profile(),
+        sets in prop::collection::vec((0..VARS.len(), value(), metadata()), 1..8),
+    ) {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        memory.insert(sandbox.path(".bash_profile"), &before);
+
+        let mut expected = HashMap::new();
+        for (var, value, metadata) in &sets {
+            crate::set_metadata(metadata.clone());
+            crate::set(VARS[*var], value).unwrap();
+            expected.insert(VARS[*var], (value.clone(), metadata.clone()));
+        }
+
+        for (var, (value, metadata)) in expected {
+            let definitions = crate::list_managed().unwrap();
+            let last = definitions.iter().rev().find(|it| it.var == var).unwrap();
+            prop_assert_eq!(&last.value, &value);
+            prop_assert_eq!(crate::metadata_of(var).unwrap(), metadata);
+        }
+    }
+
+    #[test]
+    fn lines_outside_the_block_stay_byte_identical(
+        before in profile(),
+        ops in prop::collection::vec(op(), 1..12),
+    ) {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        let path = sandbox.path(".bash_profile");
+        memory.insert(&path, &before);
+
+        for op in &ops {
+            apply(op);
+            prop_assert_eq!(outside_block(&memory.get(&path).unwrap()), before.clone());
+        }
+    }
+
+    #[test]
+    fn unset_after_set_restores_the_file(
+        before in profile(),
+        ops in prop::collection::vec(op(), 1..12),
+        metadata in metadata(),
+    ) {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        let path = sandbox.path(".bash_profile");
+        memory.insert(&path, &before);
+        crate::set_metadata(metadata);
+
+        for op in &ops {
+            apply(op);
+        }
+        for var in VARS {
+            crate::unset(var).unwrap();
+        }
+        prop_assert_eq!(memory.get(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn malformed_profiles_never_panic(
+        profile in malformed_profile(),
+        rc in malformed_profile(),
+    ) {
+        let sandbox = sandbox("/bin/bash");
+        std::fs::write(sandbox.path(".bash_profile"), &profile).unwrap();
+        std::fs::write(sandbox.path(".bashrc"), &rc).unwrap();
+        crate::set_write_limit(usize::MAX);
+
+        call_everything();
+        if let Ok(content) = String::from_utf8(profile) {
+            drop(crate::simulate(&content, "bash", &HashMap::new()));
+        }
+    }
+}
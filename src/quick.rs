@@ -0,0 +1,219 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::prelude::{persist, persist_path};
+use crate::ListOutcome;
+
+/// Starts a one-shot for the common case of putting a directory on
+/// PATH and setting a variable, safe to run on every start of a
+/// program:
+///
+/// ```no_run
+/// let summary = set_env_perm::quick()
+///     .path("~/.mytool/bin")
+///     .var("MYTOOL_HOME", "~/.mytool")
+///     .tag("mytool")
+///     .run()?;
+/// print!("{}", summary);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn quick() -> Quick {
+    let _operation = crate::events::operation();
+    Quick::default()
+}
+
+/// What `quick` makes sure of when run.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Quick {
+    paths: Vec<String>,
+    vars: Vec<(String, String)>,
+    tag: Option<String>,
+}
+
+/// What `Quick::run` did, displayed as one line per message followed
+/// by the activation hint.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Summary {
+    /// Whether a profile was changed.
+    pub changed: bool,
+    /// What was done or found, for every directory and variable.
+    pub messages: Vec<String>,
+    /// How to use the changes in the current terminal, if there are any.
+    pub activation_hint: Option<String>,
+}
+
+impl Quick {
+    /// Makes sure `dir` is on PATH, appending it unless it already is,
+    /// see `persist_path`. A leading `~` stands for the home directory.
+    pub fn path<T: fmt::Display>(mut self, dir: T) -> Self {
+        self.paths.push(dir.to_string());
+        self
+    }
+
+    /// Makes sure `var` is set to `value`, updating the line setting it
+    /// in place, see `persist`. A leading `~` stands for the home
+    /// directory.
+    pub fn var<T: fmt::Display, U: fmt::Display>(mut self, var: T, value: U) -> Self {
+        self.vars.push((var.to_string(), value.to_string()));
+        self
+    }
+
+    /// Writes `tag` as the owner in the metadata above the lines, see
+    /// `set_metadata`. Has no effect on Windows.
+    pub fn tag<T: fmt::Display>(mut self, tag: T) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    /// Makes the changes that are still missing, in the order they
+    /// were given.
+    pub fn run(&self) -> io::Result<Summary> {
+        #[cfg(target_family = "unix")]
+        let previous = self.tag.as_ref().map(|tag| {
+            crate::metadata::replace(Some(crate::Metadata {
+                owner: tag.clone(),
+                note: None,
+            }))
+        });
+        let summary = crate::batch(|| self.apply());
+        #[cfg(target_family = "unix")]
+        if let Some(previous) = previous {
+            crate::metadata::replace(previous);
+        }
+        summary
+    }
+
+    fn apply(&self) -> io::Result<Summary> {
+        let mut summary = Summary::default();
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut note = |summary: &mut Summary, path: PathBuf| {
+            summary.changed = true;
+            if !changed.contains(&path) {
+                changed.push(path);
+            }
+        };
+
+        for dir in &self.paths {
+            let message = match persist_path(expand_home(dir))? {
+                ListOutcome::Written => {
+                    note(&mut summary, crate::profile_path_for("PATH")?);
+                    format!("Added {} to PATH", dir)
+                }
+                ListOutcome::Present => format!("PATH already has {}", dir),
+                ListOutcome::Pending => format!("PATH already gets {} in new shells", dir),
+            };
+            summary.messages.push(message);
+        }
+        for (var, value) in &self.vars {
+            let message = if persist(var, expand_home(value))? {
+                note(&mut summary, crate::profile_path_for(var)?);
+                format!("Set {} to {}", var, value)
+            } else {
+                format!("{} already is {}", var, value)
+            };
+            summary.messages.push(message);
+        }
+
+        if summary.changed {
+            let syntax = crate::profile_syntax()?;
+            let sources: Vec<String> = changed
+                .iter()
+                .map(|path| syntax.source_line(path.display()))
+                .collect();
+            summary.activation_hint = Some(format!(
+                "Open a new terminal, or run `{}` to use the changes in this one",
+                sources.join("; ")
+            ));
+        }
+        Ok(summary)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for message in &self.messages {
+            writeln!(f, "{}", message)?;
+        }
+        if let Some(hint) = &self.activation_hint {
+            writeln!(f, "{}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces a leading `~` with the home directory as the shell writes it.
+#[cfg(target_family = "unix")]
+fn expand_home(value: &str) -> String {
+    match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("$HOME{}", rest),
+        _ => value.to_string(),
+    }
+}
+
+/// Replaces a leading `~` with the home directory as PowerShell writes it.
+#[cfg(target_os = "windows")]
+fn expand_home(value: &str) -> String {
+    match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            format!("\"$env:USERPROFILE{}\"", rest.replace('/', "\\"))
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    fn mytool() -> Quick {
+        quick()
+            .path("~/.set_env_quick/bin")
+            .var("SET_ENV_QUICK_HOME", "~/.set_env_quick")
+            .tag("mytool")
+    }
+
+    #[test]
+    fn running_again_changes_nothing() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias ll='ls -l'\n");
+
+        let first = mytool().run().unwrap();
+        assert!(first.changed);
+        assert_eq!(
+            first.messages,
+            [
+                "Added ~/.set_env_quick/bin to PATH",
+                "Set SET_ENV_QUICK_HOME to ~/.set_env_quick",
+            ]
+        );
+        assert!(first.activation_hint.is_some());
+        let written = sandbox.read(".bash_profile");
+        assert!(written.contains("export SET_ENV_QUICK_HOME=$HOME/.set_env_quick"));
+        assert_eq!(
+            crate::metadata_of("SET_ENV_QUICK_HOME")
+                .unwrap()
+                .unwrap()
+                .owner,
+            "mytool"
+        );
+
+        for _ in 0..2 {
+            let again = mytool().run().unwrap();
+            assert_eq!(
+                again,
+                Summary {
+                    changed: false,
+                    messages: vec![
+                        "PATH already gets ~/.set_env_quick/bin in new shells".to_string(),
+                        "SET_ENV_QUICK_HOME already is ~/.set_env_quick".to_string(),
+                    ],
+                    activation_hint: None,
+                }
+            );
+            assert_eq!(sandbox.read(".bash_profile"), written);
+        }
+        assert_eq!(crate::metadata::replace(None), None);
+    }
+}
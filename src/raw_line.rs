@@ -0,0 +1,246 @@
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+
+use crate::{DEFS_BEG, DEFS_END};
+
+/// Adds `shell_line`, which can be anything the shell runs, like
+/// `source /opt/mytool/completions.sh`, to the block managed by this
+/// crate in the profile, with the current metadata, see `set_metadata`.
+/// Nothing happens if the block already has the line, compared with
+/// surrounding and repeated whitespace ignored.
+/// Fails with `ErrorKind::InvalidInput` for an empty line or one
+/// spanning several lines.
+/// Returns whether the line was written.
+pub fn ensure_line(shell_line: &str) -> io::Result<bool> {
+    let _operation = crate::events::operation();
+    let line = normalized(shell_line)?;
+    let path = crate::profile_path_for_line(shell_line.trim())?;
+    if managed_lines(&read(&path)?).any(|it| normalize(it) == line) {
+        crate::events::skipped("", format!("the profile already has {}", line));
+        return Ok(false);
+    }
+    write(&path, shell_line.trim())?;
+    Ok(true)
+}
+
+/// Removes the lines `ensure_line` wrote for `shell_line` from the block
+/// managed by this crate, with the metadata comments above them.
+/// Returns how many lines were removed, comments included.
+/// Refuses to change a profile whose managed block is damaged.
+pub fn remove_line(shell_line: &str) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    let line = normalized(shell_line)?;
+    let path = crate::profile_path_for_line(shell_line.trim())?;
+    let content = read(&path)?;
+    check_block(&content, &path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    let mut removed = Vec::new();
+    let mut managed = false;
+    for (idx, it) in lines.iter().enumerate() {
+        let it = it.trim();
+        if it == DEFS_BEG || it == DEFS_END {
+            managed = it == DEFS_BEG;
+        } else if managed && normalize(it) == line {
+            if idx > 0 && is_metadata(lines[idx - 1]) {
+                removed.push(idx - 1);
+            }
+            removed.push(idx);
+        }
+    }
+    if removed.is_empty() {
+        return Ok(0);
+    }
+
+    // `remove_lines` passes the lines in order.
+    let idx = Cell::new(0);
+    crate::remove_lines(&path, |_| {
+        idx.set(idx.get() + 1);
+        removed.contains(&(idx.get() - 1))
+    })
+}
+
+/// Returns whether `line` in the block managed by this crate is one
+/// written by `ensure_line`, rather than an assignment or a comment.
+#[cfg(target_family = "unix")]
+pub(crate) fn is_raw_line(syntax: &crate::SyntaxInfo, line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty()
+        && !line.starts_with(syntax.comment_prefix)
+        && crate::simulate::parse_var_value(line).is_none()
+}
+
+#[cfg(target_family = "unix")]
+fn is_metadata(line: &str) -> bool {
+    crate::metadata::is_metadata(line)
+}
+
+#[cfg(target_os = "windows")]
+fn is_metadata(_line: &str) -> bool {
+    false
+}
+
+fn normalized(shell_line: &str) -> io::Result<String> {
+    if shell_line.contains(['\n', '\r']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A managed line can't span several lines",
+        ));
+    }
+    let line = normalize(shell_line);
+    if line.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A managed line can't be empty",
+        ));
+    }
+    Ok(line)
+}
+
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    match crate::filesystem::current().read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the trimmed lines in the block managed by this crate.
+fn managed_lines(content: &str) -> impl Iterator<Item = &str> {
+    let mut managed = false;
+    content.lines().map(str::trim).filter(move |line| {
+        if *line == DEFS_BEG || *line == DEFS_END {
+            managed = *line == DEFS_BEG;
+            return false;
+        }
+        managed
+    })
+}
+
+#[cfg(target_family = "unix")]
+fn check_block(content: &str, path: &Path) -> io::Result<()> {
+    crate::block::check(content, path)
+}
+
+/// Refuses a profile with a start marker but no end marker after it,
+/// where everything to the end would be taken as managed.
+#[cfg(target_os = "windows")]
+fn check_block(content: &str, path: &Path) -> io::Result<()> {
+    let mut lines = content.lines().map(str::trim);
+    if lines.any(|line| line == DEFS_BEG) && !lines.any(|line| line == DEFS_END) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no SET_ENV_DEFS_END line", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn write(path: &Path, line: &str) -> io::Result<()> {
+    crate::write_to_profile_at(path, line)
+}
+
+/// The PowerShell profile is the only one, so `path` always is it.
+#[cfg(target_os = "windows")]
+fn write(_path: &Path, line: &str) -> io::Result<()> {
+    crate::inject(line)
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{DefinitionKind, Metadata};
+
+    const LINE: &str = "source /opt/mytool/completions.sh";
+
+    #[test]
+    fn ensuring_a_line_twice_writes_it_once() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias ll='ls -l'\n");
+
+        assert!(ensure_line(LINE).unwrap());
+        assert!(!ensure_line(LINE).unwrap());
+        assert!(!ensure_line("  source   /opt/mytool/completions.sh ").unwrap());
+        assert_eq!(sandbox.read(".bash_profile").matches(LINE).count(), 1);
+
+        for invalid in ["", "   ", "a\nb"] {
+            let err = ensure_line(invalid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn removing_a_line_takes_its_metadata_along() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias ll='ls -l'\n");
+        crate::set_metadata(Some(Metadata {
+            owner: "mytool".to_string(),
+            note: None,
+        }));
+        ensure_line(LINE).unwrap();
+        let with_line = sandbox.read(".bash_profile");
+        assert!(with_line.contains("mytool"));
+
+        assert_eq!(remove_line(LINE).unwrap(), 2);
+        let without = sandbox.read(".bash_profile");
+        assert!(!without.contains(LINE));
+        assert!(!without.contains("mytool"));
+        assert!(without.starts_with("alias ll='ls -l'\n"));
+        assert_eq!(remove_line(LINE).unwrap(), 0);
+    }
+
+    #[test]
+    fn lines_live_next_to_assignments_in_the_block() {
+        let sandbox = sandbox("/bin/bash");
+        // The same line written by the user stays theirs.
+        sandbox.write(".bash_profile", &format!("{}\n", LINE));
+        crate::set("SET_ENV_RAW_LINE", "1").unwrap();
+        assert!(ensure_line(LINE).unwrap());
+
+        let kinds: Vec<DefinitionKind> = crate::list_managed()
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.kind)
+            .collect();
+        assert_eq!(kinds, [DefinitionKind::Variable, DefinitionKind::RawLine]);
+
+        assert_eq!(crate::unset("SET_ENV_RAW_LINE").unwrap(), 1);
+        assert_eq!(remove_line(LINE).unwrap(), 1);
+        let content = sandbox.read(".bash_profile");
+        assert!(content.starts_with(&format!("{}\n", LINE)));
+        assert_eq!(content.matches(LINE).count(), 1);
+        assert!(!content.contains("SET_ENV_RAW_LINE"));
+    }
+
+    #[test]
+    fn a_damaged_block_is_left_alone() {
+        let sandbox = sandbox("/bin/bash");
+        let damaged = format!("{}\nexport A=1\n{}\n", DEFS_BEG, LINE);
+        sandbox.write(".bash_profile", &damaged);
+
+        let err = remove_line(LINE).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sandbox.read(".bash_profile"), damaged);
+    }
+
+    #[test]
+    fn the_file_written_to_is_the_file_checked() {
+        let sandbox = sandbox("/bin/zsh");
+        crate::set_zsh_policy(crate::ZshPolicy::SplitByKind);
+        let line = "export PATH=\"$HOME/.mytool/bin:$PATH\"";
+
+        assert!(ensure_line(line).unwrap());
+        assert!(!ensure_line(line).unwrap());
+        assert_eq!(sandbox.read(".zprofile").matches(line).count(), 1);
+        assert!(!sandbox.path(".zshenv").exists());
+
+        assert_eq!(remove_line(line).unwrap(), 1);
+        assert!(!sandbox.read(".zprofile").contains(line));
+    }
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::path::PathBuf;
+
+#[cfg(target_family = "unix")]
+use crate::ManagedDefinition;
+
+/// Whether looking up a profile may create its directory, which the
+/// functions writing to it rely on. Every helper finding a profile
+/// takes one, so a read-only entry point can't create anything by
+/// calling the wrong one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Access {
+    /// Nothing is created, chmodded or written.
+    ReadOnly,
+    /// The directory of the profile is created if it's missing.
+    ReadWrite,
+}
+
+/// Does the same as `which_profile` without any side effect: no file
+/// or directory is created, chmodded or written, even if the profile
+/// or its directory is missing. For monitoring tools that must only
+/// read. Fails with `ErrorKind::NotFound` if the profile doesn't exist.
+pub fn which_profile_readonly() -> io::Result<PathBuf> {
+    let _operation = crate::events::operation();
+    let path = crate::profile_path_with(Access::ReadOnly)?;
+    if !crate::filesystem::current().exists(&path) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+/// Does the same as `list_managed` without any side effect, like
+/// `which_profile_readonly`. Missing files have no definitions.
+#[cfg(target_family = "unix")]
+pub fn list_managed_readonly() -> io::Result<Vec<ManagedDefinition>> {
+    let _operation = crate::events::operation();
+    crate::managed::list_managed_with(Access::ReadOnly)
+}
+
+/// The state of the profile, see `status_readonly`.
+#[cfg(target_family = "unix")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Status {
+    /// The profile the variables are written to, see `which_profile`.
+    pub profile: PathBuf,
+    /// Whether the profile exists.
+    pub exists: bool,
+    /// Whether the profile has a block managed by this crate.
+    pub has_block: bool,
+    /// The assignments and raw lines in the blocks managed by this
+    /// crate, see `list_managed`.
+    pub definitions: Vec<ManagedDefinition>,
+}
+
+/// Returns the state of the profile without any side effect, like
+/// `which_profile_readonly`. A missing profile is reported with
+/// `exists` false instead of being created.
+#[cfg(target_family = "unix")]
+pub fn status_readonly() -> io::Result<Status> {
+    let _operation = crate::events::operation();
+    let profile = crate::profile_path_with(Access::ReadOnly)?;
+    let content = match crate::filesystem::current().read_to_string(&profile) {
+        Ok(content) => Some(content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    let has_block = content.as_deref().is_some_and(|content| {
+        let lines: Vec<&str> = content.lines().collect();
+        crate::block::find(&lines).is_some()
+    });
+    Ok(Status {
+        exists: content.is_some(),
+        has_block,
+        definitions: list_managed_readonly()?,
+        profile,
+    })
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::{sandbox, snapshot};
+
+    /// Calls every read-only function, returning whether the profile
+    /// was found.
+    fn read_everything() -> bool {
+        let found = which_profile_readonly();
+        let status = status_readonly().unwrap();
+        assert_eq!(list_managed_readonly().unwrap(), status.definitions);
+        assert_eq!(found.is_ok(), status.exists);
+        status.exists
+    }
+
+    #[test]
+    fn a_pristine_home_stays_untouched() {
+        for shell in ["/bin/bash", "/bin/zsh", "/usr/bin/fish", "/bin/tcsh"] {
+            let sandbox = sandbox(shell);
+            crate::set_usage_stats(true);
+            crate::set_audit_log(Some(crate::AuditLog::new(sandbox.path("audit.log"))));
+
+            assert!(!read_everything(), "{}", shell);
+            let err = which_profile_readonly().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            assert_eq!(snapshot(&sandbox.home), Vec::new(), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn existing_profiles_stay_byte_identical() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            &format!(
+                "# mine\r\nalias ll='ls -l'\n{}\nexport FOO=1\n{}\n. ~/.extra",
+                crate::DEFS_BEG,
+                crate::DEFS_END
+            ),
+        );
+        sandbox.write(".extra", "export BAR=2\n");
+        sandbox.write(".config/fish/config.fish", "set -gx BAZ 3\n");
+        let before = snapshot(&sandbox.home);
+
+        assert!(read_everything());
+        let status = status_readonly().unwrap();
+        assert!(status.has_block);
+        assert_eq!(status.definitions.len(), 1);
+        assert_eq!(snapshot(&sandbox.home), before);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::{DEFS_BEG, DEFS_END};
+
+/// Returns the variables the blocks managed by this crate set, with
+/// references like `$PATH` expanded against the current process, as
+/// a new shell would see them.
+/// Only variables whose value differs from the current one are returned.
+pub fn managed_env() -> io::Result<HashMap<String, String>> {
+    let _operation = crate::events::operation();
+    let mut managed = String::new();
+    for path in crate::managed_profiles()? {
+        let content = match crate::filesystem::current().read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let mut inside = false;
+        for line in content.lines() {
+            match line.trim() {
+                DEFS_BEG => inside = true,
+                DEFS_END => inside = false,
+                line if inside => {
+                    managed.push_str(line);
+                    managed.push('\n');
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let base: HashMap<String, String> = env::vars().collect();
+    let result = crate::simulate(&managed, shell_name()?, &base)?;
+    Ok(result
+        .into_iter()
+        .filter(|(var, value)| base.get(var) != Some(value))
+        .collect())
+}
+
+/// Sets the variables returned by `managed_env` on `cmd`, so a child
+/// process sees them without the user opening a new shell.
+pub fn command_with_managed_env(cmd: &mut Command) -> io::Result<()> {
+    cmd.envs(managed_env()?);
+    Ok(())
+}
+
+/// Replaces the current process with a new run of the current binary,
+/// with the same arguments and the variables from `managed_env`.
+/// Only returns if that fails.
+#[cfg(target_family = "unix")]
+pub fn reexec_with_env() -> io::Error {
+    use std::os::unix::process::CommandExt;
+
+    match reexec_command() {
+        Ok(mut cmd) => cmd.exec(),
+        Err(err) => err,
+    }
+}
+
+/// Runs the current binary again, with the same arguments and the
+/// variables from `managed_env`, and exits with its exit code once it
+/// finishes, as Windows can't replace a running process.
+/// Only returns if starting it fails.
+#[cfg(target_os = "windows")]
+pub fn reexec_with_env() -> io::Error {
+    match reexec_command().and_then(|mut cmd| cmd.status()) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => err,
+    }
+}
+
+fn reexec_command() -> io::Result<Command> {
+    let mut cmd = Command::new(current_exe()?);
+    cmd.args(env::args_os().skip(1));
+    command_with_managed_env(&mut cmd)?;
+    Ok(cmd)
+}
+
+/// Returns the path of the running binary; on Linux through /proc,
+/// which still works when the binary was replaced, say by an update.
+fn current_exe() -> io::Result<PathBuf> {
+    let proc_exe = PathBuf::from("/proc/self/exe");
+    if cfg!(target_os = "linux") && proc_exe.exists() {
+        return Ok(proc_exe);
+    }
+    env::current_exe()
+}
+
+#[cfg(target_family = "unix")]
+fn shell_name() -> io::Result<&'static str> {
+    Ok(crate::detect_shell()?.map_or("sh", crate::Shell::name))
+}
+
+#[cfg(target_os = "windows")]
+fn shell_name() -> io::Result<&'static str> {
+    Ok("powershell")
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn children_inherit_the_managed_variables() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "export SET_ENV_REEXEC_USER=user\n");
+        env::set_var("SET_ENV_REEXEC_DIRS", "/usr/bin");
+        env::set_var("SET_ENV_REEXEC_SAME", "same");
+        crate::set("SET_ENV_REEXEC_HOME", "/opt/tool").unwrap();
+        crate::set("SET_ENV_REEXEC_BIN", "\"$SET_ENV_REEXEC_HOME/bin\"").unwrap();
+        crate::append("SET_ENV_REEXEC_DIRS", "/opt/tool/bin").unwrap();
+        crate::set("SET_ENV_REEXEC_SAME", "same").unwrap();
+        crate::set_disabled("SET_ENV_REEXEC_OFF", "1").unwrap();
+
+        let env = managed_env();
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(
+            "printf '%s|' \"$SET_ENV_REEXEC_HOME\" \"$SET_ENV_REEXEC_BIN\" \
+             \"$SET_ENV_REEXEC_DIRS\" \"$SET_ENV_REEXEC_SAME\" \
+             \"${SET_ENV_REEXEC_OFF-unset}\" \"${SET_ENV_REEXEC_USER-unset}\"",
+        );
+        let result = command_with_managed_env(&mut cmd).map(|()| cmd.output());
+        env::remove_var("SET_ENV_REEXEC_DIRS");
+        env::remove_var("SET_ENV_REEXEC_SAME");
+
+        let expected = [
+            ("SET_ENV_REEXEC_HOME", "/opt/tool"),
+            ("SET_ENV_REEXEC_BIN", "/opt/tool/bin"),
+            ("SET_ENV_REEXEC_DIRS", "/usr/bin:/opt/tool/bin"),
+        ];
+        assert_eq!(
+            env.unwrap(),
+            expected
+                .iter()
+                .map(|(var, value)| (var.to_string(), value.to_string()))
+                .collect()
+        );
+        let output = result.unwrap().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "/opt/tool|/opt/tool/bin|/usr/bin:/opt/tool/bin|same|unset|unset|"
+        );
+        // Nothing leaks into the process itself.
+        assert!(env::var_os("SET_ENV_REEXEC_HOME").is_none());
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes `set`, `append`, `prepend` and their `check_or_` variants, as
+/// well as the registry values written by `migrate_to_registry` and
+/// `check_or_set_nonempty`, store absolute paths under the user's known
+/// folders relative to them, so they survive relocating the profile or
+/// renaming the user: `C:\Users\alice\AppData\Local\MyTool\bin` is
+/// written as `"$env:LOCALAPPDATA\MyTool\bin"` to the PowerShell
+/// profile and as `%LOCALAPPDATA%\MyTool\bin` to the registry, which
+/// stores it as `REG_EXPAND_SZ`. The known folders are `USERPROFILE`,
+/// `APPDATA` and `LOCALAPPDATA`, the most specific one is used. Other
+/// paths are kept as they are. Disabled by default.
+pub fn set_relocatable_paths(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns `value` for the PowerShell profile, relocated if enabled.
+/// A single quoted value is meant literally and kept.
+pub(crate) fn for_profile(value: &str) -> String {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return value.to_string();
+    }
+    profile_value(value, &known_folders())
+}
+
+/// Returns `value` for the registry, relocated if enabled.
+pub(crate) fn for_registry(value: &str) -> String {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return value.to_string();
+    }
+    registry_value(value, &known_folders())
+}
+
+/// The known folders, by the variable naming them.
+fn known_folders() -> Vec<(&'static str, String)> {
+    [
+        ("USERPROFILE", dirs::home_dir()),
+        ("APPDATA", dirs::data_dir()),
+        ("LOCALAPPDATA", dirs::data_local_dir()),
+    ]
+    .into_iter()
+    .filter_map(|(var, dir)| Some((var, dir?.to_string_lossy().into_owned())))
+    .collect()
+}
+
+fn profile_value(value: &str, folders: &[(&'static str, String)]) -> String {
+    let inner = match value.strip_prefix('"').and_then(|it| it.strip_suffix('"')) {
+        Some(inner) => inner,
+        None if value.starts_with('\'') => return value.to_string(),
+        None => value,
+    };
+    let relocated = relocate(inner, folders, |var, rest| format!("$env:{}{}", var, rest));
+    if relocated == inner {
+        value.to_string()
+    } else {
+        format!("\"{}\"", relocated)
+    }
+}
+
+fn registry_value(value: &str, folders: &[(&'static str, String)]) -> String {
+    relocate(value, folders, |var, rest| format!("%{}%{}", var, rest))
+}
+
+/// Replaces the known folder starting each entry of the list `value`
+/// with what `render` returns for the variable naming it and the rest
+/// of the entry.
+fn relocate(
+    value: &str,
+    folders: &[(&'static str, String)],
+    render: impl Fn(&str, &str) -> String,
+) -> String {
+    value
+        .split(crate::LIST_SEPARATOR)
+        .map(|entry| match split_known(entry, folders) {
+            Some((var, rest)) => render(var, rest),
+            None => entry.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(&crate::LIST_SEPARATOR.to_string())
+}
+
+/// Returns the variable naming the longest of the `folders` `path` is
+/// in, or is, and the rest of `path` after it. Folders are compared
+/// ignoring case, a path on another drive is in none of them.
+fn split_known<'a>(
+    path: &'a str,
+    folders: &[(&'static str, String)],
+) -> Option<(&'static str, &'a str)> {
+    folders
+        .iter()
+        .filter_map(|(var, dir)| {
+            let dir = dir.trim_end_matches(['\\', '/']);
+            let rest = path.get(dir.len()..)?;
+            let inside = !dir.is_empty()
+                && path[..dir.len()].eq_ignore_ascii_case(dir)
+                && (rest.is_empty() || rest.starts_with(['\\', '/']));
+            inside.then_some((dir.len(), *var, rest))
+        })
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, var, rest)| (var, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folders() -> Vec<(&'static str, String)> {
+        vec![
+            ("USERPROFILE", r"C:\Users\alice".to_string()),
+            ("APPDATA", r"C:\Users\alice\AppData\Roaming".to_string()),
+            ("LOCALAPPDATA", r"C:\Users\alice\AppData\Local\".to_string()),
+        ]
+    }
+
+    #[test]
+    fn split_known_finds_the_most_specific_folder() {
+        let folders = folders();
+        let cases = [
+            (r"C:\Users\alice\bin", Some(("USERPROFILE", r"\bin"))),
+            (r"c:\USERS\Alice\bin", Some(("USERPROFILE", r"\bin"))),
+            (r"C:\Users\alice", Some(("USERPROFILE", ""))),
+            (
+                r"C:\Users\alice\AppData\Local\MyTool\bin",
+                Some(("LOCALAPPDATA", r"\MyTool\bin")),
+            ),
+            (
+                r"c:\users\alice\appdata\roaming\npm",
+                Some(("APPDATA", r"\npm")),
+            ),
+            (r"C:\Users\alice2\bin", None),
+            (r"D:\Users\alice\bin", None),
+            (r"C:\Program Files\Git", None),
+            ("", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(split_known(path, &folders), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn profile_values_are_relocated_unless_literal() {
+        let folders = folders();
+        let cases = [
+            (r"C:\Users\alice\bin", r#""$env:USERPROFILE\bin""#),
+            (r#""C:\Users\alice\bin""#, r#""$env:USERPROFILE\bin""#),
+            (r"'C:\Users\alice\bin'", r"'C:\Users\alice\bin'"),
+            (r"D:\Users\alice\bin", r"D:\Users\alice\bin"),
+            (r#""D:\tools""#, r#""D:\tools""#),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(profile_value(value, &folders), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn every_entry_of_a_list_is_relocated() {
+        let folders = folders();
+        let list = [
+            r"C:\Users\alice\bin",
+            r"D:\x",
+            r"C:\Users\alice\AppData\Roaming\npm",
+        ]
+        .join(&crate::LIST_SEPARATOR.to_string());
+        assert_eq!(
+            registry_value(&list, &folders),
+            [r"%USERPROFILE%\bin", r"D:\x", r"%APPDATA%\npm"]
+                .join(&crate::LIST_SEPARATOR.to_string())
+        );
+        assert_eq!(
+            profile_value(&list, &folders),
+            format!(
+                "\"{}\"",
+                [r"$env:USERPROFILE\bin", r"D:\x", r"$env:APPDATA\npm"]
+                    .join(&crate::LIST_SEPARATOR.to_string())
+            )
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs external programs, so their output can be faked.
+pub trait Runner {
+    /// Runs `program` with `args` and returns what it printed,
+    /// failing if it doesn't exit successfully within `timeout`.
+    fn output(&self, program: &str, args: &[&str], timeout: Duration) -> io::Result<String>;
+}
+
+/// Runs programs as child processes.
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn output(&self, program: &str, args: &[&str], timeout: Duration) -> io::Result<String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                let mut output = String::new();
+                if let Some(mut stdout) = child.stdout.take() {
+                    stdout.read_to_string(&mut output)?;
+                }
+                return if status.success() {
+                    Ok(output)
+                } else {
+                    Err(io::Error::other(format!("{} failed: {}", program, status)))
+                };
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} didn't finish within {:?}", program, timeout),
+                ));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
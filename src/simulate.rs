@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::syntax::{self, AssignmentStyle, SyntaxInfo};
+
+fn style_name(style: AssignmentStyle) -> &'static str {
+    match style {
+        AssignmentStyle::Export => "POSIX",
+        AssignmentStyle::SetGx => "fish",
+        AssignmentStyle::Setenv => "csh",
+        AssignmentStyle::PowerShellHelper => "PowerShell",
+    }
+}
+
+enum Op {
+    Set,
+    Append,
+    Prepend,
+    BeforeSystem,
+}
+
+struct Assignment<'a> {
+    syntax: AssignmentStyle,
+    op: Op,
+    var: &'a str,
+    value: &'a str,
+}
+
+/// Computes the environment that results from sourcing `profile`
+/// with `shell` on top of `base_env`, without spawning a shell.
+///
+/// Only the assignment forms this crate writes are interpreted
+/// (`export` for sh-like shells, `set -gx` for fish, `setenv` for csh
+/// and the `setenv_*` helpers for PowerShell, as well as the lines of
+/// `SyntaxInfo::before_system_line`), in file order.
+/// `$VAR`, `${VAR}`, `${VAR:+word}` and `${VAR:-word}` references are
+/// expanded against the environment as it is at that line; undefined
+/// variables expand to nothing.
+/// Unrecognized lines are ignored, but an assignment written for a
+/// different shell is rejected.
+pub fn simulate(
+    profile: &str,
+    shell: &str,
+    base_env: &HashMap<String, String>,
+) -> io::Result<HashMap<String, String>> {
+    let _operation = crate::events::operation();
+    let syntax = SyntaxInfo::for_shell(shell)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported shell {}", shell),
+            )
+        })?
+        .assignment;
+    let mut env = base_env.clone();
+
+    for (idx, line) in profile.lines().enumerate() {
+        let assignment = match parse_assignment(line.trim()) {
+            Some(assignment) => assignment,
+            None => continue,
+        };
+        if assignment.syntax != syntax {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Line {} uses {} syntax in a {} profile",
+                    idx + 1,
+                    style_name(assignment.syntax),
+                    shell
+                ),
+            ));
+        }
+
+        // fish exports list variables joined with colons when they
+        // are PATH-like and with spaces otherwise.
+        let separator = if assignment.var.ends_with("PATH") {
+            ":"
+        } else {
+            " "
+        };
+        let value = evaluate(assignment.value, syntax, separator, &env);
+        let value = match assignment.op {
+            Op::Set => value,
+            Op::Append => match env.get(assignment.var) {
+                Some(current) if !current.is_empty() => format!("{};{}", current, value),
+                _ => value,
+            },
+            Op::Prepend => match env.get(assignment.var) {
+                Some(current) if !current.is_empty() => format!("{};{}", value, current),
+                _ => value,
+            },
+            Op::BeforeSystem => {
+                let separator = match syntax {
+                    AssignmentStyle::SetGx => separator,
+                    _ => ":",
+                };
+                let current = env.get(assignment.var).map_or("", String::as_str);
+                syntax::insert_before_system(current, &value, separator)
+            }
+        };
+        env.insert(assignment.var.to_string(), value);
+    }
+
+    Ok(env)
+}
+
+/// Returns the variable and the unevaluated value assigned by `line`,
+/// if it is one of the assignment forms this crate writes.
+/// Lines written by `set_when` are recognized as well.
+pub(crate) fn parse_var_value(line: &str) -> Option<(&str, &str)> {
+    parse_assignment(line)
+        .or_else(|| parse_assignment(crate::condition::unguard(line)?.1))
+        .map(|assignment| (assignment.var, assignment.value))
+}
+
+fn parse_assignment(line: &str) -> Option<Assignment<'_>> {
+    if let Some(rest) = line.strip_prefix("export ") {
+        let (var, value) = rest.trim_start().split_once('=')?;
+        return Some(Assignment {
+            syntax: AssignmentStyle::Export,
+            op: Op::Set,
+            var: valid_name(var)?,
+            value,
+        });
+    }
+    if let Some(rest) = line.strip_prefix("set -gx ") {
+        return assignment_with_value(AssignmentStyle::SetGx, Op::Set, rest);
+    }
+    if let Some(rest) = line.strip_prefix("setenv ") {
+        return assignment_with_value(AssignmentStyle::Setenv, Op::Set, rest);
+    }
+    if let Some(rest) = line.strip_prefix("setenv_set ") {
+        return assignment_with_value(AssignmentStyle::PowerShellHelper, Op::Set, rest);
+    }
+    if let Some(rest) = line.strip_prefix("setenv_append ") {
+        return assignment_with_value(AssignmentStyle::PowerShellHelper, Op::Append, rest);
+    }
+    if let Some(rest) = line.strip_prefix("setenv_prepend ") {
+        return assignment_with_value(AssignmentStyle::PowerShellHelper, Op::Prepend, rest);
+    }
+    [SyntaxInfo::POSIX, SyntaxInfo::FISH]
+        .iter()
+        .find_map(|syntax| {
+            let (var, value) = syntax.parse_before_system_line(line)?;
+            Some(Assignment {
+                syntax: syntax.assignment,
+                op: Op::BeforeSystem,
+                var,
+                value,
+            })
+        })
+}
+
+fn assignment_with_value(syntax: AssignmentStyle, op: Op, rest: &str) -> Option<Assignment<'_>> {
+    let rest = rest.trim_start();
+    let (var, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some(Assignment {
+        syntax,
+        op,
+        var: valid_name(var)?,
+        value: value.trim(),
+    })
+}
+
+fn valid_name(var: &str) -> Option<&str> {
+    let mut chars = var.chars();
+    let first = chars.next()?;
+    if (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Some(var)
+    } else {
+        None
+    }
+}
+
+/// Evaluates the right hand side of an assignment: strips quoting
+/// and expands variable references.
+fn evaluate(
+    value: &str,
+    syntax: AssignmentStyle,
+    separator: &str,
+    env: &HashMap<String, String>,
+) -> String {
+    let mut out = String::new();
+    let mut words = Vec::new();
+    let mut chars = value.chars().peekable();
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (Some('\''), c) => out.push(c),
+            (_, '\\') if syntax != AssignmentStyle::PowerShellHelper => {
+                if let Some(next) = chars.next() {
+                    out.push(next);
+                }
+            }
+            (_, '$') if syntax != AssignmentStyle::PowerShellHelper => {
+                let braced = chars.peek() == Some(&'{');
+                if braced {
+                    chars.next();
+                }
+                let mut name = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        name.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // `${VAR:+word}` and `${VAR:-word}`, as written for
+                // variables that may be undefined.
+                if braced && !name.is_empty() && chars.peek() == Some(&':') {
+                    chars.next();
+                    let op = chars.next();
+                    let word: String = chars.by_ref().take_while(|&next| next != '}').collect();
+                    let current = env.get(&name).filter(|value| !value.is_empty());
+                    match (op, current) {
+                        (Some('+'), Some(_)) | (Some('-'), None) => {
+                            out.push_str(&evaluate(&word, syntax, separator, env))
+                        }
+                        (Some('-'), Some(value)) => out.push_str(value),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if braced && chars.peek() == Some(&'}') {
+                    chars.next();
+                }
+                if name.is_empty() {
+                    out.push('$');
+                } else if let Some(value) = env.get(&name) {
+                    out.push_str(value);
+                }
+            }
+            (None, c) if c.is_whitespace() && syntax == AssignmentStyle::SetGx => {
+                if !out.is_empty() {
+                    words.push(std::mem::take(&mut out));
+                }
+            }
+            (_, c) => out.push(c),
+        }
+    }
+
+    if words.is_empty() {
+        out
+    } else {
+        if !out.is_empty() {
+            words.push(out);
+        }
+        words.join(separator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHELLS: [(&str, SyntaxInfo); 4] = [
+        ("bash", SyntaxInfo::POSIX),
+        ("fish", SyntaxInfo::FISH),
+        ("csh", SyntaxInfo::CSH),
+        ("pwsh", SyntaxInfo::POWERSHELL),
+    ];
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn run(profile: &[String], shell: &str, base: &[(&str, &str)]) -> HashMap<String, String> {
+        simulate(&profile.join("\n"), shell, &env(base)).unwrap()
+    }
+
+    #[test]
+    fn appends_and_prepends_reference_the_current_value() {
+        for (shell, syntax) in SHELLS {
+            let separator = if shell == "pwsh" { ";" } else { ":" };
+            let profile = [
+                syntax.append_line("PATH", "/a"),
+                syntax.prepend_line("PATH", "/b"),
+                syntax.append_line("PATH", "/a"),
+            ];
+            let result = run(&profile, shell, &[("PATH", "/usr/bin")]);
+            assert_eq!(
+                result["PATH"],
+                ["/b", "/usr/bin", "/a", "/a"].join(separator),
+                "{}",
+                shell
+            );
+        }
+    }
+
+    #[test]
+    fn assignments_apply_in_file_order() {
+        for (shell, syntax) in SHELLS {
+            let profile = [
+                syntax.set_line("TOOL_HOME", "/opt/tool"),
+                syntax.set_line("TOOL_HOME", "/opt/other"),
+            ];
+            let result = run(&profile, shell, &[("TOOL_HOME", "/base")]);
+            assert_eq!(result["TOOL_HOME"], "/opt/other", "{}", shell);
+        }
+        let profile = [
+            SyntaxInfo::POSIX.set_line("TOOL_HOME", "/opt/tool"),
+            SyntaxInfo::POSIX.set_line("TOOL_BIN", "\"$TOOL_HOME/bin\""),
+            SyntaxInfo::POSIX.set_line("TOOL_HOME", "/opt/other"),
+        ];
+        let result = run(&profile, "bash", &[]);
+        assert_eq!(result["TOOL_BIN"], "/opt/tool/bin");
+    }
+
+    #[test]
+    fn undefined_references_expand_to_nothing() {
+        let expected = [
+            ("bash", ":/a"),
+            ("fish", "/a"),
+            ("csh", ":/a"),
+            ("pwsh", "/a"),
+        ];
+        for ((shell, syntax), (_, expected)) in SHELLS.into_iter().zip(expected) {
+            let result = run(&[syntax.append_line("TOOL_PATH", "/a")], shell, &[]);
+            assert_eq!(result["TOOL_PATH"], expected, "{}", shell);
+        }
+
+        let posix = SyntaxInfo::POSIX;
+        let profile = [
+            posix.guarded_append_line("APPENDED", "/a").unwrap(),
+            posix.guarded_prepend_line("PREPENDED", "/a").unwrap(),
+            posix.set_line("PLAIN", "\"${UNDEFINED}x\""),
+            posix.set_line("DEFAULT", "\"${UNDEFINED:-fallback}\""),
+            posix.set_line("ALTERNATE", "\"${UNDEFINED:+alternate}\""),
+        ];
+        let result = run(&profile, "bash", &[]);
+        assert_eq!(result["APPENDED"], "/a");
+        assert_eq!(result["PREPENDED"], "/a");
+        assert_eq!(result["PLAIN"], "x");
+        assert_eq!(result["DEFAULT"], "fallback");
+        assert_eq!(result["ALTERNATE"], "");
+        assert!(!result.contains_key("UNDEFINED"));
+
+        let result = run(
+            &profile,
+            "bash",
+            &[("APPENDED", "x"), ("PREPENDED", "x"), ("UNDEFINED", "y")],
+        );
+        assert_eq!(result["APPENDED"], "x:/a");
+        assert_eq!(result["PREPENDED"], "/a:x");
+        assert_eq!(result["DEFAULT"], "y");
+        assert_eq!(result["ALTERNATE"], "alternate");
+    }
+
+    #[test]
+    fn quoting_is_stripped() {
+        let posix = SyntaxInfo::POSIX;
+        let profile = [
+            posix.set_line("SINGLE", "'$HOME stays'"),
+            posix.set_line("ESCAPED", "\"\\$HOME\""),
+        ];
+        let result = run(&profile, "bash", &[("HOME", "/home/me")]);
+        assert_eq!(result["SINGLE"], "$HOME stays");
+        assert_eq!(result["ESCAPED"], "$HOME");
+    }
+
+    #[test]
+    fn unrecognized_lines_are_ignored() {
+        let profile =
+            "# export COMMENTED=1\nalias ll='ls -l'\nexport 1BAD=x\nif true; then\nexport GOOD=1\n";
+        let result = simulate(profile, "bash", &env(&[("BASE", "kept")])).unwrap();
+        assert_eq!(result, env(&[("BASE", "kept"), ("GOOD", "1")]));
+    }
+
+    #[test]
+    fn assignments_for_other_shells_are_rejected() {
+        for (shell, syntax) in SHELLS {
+            for (other_shell, other) in SHELLS {
+                if shell == other_shell {
+                    continue;
+                }
+                let profile = [syntax.set_line("FOO", "1"), other.set_line("BAR", "2")].join("\n");
+                let err = simulate(&profile, shell, &HashMap::new()).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert!(
+                    err.to_string().starts_with("Line 2 uses"),
+                    "{} in {}: {}",
+                    other_shell,
+                    shell,
+                    err
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_shells_are_rejected() {
+        let err = simulate("export FOO=1", "nu", &HashMap::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn the_base_environment_is_not_changed() {
+        let base = env(&[("PATH", "/usr/bin")]);
+        let result = simulate(&SyntaxInfo::POSIX.append_line("PATH", "/a"), "bash", &base).unwrap();
+        assert_eq!(base["PATH"], "/usr/bin");
+        assert_eq!(result["PATH"], "/usr/bin:/a");
+    }
+
+    /// The list before the line runs, or `None` if it's undefined, and
+    /// after it ran.
+    const BEFORE_SYSTEM: [(Option<&str>, &str); 6] = [
+        (
+            Some("/home/me/bin:/usr/local/bin:/usr/bin:/bin"),
+            "/home/me/bin:/opt/new:/usr/local/bin:/usr/bin:/bin",
+        ),
+        (
+            Some("/nix/store/x/bin:/home/me/bin"),
+            "/opt/new:/nix/store/x/bin:/home/me/bin",
+        ),
+        (
+            Some("/home/me/bin:/snap/bin"),
+            "/home/me/bin:/opt/new:/snap/bin",
+        ),
+        (Some("/home/me/bin"), "/home/me/bin:/opt/new"),
+        (Some("/usr/bin:/opt/new"), "/usr/bin:/opt/new"),
+        (None, "/opt/new"),
+    ];
+
+    #[test]
+    fn before_system_lines_go_before_the_first_system_directory() {
+        for (shell, syntax) in SHELLS {
+            let Some(line) = syntax.before_system_line("TOOL_PATH", "/opt/new") else {
+                assert!(shell == "csh" || shell == "pwsh", "{}", shell);
+                continue;
+            };
+            for (before, after) in BEFORE_SYSTEM {
+                let base: Vec<(&str, &str)> =
+                    before.map(|it| ("TOOL_PATH", it)).into_iter().collect();
+                let result = run(std::slice::from_ref(&line), shell, &base);
+                assert_eq!(result["TOOL_PATH"], after, "{} with {:?}", shell, before);
+            }
+        }
+    }
+
+    /// Runs `line` in `shell` with `TOOL_PATH` set to `before` and returns
+    /// the value it leaves, or `None` if `shell` isn't installed.
+    #[cfg(target_family = "unix")]
+    fn run_in_shell(shell: &str, line: &str, before: Option<&str>) -> Option<String> {
+        let script = match shell {
+            "fish" => format!("{}; printf %s \"$TOOL_PATH\"", line),
+            _ => format!("{}\nprintf %s \"$TOOL_PATH\"", line),
+        };
+        let mut command = std::process::Command::new(shell);
+        command.arg("-c").arg(script).env_remove("TOOL_PATH");
+        if let Some(before) = before {
+            command.env("TOOL_PATH", before);
+        }
+        match command.output() {
+            Ok(output) => {
+                assert!(output.status.success(), "{:?}", output);
+                Some(String::from_utf8(output.stdout).unwrap())
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => panic!("{}: {}", shell, err),
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn before_system_lines_run_in_the_shells_like_simulated() {
+        for (shell, syntax) in [
+            ("sh", SyntaxInfo::POSIX),
+            ("dash", SyntaxInfo::POSIX),
+            ("bash", SyntaxInfo::POSIX),
+            ("zsh", SyntaxInfo::POSIX),
+            ("fish", SyntaxInfo::FISH),
+        ] {
+            let line = syntax.before_system_line("TOOL_PATH", "/opt/new").unwrap();
+            for (before, after) in BEFORE_SYSTEM {
+                let Some(actual) = run_in_shell(shell, &line, before) else {
+                    break;
+                };
+                assert_eq!(actual, after, "{} with {:?}", shell, before);
+            }
+        }
+    }
+}
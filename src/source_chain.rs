@@ -0,0 +1,382 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{DEFS_BEG, DEFS_END};
+
+/// Most files deep a chain of sourced files is followed, counting the
+/// profile.
+const MAX_DEPTH: usize = 4;
+
+/// Most files read when following the files sourced by a profile,
+/// counting the profile.
+const MAX_FILES: usize = 32;
+
+/// A line sourcing another file, like `. ~/.bash_aliases`, numbered
+/// from 1. See `ExistingSource::Sourced`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceStep {
+    pub path: PathBuf,
+    pub line_no: usize,
+}
+
+/// A line of a profile or of a file sourced by it, see `for_each_line`.
+pub(crate) struct Line<'a> {
+    /// The source lines leading to the file, starting in the profile,
+    /// empty for a line of the profile.
+    pub chain: &'a [SourceStep],
+    pub path: &'a Path,
+    pub line_no: usize,
+    pub text: &'a str,
+    /// Whether the line is in the block managed by this crate.
+    pub managed: bool,
+}
+
+/// Calls `visit` with every line of the profile at `path` and of the
+/// files it sources, in the order the shell runs them: the lines of a
+/// sourced file right after the line sourcing it.
+///
+/// Only paths that are literal, or start with the home directory, are
+/// followed, up to `MAX_DEPTH` files deep and `MAX_FILES` files in
+/// total. Sourced files that don't exist are passed over, as they
+/// usually are guarded by a check for them. Lines that aren't followed
+/// otherwise, like ones sourcing a file already being read, are
+/// reported with `Event::SourceSkipped`.
+pub(crate) fn for_each_line(path: &Path, visit: &mut dyn FnMut(&Line)) -> io::Result<()> {
+    let content = match crate::filesystem::current().read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let mut walk = Walk {
+        home: home(),
+        files: 1,
+        chain: Vec::new(),
+    };
+    walk.file(path, &content, visit);
+    Ok(())
+}
+
+struct Walk {
+    home: Option<PathBuf>,
+    files: usize,
+    chain: Vec<SourceStep>,
+}
+
+impl Walk {
+    fn file(&mut self, path: &Path, content: &str, visit: &mut dyn FnMut(&Line)) {
+        let mut managed = false;
+        for (idx, text) in content.lines().enumerate() {
+            let trimmed = text.trim();
+            if trimmed == DEFS_BEG || trimmed == DEFS_END {
+                managed = trimmed == DEFS_BEG;
+            }
+            visit(&Line {
+                chain: &self.chain,
+                path,
+                line_no: idx + 1,
+                text,
+                managed,
+            });
+            if let Some(arg) = sourced(trimmed) {
+                self.follow(path, idx + 1, arg, visit);
+            }
+        }
+    }
+
+    fn follow(&mut self, path: &Path, line_no: usize, arg: &str, visit: &mut dyn FnMut(&Line)) {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let target = match resolve(arg, self.home.as_deref(), dir) {
+            Ok(target) => target,
+            Err(reason) => return skipped(path, line_no, reason),
+        };
+        if target == path || self.chain.iter().any(|step| step.path == target) {
+            return skipped(path, line_no, "it sources a file already being read".into());
+        }
+        if self.chain.len() + 1 >= MAX_DEPTH {
+            let reason = format!("files are followed at most {} deep", MAX_DEPTH);
+            return skipped(path, line_no, reason);
+        }
+        if self.files >= MAX_FILES {
+            let reason = format!("at most {} files are read", MAX_FILES);
+            return skipped(path, line_no, reason);
+        }
+
+        let content = match crate::filesystem::current().read_to_string(&target) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => return skipped(path, line_no, format!("{}", err)),
+        };
+        self.files += 1;
+        self.chain.push(SourceStep {
+            path: path.to_path_buf(),
+            line_no,
+        });
+        self.file(&target, &content, visit);
+        self.chain.pop();
+    }
+}
+
+fn skipped(path: &Path, line_no: usize, reason: String) {
+    crate::events::emit(crate::Event::SourceSkipped {
+        path: path.to_path_buf(),
+        line_no,
+        reason,
+    });
+}
+
+/// Returns the argument naming the file `line` sources, if it's a POSIX
+/// `.` or `source`, a fish or csh `source`, or a PowerShell dot-source.
+/// A single guard like `[ -f ~/.aliases ] && . ~/.aliases` is allowed.
+fn sourced(line: &str) -> Option<&str> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let command = line.rsplit_once("&& ").map_or(line, |(_, it)| it);
+    let rest = command
+        .strip_prefix(". ")
+        .or_else(|| command.strip_prefix("source "))?
+        .trim_start();
+    let rest = rest.strip_suffix(';').unwrap_or(rest).trim_end();
+
+    for quote in ['"', '\''] {
+        if let Some(quoted) = rest.strip_prefix(quote) {
+            let end = quoted.find(quote)?;
+            return Some(&rest[..end + 2]);
+        }
+    }
+    rest.split_whitespace().next()
+}
+
+/// Returns the file `arg` of a line in a file in `dir` names.
+fn resolve(arg: &str, home: Option<&Path>, dir: &Path) -> Result<PathBuf, String> {
+    if let Some(literal) = arg.strip_prefix('\'').and_then(|it| it.strip_suffix('\'')) {
+        return absolute(literal);
+    }
+    let quoted = arg.strip_prefix('"').and_then(|it| it.strip_suffix('"'));
+    let path = quoted.unwrap_or(arg);
+
+    // The tilde isn't expanded in quotes.
+    let mut prefixes = vec!["$HOME", "${HOME}", "$env:USERPROFILE", "$env:HOME"];
+    if quoted.is_none() {
+        prefixes.push("~");
+    }
+    let base = prefixes
+        .iter()
+        .find_map(|prefix| Some((home, path.strip_prefix(prefix)?)))
+        .or_else(|| Some((Some(dir), path.strip_prefix("$PSScriptRoot")?)));
+    let Some((base, rest)) = base else {
+        return absolute(path);
+    };
+    let Some(rest) = rest.strip_prefix(['/', '\\']) else {
+        return Err(not_literal(arg));
+    };
+    if rest.contains(['$', '`', '*', '?', '(']) {
+        return Err(not_literal(arg));
+    }
+    let base = base.ok_or_else(|| "there is no home directory".to_string())?;
+    Ok(base.join(rest))
+}
+
+fn absolute(path: &str) -> Result<PathBuf, String> {
+    if path.contains(['$', '`', '*', '?', '(']) {
+        return Err(not_literal(path));
+    }
+    if !Path::new(path).is_absolute() {
+        return Err(format!(
+            "{} is relative to the directory the shell starts in",
+            path
+        ));
+    }
+    Ok(PathBuf::from(path))
+}
+
+fn not_literal(arg: &str) -> String {
+    format!("{} isn't a literal path", arg)
+}
+
+#[cfg(target_family = "unix")]
+fn home() -> Option<PathBuf> {
+    crate::home_and_shell_with(crate::Access::ReadOnly)
+        .ok()
+        .map(|(home, _)| home)
+}
+
+#[cfg(target_os = "windows")]
+fn home() -> Option<PathBuf> {
+    dirs::home_dir()
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::events::{Event, VecSink};
+    use crate::test_support::sandbox;
+    use crate::{ExistingSource, SetOutcome};
+    use std::sync::Arc;
+
+    /// Returns the path, number and depth of every line visited.
+    fn visited(path: &Path) -> Vec<(PathBuf, usize, usize)> {
+        let mut lines = Vec::new();
+        for_each_line(path, &mut |line| {
+            lines.push((line.path.to_path_buf(), line.line_no, line.chain.len()))
+        })
+        .unwrap();
+        lines
+    }
+
+    fn skipped_events(sink: &VecSink) -> Vec<(PathBuf, usize, String)> {
+        sink.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::SourceSkipped {
+                    path,
+                    line_no,
+                    reason,
+                } => Some((path, line_no, reason)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_two_levels_of_sourcing() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            "# env\n. ~/.config/env.sh\nalias ll='ls -l'\n",
+        );
+        sandbox.write(
+            ".config/env.sh",
+            "[ -f \"$HOME/.config/deeper.sh\" ] && . \"$HOME/.config/deeper.sh\"\n",
+        );
+        sandbox.write(
+            ".config/deeper.sh",
+            "# deeper\nexport SET_ENV_CHAIN_TWO=deep\n",
+        );
+        let (profile, env, deeper) = (
+            sandbox.path(".bash_profile"),
+            sandbox.path(".config/env.sh"),
+            sandbox.path(".config/deeper.sh"),
+        );
+
+        assert_eq!(
+            visited(&profile),
+            [
+                (profile.clone(), 1, 0),
+                (profile.clone(), 2, 0),
+                (env.clone(), 1, 1),
+                (deeper.clone(), 1, 2),
+                (deeper.clone(), 2, 2),
+                (profile.clone(), 3, 0),
+            ]
+        );
+        assert_eq!(
+            crate::check_or_set_detailed("SET_ENV_CHAIN_TWO", "other").unwrap(),
+            SetOutcome::Skipped {
+                source: ExistingSource::Sourced {
+                    chain: vec![
+                        SourceStep {
+                            path: profile,
+                            line_no: 2
+                        },
+                        SourceStep {
+                            path: env,
+                            line_no: 1
+                        },
+                    ],
+                    path: deeper,
+                    line_no: 2,
+                },
+                value: Some("deep".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn a_cycle_is_skipped_with_a_note() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", ". ~/a.sh\n");
+        sandbox.write("a.sh", "source ~/b.sh\nexport SET_ENV_CHAIN_CYCLE=a\n");
+        sandbox.write("b.sh", ". ~/a.sh\n. ~/.bash_profile\n");
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        let (profile, a, b) = (
+            sandbox.path(".bash_profile"),
+            sandbox.path("a.sh"),
+            sandbox.path("b.sh"),
+        );
+        assert_eq!(
+            visited(&profile),
+            [
+                (profile, 1, 0),
+                (a.clone(), 1, 1),
+                (b.clone(), 1, 2),
+                (b.clone(), 2, 2),
+                (a, 2, 1),
+            ]
+        );
+        let reason = "it sources a file already being read".to_string();
+        assert_eq!(
+            skipped_events(&sink),
+            [(b.clone(), 1, reason.clone()), (b, 2, reason)]
+        );
+        let outcome = crate::check_or_set_detailed("SET_ENV_CHAIN_CYCLE", "other").unwrap();
+        assert!(matches!(
+            outcome,
+            SetOutcome::Skipped { value: Some(value), .. } if value == "a"
+        ));
+    }
+
+    #[test]
+    fn paths_that_are_not_literal_are_skipped_with_a_note() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(
+            ".bash_profile",
+            ". \"$XDG_CONFIG_HOME/env.sh\"\nsource env.sh\n. ~/missing.sh\n",
+        );
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        let profile = sandbox.path(".bash_profile");
+        assert_eq!(visited(&profile).len(), 3);
+        let skipped = skipped_events(&sink);
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(
+            skipped[0],
+            (
+                profile.clone(),
+                1,
+                "$XDG_CONFIG_HOME/env.sh isn't a literal path".to_string()
+            )
+        );
+        assert_eq!(
+            skipped[1],
+            (
+                profile,
+                2,
+                "env.sh is relative to the directory the shell starts in".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn chains_are_followed_only_so_deep() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", ". ~/1.sh\n");
+        for depth in 1..=MAX_DEPTH {
+            sandbox.write(&format!("{}.sh", depth), &format!(". ~/{}.sh\n", depth + 1));
+        }
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        let deepest = visited(&sandbox.path(".bash_profile"))
+            .into_iter()
+            .map(|(_, _, depth)| depth)
+            .max();
+        assert_eq!(deepest, Some(MAX_DEPTH - 1));
+        let skipped = skipped_events(&sink);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, sandbox.path(&format!("{}.sh", MAX_DEPTH - 1)));
+    }
+}
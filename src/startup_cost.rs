@@ -0,0 +1,298 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::change::ChangeWarning;
+use crate::condition::{self, Condition};
+use crate::syntax::{QuotingStyle, SyntaxInfo};
+use crate::{DEFS_BEG, DEFS_END};
+
+/// How much a line in the profile adds to the startup time of a shell,
+/// roughly, see `line_cost`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum CostClass {
+    /// Only assigns, without looking at anything outside the shell.
+    Trivial,
+    /// Looks at the filesystem, like searching the PATH for a command
+    /// or reading another file.
+    FilesystemTest,
+    /// Runs another program, like a command substitution in the value.
+    Subprocess,
+}
+
+/// What the lines managed by this crate add to the startup of a shell,
+/// see `startup_cost`. Displayed as a one line summary.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StartupCost {
+    pub trivial: usize,
+    pub filesystem_tests: usize,
+    pub subprocesses: usize,
+}
+
+impl fmt::Display for StartupCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "set_env adds ~{} filesystem checks and {} subprocesses to shell startup",
+            self.filesystem_tests, self.subprocesses
+        )
+    }
+}
+
+static ALLOW_SUBSTITUTION: AtomicBool = AtomicBool::new(false);
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Allows values running a command whenever a shell starts, like
+/// `$(brew --prefix)`, without a warning, even with
+/// `set_strict_command_substitution`.
+pub fn allow_command_substitution(allow: bool) {
+    ALLOW_SUBSTITUTION.store(allow, Ordering::SeqCst);
+}
+
+/// Makes a value running a command whenever a shell starts an error
+/// instead of a warning in the returned `Change`, unless
+/// `allow_command_substitution` was called.
+pub fn set_strict_command_substitution(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
+}
+
+/// Returns a warning about or, with `set_strict_command_substitution`,
+/// refuses a `value` for `var` with a command substitution in the
+/// syntax of the profile.
+pub(crate) fn check_value(var: &str, value: &str) -> io::Result<Option<ChangeWarning>> {
+    if ALLOW_SUBSTITUTION.load(Ordering::SeqCst)
+        || !has_command_substitution(crate::profile_syntax()?.quoting, value)
+    {
+        return Ok(None);
+    }
+
+    let warning = ChangeWarning::CommandSubstitution {
+        var: var.to_string(),
+    };
+    if STRICT.load(Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            warning.to_string(),
+        ));
+    }
+    Ok(Some(warning))
+}
+
+/// Does the same as `check_value`, emitting the warning as an
+/// `Event::Warning`, for functions returning something else than a
+/// `Change`.
+pub(crate) fn warn_value(var: &str, value: &str) -> io::Result<()> {
+    if let Some(warning) = check_value(var, value)? {
+        crate::events::warn(warning.to_string());
+    }
+    Ok(())
+}
+
+/// Returns the cost of `line`, written in the syntax of `syntax`.
+pub fn line_cost(syntax: &SyntaxInfo, line: &str) -> CostClass {
+    let line = line.trim();
+    let (guard, line) = match condition::unguard(line) {
+        Some((condition, guarded)) => (condition_cost(&condition), guarded),
+        None => (CostClass::Trivial, line),
+    };
+    let source = syntax.source_line("\0");
+    let prefix = source.split('\0').next().unwrap_or_default();
+    let own = if has_command_substitution(syntax.quoting, line) {
+        CostClass::Subprocess
+    } else if line.starts_with(prefix) {
+        CostClass::FilesystemTest
+    } else {
+        CostClass::Trivial
+    };
+    guard.max(own)
+}
+
+fn condition_cost(condition: &Condition) -> CostClass {
+    match condition {
+        Condition::Interactive | Condition::EnvSet(_) => CostClass::Trivial,
+        // Searches every directory of the PATH.
+        Condition::CommandExists(_) => CostClass::FilesystemTest,
+    }
+}
+
+/// Returns what the lines in the blocks managed by this crate add to
+/// the startup of the user's shell. Disabled lines are free.
+pub fn startup_cost() -> io::Result<StartupCost> {
+    let _operation = crate::events::operation();
+    let syntax = crate::profile_syntax()?;
+    let mut cost = StartupCost::default();
+    for path in crate::managed_profiles()? {
+        let content = match crate::filesystem::current().read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let mut managed = false;
+        for line in content.lines() {
+            let line = line.trim();
+            if line == DEFS_BEG || line == DEFS_END {
+                managed = line == DEFS_BEG;
+                continue;
+            }
+            if !managed || line.is_empty() || line.starts_with(syntax.comment_prefix) {
+                continue;
+            }
+            match line_cost(&syntax, line) {
+                CostClass::Trivial => cost.trivial += 1,
+                CostClass::FilesystemTest => cost.filesystem_tests += 1,
+                CostClass::Subprocess => cost.subprocesses += 1,
+            }
+        }
+    }
+    Ok(cost)
+}
+
+/// Returns whether `text` runs a command when a shell with `quoting`
+/// evaluates it: `$(...)` and backticks in POSIX shells, backticks in
+/// csh, `(...)` and `$(...)` in fish, and `$(...)` and `(...)` starting
+/// a word in PowerShell. Nothing in single quotes counts, nor POSIX arithmetic
+/// like `$((1 + 2))`.
+pub(crate) fn has_command_substitution(quoting: QuotingStyle, text: &str) -> bool {
+    let escape = match quoting {
+        QuotingStyle::PowerShell => '`',
+        _ => '\\',
+    };
+    let mut chars = text.chars().peekable();
+    let (mut single, mut double) = (false, false);
+    let mut word_start = true;
+    while let Some(c) = chars.next() {
+        let at_word_start = std::mem::replace(&mut word_start, c.is_whitespace());
+        match c {
+            _ if c == escape && !single => {
+                chars.next();
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            _ if single => {}
+            '`' if matches!(quoting, QuotingStyle::Posix | QuotingStyle::Csh) => return true,
+            '$' if chars.peek() == Some(&'(') && quoting != QuotingStyle::Csh => {
+                chars.next();
+                if quoting != QuotingStyle::Posix || chars.peek() != Some(&'(') {
+                    return true;
+                }
+            }
+            '(' if !double && quoting == QuotingStyle::Fish => return true,
+            '(' if !double && quoting == QuotingStyle::PowerShell && at_word_start => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_family = "unix")]
+    use crate::events::{Event, VecSink};
+    #[cfg(target_family = "unix")]
+    use crate::test_support::sandbox;
+    #[cfg(target_family = "unix")]
+    use std::sync::Arc;
+
+    #[cfg(target_family = "unix")]
+    const VAR: &str = "SET_ENV_SUBSTITUTION";
+
+    #[test]
+    fn finds_command_substitutions() {
+        let cases = [
+            (QuotingStyle::Posix, "$(brew --prefix)/bin", true),
+            (QuotingStyle::Posix, "`uname`", true),
+            (QuotingStyle::Posix, "\"$(date)\"", true),
+            (QuotingStyle::Posix, "$(dirname \"$(which rustc)\")", true),
+            (QuotingStyle::Posix, "'it'\"s $(whoami)\"", true),
+            (QuotingStyle::Posix, "\"'$(whoami)'\"", true),
+            (QuotingStyle::Posix, "'$(date)'", false),
+            (QuotingStyle::Posix, "'`date`'", false),
+            (QuotingStyle::Posix, "\\$(date)", false),
+            (QuotingStyle::Posix, "\\`date\\`", false),
+            (QuotingStyle::Posix, "\"\\$(date)\"", false),
+            (QuotingStyle::Posix, "$((1 + 2))", false),
+            (QuotingStyle::Posix, "$HOME/bin:${PATH}", false),
+            (QuotingStyle::Csh, "`hostname`", true),
+            (QuotingStyle::Csh, "\"`hostname`\"", true),
+            (QuotingStyle::Csh, "'`hostname`'", false),
+            (QuotingStyle::Csh, "$(hostname)", false),
+            (QuotingStyle::Fish, "(uname)", true),
+            (QuotingStyle::Fish, "$(uname)", true),
+            (QuotingStyle::Fish, "\"$(uname)\"", true),
+            (QuotingStyle::Fish, "(dirname (which rustc))", true),
+            (QuotingStyle::Fish, "\"(uname)\"", false),
+            (QuotingStyle::Fish, "'(uname)'", false),
+            (QuotingStyle::Fish, "\\(uname\\)", false),
+            (QuotingStyle::PowerShell, "$(Get-Date)", true),
+            (QuotingStyle::PowerShell, "(Get-Location)", true),
+            (QuotingStyle::PowerShell, "\"$(Get-Date)\"", true),
+            (QuotingStyle::PowerShell, "C:\\Program Files\\(x86)", false),
+            (QuotingStyle::PowerShell, "'$(Get-Date)'", false),
+            (QuotingStyle::PowerShell, "`$(Get-Date)", false),
+        ];
+        for (quoting, text, expected) in cases {
+            assert_eq!(
+                has_command_substitution(quoting, text),
+                expected,
+                "{:?} {}",
+                quoting,
+                text
+            );
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn a_command_substitution_is_warned_about() {
+        let sandbox = sandbox("/bin/bash");
+
+        let change = crate::set(VAR, "$(brew --prefix)").unwrap();
+        assert_eq!(
+            change.warnings,
+            [ChangeWarning::CommandSubstitution {
+                var: VAR.to_string()
+            }]
+        );
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("export SET_ENV_SUBSTITUTION=$(brew --prefix)"));
+        assert!(crate::set(VAR, "'$(literal)'").unwrap().warnings.is_empty());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn functions_without_a_change_emit_the_warning() {
+        let _sandbox = sandbox("/bin/bash");
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        crate::set_disabled(VAR, "`uname`").unwrap();
+        let warnings: Vec<String> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::Warning { .. }))
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("runs a command whenever a shell starts"));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn strict_mode_refuses_unless_allowed() {
+        let sandbox = sandbox("/bin/bash");
+        set_strict_command_substitution(true);
+
+        let err = crate::append(VAR, "$(brew --prefix)/bin").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!sandbox.path(".bash_profile").exists());
+
+        allow_command_substitution(true);
+        let change = crate::append(VAR, "$(brew --prefix)/bin").unwrap();
+        assert!(change.warnings.is_empty());
+        assert!(sandbox
+            .read(".bash_profile")
+            .contains("$(brew --prefix)/bin"));
+    }
+}
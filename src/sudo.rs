@@ -0,0 +1,305 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::Access;
+
+/// What to do when running under `sudo`, see `set_sudo_policy`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SudoPolicy {
+    /// Write to the profile of the user who invoked `sudo`,
+    /// found through the passwd database, and give the file to them.
+    TargetInvokingUser,
+    /// Write to the profile of root, as found through `HOME` and `SHELL`.
+    TargetRoot,
+    /// Refuse to write, asking to re-run without `sudo`. Functions that
+    /// only read, like `which_profile_readonly`, look at the profile of
+    /// root.
+    #[default]
+    Error,
+}
+
+static POLICY: Mutex<SudoPolicy> = Mutex::new(SudoPolicy::Error);
+
+/// Sets what to do when the process runs under `sudo`, detected
+/// through an effective user id of 0 and the `SUDO_UID` variable.
+/// Depending on how sudo is configured `HOME` is either still the
+/// one of the invoking user or the one of root, so which profile would
+/// be changed is not obvious.
+/// Defaults to `SudoPolicy::Error`.
+pub fn set_sudo_policy(policy: SudoPolicy) {
+    *POLICY.lock().unwrap_or_else(|err| err.into_inner()) = policy;
+}
+
+/// An entry of the passwd database, see `PasswdResolver`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+    pub shell: String,
+}
+
+/// Finds out who runs the process and who invoked `sudo`, see
+/// `set_passwd_resolver`.
+pub trait PasswdResolver: Send + Sync {
+    /// Returns the effective user id of the process.
+    fn effective_uid(&self) -> u32;
+    /// Returns the user with the id `uid`, or `None` if there is none.
+    fn user(&self, uid: u32) -> io::Result<Option<PasswdEntry>>;
+}
+
+/// The resolver used unless `set_passwd_resolver` is called, reading
+/// the passwd file of the platform through the configured `Filesystem`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PasswdFile;
+
+impl PasswdResolver for PasswdFile {
+    fn effective_uid(&self) -> u32 {
+        // SAFETY: geteuid has no preconditions and can't fail.
+        unsafe { libc::geteuid() }
+    }
+
+    fn user(&self, uid: u32) -> io::Result<Option<PasswdEntry>> {
+        let path = crate::platform().passwd.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "There is no passwd database to find the user invoking sudo in",
+            )
+        })?;
+        let passwd = crate::filesystem::current().read_to_string(Path::new(path))?;
+        Ok(find_user(&passwd, uid))
+    }
+}
+
+static RESOLVER: Mutex<Option<Arc<dyn PasswdResolver>>> = Mutex::new(None);
+
+/// Sets how the user invoking `sudo` is looked up, like through NSS
+/// instead of the passwd file, or with a fixed table in tests.
+/// Defaults to `PasswdFile`.
+pub fn set_passwd_resolver<R: PasswdResolver + 'static>(resolver: R) {
+    *RESOLVER.lock().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(resolver));
+}
+
+fn resolver() -> Arc<dyn PasswdResolver> {
+    RESOLVER
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(PasswdFile))
+}
+
+/// Returns the user to use the profiles of instead of the current one,
+/// or `None` when not running under `sudo` or targeting root.
+/// Only `Access::ReadWrite` fails with `SudoPolicy::Error`.
+pub(crate) fn invoking_user(access: Access) -> io::Result<Option<PasswdEntry>> {
+    let Some(uid) = sudo_uid() else {
+        return Ok(None);
+    };
+    let name = env::var("SUDO_USER").unwrap_or_else(|_| format!("uid {}", uid));
+
+    let policy = *POLICY.lock().unwrap_or_else(|err| err.into_inner());
+    match (policy, access) {
+        (SudoPolicy::TargetRoot, _) | (SudoPolicy::Error, Access::ReadOnly) => Ok(None),
+        (SudoPolicy::Error, Access::ReadWrite) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Running under sudo for {}, re-run without sudo to change their profile",
+                name
+            ),
+        )),
+        (SudoPolicy::TargetInvokingUser, _) => resolver().user(uid)?.map(Some).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "User {} invoking sudo not found in the passwd database",
+                    name
+                ),
+            )
+        }),
+    }
+}
+
+/// Returns the id of the user who invoked `sudo`, if the process runs as
+/// root through `sudo` run by another user. `SUDO_UID` alone is
+/// inherited by anything started from such a process, even after it
+/// dropped its privileges.
+pub(crate) fn sudo_uid() -> Option<u32> {
+    if resolver().effective_uid() != 0 {
+        return None;
+    }
+    env::var("SUDO_UID")
+        .ok()
+        .and_then(|uid| uid.parse().ok())
+        .filter(|uid| *uid != 0)
+}
+
+/// Finds the user with the id `uid` in the contents of a passwd file.
+fn find_user(passwd: &str, uid: u32) -> Option<PasswdEntry> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 7 || fields[2].parse() != Ok(uid) {
+            return None;
+        }
+        Some(PasswdEntry {
+            name: fields[0].to_string(),
+            uid,
+            gid: fields[3].parse().ok()?,
+            home: PathBuf::from(fields[5]),
+            shell: fields[6].to_string(),
+        })
+    })
+}
+
+/// Gives the file at `path` to the invoking user, if there is one and
+/// the file is in their home directory, unlike system-wide files.
+pub(crate) fn chown_to_invoking_user(path: &Path) -> io::Result<()> {
+    if let Some(user) = invoking_user(Access::ReadWrite)? {
+        if path.starts_with(&user.home) {
+            crate::filesystem::current().chown(path, user.uid, user.gid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    /// A passwd database with root and one user, running as `euid`.
+    struct Users {
+        euid: u32,
+        home: PathBuf,
+    }
+
+    impl PasswdResolver for Users {
+        fn effective_uid(&self) -> u32 {
+            self.euid
+        }
+
+        fn user(&self, uid: u32) -> io::Result<Option<PasswdEntry>> {
+            Ok((uid == 1000).then(|| PasswdEntry {
+                name: "alice".to_string(),
+                uid,
+                gid: 1000,
+                home: self.home.clone(),
+                shell: "/usr/bin/zsh".to_string(),
+            }))
+        }
+    }
+
+    fn under_sudo(euid: u32, home: &Path) {
+        set_passwd_resolver(Users {
+            euid,
+            home: home.to_path_buf(),
+        });
+        env::set_var("SUDO_USER", "alice");
+        env::set_var("SUDO_UID", "1000");
+        env::set_var("SUDO_GID", "1000");
+    }
+
+    #[test]
+    fn passwd_entries_are_found_by_uid() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:100::/home/alice:/bin/zsh\n";
+        let alice = find_user(passwd, 1000).unwrap();
+        assert_eq!(alice.name, "alice");
+        assert_eq!(alice.gid, 100);
+        assert_eq!(alice.home, PathBuf::from("/home/alice"));
+        assert_eq!(alice.shell, "/bin/zsh");
+        assert_eq!(find_user(passwd, 1001), None);
+        assert_eq!(find_user("broken:x:1000\n", 1000), None);
+    }
+
+    #[test]
+    fn sudo_variables_without_root_are_ignored() {
+        let sandbox = sandbox("/bin/bash");
+        under_sudo(1000, &sandbox.path("alice"));
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").contains("export FOO=1"));
+    }
+
+    #[test]
+    fn sudo_as_root_by_root_is_ignored() {
+        let sandbox = sandbox("/bin/bash");
+        under_sudo(0, &sandbox.path("alice"));
+        env::set_var("SUDO_UID", "0");
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").contains("export FOO=1"));
+    }
+
+    #[test]
+    fn error_policy_refuses_writes_but_not_reads() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias a=b\n");
+        under_sudo(0, &sandbox.path("alice"));
+
+        let err = crate::set("FOO", "1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("alice"));
+        assert_eq!(
+            crate::which_profile_readonly().unwrap(),
+            sandbox.path(".bash_profile")
+        );
+        assert_eq!(crate::list_managed_readonly().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn invoking_user_policy_writes_to_their_profile() {
+        let sandbox = sandbox("/bin/bash");
+        std::fs::create_dir(sandbox.path("alice")).unwrap();
+        under_sudo(0, &sandbox.path("alice"));
+        set_sudo_policy(SudoPolicy::TargetInvokingUser);
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read("alice/.zshenv").contains("export FOO=1"));
+        assert!(!sandbox.path(".bash_profile").exists());
+        assert_eq!(
+            crate::which_profile_readonly().unwrap(),
+            sandbox.path("alice/.zshenv")
+        );
+    }
+
+    #[test]
+    fn unknown_invoking_users_are_reported() {
+        let sandbox = sandbox("/bin/bash");
+        under_sudo(0, &sandbox.path("alice"));
+        env::set_var("SUDO_UID", "1001");
+        set_sudo_policy(SudoPolicy::TargetInvokingUser);
+
+        let err = crate::set("FOO", "1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn root_policy_writes_to_the_profile_of_root() {
+        let sandbox = sandbox("/bin/bash");
+        under_sudo(0, &sandbox.path("alice"));
+        set_sudo_policy(SudoPolicy::TargetRoot);
+
+        crate::set("FOO", "1").unwrap();
+        assert!(sandbox.read(".bash_profile").contains("export FOO=1"));
+    }
+
+    /// Needs to run as root to give files away.
+    #[test]
+    fn profiles_are_given_to_the_invoking_user() {
+        use std::os::unix::fs::MetadataExt;
+
+        if PasswdFile.effective_uid() != 0 {
+            return;
+        }
+        let sandbox = sandbox("/bin/bash");
+        std::fs::create_dir(sandbox.path("alice")).unwrap();
+        under_sudo(0, &sandbox.path("alice"));
+        set_sudo_policy(SudoPolicy::TargetInvokingUser);
+
+        crate::set("FOO", "1").unwrap();
+        let metadata = std::fs::metadata(sandbox.path("alice/.zshenv")).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (1000, 1000));
+    }
+}
@@ -0,0 +1,601 @@
+use std::fmt;
+
+/// How a shell assigns an exported variable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AssignmentStyle {
+    /// `export VAR=value`, used by sh, bash, zsh and ksh.
+    Export,
+    /// `set -gx VAR value`, used by fish.
+    SetGx,
+    /// `setenv VAR value`, used by csh and tcsh.
+    Setenv,
+    /// `setenv_set VAR value`, the helpers this crate installs
+    /// in the PowerShell profile.
+    PowerShellHelper,
+}
+
+/// How a shell represents list variables like PATH.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListStyle {
+    /// A single string with entries separated by colons.
+    Colon,
+    /// A single string with entries separated by semicolons.
+    Semicolon,
+    /// A list of separate elements, joined with colons when exported.
+    Elements,
+}
+
+/// How a shell quotes strings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuotingStyle {
+    /// Single quotes are literal, double quotes expand `$VAR` and `${VAR}`.
+    Posix,
+    /// Like `Posix`, but `${VAR}` is not supported.
+    Fish,
+    /// Like `Posix`, but a backslash can't escape a quote inside quotes.
+    Csh,
+    /// Single quotes are literal, double quotes expand `$env:VAR`.
+    PowerShell,
+}
+
+/// The syntax of a shell, so snippets written next to the lines
+/// this crate writes can match them.
+/// The crate formats its own lines with it as well.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SyntaxInfo {
+    /// Starts a comment that runs until the end of the line.
+    pub comment_prefix: &'static str,
+    pub assignment: AssignmentStyle,
+    pub list: ListStyle,
+    pub quoting: QuotingStyle,
+}
+
+impl SyntaxInfo {
+    pub const POSIX: SyntaxInfo = SyntaxInfo {
+        comment_prefix: "#",
+        assignment: AssignmentStyle::Export,
+        list: ListStyle::Colon,
+        quoting: QuotingStyle::Posix,
+    };
+
+    pub const FISH: SyntaxInfo = SyntaxInfo {
+        comment_prefix: "#",
+        assignment: AssignmentStyle::SetGx,
+        list: ListStyle::Elements,
+        quoting: QuotingStyle::Fish,
+    };
+
+    pub const CSH: SyntaxInfo = SyntaxInfo {
+        comment_prefix: "#",
+        assignment: AssignmentStyle::Setenv,
+        list: ListStyle::Colon,
+        quoting: QuotingStyle::Csh,
+    };
+
+    pub const POWERSHELL: SyntaxInfo = SyntaxInfo {
+        comment_prefix: "#",
+        assignment: AssignmentStyle::PowerShellHelper,
+        list: ListStyle::Semicolon,
+        quoting: QuotingStyle::PowerShell,
+    };
+
+    /// Returns the syntax of the shell called `name`,
+    /// like `bash` or `pwsh`.
+    pub fn for_shell(name: &str) -> Option<SyntaxInfo> {
+        match name {
+            "sh" | "bash" | "zsh" | "ksh" => Some(SyntaxInfo::POSIX),
+            "fish" => Some(SyntaxInfo::FISH),
+            "csh" | "tcsh" => Some(SyntaxInfo::CSH),
+            "powershell" | "pwsh" => Some(SyntaxInfo::POWERSHELL),
+            _ => None,
+        }
+    }
+
+    /// Formats a line setting `var` to `value`.
+    pub fn set_line<T: fmt::Display, U: fmt::Display>(&self, var: T, value: U) -> String {
+        match self.assignment {
+            AssignmentStyle::Export => format!("export {}={}", var, value),
+            AssignmentStyle::SetGx => format!("set -gx {} {}", var, value),
+            AssignmentStyle::Setenv => format!("setenv {} {}", var, value),
+            AssignmentStyle::PowerShellHelper => format!("setenv_set {} {}", var, value),
+        }
+    }
+
+    /// Formats a line appending `value` to the list variable `var`.
+    pub fn append_line<T: fmt::Display, U: fmt::Display>(&self, var: T, value: U) -> String {
+        match self.assignment {
+            AssignmentStyle::Export => format!("export {}=\"${}:{}\"", var, var, value),
+            AssignmentStyle::SetGx => format!("set -gx {} ${} {}", var, var, value),
+            AssignmentStyle::Setenv => format!("setenv {} \"${{{}}}:{}\"", var, var, value),
+            AssignmentStyle::PowerShellHelper => format!("setenv_append {} {}", var, value),
+        }
+    }
+
+    /// Formats a line prepending `value` to the list variable `var`.
+    pub fn prepend_line<T: fmt::Display, U: fmt::Display>(&self, var: T, value: U) -> String {
+        match self.assignment {
+            AssignmentStyle::Export => format!("export {}=\"{}:${}\"", var, value, var),
+            AssignmentStyle::SetGx => format!("set -gx {} {} ${}", var, value, var),
+            AssignmentStyle::Setenv => format!("setenv {} \"{}:${{{}}}\"", var, value, var),
+            AssignmentStyle::PowerShellHelper => format!("setenv_prepend {} {}", var, value),
+        }
+    }
+
+    /// Like `append_line`, but for a `var` that may not be defined when
+    /// the line runs, where referencing it would fail in shells like
+    /// `bash -u`. fish and the PowerShell helpers already handle that,
+    /// so they get the same line as from `append_line`.
+    /// Returns `None` for csh, which has no conditional expansion.
+    pub fn guarded_append_line<T: fmt::Display, U: fmt::Display>(
+        &self,
+        var: T,
+        value: U,
+    ) -> Option<String> {
+        match self.assignment {
+            AssignmentStyle::Export => Some(format!(
+                "export {}=\"${{{}:+${}:}}{}\"",
+                var, var, var, value
+            )),
+            AssignmentStyle::Setenv => None,
+            AssignmentStyle::SetGx | AssignmentStyle::PowerShellHelper => {
+                Some(self.append_line(var, value))
+            }
+        }
+    }
+
+    /// Like `prepend_line`, for a `var` that may not be defined when
+    /// the line runs, see `guarded_append_line`.
+    pub fn guarded_prepend_line<T: fmt::Display, U: fmt::Display>(
+        &self,
+        var: T,
+        value: U,
+    ) -> Option<String> {
+        match self.assignment {
+            AssignmentStyle::Export => Some(format!(
+                "export {}=\"{}${{{}:+:${}}}\"",
+                var, value, var, var
+            )),
+            AssignmentStyle::Setenv => None,
+            AssignmentStyle::SetGx | AssignmentStyle::PowerShellHelper => {
+                Some(self.prepend_line(var, value))
+            }
+        }
+    }
+
+    /// Formats a line inserting `value` into the list variable `var`
+    /// right before its first system directory, see `is_system_dir`, so
+    /// it comes after the user's own directories like `~/bin` but
+    /// shadows the system's programs. The line does nothing if `var`
+    /// already has the entry, and only uses shell builtins.
+    /// Returns `None` for shells that can't loop in one line, csh, and
+    /// for PowerShell.
+    pub fn before_system_line<T: fmt::Display, U: fmt::Display>(
+        &self,
+        var: T,
+        value: U,
+    ) -> Option<String> {
+        match self.assignment {
+            AssignmentStyle::Export => Some(format!(
+                "_set_env_add=\"{value}\"; case \":${{{var}-}}:\" in *\":$_set_env_add:\"*) ;; *) \
+                 _set_env_rest=\"${{{var}-}}:\"; _set_env_new=; \
+                 while [ -n \"$_set_env_rest\" ]; do _set_env_dir=\"${{_set_env_rest%%:*}}\"; \
+                 _set_env_rest=\"${{_set_env_rest#*:}}\"; case \"$_set_env_dir\" in {patterns}) \
+                 if [ -n \"$_set_env_add\" ]; then \
+                 _set_env_new=\"$_set_env_new${{_set_env_new:+:}}$_set_env_add\"; _set_env_add=; fi;; esac; \
+                 _set_env_new=\"$_set_env_new${{_set_env_new:+:}}$_set_env_dir\"; done; \
+                 [ -z \"$_set_env_add\" ] || _set_env_new=\"$_set_env_new${{_set_env_new:+:}}$_set_env_add\"; \
+                 export {var}=\"$_set_env_new\";; esac; \
+                 unset _set_env_add _set_env_rest _set_env_new _set_env_dir",
+                patterns = SYSTEM_DIRS.join("|"),
+            )),
+            AssignmentStyle::SetGx => Some(format!(
+                "begin; set -l _set_env_add \"{value}\"; if not contains -- $_set_env_add ${var}; \
+                 set -l _set_env_new; for _set_env_dir in ${var}; switch $_set_env_dir; case {patterns}; \
+                 if set -q _set_env_add[1]; set -a _set_env_new $_set_env_add; set _set_env_add; end; end; \
+                 set -a _set_env_new $_set_env_dir; end; \
+                 if set -q _set_env_add[1]; set -a _set_env_new $_set_env_add; end; \
+                 set -gx {var} $_set_env_new; end; end",
+                patterns = SYSTEM_DIRS
+                    .iter()
+                    .map(|it| format!("'{}'", it))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )),
+            AssignmentStyle::Setenv | AssignmentStyle::PowerShellHelper => None,
+        }
+    }
+
+    /// Returns the variable and the value of `line`, if it was formatted
+    /// by `before_system_line`.
+    pub(crate) fn parse_before_system_line<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        let template = self.before_system_line('\u{1}', '\0')?;
+        let (prefix, rest) = template.split_once('\0')?;
+        let (middle, _) = rest.split_once('\u{1}')?;
+        let (value, rest) = line.strip_prefix(prefix)?.split_once(middle)?;
+        let var = &rest[..rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len())];
+        let formatted = !var.is_empty()
+            && !value.is_empty()
+            && self.before_system_line(var, value).as_deref() == Some(line);
+        formatted.then_some((var, value))
+    }
+
+    /// Formats a line running the file at `path` in the current shell.
+    pub fn source_line<T: fmt::Display>(&self, path: T) -> String {
+        match self.assignment {
+            AssignmentStyle::Export | AssignmentStyle::PowerShellHelper => {
+                format!(". \"{}\"", path)
+            }
+            AssignmentStyle::SetGx | AssignmentStyle::Setenv => format!("source \"{}\"", path),
+        }
+    }
+
+    /// Returns the value `line` appends or prepends to `var`, if it was
+    /// formatted by `append_line`, `prepend_line` or their guarded forms.
+    pub(crate) fn list_value<'a>(&self, var: &str, line: &'a str) -> Option<&'a str> {
+        self.parse_list_line(var, line).map(|(_, value)| value)
+    }
+
+    /// Like `list_value`, but also returns which of the lines it is.
+    pub(crate) fn parse_list_line<'a>(
+        &self,
+        var: &str,
+        line: &'a str,
+    ) -> Option<(ListOp, &'a str)> {
+        ListOp::ALL
+            .into_iter()
+            .find_map(|op| {
+                let (prefix, suffix) = split_around(&self.list_line(op, var, "\0")?);
+                let value = line
+                    .strip_prefix(prefix.as_str())
+                    .and_then(|rest| rest.strip_suffix(suffix.as_str()))?;
+                Some((op, value))
+            })
+            .filter(|(_, value)| !value.is_empty())
+    }
+
+    /// Formats the line `op` stands for, or returns `None` if the shell
+    /// has no guarded form of it.
+    pub(crate) fn list_line<T: fmt::Display, U: fmt::Display>(
+        &self,
+        op: ListOp,
+        var: T,
+        value: U,
+    ) -> Option<String> {
+        match op {
+            ListOp::Append => Some(self.append_line(var, value)),
+            ListOp::Prepend => Some(self.prepend_line(var, value)),
+            ListOp::GuardedAppend => self.guarded_append_line(var, value),
+            ListOp::GuardedPrepend => self.guarded_prepend_line(var, value),
+        }
+    }
+
+    /// Splits the value of a line formatted by `append_line` or
+    /// `prepend_line` into the entries it adds.
+    pub(crate) fn split_entries<'a>(&self, value: &'a str) -> Vec<&'a str> {
+        value
+            .split(self.entry_separator())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+
+    /// Joins entries into the value of one line, see `split_entries`.
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    pub(crate) fn join_entries<T: AsRef<str>>(&self, entries: &[T]) -> String {
+        let entries: Vec<&str> = entries.iter().map(AsRef::as_ref).collect();
+        entries.join(&self.entry_separator().to_string())
+    }
+
+    fn entry_separator(&self) -> char {
+        match self.assignment {
+            AssignmentStyle::Export | AssignmentStyle::Setenv => ':',
+            AssignmentStyle::SetGx => ' ',
+            AssignmentStyle::PowerShellHelper => ';',
+        }
+    }
+}
+
+/// The lines adding to a list variable, see `SyntaxInfo::list_line`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ListOp {
+    Append,
+    Prepend,
+    GuardedAppend,
+    GuardedPrepend,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl ListOp {
+    const ALL: [ListOp; 4] = [
+        ListOp::Append,
+        ListOp::Prepend,
+        ListOp::GuardedAppend,
+        ListOp::GuardedPrepend,
+    ];
+
+    pub(crate) fn appends(self) -> bool {
+        matches!(self, ListOp::Append | ListOp::GuardedAppend)
+    }
+
+    pub(crate) fn guarded(self) -> ListOp {
+        if self.appends() {
+            ListOp::GuardedAppend
+        } else {
+            ListOp::GuardedPrepend
+        }
+    }
+
+    pub(crate) fn unguarded(self) -> ListOp {
+        if self.appends() {
+            ListOp::Append
+        } else {
+            ListOp::Prepend
+        }
+    }
+}
+
+/// The directories of the operating system and package managers, see
+/// `SyntaxInfo::before_system_line`. A pattern ending in `/*` matches
+/// every directory below the one before it.
+const SYSTEM_DIRS: [&str; 8] = [
+    "/bin",
+    "/sbin",
+    "/usr/*",
+    "/opt/homebrew/*",
+    "/opt/local/*",
+    "/nix/*",
+    "/run/current-system/*",
+    "/snap/bin",
+];
+
+/// Returns whether `dir` is one of the `SYSTEM_DIRS`.
+pub(crate) fn is_system_dir(dir: &str) -> bool {
+    SYSTEM_DIRS
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(parent) => dir
+                .strip_prefix(parent)
+                .is_some_and(|rest| rest.starts_with('/')),
+            None => dir == *pattern,
+        })
+}
+
+/// Returns the list `current`, with entries separated by `separator`,
+/// with `value` inserted like the line `SyntaxInfo::before_system_line`
+/// formats does.
+pub(crate) fn insert_before_system(current: &str, value: &str, separator: &str) -> String {
+    let mut entries: Vec<&str> = current
+        .split(separator)
+        .filter(|it| !it.is_empty())
+        .collect();
+    if entries.contains(&value) {
+        return current.to_string();
+    }
+    let at = entries
+        .iter()
+        .position(|it| is_system_dir(it))
+        .unwrap_or(entries.len());
+    entries.insert(at, value);
+    entries.join(separator)
+}
+
+/// Splits a formatted line at the placeholder `\0` standing for the value.
+fn split_around(line: &str) -> (String, String) {
+    let (prefix, suffix) = line.split_once('\0').unwrap_or((line, ""));
+    (prefix.to_string(), suffix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHELLS: [(&str, SyntaxInfo); 4] = [
+        ("bash", SyntaxInfo::POSIX),
+        ("fish", SyntaxInfo::FISH),
+        ("csh", SyntaxInfo::CSH),
+        ("pwsh", SyntaxInfo::POWERSHELL),
+    ];
+
+    #[test]
+    fn guarded_lines_per_shell() {
+        let expected = [
+            (
+                Some("export MANPATH=\"${MANPATH:+$MANPATH:}/opt/man\""),
+                Some("export MANPATH=\"/opt/man${MANPATH:+:$MANPATH}\""),
+            ),
+            (
+                Some("set -gx MANPATH $MANPATH /opt/man"),
+                Some("set -gx MANPATH /opt/man $MANPATH"),
+            ),
+            (None, None),
+            (
+                Some("setenv_append MANPATH /opt/man"),
+                Some("setenv_prepend MANPATH /opt/man"),
+            ),
+        ];
+        for ((shell, syntax), (append, prepend)) in SHELLS.into_iter().zip(expected) {
+            assert_eq!(
+                syntax.guarded_append_line("MANPATH", "/opt/man").as_deref(),
+                append,
+                "{}",
+                shell
+            );
+            assert_eq!(
+                syntax
+                    .guarded_prepend_line("MANPATH", "/opt/man")
+                    .as_deref(),
+                prepend,
+                "{}",
+                shell
+            );
+        }
+    }
+
+    #[test]
+    fn guarded_lines_are_parsed_back() {
+        for (shell, syntax) in SHELLS {
+            for op in ListOp::ALL {
+                let Some(line) = syntax.list_line(op, "MANPATH", "/opt/man:/usr/man") else {
+                    continue;
+                };
+                // Shells with nothing to guard write the same line, which
+                // is read as the unguarded one.
+                let same = syntax.list_line(op.unguarded(), "MANPATH", "/opt/man:/usr/man");
+                let expected = if same.as_ref() == Some(&line) {
+                    op.unguarded()
+                } else {
+                    op
+                };
+                assert_eq!(
+                    syntax.parse_list_line("MANPATH", &line),
+                    Some((expected, "/opt/man:/usr/man")),
+                    "{}: {}",
+                    shell,
+                    line
+                );
+                assert_eq!(syntax.list_value("OTHER", &line), None, "{}", shell);
+            }
+        }
+    }
+
+    #[test]
+    fn formatted_lines_per_shell() {
+        // The lines each shell got before `SyntaxInfo` formatted them:
+        // set, append, prepend and source.
+        let expected = [
+            [
+                "export EDITOR=vim",
+                "export PATH=\"$PATH:/opt/bin\"",
+                "export PATH=\"/opt/bin:$PATH\"",
+                ". \"/etc/tool.sh\"",
+            ],
+            [
+                "set -gx EDITOR vim",
+                "set -gx PATH $PATH /opt/bin",
+                "set -gx PATH /opt/bin $PATH",
+                "source \"/etc/tool.sh\"",
+            ],
+            [
+                "setenv EDITOR vim",
+                "setenv PATH \"${PATH}:/opt/bin\"",
+                "setenv PATH \"/opt/bin:${PATH}\"",
+                "source \"/etc/tool.sh\"",
+            ],
+            [
+                "setenv_set EDITOR vim",
+                "setenv_append PATH /opt/bin",
+                "setenv_prepend PATH /opt/bin",
+                ". \"/etc/tool.sh\"",
+            ],
+        ];
+        for ((shell, syntax), expected) in SHELLS.into_iter().zip(expected) {
+            let lines = [
+                syntax.set_line("EDITOR", "vim"),
+                syntax.append_line("PATH", "/opt/bin"),
+                syntax.prepend_line("PATH", "/opt/bin"),
+                syntax.source_line("/etc/tool.sh"),
+            ];
+            assert_eq!(lines, expected, "{}", shell);
+        }
+    }
+
+    /// Each shell writes the lines its `SyntaxInfo` formats, and they
+    /// start with a comment in its block markers.
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn each_shell_writes_what_its_syntax_formats() {
+        for shell in crate::SHELLS {
+            let _sandbox = crate::test_support::sandbox(&format!("/bin/{}", shell.name()));
+            let syntax = crate::detect_shell().unwrap().unwrap().syntax();
+            assert_eq!(Some(syntax), SyntaxInfo::for_shell(shell.name()));
+            assert_eq!(shell.syntax(), syntax);
+
+            crate::set("SET_ENV_SYNTAX", "1").unwrap();
+            crate::set("SET_ENV_SYNTAX_LIST", "/base").unwrap();
+            crate::append("SET_ENV_SYNTAX_LIST", "/a").unwrap();
+            crate::prepend("SET_ENV_SYNTAX_LIST", "/b").unwrap();
+
+            let profile = crate::which_profile().unwrap();
+            let content = std::fs::read_to_string(&profile).unwrap();
+            let lines = [
+                crate::DEFS_BEG.to_string(),
+                syntax.set_line("SET_ENV_SYNTAX", "1"),
+                syntax.set_line("SET_ENV_SYNTAX_LIST", "/base"),
+                syntax.append_line("SET_ENV_SYNTAX_LIST", "/a"),
+                syntax.prepend_line("SET_ENV_SYNTAX_LIST", "/b"),
+                crate::DEFS_END.to_string(),
+            ];
+            assert_eq!(content, lines.join("\n") + "\n", "{}", shell.name());
+            assert!(crate::DEFS_BEG.starts_with(syntax.comment_prefix));
+            assert!(crate::DEFS_END.starts_with(syntax.comment_prefix));
+
+            let env = crate::simulate::simulate(&content, shell.name(), &Default::default());
+            let expected = match syntax.list {
+                ListStyle::Elements => "/b /base /a",
+                _ => "/b:/base:/a",
+            };
+            assert_eq!(
+                env.unwrap()["SET_ENV_SYNTAX_LIST"],
+                expected,
+                "{}",
+                shell.name()
+            );
+        }
+    }
+
+    /// Runs `line` with `bash -u`, with `MANPATH` set to `manpath` if
+    /// given, and returns whether it succeeded and `MANPATH` after it,
+    /// or `None` if bash isn't installed.
+    #[cfg(target_family = "unix")]
+    fn run_in_bash_u(line: &str, manpath: Option<&str>) -> Option<(bool, String)> {
+        let script = format!("{}\nprintf %s \"$MANPATH\"", line);
+        let mut command = std::process::Command::new("bash");
+        command
+            .arg("--norc")
+            .arg("-u")
+            .arg("-c")
+            .arg(script)
+            .env_remove("MANPATH")
+            .stdin(std::process::Stdio::null());
+        if let Some(manpath) = manpath {
+            command.env("MANPATH", manpath);
+        }
+        match command.output() {
+            Ok(output) => Some((
+                output.status.success(),
+                String::from_utf8(output.stdout).unwrap(),
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => panic!("bash: {}", err),
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn guarded_lines_run_under_bash_u() {
+        let syntax = SyntaxInfo::POSIX;
+        let Some((ok, _)) = run_in_bash_u(&syntax.append_line("MANPATH", "/opt/man"), None) else {
+            return;
+        };
+        assert!(!ok, "the unguarded line fails with MANPATH unset");
+
+        let append = syntax.guarded_append_line("MANPATH", "/opt/man").unwrap();
+        let prepend = syntax.guarded_prepend_line("MANPATH", "/opt/man").unwrap();
+        let cases = [
+            (&append, None, "/opt/man"),
+            (&append, Some("/usr/man"), "/usr/man:/opt/man"),
+            (&prepend, None, "/opt/man"),
+            (&prepend, Some("/usr/man"), "/opt/man:/usr/man"),
+        ];
+        for (line, manpath, expected) in cases {
+            assert_eq!(
+                run_in_bash_u(line, manpath).unwrap(),
+                (true, expected.to_string()),
+                "{} with {:?}",
+                line,
+                manpath
+            );
+        }
+    }
+}
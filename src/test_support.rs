@@ -0,0 +1,211 @@
+//! Shared setup for the tests. Most of the crate's settings are
+//! process-wide, so tests touching them run one at a time in a
+//! sandbox with its own home directory.
+
+use std::env;
+use std::fs;
+#[cfg(target_family = "unix")]
+use std::io;
+#[cfg(target_family = "unix")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(target_family = "unix")]
+use crate::filesystem::{FileMetadata, Filesystem, StdFilesystem};
+
+static LOCK: Mutex<()> = Mutex::new(());
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Variables changing which profile is picked or what is written.
+const CLEARED: &[&str] = &[
+    "SUDO_USER",
+    "SUDO_UID",
+    "SUDO_GID",
+    "ZDOTDIR",
+    "BASH_ENV",
+    "ENV",
+    "SET_ENV_DRY_RUN",
+    "XDG_CURRENT_DESKTOP",
+    "FLATPAK_ID",
+    "SNAP",
+];
+
+/// An empty home directory for the shell `shell`, with the settings of
+/// the crate at their defaults. Everything is reset when dropped.
+pub(crate) struct Sandbox {
+    pub home: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+pub(crate) fn sandbox(shell: &str) -> Sandbox {
+    let lock = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let home = env::temp_dir().join(format!(
+        "set_env_test_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(&home).expect("create the sandbox home");
+    // Symlinks like /tmp -> /private/tmp would make paths differ.
+    let home = fs::canonicalize(&home).expect("resolve the sandbox home");
+    env::set_var("HOME", &home);
+    env::set_var("SHELL", shell);
+    for var in CLEARED {
+        env::remove_var(var);
+    }
+    reset();
+    Sandbox { home, _lock: lock }
+}
+
+impl Sandbox {
+    /// Returns the path of `name` in the home directory.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.home.join(name)
+    }
+
+    /// Writes `content` to `name` in the home directory.
+    pub fn write(&self, name: &str, content: &str) {
+        let path = self.path(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).expect("create the directory");
+        }
+        fs::write(path, content).expect("write the file");
+    }
+
+    /// Returns the content of `name` in the home directory.
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path(name)).expect("read the file")
+    }
+
+    /// Makes the crate take `Documents/PowerShell/profile.ps1` in the
+    /// home directory for the PowerShell profile, and returns its path.
+    #[cfg(target_os = "windows")]
+    pub fn powershell_profile(&self) -> PathBuf {
+        let path = self.path("Documents/PowerShell/profile.ps1");
+        crate::windows::set_profile_location(Some((
+            path.clone(),
+            crate::windows::ProfileSource::KnownFolder,
+        )));
+        path
+    }
+
+    /// Makes the crate use an in-memory filesystem with the home
+    /// directory in it, and returns it.
+    #[cfg(target_family = "unix")]
+    pub fn memory(&self) -> crate::filesystem::MemoryFilesystem {
+        let memory = crate::filesystem::MemoryFilesystem::new();
+        crate::Filesystem::create_dir_all(&memory, &self.home).expect("create the home");
+        crate::set_filesystem(memory.clone());
+        memory
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        reset();
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+/// Reads the real files and fails every write with
+/// `ErrorKind::PermissionDenied`, like a read-only home directory even
+/// when the tests run as root.
+#[cfg(target_family = "unix")]
+pub(crate) struct ReadOnlyFilesystem;
+
+#[cfg(target_family = "unix")]
+fn refused(path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} is read-only", path.display()),
+    ))
+}
+
+#[cfg(target_family = "unix")]
+impl Filesystem for ReadOnlyFilesystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        StdFilesystem.read_to_string(path)
+    }
+    fn write(&self, path: &Path, _content: &str) -> io::Result<()> {
+        refused(path)
+    }
+    fn append(&self, path: &Path, _content: &str) -> io::Result<()> {
+        refused(path)
+    }
+    fn exists(&self, path: &Path) -> bool {
+        StdFilesystem.exists(path)
+    }
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        StdFilesystem.metadata(path)
+    }
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        refused(path)
+    }
+    fn rename(&self, from: &Path, _to: &Path) -> io::Result<()> {
+        refused(from)
+    }
+}
+
+/// Returns every path under `dir` with its content, or `None` for
+/// directories, to compare trees.
+#[cfg(target_family = "unix")]
+pub(crate) fn snapshot(dir: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).expect("list the directory") {
+            let path = entry.expect("read the entry").path();
+            let metadata = fs::symlink_metadata(&path).expect("stat the entry");
+            if metadata.is_dir() {
+                pending.push(path.clone());
+                entries.push((path, None));
+            } else {
+                let content = fs::read(&path).unwrap_or_default();
+                entries.push((path, Some(content)));
+            }
+        }
+    }
+    entries.sort();
+    entries
+}
+
+fn reset() {
+    crate::set_backend(crate::Backend::Profile);
+    while crate::is_dry_run() {
+        crate::set_dry_run(false);
+    }
+    crate::drain_planned_changes();
+    crate::set_filesystem(crate::StdFilesystem);
+    crate::set_write_limit(crate::DEFAULT_WRITE_LIMIT);
+    crate::write_limit::clear();
+    crate::set_event_sink(None);
+    crate::set_audit_log(None);
+    crate::set_usage_stats(false);
+    crate::set_preferences_file(None);
+    crate::set_hook_runner(crate::SystemRunner);
+    crate::hooks::clear();
+    crate::set_strict_verify(false);
+    crate::set_strict_lists(false);
+    crate::var_kind::clear();
+    crate::set_sanitize_values(false);
+    crate::ignore_conflicts(false);
+    crate::allow_command_substitution(false);
+    crate::set_strict_command_substitution(false);
+    #[cfg(target_os = "windows")]
+    {
+        crate::windows::set_profile_location(None);
+        crate::set_relocatable_paths(false);
+    }
+    #[cfg(target_family = "unix")]
+    {
+        crate::set_metadata(None);
+        crate::set_metadata_format(crate::DefaultMetadataFormat);
+        crate::set_sudo_policy(crate::SudoPolicy::Error);
+        crate::set_passwd_resolver(crate::PasswdFile);
+        crate::set_zsh_policy(crate::ZshPolicy::FirstExisting);
+        crate::set_write_mode(crate::WriteMode::Auto);
+        crate::set_dangling_link_policy(crate::DanglingLinkPolicy::CreateTarget);
+    }
+}
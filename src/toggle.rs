@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+
+use crate::block;
+
+/// Put in front of a line in the block managed by this crate to disable
+/// it. Every supported shell starts comments with `#`.
+const DISABLED: &str = "# set_env disabled: ";
+
+/// Returns the variable `line` assigns, the unevaluated value and
+/// whether the line is enabled, for disabled lines as well.
+pub(crate) fn parse_entry(line: &str) -> Option<(&str, &str, bool)> {
+    match line.strip_prefix(DISABLED) {
+        Some(disabled) => {
+            crate::simulate::parse_var_value(disabled).map(|(var, value)| (var, value, false))
+        }
+        None => crate::simulate::parse_var_value(line).map(|(var, value)| (var, value, true)),
+    }
+}
+
+/// Writes a line setting `var` to `value` to the block managed by this
+/// crate, but disabled, so it has no effect until `enable` is called,
+/// like for a variable shipped off by default.
+pub fn set_disabled<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<()> {
+    let _operation = crate::events::operation();
+    crate::startup_cost::warn_value(&var.to_string(), &value.to_string())?;
+    let line = crate::profile_syntax()?.set_line(&var, value);
+    write_disabled(&var.to_string(), &line)
+}
+
+/// Disables the lines assigning `var` in the block managed by this
+/// crate by commenting them out, keeping their value and metadata.
+/// Returns how many lines were disabled.
+pub fn disable<T: fmt::Display>(var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    toggle(&var.to_string(), false)
+}
+
+/// Enables the lines assigning `var` disabled by `disable` or written
+/// by `set_disabled`.
+/// Returns how many lines were enabled.
+pub fn enable<T: fmt::Display>(var: T) -> io::Result<usize> {
+    let _operation = crate::events::operation();
+    toggle(&var.to_string(), true)
+}
+
+/// Returns whether the block managed by this crate assigns `var` only
+/// in disabled lines, in which case `set` keeps it disabled.
+pub(crate) fn is_disabled(var: &str) -> io::Result<bool> {
+    let content = read(var)?;
+    let mut disabled = false;
+    for (_, line) in block::managed_lines(&content) {
+        match parse_entry(line) {
+            Some((assigned, _, true)) if assigned == var => return Ok(false),
+            Some((assigned, _, false)) if assigned == var => disabled = true,
+            _ => {}
+        }
+    }
+    Ok(disabled)
+}
+
+/// Returns `line` disabled.
+pub(crate) fn disabled(line: &str) -> String {
+    format!("{}{}", DISABLED, line)
+}
+
+/// Returns `line` without the prefix disabling it, if it has one.
+pub(crate) fn undisabled(line: &str) -> &str {
+    line.strip_prefix(DISABLED).unwrap_or(line)
+}
+
+/// Adds `line` assigning `var` to the block managed by this crate,
+/// disabled.
+pub(crate) fn write_disabled(var: &str, line: &str) -> io::Result<()> {
+    let path = crate::profile_path_for(var)?;
+    let entry = crate::metadata::tag(&disabled(line));
+    crate::edit_file(&path, |content| {
+        block::check(content, &path)?;
+        Ok((block::insert(content, &entry, None), ()))
+    })
+}
+
+fn toggle(var: &str, enabled: bool) -> io::Result<usize> {
+    let path = crate::profile_path_for(var)?;
+    if toggled(&read(var)?, var, enabled).1.is_empty() {
+        return Ok(0);
+    }
+    crate::edit_file(&path, |content| {
+        block::check(content, &path)?;
+        let (content, toggled) = toggled(content, var, enabled);
+        Ok((content, toggled.len()))
+    })
+}
+
+/// Returns `content` with the managed lines assigning `var` enabled or
+/// disabled, and the lines that changed.
+fn toggled(content: &str, var: &str, enabled: bool) -> (String, Vec<String>) {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut toggled = Vec::new();
+    for (idx, line) in block::managed_lines(content) {
+        let line = match parse_entry(line) {
+            Some((assigned, _, was_enabled)) if assigned == var && was_enabled != enabled => {
+                if enabled {
+                    line.strip_prefix(DISABLED).unwrap_or(line).to_string()
+                } else {
+                    disabled(line)
+                }
+            }
+            _ => continue,
+        };
+        lines[idx] = line.clone();
+        toggled.push(line);
+    }
+    (crate::line_endings::rejoin(content, &lines), toggled)
+}
+
+fn read(var: &str) -> io::Result<String> {
+    match crate::filesystem::current().read_to_string(&crate::profile_path_for(var)?) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn disable_and_enable_round_trip() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias ll='ls -l'\n");
+        crate::set("SET_ENV_TOGGLED", "1").unwrap();
+        crate::set("SET_ENV_TOGGLED_OTHER", "2").unwrap();
+        let enabled = sandbox.read(".bash_profile");
+
+        assert_eq!(disable("SET_ENV_TOGGLED").unwrap(), 1);
+        let disabled = sandbox.read(".bash_profile");
+        assert_eq!(
+            disabled,
+            enabled.replace(
+                "export SET_ENV_TOGGLED=1",
+                "# set_env disabled: export SET_ENV_TOGGLED=1"
+            )
+        );
+        assert!(is_disabled("SET_ENV_TOGGLED").unwrap());
+        assert!(!is_disabled("SET_ENV_TOGGLED_OTHER").unwrap());
+
+        assert_eq!(disable("SET_ENV_TOGGLED").unwrap(), 0);
+        assert_eq!(sandbox.read(".bash_profile"), disabled);
+
+        assert_eq!(enable("SET_ENV_TOGGLED").unwrap(), 1);
+        assert_eq!(sandbox.read(".bash_profile"), enabled);
+        assert!(!is_disabled("SET_ENV_TOGGLED").unwrap());
+        assert_eq!(enable("SET_ENV_TOGGLED").unwrap(), 0);
+        assert_eq!(sandbox.read(".bash_profile"), enabled);
+    }
+
+    #[test]
+    fn set_disabled_lines_are_enabled_in_place() {
+        let sandbox = sandbox("/bin/bash");
+        crate::set("SET_ENV_TOGGLED_BEFORE", "1").unwrap();
+        set_disabled("SET_ENV_TOGGLED_SHIPPED", "off").unwrap();
+        crate::set("SET_ENV_TOGGLED_AFTER", "2").unwrap();
+        assert!(is_disabled("SET_ENV_TOGGLED_SHIPPED").unwrap());
+        assert_eq!(disable("SET_ENV_TOGGLED_SHIPPED").unwrap(), 0);
+
+        assert_eq!(enable("SET_ENV_TOGGLED_SHIPPED").unwrap(), 1);
+        assert_eq!(
+            sandbox.read(".bash_profile"),
+            format!(
+                "{}\nexport SET_ENV_TOGGLED_BEFORE=1\nexport SET_ENV_TOGGLED_SHIPPED=off\n\
+                 export SET_ENV_TOGGLED_AFTER=2\n{}\n",
+                crate::DEFS_BEG,
+                crate::DEFS_END
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_left_alone() {
+        let sandbox = sandbox("/bin/bash");
+        assert_eq!(disable("SET_ENV_TOGGLED_MISSING").unwrap(), 0);
+        assert_eq!(enable("SET_ENV_TOGGLED_MISSING").unwrap(), 0);
+        assert!(!sandbox.path(".bash_profile").exists());
+
+        crate::set("SET_ENV_TOGGLED_KNOWN", "1").unwrap();
+        let content = sandbox.read(".bash_profile");
+        assert_eq!(disable("SET_ENV_TOGGLED_MISSING").unwrap(), 0);
+        assert_eq!(enable("SET_ENV_TOGGLED_MISSING").unwrap(), 0);
+        assert!(!is_disabled("SET_ENV_TOGGLED_MISSING").unwrap());
+        assert_eq!(sandbox.read(".bash_profile"), content);
+    }
+}
@@ -0,0 +1,202 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables recording what this crate changes, per
+/// variable, in a local file that `stats` reads.
+/// Nothing is ever sent anywhere. Disabled by default.
+pub fn set_usage_stats(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// What was recorded for a variable, see `stats`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct VarStats {
+    /// How often it was set.
+    pub times_set: usize,
+    /// How often an entry was appended or prepended to it.
+    pub times_added: usize,
+    /// How often a damaged definition of it was repaired.
+    pub times_repaired: usize,
+    /// When a definition of it was last found changed by someone else,
+    /// right after this crate wrote it, see `set_strict_verify`.
+    pub last_drift: Option<SystemTime>,
+    /// The files changed for it.
+    pub files_touched: Vec<PathBuf>,
+}
+
+/// A recorded change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Event {
+    Set,
+    Added,
+    /// Only unix profiles have blocks that can be repaired.
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    Repaired,
+    /// A definition was found changed by someone else, see
+    /// `set_strict_verify`.
+    Drift,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Set => "set",
+            Event::Added => "added",
+            Event::Repaired => "repaired",
+            Event::Drift => "drift",
+        }
+    }
+}
+
+/// Returns what was recorded for `var` since `set_usage_stats` was
+/// first enabled on this machine.
+pub fn stats(var: &str) -> io::Result<VarStats> {
+    let _operation = crate::events::operation();
+    let content = match crate::filesystem::current().read_to_string(&stats_file()?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut stats = VarStats::default();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.splitn(4, '\t').collect();
+        let [secs, event, recorded_var, path] = fields[..] else {
+            continue;
+        };
+        if recorded_var != var {
+            continue;
+        }
+        let time = secs
+            .parse()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        match event {
+            "set" => stats.times_set += 1,
+            "added" => stats.times_added += 1,
+            "repaired" => stats.times_repaired += 1,
+            "drift" => stats.last_drift = stats.last_drift.max(time),
+            _ => continue,
+        }
+        let path = PathBuf::from(path);
+        if !stats.files_touched.contains(&path) {
+            stats.files_touched.push(path);
+        }
+    }
+    Ok(stats)
+}
+
+/// Records `event` for `var` in the profile it was written to,
+/// if enabled and not in dry run.
+/// Failing to record only emits an `Event::Warning`, the change itself was
+/// already made.
+pub(crate) fn record(event: Event, var: &str) {
+    if !ENABLED.load(Ordering::SeqCst) || crate::is_dry_run() {
+        return;
+    }
+    if let Err(err) = crate::profile_path_for(var).and_then(|path| append(event, var, &path)) {
+        crate::events::warn(format!("usage stats could not be recorded: {}", err));
+    }
+}
+
+/// Does the same as `record` for a change to `var` in the file at `path`.
+pub(crate) fn record_in(event: Event, var: &str, path: &Path) {
+    if !ENABLED.load(Ordering::SeqCst) || crate::is_dry_run() {
+        return;
+    }
+    if let Err(err) = append(event, var, path) {
+        crate::events::warn(format!("usage stats could not be recorded: {}", err));
+    }
+}
+
+fn append(event: Event, var: &str, path: &Path) -> io::Result<()> {
+    let fs = crate::filesystem::current();
+    let file = stats_file()?;
+    if let Some(dir) = file.parent() {
+        fs.create_dir_all(dir)?;
+    }
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    fs.append(
+        &file,
+        &format!("{}\t{}\t{}\t{}\n", secs, event.name(), var, path.display()),
+    )?;
+    #[cfg(target_family = "unix")]
+    crate::sudo::chown_to_invoking_user(&file)?;
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn stats_file() -> io::Result<PathBuf> {
+    let (home_dir, _) = crate::home_and_shell()?;
+    Ok(home_dir.join(".config/set_env/stats.tsv"))
+}
+
+#[cfg(target_os = "windows")]
+fn stats_file() -> io::Result<PathBuf> {
+    let dir = dirs::config_dir().ok_or_else(|| io::Error::other("No config directory"))?;
+    Ok(dir.join("set_env").join("stats.tsv"))
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::events::Event as Happened;
+    use crate::test_support::sandbox;
+    use std::sync::Arc;
+
+    #[test]
+    fn stats_follow_sets_repairs_and_drift() {
+        let sandbox = sandbox("/bin/bash");
+        let profile = sandbox.path(".bash_profile");
+        set_usage_stats(true);
+
+        crate::set("FOO", "1").unwrap();
+        crate::set("FOO", "2").unwrap();
+        crate::append("PATH", "/opt/bin").unwrap();
+        crate::set("BAR", "1").unwrap();
+
+        let damaged = sandbox.read(".bash_profile").replace(crate::DEFS_END, "");
+        sandbox.write(".bash_profile", &damaged);
+        crate::repair().unwrap();
+
+        // Someone else changes FOO between writing and reading back.
+        crate::set_strict_verify(true);
+        crate::set_event_sink(Some(Arc::new(|event: &Happened| {
+            if let Happened::Staged { temp, .. } = event {
+                let mut content = std::fs::read_to_string(temp).unwrap();
+                content.push_str("export FOO=theirs\n");
+                std::fs::write(temp, content).unwrap();
+            }
+        })));
+        let before = SystemTime::now() - Duration::from_secs(1);
+        assert!(crate::set("FOO", "3").is_err());
+
+        let foo = stats("FOO").unwrap();
+        assert_eq!(
+            (foo.times_set, foo.times_added, foo.times_repaired),
+            (2, 0, 1)
+        );
+        assert!(foo.last_drift.is_some_and(|drift| drift >= before));
+        assert_eq!(foo.files_touched, vec![profile.clone()]);
+
+        let path = stats("PATH").unwrap();
+        assert_eq!((path.times_set, path.times_added), (0, 1));
+        assert_eq!(path.last_drift, None);
+        assert_eq!(stats("BAR").unwrap().last_drift, None);
+        assert_eq!(stats("UNKNOWN").unwrap(), VarStats::default());
+    }
+
+    #[test]
+    fn nothing_is_recorded_unless_enabled() {
+        let sandbox = sandbox("/bin/bash");
+        crate::set("FOO", "1").unwrap();
+        assert_eq!(stats("FOO").unwrap(), VarStats::default());
+        assert!(!sandbox.path(".config/set_env/stats.tsv").exists());
+    }
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::change::ChangeWarning;
+
+/// Whether a variable holds a single value or a list of entries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VarKind {
+    /// A single value like EDITOR, appending to which makes no sense.
+    Scalar,
+    /// A list of entries like PATH.
+    List,
+    /// Neither known nor registered.
+    Unknown,
+}
+
+/// Variables that commonly hold a single value.
+static SCALARS: &[&str] = &[
+    "EDITOR",
+    "VISUAL",
+    "PAGER",
+    "BROWSER",
+    "SHELL",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "TERM",
+    "LANG",
+    "LANGUAGE",
+    "LC_ALL",
+    "TZ",
+    "PWD",
+    "TMPDIR",
+    "DISPLAY",
+    "JAVA_HOME",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "TEMP",
+    "TMP",
+];
+
+static REGISTERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Registers `var` as a list variable, so appending or prepending to it
+/// is never warned about even if it commonly holds a single value.
+pub fn register_list_var<T: ToString>(var: T) {
+    let var = var.to_string();
+    let mut registered = REGISTERED.lock().unwrap_or_else(|err| err.into_inner());
+    if !registered.contains(&var) {
+        registered.push(var);
+    }
+}
+
+/// Makes appending or prepending to a variable classified as
+/// `VarKind::Scalar` an error instead of a warning in the returned
+/// `Change`.
+pub fn set_strict_lists(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
+}
+
+/// Classifies `var` by the variables registered with `register_list_var`,
+/// a built-in list of common single-value variables,
+/// and names ending with `PATH` or `DIRS`, which are taken as lists.
+pub fn var_kind(var: &str) -> VarKind {
+    let registered = REGISTERED.lock().unwrap_or_else(|err| err.into_inner());
+    if registered.iter().any(|it| it == var) {
+        VarKind::List
+    } else if SCALARS.iter().any(|it| it.eq_ignore_ascii_case(var)) {
+        VarKind::Scalar
+    } else if var.ends_with("PATH") || var.ends_with("DIRS") || var == "PSModulePath" {
+        VarKind::List
+    } else {
+        VarKind::Unknown
+    }
+}
+
+/// Returns a warning about or, with `set_strict_lists`, refuses adding
+/// an entry to a variable that holds a single value.
+pub(crate) fn check_list(var: &str) -> io::Result<Option<ChangeWarning>> {
+    if var_kind(var) != VarKind::Scalar {
+        return Ok(None);
+    }
+
+    let warning = ChangeWarning::NotAList {
+        var: var.to_string(),
+    };
+    if STRICT.load(Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            warning.to_string(),
+        ));
+    }
+    Ok(Some(warning))
+}
+
+/// Forgets the registered list variables.
+#[cfg(test)]
+pub(crate) fn clear() {
+    REGISTERED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clear();
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn variables_are_classified() {
+        let _sandbox = sandbox("/bin/bash");
+        assert_eq!(var_kind("EDITOR"), VarKind::Scalar);
+        assert_eq!(var_kind("lang"), VarKind::Scalar);
+        assert_eq!(var_kind("PATH"), VarKind::List);
+        assert_eq!(var_kind("LD_LIBRARY_PATH"), VarKind::List);
+        assert_eq!(var_kind("XDG_DATA_DIRS"), VarKind::List);
+        assert_eq!(var_kind("MYTOOL_OPTS"), VarKind::Unknown);
+
+        register_list_var("TERM");
+        register_list_var("MYTOOL_OPTS");
+        assert_eq!(var_kind("TERM"), VarKind::List);
+        assert_eq!(var_kind("MYTOOL_OPTS"), VarKind::List);
+    }
+
+    #[test]
+    fn appending_to_a_scalar_is_warned_about() {
+        let sandbox = sandbox("/bin/bash");
+        let change = crate::append("EDITOR", "nvim").unwrap();
+        assert_eq!(
+            change.warnings,
+            vec![ChangeWarning::NotAList {
+                var: "EDITOR".to_string()
+            }]
+        );
+        assert!(change.warnings[0].to_string().contains("register_list_var"));
+        assert!(sandbox.read(".bash_profile").contains("nvim"));
+    }
+
+    #[test]
+    fn appending_to_lists_and_unknown_variables_is_not() {
+        let _sandbox = sandbox("/bin/bash");
+        register_list_var("EDITOR");
+        for var in ["PATH", "MYTOOL_OPTS", "EDITOR"] {
+            assert_eq!(crate::prepend(var, "x").unwrap().warnings, Vec::new());
+        }
+    }
+
+    #[test]
+    fn strict_lists_refuse_scalars() {
+        let sandbox = sandbox("/bin/bash");
+        set_strict_lists(true);
+        let err = crate::prepend("SHELL", "/bin/zsh").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!sandbox.path(".bash_profile").exists());
+        crate::append("PATH", "/opt/bin").unwrap();
+    }
+}
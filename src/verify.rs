@@ -0,0 +1,223 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Enables reading every file back right after writing it and failing
+/// if it isn't exactly what was written, for installers that want to
+/// know about filesystem quirks or other programs racing them right
+/// away rather than at the next shell start. On Windows registry
+/// values are read back as well. Disabled by default.
+///
+/// The error has `ErrorKind::Other` and a message starting with
+/// `Post-condition failed`, listing the lines that went missing and
+/// the ones that showed up. The variables they assign are recorded as
+/// drifted, see `VarStats::last_drift`.
+pub fn set_strict_verify(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
+}
+
+pub(crate) fn is_strict() -> bool {
+    STRICT.load(Ordering::SeqCst)
+}
+
+/// Checks that the file at `path` has the `expected` content just
+/// written to it, if strict verification is enabled.
+pub(crate) fn written(path: &Path, expected: &str) -> io::Result<()> {
+    if !is_strict() {
+        return Ok(());
+    }
+    let actual = read_back(path)?;
+    if actual == expected {
+        verified(path);
+        return Ok(());
+    }
+    let (unexpected, missing) = crate::audit::changed_lines(expected, &actual);
+    drifted(path, missing.iter().chain(&unexpected).copied());
+    Err(failed(
+        path,
+        &format!(
+            "it doesn't have the content written to it, missing lines: {:?}, unexpected lines: {:?}",
+            missing, unexpected
+        ),
+    ))
+}
+
+/// Checks that `actual`, read back from where `expected` was just
+/// stored, is the same, if strict verification is enabled.
+#[cfg(target_os = "windows")]
+pub(crate) fn stored(what: &str, expected: &str, actual: Option<&str>) -> io::Result<()> {
+    if !is_strict() || actual == Some(expected) {
+        return Ok(());
+    }
+    Err(crate::events::error(io::Error::other(format!(
+        "Post-condition failed for {}: read back {:?} instead of {:?}",
+        what, actual, expected
+    ))))
+}
+
+fn read_back(path: &Path) -> io::Result<String> {
+    crate::filesystem::current()
+        .read_to_string(path)
+        .map_err(|err| failed(path, &format!("it can't be read back: {}", err)))
+}
+
+/// Records drift for the variables assigned by `lines`, which differ
+/// between what was written to the file at `path` and what it has.
+fn drifted<'a>(path: &Path, lines: impl Iterator<Item = &'a str>) {
+    let mut vars: Vec<&str> = lines
+        .filter_map(|line| crate::simulate::parse_var_value(line.trim()))
+        .map(|(var, _)| var)
+        .collect();
+    vars.sort_unstable();
+    vars.dedup();
+    for var in vars {
+        crate::usage::record_in(crate::usage::Event::Drift, var, path);
+    }
+}
+
+fn verified(path: &Path) {
+    crate::events::emit(crate::events::Event::Verified {
+        path: path.to_path_buf(),
+    });
+}
+
+fn failed(path: &Path, reason: &str) -> io::Error {
+    crate::events::error(io::Error::other(format!(
+        "Post-condition failed for {}: {}",
+        path.display(),
+        reason
+    )))
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::filesystem::{FileMetadata, MemoryFilesystem};
+    use crate::test_support::sandbox;
+    use crate::{Event, Filesystem, VecSink};
+
+    /// How `Faulty` breaks writing the profile.
+    #[derive(Clone, Copy)]
+    enum Fault {
+        /// Renaming the new content over the profile succeeds without
+        /// doing anything, so the write doesn't stick.
+        LostRename,
+        /// Reading the profile returns it with `FOO` set to another
+        /// value, like a program racing this one.
+        ChangedOnRead,
+    }
+
+    struct Faulty(MemoryFilesystem, Fault);
+
+    impl Filesystem for Faulty {
+        fn read_to_string(&self, path: &Path) -> io::Result<String> {
+            let content = self.0.read_to_string(path)?;
+            match self.1 {
+                Fault::ChangedOnRead if path.ends_with(".bash_profile") => {
+                    Ok(content.replace("export FOO=2", "export FOO=theirs"))
+                }
+                _ => Ok(content),
+            }
+        }
+        fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+            self.0.write(path, content)
+        }
+        fn append(&self, path: &Path, content: &str) -> io::Result<()> {
+            self.0.append(path, content)
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.0.exists(path)
+        }
+        fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+            self.0.metadata(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.0.create_dir_all(path)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            match self.1 {
+                Fault::LostRename if to.ends_with(".bash_profile") => Ok(()),
+                _ => self.0.rename(from, to),
+            }
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.0.remove_file(path)
+        }
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.0.read_dir(path)
+        }
+    }
+
+    /// Sets `FOO` to 1 and then to 2 with `fault` and strict
+    /// verification, and returns the error of the second write.
+    fn set_with(fault: Fault) -> (MemoryFilesystem, io::Error) {
+        let sandbox = sandbox("/bin/bash");
+        let memory = sandbox.memory();
+        crate::set("FOO", "1").unwrap();
+        crate::set_usage_stats(true);
+        crate::set_strict_verify(true);
+        crate::set_filesystem(Faulty(memory.clone(), fault));
+
+        let err = crate::set("FOO", "2").unwrap_err();
+        assert!(crate::stats("FOO").unwrap().last_drift.is_some());
+        (memory, err)
+    }
+
+    #[test]
+    fn a_write_that_does_not_stick_fails() {
+        let (memory, err) = set_with(Fault::LostRename);
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(
+            err.to_string().starts_with(&format!(
+                "Post-condition failed for {}: it doesn't have the content written to it, \
+                 missing lines: [\"export FOO=2\"], unexpected lines: []",
+                dirs::home_dir().unwrap().join(".bash_profile").display()
+            )),
+            "{}",
+            err
+        );
+        let profile = dirs::home_dir().unwrap().join(".bash_profile");
+        let content = memory.read_to_string(&profile).unwrap();
+        assert!(content.contains("export FOO=1\n"));
+        assert!(!content.contains("export FOO=2"));
+    }
+
+    #[test]
+    fn a_read_back_mismatch_fails() {
+        let (_, err) = set_with(Fault::ChangedOnRead);
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(
+            err.to_string().ends_with(
+                "missing lines: [\"export FOO=2\"], unexpected lines: [\"export FOO=theirs\"]"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn verified_writes_are_reported() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.memory();
+        let sink = Arc::new(VecSink::new());
+        crate::set_event_sink(Some(sink.clone()));
+
+        crate::set("FOO", "1").unwrap();
+        let verified = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .filter(|event| matches!(event, Event::Verified { .. }))
+                .count()
+        };
+        assert_eq!(verified(sink.take()), 0);
+
+        crate::set_strict_verify(true);
+        crate::set("FOO", "2").unwrap();
+        assert_eq!(verified(sink.take()), 1);
+    }
+}
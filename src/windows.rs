@@ -0,0 +1,983 @@
+//! Windows specific helpers dealing with the user environment
+//! stored in the registry next to the PowerShell profile.
+
+use std::cell::Cell;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub use crate::runner::{Runner, SystemRunner};
+
+/// Access to the persistent user environment variables.
+pub trait Registry {
+    /// Returns the value of `var`, or `None` if it isn't set.
+    fn get(&self, var: &str) -> io::Result<Option<String>>;
+    /// Sets `var` to `value`.
+    fn set(&self, var: &str, value: &str) -> io::Result<()>;
+}
+
+/// The variables under `HKEY_CURRENT_USER\Environment`,
+/// accessed through `reg.exe`.
+pub struct UserEnvironment;
+
+const ENVIRONMENT_KEY: &str = r"HKCU\Environment";
+
+impl Registry for UserEnvironment {
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        let output = Command::new("reg")
+            .args(["query", ENVIRONMENT_KEY, "/v", var])
+            .output()?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(parse_query(&String::from_utf8_lossy(&output.stdout), var))
+    }
+
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        // Values referencing other variables have to be expanded
+        // when read, which only REG_EXPAND_SZ values are.
+        let kind = if value.contains('%') {
+            "REG_EXPAND_SZ"
+        } else {
+            "REG_SZ"
+        };
+        let status = Command::new("reg")
+            .args([
+                "add",
+                ENVIRONMENT_KEY,
+                "/v",
+                var,
+                "/t",
+                kind,
+                "/d",
+                value,
+                "/f",
+            ])
+            .status()?;
+        if status.success() {
+            crate::events::emit(crate::events::Event::RegistryWritten {
+                var: var.to_string(),
+            });
+            let stored = if crate::verify::is_strict() {
+                self.get(var)?
+            } else {
+                None
+            };
+            crate::verify::stored(
+                &format!("{}\\{}", ENVIRONMENT_KEY, var),
+                value,
+                stored.as_deref(),
+            )
+        } else {
+            Err(io::Error::other(format!(
+                "reg add for {} failed: {}",
+                var, status
+            )))
+        }
+    }
+}
+
+/// Sets `var` to `value` in `registry`, unless dry run is enabled, in
+/// which case it's only planned, whatever the registry.
+fn store(registry: &dyn Registry, var: &str, value: &str) -> io::Result<()> {
+    if crate::dry_run::plan(|| crate::PlannedChange::SetRegistry {
+        var: var.to_string(),
+        value: value.to_string(),
+    }) {
+        return Ok(());
+    }
+    registry.set(var, value)
+}
+
+/// Extracts the value of `var` from the output of `reg query`,
+/// which lists values as `name    REG_TYPE    data`.
+fn parse_query(output: &str, var: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let mut parts = line.trim_start().splitn(3, "    ");
+        let name = parts.next()?;
+        let kind = parts.next()?;
+        if name.eq_ignore_ascii_case(var) && kind.starts_with("REG_") {
+            Some(parts.next().unwrap_or("").trim_end().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// How `reconcile` resolves entries found both in the registry
+/// and in the PowerShell profile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    /// Only report the overlapping entries.
+    ReportOnly,
+    /// Keep the registry entry and remove the line from the profile.
+    KeepRegistry,
+    /// Keep the line in the profile and remove the entry from the registry.
+    KeepProfile,
+}
+
+/// An entry of a list variable found both in the registry and in the
+/// PowerShell profile.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Overlap {
+    /// The entry as found in the registry.
+    pub registry_entry: String,
+    /// The `setenv_append` or `setenv_prepend` line adding it in the profile.
+    pub profile_line: String,
+}
+
+/// Finds entries of the list variable `var` that are both in the user
+/// registry value, for example added by `setx` or an installer, and
+/// appended or prepended in the PowerShell profile by this crate.
+/// Entries are compared ignoring case and trailing separators.
+/// Returns the overlapping entries after resolving them with `strategy`.
+pub fn reconcile(var: &str, strategy: Strategy) -> io::Result<Vec<Overlap>> {
+    reconcile_with(&UserEnvironment, var, strategy)
+}
+
+/// Does the same as `reconcile` with the given registry.
+pub fn reconcile_with(
+    registry: &dyn Registry,
+    var: &str,
+    strategy: Strategy,
+) -> io::Result<Vec<Overlap>> {
+    let _operation = crate::events::operation();
+    let registry_value = registry.get(var)?.unwrap_or_default();
+    let registry_entries: Vec<&str> = registry_value
+        .split(crate::LIST_SEPARATOR)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+
+    let mut overlaps = Vec::new();
+    for (line, value) in crate::managed_list_lines(var)? {
+        if let Some(entry) = registry_entries
+            .iter()
+            .find(|entry| crate::same_entry(entry, &value))
+        {
+            overlaps.push(Overlap {
+                registry_entry: entry.to_string(),
+                profile_line: line,
+            });
+        }
+    }
+
+    match strategy {
+        Strategy::ReportOnly => {}
+        Strategy::KeepRegistry => {
+            // The same line outside the block was written by the user.
+            let inside = Cell::new(false);
+            crate::remove_lines(&crate::profile_path()?, |line| {
+                let line = line.trim();
+                if line == crate::DEFS_BEG || line == crate::DEFS_END {
+                    inside.set(line == crate::DEFS_BEG);
+                    return false;
+                }
+                inside.get() && overlaps.iter().any(|overlap| overlap.profile_line == line)
+            })?;
+        }
+        Strategy::KeepProfile => {
+            if !overlaps.is_empty() {
+                let kept: Vec<&str> = registry_value
+                    .split(crate::LIST_SEPARATOR)
+                    .filter(|entry| {
+                        !overlaps
+                            .iter()
+                            .any(|overlap| overlap.registry_entry == *entry)
+                    })
+                    .collect();
+                store(
+                    registry,
+                    var,
+                    &kept.join(&crate::LIST_SEPARATOR.to_string()),
+                )?;
+            }
+        }
+    }
+
+    Ok(overlaps)
+}
+
+/// How the PowerShell profile was located.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProfileSource {
+    /// Asked PowerShell for `$PROFILE.CurrentUserAllHosts`.
+    PowerShell,
+    /// Derived from the documents folder, because PowerShell
+    /// couldn't be asked.
+    KnownFolder,
+}
+
+const POWERSHELL_TIMEOUT: Duration = Duration::from_secs(10);
+
+static PROFILE_LOCATION: Mutex<Option<(PathBuf, ProfileSource)>> = Mutex::new(None);
+
+/// Returns the PowerShell profile this crate writes to, and how it
+/// was located.
+/// PowerShell knows where the profile is even when the documents
+/// folder was moved, for example to OneDrive, so it is asked first.
+/// The result is cached for the rest of the process.
+pub fn profile_location() -> io::Result<(PathBuf, ProfileSource)> {
+    let mut cached = PROFILE_LOCATION
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(location) = cached.as_ref() {
+        return Ok(location.clone());
+    }
+
+    let location = profile_location_with(&SystemRunner)?;
+    *cached = Some(location.clone());
+    Ok(location)
+}
+
+/// Makes `profile_location` return `location`, or look the profile up
+/// again if it's `None`.
+#[cfg(test)]
+pub(crate) fn set_profile_location(location: Option<(PathBuf, ProfileSource)>) {
+    *PROFILE_LOCATION
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = location;
+}
+
+/// Does the same as `profile_location` with the given runner,
+/// without caching.
+pub fn profile_location_with(runner: &dyn Runner) -> io::Result<(PathBuf, ProfileSource)> {
+    let _operation = crate::events::operation();
+    let asked = runner.output(
+        "powershell",
+        &[
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "$PROFILE.CurrentUserAllHosts",
+        ],
+        POWERSHELL_TIMEOUT,
+    );
+    let location = match asked {
+        Ok(output) if !output.trim().is_empty() => {
+            (PathBuf::from(output.trim()), ProfileSource::PowerShell)
+        }
+        _ => {
+            let documents = dirs::document_dir()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No documents directory"))?;
+            (
+                documents.join("WindowsPowerShell/Profile.ps1"),
+                ProfileSource::KnownFolder,
+            )
+        }
+    };
+    let exists = crate::filesystem::current().exists(&location.0);
+    crate::events::looked_up("powershell", vec![(location.0.clone(), exists)]);
+    Ok(location)
+}
+
+/// Does the same as `check_or_set_nonempty` with the given registry.
+/// The PowerShell profile runs after the registry values are read, so
+/// a value it assigns takes precedence.
+pub fn check_or_set_nonempty_with<T: std::fmt::Display, U: std::fmt::Display>(
+    registry: &dyn Registry,
+    var: T,
+    value: U,
+) -> io::Result<crate::SetOutcome> {
+    let _operation = crate::events::operation();
+    use crate::nonempty::{self, is_blank};
+    use crate::ExistingSource;
+
+    let (var, value) = (var.to_string(), value.to_string());
+    crate::startup_cost::warn_value(&var, &value)?;
+    if let Some(skipped) = nonempty::in_process(&var) {
+        return Ok(skipped);
+    }
+    let mut repaired = Vec::new();
+    if let Some(skipped) = nonempty::in_profile(&var, &value, &mut repaired)? {
+        return Ok(skipped);
+    }
+    match registry.get(&var)? {
+        Some(stored) if is_blank(&stored) => {
+            let Some(translated) = registry_value(&value) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} can't be stored in the registry", value),
+                ));
+            };
+            store(registry, &var, &crate::relocate::for_registry(&translated))?;
+            repaired.push(ExistingSource::Registry);
+        }
+        Some(stored) if repaired.is_empty() => {
+            crate::events::skipped(&var, "set in the registry");
+            return Ok(crate::SetOutcome::Skipped {
+                source: ExistingSource::Registry,
+                value: Some(stored),
+            });
+        }
+        _ => {}
+    }
+    nonempty::finish(&var, &value, repaired)
+}
+
+/// What `migrate_to_registry` did with a variable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MigrationOutcome {
+    /// The registry value was set to `value` and the lines were removed
+    /// from the profile.
+    Migrated { value: String },
+    /// The lines were left in the profile, because `line` uses a
+    /// PowerShell expression the registry can't represent.
+    Kept { line: String },
+}
+
+/// A variable `migrate_to_registry` looked at.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Migration {
+    pub var: String,
+    pub outcome: MigrationOutcome,
+}
+
+/// Moves the variables set, appended to and prepended to in the block
+/// managed by this crate in the PowerShell profile to the user registry,
+/// so cmd.exe and programs started from Explorer see them as well.
+/// Only the variables in `vars` are moved if given.
+/// A variable with a line that can't be represented in the registry
+/// stays in the profile entirely. Afterwards running programs are told
+/// the environment changed.
+pub fn migrate_to_registry(vars: Option<&[&str]>) -> io::Result<Vec<Migration>> {
+    migrate_to_registry_with(&UserEnvironment, &SystemRunner, vars)
+}
+
+/// Does the same as `migrate_to_registry` with the given registry, and
+/// the given runner for telling running programs.
+pub fn migrate_to_registry_with(
+    registry: &dyn Registry,
+    runner: &dyn Runner,
+    vars: Option<&[&str]>,
+) -> io::Result<Vec<Migration>> {
+    let _operation = crate::events::operation();
+    let profile_path = crate::profile_path()?;
+    let content = match crate::filesystem::current().read_to_string(&profile_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    // The managed lines per variable, in the order they run.
+    let mut managed: Vec<(String, Vec<(String, HelperLine)>)> = Vec::new();
+    let mut inside = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line == crate::DEFS_BEG || line == crate::DEFS_END {
+            inside = line == crate::DEFS_BEG;
+            continue;
+        }
+        let Some(parsed) = inside.then(|| HelperLine::parse(line)).flatten() else {
+            continue;
+        };
+        if vars.is_some_and(|vars| !vars.iter().any(|var| var.eq_ignore_ascii_case(parsed.var))) {
+            continue;
+        }
+        let entry = (line.to_string(), parsed.clone());
+        match managed
+            .iter_mut()
+            .find(|(var, _)| var.eq_ignore_ascii_case(parsed.var))
+        {
+            Some((_, lines)) => lines.push(entry),
+            None => managed.push((parsed.var.to_string(), vec![entry])),
+        }
+    }
+
+    let mut migrations = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    for (var, lines) in managed {
+        let mut value = registry.get(&var)?.unwrap_or_default();
+        let mut unrepresentable = None;
+        for (line, parsed) in &lines {
+            let Some(translated) = registry_value(parsed.value) else {
+                unrepresentable = Some(line.clone());
+                break;
+            };
+            let sep = crate::LIST_SEPARATOR;
+            value = match parsed.op {
+                HelperOp::Set => translated,
+                HelperOp::Append if value.is_empty() => translated,
+                HelperOp::Append => format!("{}{}{}", value, sep, translated),
+                HelperOp::Prepend if value.is_empty() => translated,
+                HelperOp::Prepend => format!("{}{}{}", translated, sep, value),
+            };
+        }
+
+        let outcome = match unrepresentable {
+            Some(line) => MigrationOutcome::Kept { line },
+            None => {
+                let value = crate::relocate::for_registry(&value);
+                store(registry, &var, &value)?;
+                removed.extend(lines.into_iter().map(|(line, _)| line));
+                MigrationOutcome::Migrated { value }
+            }
+        };
+        migrations.push(Migration { var, outcome });
+    }
+
+    if !removed.is_empty() {
+        let inside = Cell::new(false);
+        crate::remove_lines(&profile_path, |line| {
+            let line = line.trim();
+            if line == crate::DEFS_BEG || line == crate::DEFS_END {
+                inside.set(line == crate::DEFS_BEG);
+                return false;
+            }
+            inside.get() && removed.iter().any(|it| it == line)
+        })?;
+        if !crate::is_dry_run() {
+            if let Err(err) = runner.output(
+                "powershell",
+                &["-NoProfile", "-NonInteractive", "-Command", BROADCAST],
+                POWERSHELL_TIMEOUT,
+            ) {
+                crate::events::warn(format!(
+                    "running programs were not told about the migration: {}",
+                    err
+                ));
+            }
+        }
+    }
+    Ok(migrations)
+}
+
+/// Sends WM_SETTINGCHANGE for "Environment" to all top level windows,
+/// like the environment variables dialog does, so Explorer picks up
+/// the new registry values for programs it starts.
+const BROADCAST: &str = "Add-Type -Namespace SetEnv -Name Native -MemberDefinition \
+    '[DllImport(\"user32.dll\", CharSet = CharSet.Unicode)] public static extern IntPtr \
+    SendMessageTimeout(IntPtr hWnd, uint Msg, UIntPtr wParam, string lParam, uint fuFlags, \
+    uint uTimeout, out UIntPtr lpdwResult);'; $result = [UIntPtr]::Zero; \
+    [SetEnv.Native]::SendMessageTimeout([IntPtr]0xffff, 0x1A, [UIntPtr]::Zero, 'Environment', \
+    2, 5000, [ref]$result) | Out-Null";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum HelperOp {
+    Set,
+    Append,
+    Prepend,
+}
+
+/// A line calling one of the helpers in the PowerShell profile.
+#[derive(Clone, Debug)]
+struct HelperLine<'a> {
+    op: HelperOp,
+    var: &'a str,
+    value: &'a str,
+}
+
+impl<'a> HelperLine<'a> {
+    fn parse(line: &'a str) -> Option<HelperLine<'a>> {
+        let (op, rest) = [
+            ("setenv_set ", HelperOp::Set),
+            ("setenv_append ", HelperOp::Append),
+            ("setenv_prepend ", HelperOp::Prepend),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| Some((op, line.strip_prefix(prefix)?)))?;
+        let (var, value) = rest.trim_start().split_once(char::is_whitespace)?;
+        Some(HelperLine {
+            op,
+            var,
+            value: value.trim(),
+        })
+    }
+}
+
+/// Translates a value passed to the PowerShell helpers to the notation
+/// of the registry, where `$env:VAR` becomes `%VAR%`.
+/// Returns `None` for other expressions, escapes, and a `%`, which the
+/// registry would take for the start of a reference.
+fn registry_value(value: &str) -> Option<String> {
+    if let Some(literal) = value
+        .strip_prefix('\'')
+        .and_then(|it| it.strip_suffix('\''))
+    {
+        return (!literal.contains(['\'', '%'])).then(|| literal.to_string());
+    }
+    let inner = match value.strip_prefix('"').and_then(|it| it.strip_suffix('"')) {
+        Some(inner) => inner,
+        None if value.contains(char::is_whitespace) => return None,
+        None => value,
+    };
+    if inner.contains(['`', '"', '%']) {
+        return None;
+    }
+
+    let mut out = String::new();
+    let mut rest = inner;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = rest[start + 1..].strip_prefix("env:")?;
+        let len = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        if len == 0 {
+            return None;
+        }
+        out.push('%');
+        out.push_str(&after[..len]);
+        out.push('%');
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+    use crate::{DEFS_BEG, DEFS_END};
+    use std::collections::HashMap;
+
+    /// The user registry, in memory.
+    #[derive(Default)]
+    struct FakeRegistry {
+        values: Mutex<HashMap<String, String>>,
+        sets: Mutex<Vec<(String, String)>>,
+        /// Whether setting a value fails, like without access.
+        read_only: bool,
+    }
+
+    impl FakeRegistry {
+        fn with(values: &[(&str, &str)]) -> FakeRegistry {
+            let registry = FakeRegistry::default();
+            for (var, value) in values {
+                registry.lock().insert(var.to_string(), value.to_string());
+            }
+            registry
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+            self.values.lock().unwrap()
+        }
+
+        fn sets(&self) -> Vec<(String, String)> {
+            self.sets.lock().unwrap().clone()
+        }
+    }
+
+    impl Registry for FakeRegistry {
+        fn get(&self, var: &str) -> io::Result<Option<String>> {
+            Ok(self.lock().get(var).cloned())
+        }
+
+        fn set(&self, var: &str, value: &str) -> io::Result<()> {
+            if self.read_only {
+                return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+            }
+            self.sets
+                .lock()
+                .unwrap()
+                .push((var.to_string(), value.to_string()));
+            self.lock().insert(var.to_string(), value.to_string());
+            Ok(())
+        }
+    }
+
+    const PROFILE: &str = "Documents/PowerShell/profile.ps1";
+    const APPENDED: &str = r"setenv_append PATH C:\Tools";
+    const PREPENDED: &str = r"setenv_prepend PATH D:\Bin";
+
+    /// A profile appending and prepending to PATH in the block, and
+    /// appending the same entry outside of it.
+    fn profile() -> String {
+        format!(
+            "{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n",
+            APPENDED, DEFS_BEG, APPENDED, PREPENDED, DEFS_END
+        )
+    }
+
+    #[test]
+    fn reconcile_reports_the_entries_in_both_places() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+        let registry = FakeRegistry::with(&[("PATH", r"C:\Windows;c:\tools\;E:\x")]);
+
+        let overlaps = reconcile_with(&registry, "PATH", Strategy::ReportOnly).unwrap();
+        assert_eq!(
+            overlaps,
+            [Overlap {
+                registry_entry: r"c:\tools\".to_string(),
+                profile_line: APPENDED.to_string(),
+            }]
+        );
+        assert_eq!(registry.sets(), []);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profile());
+    }
+
+    #[test]
+    fn reconcile_keeps_the_registry_entries() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+        let registry = FakeRegistry::with(&[("PATH", r"C:\Windows;c:\tools\;d:\bin")]);
+
+        let overlaps = reconcile_with(&registry, "PATH", Strategy::KeepRegistry).unwrap();
+        assert_eq!(overlaps.len(), 2);
+        assert_eq!(registry.sets(), []);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\r\n{}\r\n{}\r\n", APPENDED, DEFS_BEG, DEFS_END)
+        );
+    }
+
+    #[test]
+    fn reconcile_keeps_the_profile_lines() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+        let registry = FakeRegistry::with(&[("PATH", r"C:\Windows;c:\tools\;E:\x")]);
+
+        assert_eq!(
+            reconcile_with(&registry, "PATH", Strategy::KeepProfile)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            registry.sets(),
+            [("PATH".to_string(), r"C:\Windows;E:\x".to_string())]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profile());
+    }
+
+    #[test]
+    fn reconcile_changes_nothing_without_overlaps() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+
+        for registry in [
+            FakeRegistry::default(),
+            FakeRegistry::with(&[("PATH", r"C:\Windows;E:\x")]),
+        ] {
+            for strategy in [
+                Strategy::ReportOnly,
+                Strategy::KeepRegistry,
+                Strategy::KeepProfile,
+            ] {
+                assert_eq!(reconcile_with(&registry, "PATH", strategy).unwrap(), []);
+            }
+            assert_eq!(registry.sets(), []);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profile());
+    }
+
+    /// Answers every call with what `answer` returns.
+    struct FakeRunner<F: Fn() -> io::Result<String>> {
+        answer: F,
+        calls: Mutex<Vec<(String, Vec<String>)>>,
+    }
+
+    impl<F: Fn() -> io::Result<String>> FakeRunner<F> {
+        fn new(answer: F) -> FakeRunner<F> {
+            FakeRunner {
+                answer,
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl<F: Fn() -> io::Result<String>> Runner for FakeRunner<F> {
+        fn output(&self, program: &str, args: &[&str], _timeout: Duration) -> io::Result<String> {
+            self.calls.lock().unwrap().push((
+                program.to_string(),
+                args.iter().map(|it| it.to_string()).collect(),
+            ));
+            (self.answer)()
+        }
+    }
+
+    #[test]
+    fn profile_location_asks_powershell() {
+        let _sandbox = sandbox("pwsh");
+        let runner = FakeRunner::new(|| {
+            Ok("C:\\Users\\me\\OneDrive\\Documents\\PowerShell\\profile.ps1\r\n".to_string())
+        });
+
+        assert_eq!(
+            profile_location_with(&runner).unwrap(),
+            (
+                PathBuf::from(r"C:\Users\me\OneDrive\Documents\PowerShell\profile.ps1"),
+                ProfileSource::PowerShell
+            )
+        );
+        let calls = runner.calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "powershell");
+        assert_eq!(
+            calls[0].1.last().map(String::as_str),
+            Some("$PROFILE.CurrentUserAllHosts")
+        );
+    }
+
+    #[test]
+    fn profile_location_falls_back_to_the_documents_folder() {
+        let _sandbox = sandbox("pwsh");
+        let fallback = dirs::document_dir()
+            .unwrap()
+            .join("WindowsPowerShell/Profile.ps1");
+        let failures: [fn() -> io::Result<String>; 4] = [
+            || Ok(" \r\n".to_string()),
+            || Err(io::Error::other("powershell failed: exit code: 1")),
+            || {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "powershell didn't finish within 10s",
+                ))
+            },
+            || Err(io::Error::from(io::ErrorKind::NotFound)),
+        ];
+        for failure in failures {
+            let runner = FakeRunner::new(failure);
+            assert_eq!(
+                profile_location_with(&runner).unwrap(),
+                (fallback.clone(), ProfileSource::KnownFolder)
+            );
+        }
+    }
+
+    const JAVA_HOME: &str = r"setenv_set JAVA_HOME 'C:\Java'";
+    const JAVA_BIN: &str = r#"setenv_append PATH "$env:JAVA_HOME\bin""#;
+    const TOOL: &str = r#"setenv_set TOOL "$(Get-Location)\tool""#;
+
+    /// A profile with variables that can be moved to the registry and
+    /// one that can't.
+    fn migrated_profile() -> String {
+        [DEFS_BEG, JAVA_HOME, JAVA_BIN, PREPENDED, TOOL, DEFS_END]
+            .map(|line| format!("{}\r\n", line))
+            .concat()
+    }
+
+    fn migrated(var: &str, value: &str) -> Migration {
+        Migration {
+            var: var.to_string(),
+            outcome: MigrationOutcome::Migrated {
+                value: value.to_string(),
+            },
+        }
+    }
+
+    fn kept(var: &str, line: &str) -> Migration {
+        Migration {
+            var: var.to_string(),
+            outcome: MigrationOutcome::Kept {
+                line: line.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn migrates_what_the_registry_can_hold() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &migrated_profile());
+        let registry = FakeRegistry::with(&[("PATH", r"C:\Windows")]);
+        let runner = FakeRunner::new(|| Ok(String::new()));
+
+        assert_eq!(
+            migrate_to_registry_with(&registry, &runner, None).unwrap(),
+            [
+                migrated("JAVA_HOME", r"C:\Java"),
+                migrated("PATH", r"D:\Bin;C:\Windows;%JAVA_HOME%\bin"),
+                kept("TOOL", TOOL),
+            ]
+        );
+        assert_eq!(
+            registry.sets(),
+            [
+                ("JAVA_HOME".to_string(), r"C:\Java".to_string()),
+                (
+                    "PATH".to_string(),
+                    r"D:\Bin;C:\Windows;%JAVA_HOME%\bin".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\r\n{}\r\n{}\r\n", DEFS_BEG, TOOL, DEFS_END)
+        );
+        let calls = runner.calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].1.last().map(String::as_str), Some(BROADCAST));
+    }
+
+    #[test]
+    fn migrates_only_the_given_variables() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &migrated_profile());
+        let registry = FakeRegistry::default();
+        let runner = FakeRunner::new(|| Ok(String::new()));
+
+        assert_eq!(
+            migrate_to_registry_with(&registry, &runner, Some(&["path"])).unwrap(),
+            [migrated("PATH", r"D:\Bin;%JAVA_HOME%\bin")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            [DEFS_BEG, JAVA_HOME, TOOL, DEFS_END]
+                .map(|line| format!("{}\r\n", line))
+                .concat()
+        );
+
+        assert_eq!(
+            migrate_to_registry_with(&registry, &runner, Some(&["TOOL"])).unwrap(),
+            [kept("TOOL", TOOL)]
+        );
+        assert_eq!(registry.sets().len(), 1);
+        assert_eq!(runner.calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn a_failed_migration_leaves_the_profile() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &migrated_profile());
+        let registry = FakeRegistry {
+            read_only: true,
+            ..FakeRegistry::default()
+        };
+        let runner = FakeRunner::new(|| Ok(String::new()));
+
+        let err = migrate_to_registry_with(&registry, &runner, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), migrated_profile());
+        assert!(runner.calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_failed_broadcast_is_warned_about() {
+        let sandbox = sandbox("pwsh");
+        sandbox.powershell_profile();
+        sandbox.write(PROFILE, &migrated_profile());
+        let events = std::sync::Arc::new(crate::VecSink::new());
+        crate::set_event_sink(Some(events.clone()));
+        let runner = FakeRunner::new(|| Err(io::Error::from(io::ErrorKind::TimedOut)));
+
+        let migrations = migrate_to_registry_with(&FakeRegistry::default(), &runner, None).unwrap();
+        assert_eq!(migrations.len(), 3);
+        assert!(events.events().iter().any(|event| matches!(
+            event,
+            crate::Event::Warning { message }
+                if message.starts_with("running programs were not told")
+        )));
+    }
+
+    #[test]
+    fn a_dry_run_migration_only_plans() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &migrated_profile());
+        let registry = FakeRegistry::with(&[("PATH", r"C:\Windows")]);
+        let runner = FakeRunner::new(|| Ok(String::new()));
+        crate::set_dry_run(true);
+
+        assert_eq!(
+            migrate_to_registry_with(&registry, &runner, None)
+                .unwrap()
+                .len(),
+            3
+        );
+        let set = |var: &str, value: &str| crate::PlannedChange::SetRegistry {
+            var: var.to_string(),
+            value: value.to_string(),
+        };
+        let remove = |line: &str| crate::PlannedChange::RemoveLine {
+            path: path.clone(),
+            line: line.to_string(),
+        };
+        assert_eq!(
+            crate::drain_planned_changes(),
+            [
+                set("JAVA_HOME", r"C:\Java"),
+                set("PATH", r"D:\Bin;C:\Windows;%JAVA_HOME%\bin"),
+                remove(JAVA_HOME),
+                remove(JAVA_BIN),
+                remove(PREPENDED),
+            ]
+        );
+        assert_eq!(registry.sets(), []);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), migrated_profile());
+        assert!(runner.calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn check_or_set_nonempty_finds_the_variable_in_the_registry() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+        let registry = FakeRegistry::with(&[("SET_ENV_FOUND_REGISTRY", r"C:\Tools")]);
+
+        assert_eq!(
+            check_or_set_nonempty_with(&registry, "SET_ENV_FOUND_REGISTRY", r"D:\Other").unwrap(),
+            crate::SetOutcome::Skipped {
+                source: crate::ExistingSource::Registry,
+                value: Some(r"C:\Tools".to_string()),
+            }
+        );
+        assert_eq!(registry.sets(), []);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profile());
+    }
+
+    #[test]
+    fn check_or_set_nonempty_repairs_a_blank_registry_value() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        sandbox.write(PROFILE, &profile());
+        let registry = FakeRegistry::with(&[
+            ("SET_ENV_BLANK_REGISTRY", " \t"),
+            ("SET_ENV_BLANK_KEPT", " kept "),
+        ]);
+
+        assert_eq!(
+            check_or_set_nonempty_with(&registry, "SET_ENV_BLANK_REGISTRY", r"C:\Tools").unwrap(),
+            crate::SetOutcome::Repaired {
+                sources: vec![crate::ExistingSource::Registry],
+            }
+        );
+        assert_eq!(
+            check_or_set_nonempty_with(&registry, "SET_ENV_BLANK_KEPT", r"C:\Tools").unwrap(),
+            crate::SetOutcome::Skipped {
+                source: crate::ExistingSource::Registry,
+                value: Some(" kept ".to_string()),
+            }
+        );
+        assert_eq!(
+            registry.sets(),
+            [(
+                "SET_ENV_BLANK_REGISTRY".to_string(),
+                r"C:\Tools".to_string()
+            )]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profile());
+    }
+
+    #[test]
+    fn check_or_set_nonempty_repairs_a_blank_profile_line() {
+        let sandbox = sandbox("pwsh");
+        let path = sandbox.powershell_profile();
+        let blank = |value: &str| {
+            format!(
+                "{}\r\nsetenv_set SET_ENV_BLANK_LINE {}\r\n{}\r\n",
+                DEFS_BEG, value, DEFS_END
+            )
+        };
+        sandbox.write(PROFILE, &blank("' '"));
+        let registry = FakeRegistry::default();
+
+        assert_eq!(
+            check_or_set_nonempty_with(&registry, "SET_ENV_BLANK_LINE", "set").unwrap(),
+            crate::SetOutcome::Repaired {
+                sources: vec![crate::ExistingSource::ManagedEntry(path.clone())],
+            }
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), blank("set"));
+        assert_eq!(registry.sets(), []);
+    }
+}
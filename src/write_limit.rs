@@ -0,0 +1,201 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Default for the number of writes allowed against a single profile
+/// in one process, see `set_write_limit`.
+pub const DEFAULT_WRITE_LIMIT: usize = 50;
+
+static WRITE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_WRITE_LIMIT);
+static WRITE_COUNTS: Mutex<Vec<(PathBuf, usize)>> = Mutex::new(Vec::new());
+/// The profiles written since `batch` started, if it runs.
+static BATCH: Mutex<Option<Vec<PathBuf>>> = Mutex::new(None);
+
+/// Why a profile wasn't changed: this process already wrote to it as
+/// often as `set_write_limit` allows. The payload of the error, which
+/// has `ErrorKind::QuotaExceeded`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WriteLimitExceeded {
+    pub path: PathBuf,
+    pub limit: usize,
+}
+
+impl fmt::Display for WriteLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refusing to write to {} more than {} times in one run, see set_write_limit",
+            self.path.display(),
+            self.limit
+        )
+    }
+}
+
+impl Error for WriteLimitExceeded {}
+
+/// Sets how many times this process may write to the same profile
+/// before further writes are refused.
+/// This guards against callers looping over `append` and the like,
+/// which would otherwise fill the profile with identical lines.
+pub fn set_write_limit(limit: usize) {
+    WRITE_LIMIT.store(limit, Ordering::SeqCst);
+}
+
+/// Returns how many times this process has written to the profile at `path`.
+pub fn write_count(path: &Path) -> usize {
+    let counts = WRITE_COUNTS.lock().unwrap_or_else(|err| err.into_inner());
+    counts
+        .iter()
+        .find(|(counted, _)| counted == path)
+        .map_or(0, |(_, count)| *count)
+}
+
+/// Runs `changes`, counting all writes it makes to a profile as one
+/// against the write limit, for callers making many changes on purpose:
+///
+/// ```no_run
+/// set_env_perm::batch(|| {
+///     for dir in ["/opt/a/bin", "/opt/b/bin"] {
+///         set_env_perm::append("PATH", dir)?;
+///     }
+///     Ok(())
+/// })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn batch<R>(changes: impl FnOnce() -> io::Result<R>) -> io::Result<R> {
+    if lock_batch().is_some() {
+        return changes();
+    }
+
+    /// Ends the batch even if `changes` panics.
+    struct Batch;
+    impl Drop for Batch {
+        fn drop(&mut self) {
+            for path in lock_batch().take().unwrap_or_default() {
+                increment(&path);
+            }
+        }
+    }
+
+    *lock_batch() = Some(Vec::new());
+    let _batch = Batch;
+    changes()
+}
+
+fn lock_batch() -> std::sync::MutexGuard<'static, Option<Vec<PathBuf>>> {
+    BATCH.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Fails with a `WriteLimitExceeded` if the profile at `path` can't be
+/// written to again.
+pub(crate) fn check(path: &Path) -> io::Result<()> {
+    if lock_batch()
+        .as_ref()
+        .is_some_and(|written| written.iter().any(|it| it == path))
+    {
+        return Ok(());
+    }
+    let limit = WRITE_LIMIT.load(Ordering::SeqCst);
+    if write_count(path) < limit {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::QuotaExceeded,
+        WriteLimitExceeded {
+            path: path.to_path_buf(),
+            limit,
+        },
+    ))
+}
+
+/// Counts a completed write to the profile at `path`.
+pub(crate) fn count(path: &Path) {
+    if let Some(written) = lock_batch().as_mut() {
+        if !written.iter().any(|it| it == path) {
+            written.push(path.to_path_buf());
+        }
+        return;
+    }
+    increment(path);
+}
+
+fn increment(path: &Path) {
+    let mut counts = WRITE_COUNTS.lock().unwrap_or_else(|err| err.into_inner());
+    match counts.iter_mut().find(|(counted, _)| counted == path) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((path.to_path_buf(), 1)),
+    }
+}
+
+/// Forgets every write, for tests.
+#[cfg(test)]
+pub(crate) fn clear() {
+    WRITE_COUNTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clear();
+    *lock_batch() = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn writes_past_the_limit_fail() {
+        let sandbox = sandbox("/bin/bash");
+        set_write_limit(3);
+        for value in 0..3 {
+            crate::set("FOO", value).unwrap();
+        }
+        let path = sandbox.path(".bash_profile");
+        assert_eq!(write_count(&path), 3);
+
+        let err = crate::set("FOO", 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::QuotaExceeded);
+        let payload = err.get_ref().unwrap().downcast_ref::<WriteLimitExceeded>();
+        assert_eq!(payload, Some(&WriteLimitExceeded { path, limit: 3 }));
+        assert!(!sandbox.read(".bash_profile").contains("FOO=3"));
+    }
+
+    #[test]
+    fn the_limit_can_be_raised() {
+        let _sandbox = sandbox("/bin/bash");
+        set_write_limit(1);
+        crate::set("FOO", 1).unwrap();
+        assert!(crate::set("FOO", 2).is_err());
+
+        set_write_limit(2);
+        crate::set("FOO", 2).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_are_not_counted() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "<<<<<<< HEAD\n");
+        assert!(crate::set("FOO", 1).is_err());
+        assert_eq!(write_count(&sandbox.path(".bash_profile")), 0);
+    }
+
+    #[test]
+    fn a_batch_counts_as_one_write() {
+        let sandbox = sandbox("/bin/bash");
+        set_write_limit(2);
+        batch(|| {
+            for value in 0..10 {
+                crate::set(format!("VAR_{}", value), value)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(write_count(&sandbox.path(".bash_profile")), 1);
+
+        crate::set("FOO", 1).unwrap();
+        assert!(crate::set("FOO", 2).is_err());
+        assert!(batch(|| crate::set("FOO", 3)).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How profiles are rewritten, see `set_write_mode`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum WriteMode {
+    /// `Locked` on network and FUSE filesystems, `InPlace` elsewhere.
+    #[default]
+    Auto,
+    /// Read and change the profile without a lock.
+    InPlace,
+    /// Take a lock file next to the profile and read and change it
+    /// while holding the lock.
+    Locked,
+}
+
+static MODE: Mutex<WriteMode> = Mutex::new(WriteMode::Auto);
+
+/// How long to wait for another process to release the lock file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sets how profiles are rewritten.
+/// On NFS, SMB and FUSE filesystems concurrent writers can interleave
+/// partial lines, which `WriteMode::Locked` prevents.
+/// Defaults to `WriteMode::Auto`.
+pub fn set_write_mode(mode: WriteMode) {
+    *MODE.lock().unwrap_or_else(|err| err.into_inner()) = mode;
+}
+
+/// A filesystem on which writes of concurrent processes may interleave.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetworkFilesystem {
+    Nfs,
+    Smb,
+    Fuse,
+}
+
+/// Returns the kind of network or FUSE filesystem `path` is on,
+/// or `None` if it's on a local one or the kind can't be determined.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+pub fn network_filesystem(path: &Path) -> Option<NetworkFilesystem> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // The profile itself may not exist yet.
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `c_path` is a valid C string and `stat` is only read
+    // after statfs reported success and thereby initialized it.
+    let stat = unsafe {
+        if libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    classify(&stat)
+}
+
+/// Returns the kind of network or FUSE filesystem `path` is on,
+/// or `None` if it's on a local one or the kind can't be determined,
+/// which is always the case on systems without a usable statfs, like
+/// Haiku and Redox.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+pub fn network_filesystem(_path: &Path) -> Option<NetworkFilesystem> {
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn classify(stat: &libc::statfs) -> Option<NetworkFilesystem> {
+    from_magic(stat.f_type as u32)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn classify(stat: &libc::statfs) -> Option<NetworkFilesystem> {
+    let name: Vec<u8> = stat
+        .f_fstypename
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    from_type_name(&String::from_utf8_lossy(&name))
+}
+
+/// Maps the filesystem magic numbers reported by statfs on Linux.
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+fn from_magic(magic: u32) -> Option<NetworkFilesystem> {
+    match magic {
+        0x6969 => Some(NetworkFilesystem::Nfs),
+        0x517B | 0xFE53_4D42 | 0xFF53_4D42 => Some(NetworkFilesystem::Smb),
+        0x6573_5546 => Some(NetworkFilesystem::Fuse),
+        _ => None,
+    }
+}
+
+/// Maps the filesystem type names reported by statfs on BSDs and macOS.
+#[cfg_attr(
+    not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")),
+    allow(dead_code)
+)]
+fn from_type_name(name: &str) -> Option<NetworkFilesystem> {
+    match name {
+        "nfs" => Some(NetworkFilesystem::Nfs),
+        "smbfs" | "cifs" => Some(NetworkFilesystem::Smb),
+        _ if name.contains("fuse") => Some(NetworkFilesystem::Fuse),
+        _ => None,
+    }
+}
+
+/// Replaces the content of the file at `path` with what `edit` returns
+/// for its current content, which is empty if the file doesn't exist.
+/// If `edit` fails the file is left alone.
+pub(crate) fn rewrite<R>(
+    path: &Path,
+    edit: impl FnOnce(&str) -> io::Result<(String, R)>,
+) -> io::Result<R> {
+    let _lock = if is_locked(path) {
+        Some(LockFile::acquire(lock_path(path)?)?)
+    } else {
+        None
+    };
+    let (content, result) = edit(&read(path)?)?;
+    crate::filesystem::replace(path, &content)?;
+    crate::verify::written(path, &content)?;
+    Ok(result)
+}
+
+/// Returns the configured write mode.
+pub(crate) fn write_mode() -> WriteMode {
+    *MODE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Returns whether `rewrite` takes a lock for `path`.
+pub(crate) fn is_locked(path: &Path) -> bool {
+    match write_mode() {
+        WriteMode::Auto => network_filesystem(path).is_some(),
+        WriteMode::InPlace => false,
+        WriteMode::Locked => true,
+    }
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    match crate::filesystem::current().read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the lock file for `path`, next to the file it points to if
+/// it's a symlink, as that is the file replaced.
+fn lock_path(path: &Path) -> io::Result<PathBuf> {
+    let target = match crate::filesystem::current().canonicalize(path) {
+        Ok(target) => target,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    Ok(crate::filesystem::sibling(&target, "lock"))
+}
+
+/// A lock file that is removed again when dropped.
+struct LockFile(PathBuf);
+
+impl LockFile {
+    fn acquire(path: PathBuf) -> io::Result<LockFile> {
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            match crate::filesystem::current().create_new(&path, "") {
+                Ok(_) => return Ok(LockFile(path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            format!(
+                                "{} is held by another process, remove it if it is stale",
+                                path.display()
+                            ),
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = crate::filesystem::current().remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    #[test]
+    fn rewrite_replaces_the_file_a_symlink_points_to() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write("dotfiles/bash_profile", "alias a=b\n");
+        std::os::unix::fs::symlink(
+            sandbox.path("dotfiles/bash_profile"),
+            sandbox.path(".bash_profile"),
+        )
+        .unwrap();
+
+        for mode in [WriteMode::InPlace, WriteMode::Locked] {
+            set_write_mode(mode);
+            rewrite(&sandbox.path(".bash_profile"), |content| {
+                Ok((format!("{}# {:?}\n", content, mode), ()))
+            })
+            .unwrap();
+        }
+
+        let link = std::fs::symlink_metadata(sandbox.path(".bash_profile")).unwrap();
+        assert!(link.file_type().is_symlink());
+        assert_eq!(
+            sandbox.read("dotfiles/bash_profile"),
+            "alias a=b\n# InPlace\n# Locked\n"
+        );
+        let mut names: Vec<_> = std::fs::read_dir(sandbox.path("dotfiles"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["bash_profile"]);
+    }
+
+    #[test]
+    fn failed_edits_leave_the_file_alone() {
+        let sandbox = sandbox("/bin/bash");
+        sandbox.write(".bash_profile", "alias a=b\n");
+
+        let result: io::Result<()> = rewrite(&sandbox.path(".bash_profile"), |_| {
+            Err(io::Error::other("no"))
+        });
+        assert!(result.is_err());
+        assert_eq!(sandbox.read(".bash_profile"), "alias a=b\n");
+        assert_eq!(std::fs::read_dir(&sandbox.home).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,177 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::var_kind::{var_kind, VarKind};
+
+/// Which zsh config file is changed, see `set_zsh_policy`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ZshPolicy {
+    /// The first existing of `.zshenv`, `.zprofile`, `.zshrc` and
+    /// `.zlogin`, the default everywhere but on macOS.
+    FirstExisting,
+    /// `.zprofile` for list variables like PATH, so the order macOS's
+    /// path_helper establishes in `/etc/zprofile` isn't fought,
+    /// and `.zshenv` for everything else, so `zsh -c` sees it too.
+    /// The default on macOS, where Homebrew often creates only
+    /// `.zprofile`, which non-login shells don't read.
+    SplitByKind,
+}
+
+static POLICY: Mutex<Option<ZshPolicy>> = Mutex::new(None);
+
+/// Sets which zsh config file is changed.
+pub fn set_zsh_policy(policy: ZshPolicy) {
+    *POLICY.lock().unwrap_or_else(|err| err.into_inner()) = Some(policy);
+}
+
+pub(crate) fn zsh_policy() -> ZshPolicy {
+    let policy = *POLICY.lock().unwrap_or_else(|err| err.into_inner());
+    policy.unwrap_or(if cfg!(target_os = "macos") {
+        ZshPolicy::SplitByKind
+    } else {
+        ZshPolicy::FirstExisting
+    })
+}
+
+/// Returns the directory zsh reads `config_file` from: `ZDOTDIR` if
+/// set, except for a `.zshenv` in `home_dir`, which usually is where
+/// `ZDOTDIR` gets set.
+pub(crate) fn config_dir(home_dir: &Path, config_file: &str) -> PathBuf {
+    // Under sudo ZDOTDIR may belong to another user.
+    let zdotdir =
+        env::var_os("ZDOTDIR").filter(|dir| !dir.is_empty() && crate::sudo::sudo_uid().is_none());
+    match zdotdir {
+        Some(dir)
+            if config_file != ".zshenv"
+                || !crate::filesystem::current().exists(&home_dir.join(".zshenv")) =>
+        {
+            PathBuf::from(dir)
+        }
+        _ => home_dir.to_path_buf(),
+    }
+}
+
+/// Returns the file lines changing `var` go to under
+/// `ZshPolicy::SplitByKind`, or the one for everything but list
+/// variables if `var` is `None`.
+/// Returns `None` if `shell` isn't zsh or the policy doesn't apply.
+pub(crate) fn split_profile(home_dir: &Path, shell: &str, var: Option<&str>) -> Option<PathBuf> {
+    if crate::select_shell(shell)?.name() != "zsh" || zsh_policy() != ZshPolicy::SplitByKind {
+        return None;
+    }
+
+    let file = match var.map(var_kind) {
+        Some(VarKind::List) => ".zprofile",
+        _ => ".zshenv",
+    };
+    Some(config_dir(home_dir, file).join(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    /// Runs every operation writing a line, and returns the file each
+    /// one wrote to, relative to `home`.
+    fn written(home: &Path) -> Vec<(&'static str, String)> {
+        type Operation = fn() -> std::io::Result<()>;
+        let operations: [(&str, Operation); 4] = [
+            ("set", || crate::set("SET_ENV_ZSH_EDITOR", "vim").map(drop)),
+            ("append", || crate::append("PATH", "/opt/zsh/bin").map(drop)),
+            ("prepend", || {
+                crate::prepend("PATH", "/opt/zsh/first").map(drop)
+            }),
+            ("set_disabled", || {
+                crate::set_disabled("SET_ENV_ZSH_PAGER", "less")
+            }),
+        ];
+        let mut snapshot = crate::test_support::snapshot(home);
+        operations
+            .into_iter()
+            .map(|(name, operation)| {
+                operation().unwrap();
+                let after = crate::test_support::snapshot(home);
+                let changed: Vec<String> = after
+                    .iter()
+                    .filter(|it| it.1.is_some() && !snapshot.contains(it))
+                    .map(|(path, _)| path.strip_prefix(home).unwrap().display().to_string())
+                    .collect();
+                assert_eq!(changed.len(), 1, "{}: {:?}", name, changed);
+                snapshot = after;
+                (name, changed[0].clone())
+            })
+            .collect()
+    }
+
+    fn expected(files: [&str; 4]) -> Vec<(&'static str, String)> {
+        ["set", "append", "prepend", "set_disabled"]
+            .into_iter()
+            .zip(files.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn first_existing_creates_zshenv() {
+        let sandbox = sandbox("/bin/zsh");
+        set_zsh_policy(ZshPolicy::FirstExisting);
+        assert_eq!(written(&sandbox.home), expected([".zshenv"; 4]));
+    }
+
+    #[test]
+    fn first_existing_writes_everything_to_the_first_existing_file() {
+        let sandbox = sandbox("/bin/zsh");
+        sandbox.write(".zshrc", "");
+        sandbox.write(".zlogin", "");
+        set_zsh_policy(ZshPolicy::FirstExisting);
+        assert_eq!(written(&sandbox.home), expected([".zshrc"; 4]));
+    }
+
+    #[test]
+    fn split_by_kind_writes_lists_to_zprofile() {
+        let sandbox = sandbox("/bin/zsh");
+        sandbox.write(".zshrc", "");
+        set_zsh_policy(ZshPolicy::SplitByKind);
+        assert_eq!(
+            written(&sandbox.home),
+            expected([".zshenv", ".zprofile", ".zprofile", ".zshenv"])
+        );
+        assert_eq!(sandbox.read(".zshrc"), "");
+        assert_eq!(crate::unset("SET_ENV_ZSH_EDITOR").unwrap(), 1);
+    }
+
+    #[test]
+    fn split_by_kind_follows_zdotdir() {
+        let sandbox = sandbox("/bin/zsh");
+        sandbox.write("zdot/.zshrc", "");
+        env::set_var("ZDOTDIR", sandbox.path("zdot"));
+        set_zsh_policy(ZshPolicy::SplitByKind);
+        let files = written(&sandbox.home);
+        env::remove_var("ZDOTDIR");
+        assert_eq!(
+            files,
+            expected([
+                "zdot/.zshenv",
+                "zdot/.zprofile",
+                "zdot/.zprofile",
+                "zdot/.zshenv"
+            ])
+        );
+    }
+
+    #[test]
+    fn split_by_kind_keeps_a_zshenv_setting_zdotdir_in_home() {
+        let sandbox = sandbox("/bin/zsh");
+        sandbox.write(".zshenv", "export ZDOTDIR=\"$HOME/zdot\"\n");
+        sandbox.write("zdot/.zshrc", "");
+        env::set_var("ZDOTDIR", sandbox.path("zdot"));
+        set_zsh_policy(ZshPolicy::SplitByKind);
+        let files = written(&sandbox.home);
+        env::remove_var("ZDOTDIR");
+        assert_eq!(
+            files,
+            expected([".zshenv", "zdot/.zprofile", "zdot/.zprofile", ".zshenv"])
+        );
+    }
+}